@@ -0,0 +1,74 @@
+//! Process arguments and environment, read from the initial stack.
+use core::{ptr, slice, str};
+
+static mut ARGC: usize = 0;
+static mut ARGV: *const *const u8 = ptr::null();
+static mut ENVP: *const *const u8 = ptr::null();
+
+pub(crate) unsafe fn init(argc: usize, argv: *const *const u8, envp: *const *const u8) {
+    ARGC = argc;
+    ARGV = argv;
+    ENVP = envp;
+}
+
+unsafe fn c_str(p: *const u8) -> &'static str {
+    let mut len = 0;
+    while *p.add(len) != 0 {
+        len += 1;
+    }
+    str::from_utf8(slice::from_raw_parts(p, len)).unwrap_or("")
+}
+
+/// The program's arguments; index 0 is the program path.
+#[derive(Copy, Clone)]
+pub struct Args {
+    argc: usize,
+    argv: *const *const u8,
+}
+
+impl Args {
+    pub(crate) fn new() -> Self {
+        unsafe { Args { argc: ARGC, argv: ARGV } }
+    }
+
+    pub fn len(&self) -> usize {
+        self.argc
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.argc == 0
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&'static str> {
+        if idx >= self.argc {
+            return None;
+        }
+        unsafe { Some(c_str(*self.argv.add(idx))) }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'static str> {
+        let args = *self;
+        (0..args.argc).filter_map(move |i| args.get(i))
+    }
+}
+
+pub fn args() -> Args {
+    Args::new()
+}
+
+/// Iterates over `NAME=value` pairs passed by the shell.
+pub fn vars() -> impl Iterator<Item = (&'static str, &'static str)> {
+    let mut cursor = unsafe { ENVP };
+    core::iter::from_fn(move || unsafe {
+        if cursor.is_null() || (*cursor).is_null() {
+            return None;
+        }
+        let entry = c_str(*cursor);
+        cursor = cursor.add(1);
+        Some(entry.split_once('=').unwrap_or((entry, "")))
+    })
+}
+
+pub fn var(name: &str) -> Option<&'static str> {
+    vars().find(|(key, _)| *key == name).map(|(_, value)| value)
+}
@@ -0,0 +1,105 @@
+//! File access on the kernel's mounted volume. Paths use `\` or `/` separators and are
+//! resolved against the shell's current directory.
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::io;
+use crate::syscall::{self, Error, Result, EINVAL, O_APPEND, O_CREAT, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY};
+
+pub struct File {
+    fd: u64,
+}
+
+impl File {
+    /// Opens an existing file for reading.
+    pub fn open(path: &str) -> Result<File> {
+        Self::open_with(path, O_RDONLY)
+    }
+
+    /// Creates or truncates a file for writing.
+    pub fn create(path: &str) -> Result<File> {
+        Self::open_with(path, O_WRONLY | O_CREAT | O_TRUNC)
+    }
+
+    /// Opens a file for appending, creating it if needed.
+    pub fn append(path: &str) -> Result<File> {
+        Self::open_with(path, O_WRONLY | O_CREAT | O_APPEND)
+    }
+
+    /// Opens a file for reading and writing, creating it if needed.
+    pub fn open_rw(path: &str) -> Result<File> {
+        Self::open_with(path, O_RDWR | O_CREAT)
+    }
+
+    pub fn open_with(path: &str, flags: u64) -> Result<File> {
+        syscall::open(path, flags).map(|fd| File { fd })
+    }
+
+    pub fn fd(&self) -> u64 {
+        self.fd
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        syscall::read(self.fd, buf)
+    }
+
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        syscall::write(self.fd, buf)
+    }
+
+    pub fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        io::write_all(self.fd, buf)
+    }
+
+    pub fn read_to_end(&mut self, out: &mut Vec<u8>) -> Result<usize> {
+        let start = out.len();
+        let mut chunk = [0u8; 1024];
+        loop {
+            let n = self.read(&mut chunk)?;
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&chunk[..n]);
+        }
+        Ok(out.len() - start)
+    }
+
+    pub fn read_to_string(&mut self, out: &mut String) -> Result<usize> {
+        let mut bytes = Vec::new();
+        let n = self.read_to_end(&mut bytes)?;
+        let text = core::str::from_utf8(&bytes).map_err(|_| Error(EINVAL))?;
+        out.push_str(text);
+        Ok(n)
+    }
+
+    /// Closes the file, reporting any error from writing it back to disk.
+    pub fn close(self) -> Result<()> {
+        let fd = self.fd;
+        core::mem::forget(self);
+        syscall::close(fd)
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        let _ = syscall::close(self.fd);
+    }
+}
+
+pub fn read(path: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    File::open(path)?.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+pub fn read_to_string(path: &str) -> Result<String> {
+    let mut out = String::new();
+    File::open(path)?.read_to_string(&mut out)?;
+    Ok(out)
+}
+
+pub fn write(path: &str, data: &[u8]) -> Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(data)?;
+    file.close()
+}
@@ -0,0 +1,63 @@
+//! Global allocator: a linked-list heap that grows by mapping more anonymous memory.
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+use linked_list_allocator::LockedHeap;
+
+use crate::syscall::{self, PROT_READ, PROT_WRITE};
+
+const INITIAL_HEAP: usize = 256 * 1024;
+const GROW_STEP: usize = 256 * 1024;
+
+struct MmapHeap(LockedHeap);
+
+#[global_allocator]
+static HEAP: MmapHeap = MmapHeap(LockedHeap::empty());
+
+pub(crate) fn init() {
+    if let Ok(base) = syscall::mmap(INITIAL_HEAP, PROT_READ | PROT_WRITE) {
+        unsafe { HEAP.0.lock().init(base, INITIAL_HEAP) };
+    }
+}
+
+impl MmapHeap {
+    // The kernel hands out mmap regions back to back, so a fresh region normally starts
+    // right where the heap ends and can be appended to it.
+    fn grow(&self, min: usize) -> bool {
+        let len = min.div_ceil(GROW_STEP) * GROW_STEP;
+        let mut heap = self.0.lock();
+        let Ok(region) = syscall::mmap(len, PROT_READ | PROT_WRITE) else {
+            return false;
+        };
+        if heap.size() == 0 {
+            unsafe { heap.init(region, len) };
+            return true;
+        }
+        if region != heap.top() {
+            return false;
+        }
+        unsafe { heap.extend(len) };
+        true
+    }
+}
+
+unsafe impl GlobalAlloc for MmapHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if let Ok(p) = self.0.lock().allocate_first_fit(layout) {
+            return p.as_ptr();
+        }
+        if !self.grow(layout.size() + layout.align()) {
+            return ptr::null_mut();
+        }
+        self.0
+            .lock()
+            .allocate_first_fit(layout)
+            .map(|p| p.as_ptr())
+            .unwrap_or_default()
+    }
+
+    unsafe fn dealloc(&self, p: *mut u8, layout: Layout) {
+        if let Some(p) = NonNull::new(p) {
+            self.0.lock().deallocate(p, layout);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! Console I/O and the `print!` family of macros.
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::syscall::{self, Result, STDERR, STDIN, STDOUT};
+
+/// Writes formatted output to `fd`, batching the pieces of one `format_args!` into as
+/// few syscalls as possible.
+struct FdWriter {
+    fd: u64,
+    buf: [u8; 512],
+    len: usize,
+}
+
+impl FdWriter {
+    fn new(fd: u64) -> Self {
+        FdWriter { fd, buf: [0; 512], len: 0 }
+    }
+
+    fn flush(&mut self) {
+        if self.len > 0 {
+            let _ = write_all(self.fd, &self.buf[..self.len]);
+            self.len = 0;
+        }
+    }
+}
+
+impl fmt::Write for FdWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let bytes = s.as_bytes();
+        if bytes.len() > self.buf.len() {
+            self.flush();
+            return write_all(self.fd, bytes).map_err(|_| fmt::Error);
+        }
+        if self.len + bytes.len() > self.buf.len() {
+            self.flush();
+        }
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+        Ok(())
+    }
+}
+
+pub fn write_all(fd: u64, mut data: &[u8]) -> Result<()> {
+    while !data.is_empty() {
+        let n = syscall::write(fd, data)?;
+        if n == 0 {
+            break;
+        }
+        data = &data[n..];
+    }
+    Ok(())
+}
+
+#[doc(hidden)]
+pub fn _print(fd: u64, args: fmt::Arguments) {
+    use fmt::Write;
+    let mut out = FdWriter::new(fd);
+    let _ = out.write_fmt(args);
+    out.flush();
+}
+
+pub fn stdout_fd() -> u64 {
+    STDOUT
+}
+
+pub fn stderr_fd() -> u64 {
+    STDERR
+}
+
+/// Reads whatever is available on stdin into `buf`. Returns 0 at end of input.
+pub fn read(buf: &mut [u8]) -> Result<usize> {
+    syscall::read(STDIN, buf)
+}
+
+/// Reads one line from stdin, including the trailing newline if there was one.
+/// Returns an empty string at end of input.
+pub fn read_line() -> Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        if syscall::read(STDIN, &mut byte)? == 0 {
+            break;
+        }
+        line.push(byte[0]);
+        if byte[0] == b'\n' {
+            break;
+        }
+    }
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+/// Reads stdin until end of input.
+pub fn read_to_end(out: &mut Vec<u8>) -> Result<usize> {
+    let start = out.len();
+    let mut chunk = [0u8; 512];
+    loop {
+        let n = syscall::read(STDIN, &mut chunk)?;
+        if n == 0 {
+            break;
+        }
+        out.extend_from_slice(&chunk[..n]);
+    }
+    Ok(out.len() - start)
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::stdout_fd(), format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::stdout_fd(), format_args!("{}\n", format_args!($($arg)*))));
+}
+
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::stderr_fd(), format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::stderr_fd(), format_args!("{}\n", format_args!($($arg)*))));
+}
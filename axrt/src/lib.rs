@@ -0,0 +1,43 @@
+//! Minimal runtime for Axiomata user programs: syscall wrappers, process startup,
+//! a heap over `mmap`, console I/O and file access.
+#![no_std]
+
+pub extern crate alloc;
+
+pub mod env;
+pub mod fs;
+pub mod heap;
+pub mod io;
+mod start;
+pub mod syscall;
+
+pub use env::Args;
+pub use syscall::{Error, Result};
+
+/// Declares the program entry point. The function receives the process arguments and
+/// returns the exit status.
+///
+/// ```ignore
+/// axrt::entry!(main);
+/// fn main(args: axrt::Args) -> i32 { 0 }
+/// ```
+#[macro_export]
+macro_rules! entry {
+    ($path:path) => {
+        #[no_mangle]
+        pub extern "Rust" fn __axrt_main(args: $crate::Args) -> i32 {
+            let f: fn($crate::Args) -> i32 = $path;
+            f(args)
+        }
+    };
+}
+
+pub fn exit(code: i32) -> ! {
+    syscall::exit(code as i64)
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    eprintln!("panic: {}", info);
+    syscall::exit(101)
+}
@@ -0,0 +1,32 @@
+use core::arch::global_asm;
+
+use crate::{env, heap, syscall, Args};
+
+// The kernel enters at _start with rsp pointing at argc, followed by the argv and
+// envp vectors and the auxiliary vector.
+global_asm!(
+    ".pushsection .text._start",
+    ".global _start",
+    "_start:",
+    "    xor ebp, ebp",
+    "    mov rdi, rsp",
+    "    and rsp, -16",
+    "    call {start}",
+    "    ud2",
+    ".popsection",
+    start = sym axrt_start,
+);
+
+extern "Rust" {
+    fn __axrt_main(args: Args) -> i32;
+}
+
+unsafe extern "C" fn axrt_start(sp: *const u64) -> ! {
+    let argc = *sp as usize;
+    let argv = sp.add(1) as *const *const u8;
+    let envp = argv.add(argc + 1);
+    env::init(argc, argv, envp);
+    heap::init();
+    let code = __axrt_main(Args::new());
+    syscall::exit(code as i64)
+}
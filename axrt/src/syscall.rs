@@ -0,0 +1,173 @@
+//! Raw system calls. Numbers, errno values and flags mirror the kernel's `syscall.rs`.
+use core::arch::asm;
+use core::fmt;
+
+pub const SYS_EXIT: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_READ: u64 = 2;
+pub const SYS_OPEN: u64 = 3;
+pub const SYS_CLOSE: u64 = 4;
+pub const SYS_MMAP: u64 = 5;
+pub const SYS_GETPID: u64 = 6;
+pub const SYS_YIELD: u64 = 7;
+pub const SYS_TIME: u64 = 8;
+
+pub const ENOENT: i64 = -2;
+pub const EIO: i64 = -5;
+pub const EBADF: i64 = -9;
+pub const ENOMEM: i64 = -12;
+pub const EFAULT: i64 = -14;
+pub const EINVAL: i64 = -22;
+pub const EMFILE: i64 = -24;
+pub const EPIPE: i64 = -32;
+pub const ENOSYS: i64 = -38;
+
+pub const O_RDONLY: u64 = 0;
+pub const O_WRONLY: u64 = 1;
+pub const O_RDWR: u64 = 2;
+pub const O_CREAT: u64 = 0x40;
+pub const O_TRUNC: u64 = 0x200;
+pub const O_APPEND: u64 = 0x400;
+
+pub const PROT_READ: u64 = 1;
+pub const PROT_WRITE: u64 = 2;
+pub const PROT_EXEC: u64 = 4;
+
+pub const STDIN: u64 = 0;
+pub const STDOUT: u64 = 1;
+pub const STDERR: u64 = 2;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Error(pub i64);
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+impl Error {
+    pub fn as_str(&self) -> &'static str {
+        match self.0 {
+            ENOENT => "No such file or directory",
+            EIO => "I/O error",
+            EBADF => "Bad file descriptor",
+            ENOMEM => "Out of memory",
+            EFAULT => "Bad address",
+            EINVAL => "Invalid argument",
+            EMFILE => "Too many open files",
+            EPIPE => "Broken pipe",
+            ENOSYS => "Function not implemented",
+            _ => "Unknown error",
+        }
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Error({}: {})", self.0, self.as_str())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+fn check(ret: i64) -> Result<u64> {
+    if ret < 0 {
+        Err(Error(ret))
+    } else {
+        Ok(ret as u64)
+    }
+}
+
+/// # Safety
+/// Pointer arguments must be valid for the kernel to read or write as the call requires.
+#[inline(always)]
+pub unsafe fn syscall0(nr: u64) -> i64 {
+    let ret: i64;
+    asm!("syscall", inlateout("rax") nr as i64 => ret, lateout("rcx") _, lateout("r11") _, options(nostack));
+    ret
+}
+
+/// # Safety
+/// See [`syscall0`].
+#[inline(always)]
+pub unsafe fn syscall1(nr: u64, a0: u64) -> i64 {
+    let ret: i64;
+    asm!(
+        "syscall",
+        inlateout("rax") nr as i64 => ret,
+        in("rdi") a0,
+        lateout("rcx") _,
+        lateout("r11") _,
+        options(nostack)
+    );
+    ret
+}
+
+/// # Safety
+/// See [`syscall0`].
+#[inline(always)]
+pub unsafe fn syscall3(nr: u64, a0: u64, a1: u64, a2: u64) -> i64 {
+    let ret: i64;
+    asm!(
+        "syscall",
+        inlateout("rax") nr as i64 => ret,
+        in("rdi") a0,
+        in("rsi") a1,
+        in("rdx") a2,
+        lateout("rcx") _,
+        lateout("r11") _,
+        options(nostack)
+    );
+    ret
+}
+
+pub fn exit(code: i64) -> ! {
+    unsafe {
+        syscall1(SYS_EXIT, code as u64);
+    }
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+pub fn write(fd: u64, buf: &[u8]) -> Result<usize> {
+    check(unsafe { syscall3(SYS_WRITE, fd, buf.as_ptr() as u64, buf.len() as u64) }).map(|n| n as usize)
+}
+
+pub fn read(fd: u64, buf: &mut [u8]) -> Result<usize> {
+    check(unsafe { syscall3(SYS_READ, fd, buf.as_mut_ptr() as u64, buf.len() as u64) }).map(|n| n as usize)
+}
+
+pub fn open(path: &str, flags: u64) -> Result<u64> {
+    check(unsafe { syscall3(SYS_OPEN, path.as_ptr() as u64, path.len() as u64, flags) })
+}
+
+pub fn close(fd: u64) -> Result<()> {
+    check(unsafe { syscall1(SYS_CLOSE, fd) }).map(|_| ())
+}
+
+/// Maps `len` bytes of zeroed anonymous memory and returns its address.
+pub fn mmap(len: usize, prot: u64) -> Result<*mut u8> {
+    check(unsafe { syscall3(SYS_MMAP, 0, len as u64, prot) }).map(|addr| addr as *mut u8)
+}
+
+pub fn getpid() -> u32 {
+    unsafe { syscall0(SYS_GETPID) as u32 }
+}
+
+pub fn yield_now() {
+    unsafe {
+        syscall0(SYS_YIELD);
+    }
+}
+
+/// Seconds since the Unix epoch, if the kernel knows the wall-clock time.
+pub fn time() -> Result<u64> {
+    check(unsafe { syscall1(SYS_TIME, 0) })
+}
+
+/// Timer ticks since boot.
+pub fn ticks() -> u64 {
+    unsafe { syscall1(SYS_TIME, 1) as u64 }
+}
@@ -1,3 +1,4 @@
+use core::cmp;
 use core::convert::TryInto;
 use core::hint::spin_loop;
 use lazy_static::lazy_static;
@@ -19,9 +20,20 @@ const CMD_IDENTIFY: u8 = 0xEC;
 const CMD_READ_SECTORS: u8 = 0x20;
 const CMD_WRITE_SECTORS: u8 = 0x30;
 const CMD_CACHE_FLUSH: u8 = 0xE7;
+const CMD_PACKET: u8 = 0xA0;
+const CMD_IDENTIFY_PACKET: u8 = 0xA1;
+
+// SCSI commands sent to ATAPI drives inside a packet.
+const SCSI_READ_CAPACITY: u8 = 0x25;
+const SCSI_READ_10: u8 = 0x28;
+
+/// LBA mid/high after a failed IDENTIFY when the drive speaks the packet protocol.
+const ATAPI_SIGNATURE: (u8, u8) = (0x14, 0xEB);
 
 const MAX_POLL: usize = 100_000;
 const SECTOR_SIZE: usize = 512;
+/// Sector size of CD and DVD media; `AtaDevice` still counts in 512-byte blocks.
+pub const ATAPI_SECTOR_SIZE: usize = 2048;
 
 #[derive(Copy, Clone)]
 pub struct PciIdeInfo {
@@ -69,10 +81,13 @@ pub enum DriveSelect {
     SecondarySlave,
 }
 
+/// A drive on one of the IDE channels. `sectors` counts 512-byte blocks; ATAPI (CD-ROM)
+/// drives read whole 2048-byte sectors behind that and cannot be written.
 #[derive(Clone, Copy, Debug)]
 pub struct AtaDevice {
     pub drive: DriveSelect,
     pub sectors: u64,
+    pub atapi: bool,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -80,6 +95,8 @@ pub enum AtaError {
     NoDevice,
     Timeout,
     Error,
+    NoMedium,
+    ReadOnly,
 }
 
 impl BlockDeviceError for AtaError {
@@ -88,6 +105,8 @@ impl BlockDeviceError for AtaError {
             AtaError::NoDevice => "ATA device not found.",
             AtaError::Timeout => "ATA timeout.",
             AtaError::Error => "ATA error.",
+            AtaError::NoMedium => "No disc in drive.",
+            AtaError::ReadOnly => "ATAPI media is read-only.",
         }
     }
 }
@@ -102,11 +121,22 @@ impl BlockDevice for AtaDevice {
         if buf.len() != SECTOR_SIZE {
             return Err(AtaError::Error);
         }
+        if self.atapi {
+            let blocks_per_sector = (ATAPI_SECTOR_SIZE / SECTOR_SIZE) as u64;
+            let mut sector = [0u8; ATAPI_SECTOR_SIZE];
+            read_packet_sector(self.drive, (block / blocks_per_sector) as u32, &mut sector)?;
+            let offset = (block % blocks_per_sector) as usize * SECTOR_SIZE;
+            buf.copy_from_slice(&sector[offset..offset + SECTOR_SIZE]);
+            return Ok(());
+        }
         let buf: &mut [u8; SECTOR_SIZE] = buf.try_into().map_err(|_| AtaError::Error)?;
         read_sector(self.drive, block as u32, buf)
     }
 
     fn write_block(&self, block: u64, buf: &[u8]) -> Result<(), Self::Error> {
+        if self.atapi {
+            return Err(AtaError::ReadOnly);
+        }
         if block > u32::MAX as u64 {
             return Err(AtaError::Error);
         }
@@ -301,6 +331,11 @@ pub fn identify(drive: DriveSelect) -> Result<AtaDevice, AtaError> {
     unsafe { ports.status_cmd.write(CMD_IDENTIFY); }
 
     wait_not_busy(&mut ports)?;
+    // Packet devices abort IDENTIFY and leave their signature behind.
+    let signature: (u8, u8) = unsafe { (ports.lba1.read(), ports.lba2.read()) };
+    if signature == ATAPI_SIGNATURE {
+        return identify_packet(drive);
+    }
     wait_drq(&mut ports)?;
 
     let mut words = [0u16; 256];
@@ -324,7 +359,112 @@ pub fn identify(drive: DriveSelect) -> Result<AtaDevice, AtaError> {
         return Err(AtaError::Error);
     }
 
-    Ok(AtaDevice { drive, sectors })
+    Ok(AtaDevice { drive, sectors, atapi: false })
+}
+
+// Identifies an ATAPI drive and sizes the disc in it with READ CAPACITY.
+fn identify_packet(drive: DriveSelect) -> Result<AtaDevice, AtaError> {
+    let mut ports = AtaPorts::new(drive);
+    unsafe { ports.drive.write(drive_head(drive, 0)); }
+    io_wait(&mut ports.control);
+    unsafe { ports.status_cmd.write(CMD_IDENTIFY_PACKET); }
+    wait_not_busy(&mut ports)?;
+    wait_drq(&mut ports)?;
+
+    let mut words = [0u16; 256];
+    for slot in words.iter_mut() {
+        *slot = unsafe { ports.data.read() };
+    }
+    // Word 0: bits 15-14 are 10b for ATAPI, bits 12-8 the SCSI device type (5 = CD/DVD).
+    if words[0] >> 14 != 0b10 || (words[0] >> 8) & 0x1F != 0x05 {
+        return Err(AtaError::NoDevice);
+    }
+
+    let mut packet = [0u8; 12];
+    packet[0] = SCSI_READ_CAPACITY;
+    let mut reply = [0u8; 8];
+    // A freshly inserted disc answers the first command with a unit attention.
+    let mut result = Err(AtaError::NoMedium);
+    for _ in 0..3 {
+        result = packet_command(drive, &packet, &mut reply);
+        if result.is_ok() {
+            break;
+        }
+    }
+    if result.is_err() {
+        return Err(AtaError::NoMedium);
+    }
+
+    let last_lba = u32::from_be_bytes([reply[0], reply[1], reply[2], reply[3]]);
+    let block_len = u32::from_be_bytes([reply[4], reply[5], reply[6], reply[7]]);
+    if block_len as usize != ATAPI_SECTOR_SIZE {
+        return Err(AtaError::Error);
+    }
+    let sectors = (last_lba as u64 + 1) * (ATAPI_SECTOR_SIZE / SECTOR_SIZE) as u64;
+    Ok(AtaDevice { drive, sectors, atapi: true })
+}
+
+// Sends a 12-byte SCSI packet in PIO mode and reads the reply into `buf`, which the
+// drive may deliver in several DRQ blocks. Returns the number of bytes received.
+fn packet_command(drive: DriveSelect, packet: &[u8; 12], buf: &mut [u8]) -> Result<usize, AtaError> {
+    let mut ports = AtaPorts::new(drive);
+    unsafe { ports.control.write(0); }
+    unsafe { ports.drive.write(drive_head(drive, 0)); }
+    io_wait(&mut ports.control);
+    wait_not_busy(&mut ports)?;
+    let limit = cmp::min(buf.len(), 0xFFFE) as u16;
+    unsafe {
+        ports.error.write(0);
+        ports.lba1.write(limit as u8);
+        ports.lba2.write((limit >> 8) as u8);
+        ports.status_cmd.write(CMD_PACKET);
+    }
+    io_wait(&mut ports.control);
+    wait_not_busy(&mut ports)?;
+    wait_drq(&mut ports)?;
+    for pair in packet.chunks_exact(2) {
+        unsafe { ports.data.write(u16::from_le_bytes([pair[0], pair[1]])); }
+    }
+
+    let mut received = 0usize;
+    loop {
+        io_wait(&mut ports.control);
+        wait_not_busy(&mut ports)?;
+        let status: u8 = unsafe { ports.status_cmd.read() };
+        if status & STATUS_ERR != 0 {
+            let _ = unsafe { ports.error.read() };
+            return Err(AtaError::Error);
+        }
+        if status & STATUS_DRQ == 0 {
+            return Ok(received);
+        }
+        let count: (u8, u8) = unsafe { (ports.lba1.read(), ports.lba2.read()) };
+        let count = u16::from_le_bytes([count.0, count.1]) as usize;
+        if count == 0 {
+            return Err(AtaError::Error);
+        }
+        for _ in 0..count.div_ceil(2) {
+            let word: u16 = unsafe { ports.data.read() };
+            for byte in word.to_le_bytes() {
+                if received < buf.len() {
+                    buf[received] = byte;
+                    received += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Reads one 2048-byte sector from the disc in an ATAPI drive.
+pub fn read_packet_sector(drive: DriveSelect, lba: u32, buf: &mut [u8; ATAPI_SECTOR_SIZE]) -> Result<(), AtaError> {
+    let mut packet = [0u8; 12];
+    packet[0] = SCSI_READ_10;
+    packet[2..6].copy_from_slice(&lba.to_be_bytes());
+    packet[8] = 1;
+    if packet_command(drive, &packet, buf)? != ATAPI_SECTOR_SIZE {
+        return Err(AtaError::Error);
+    }
+    Ok(())
 }
 
 pub fn read_sector(drive: DriveSelect, lba: u32, buf: &mut [u8; 512]) -> Result<(), AtaError> {
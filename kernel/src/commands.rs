@@ -1,4 +1,4 @@
-use crate::{cdmo, debug, console, time, serial, wait, history, memory, OS_NAME, OS_VERSION, fs, forth, editor, desktop};
+use crate::{cdmo, debug, console, time, serial, wait, history, memory, OS_NAME, OS_VERSION, fs, forth, editor, desktop, elf, env, fd, script, config, textutil, inspect, users};
 use crate::help::{BSOD_HEIGHT, BSOD_IMAGE, BSOD_WIDTH};
 use alloc::borrow::ToOwned;
 use alloc::string::ToString;
@@ -8,7 +8,7 @@ use crate::console::{
     FontKind,
 };
 use alloc::format;
-use heapless::{String as HString, Vec, LinearMap};
+use heapless::{String as HString, LinearMap};
 use spin::Mutex;
 use raw_cpuid::CpuId;
 use core::fmt::Write;
@@ -20,7 +20,10 @@ pub static mut TICKS: u64 = 0;
 pub const COMMANDS: &[&str] = &[
     "help", "about", "os", "echo", "cls", "clear", "uptime", "reboot", "shutdown", "meminfo", 
     "memtest", "cpuinfo", "fbinfo", "version", "alias", "unalias", "aliases", "cecho", "secho",
-    "ls", "cd", "pwd", "mkdir", "rmdir", "touch", "cat", "rm", "del", "vight", "forth",
+    "ls", "cd", "pwd", "mkdir", "rmdir", "touch", "cat", "rm", "del", "vight", "forth", "run",
+    "exec", "set", "unset", "export", "env", "source", "sh", "test", "true", "false", "history",
+    "grep", "head", "tail", "wc", "sort", "uniq", "find", "hexdump", "xxd", "readsec", "fsck", "stat",
+    "ln", "readlink", "truncate", "whoami", "su", "passwd", "useradd", "chmod", "chown",
 ];
 
 static mut CMD_COMPLETION_ENABLED: bool = false;
@@ -39,6 +42,18 @@ pub fn is_scroll_inverted() -> bool {
     unsafe { SCROLL_INVERTED }
 }
 
+pub fn set_completion_enabled(enabled: bool) {
+    unsafe { CMD_COMPLETION_ENABLED = enabled; }
+}
+
+pub fn set_prompt_path_enabled(enabled: bool) {
+    unsafe { PROMPT_PATH_ENABLED = enabled; }
+}
+
+pub fn set_scroll_inverted(inverted: bool) {
+    unsafe { SCROLL_INVERTED = inverted; }
+}
+
 fn toggle_prompt_path() -> bool {
     unsafe {
         PROMPT_PATH_ENABLED = !PROMPT_PATH_ENABLED;
@@ -93,27 +108,38 @@ pub fn suggest_command(input: &str) -> Option<HString<32>> {
 static ALIASES: Mutex<LinearMap<HString<32>, HString<32>, 32>> =
     Mutex::new(LinearMap::new());
 
-pub fn add_alias(alias: &str, command: &str) {
+fn insert_alias(alias: &str, command: &str) -> Result<(), &'static str> {
     let mut aliases = ALIASES.lock();
 
-    let alias_lower = alias.to_ascii_lowercase();
-    let command_lower = command.to_ascii_lowercase();
-
     let mut alias_str: HString<32> = HString::new();
     let mut command_str: HString<32> = HString::new();
 
-    if alias_str.push_str(&alias_lower).is_err() || command_str.push_str(&command_lower).is_err() {
-        console::write_line("Alias too long (max 32 chars).");
-        return;
+    if alias_str.push_str(&alias.to_ascii_lowercase()).is_err()
+        || command_str.push_str(&command.to_ascii_lowercase()).is_err()
+    {
+        return Err("Alias too long (max 32 chars).");
     }
 
     if aliases.contains_key(&alias_str) || aliases.values().any(|v| v == &alias_str) {
-        console::write_line("Alias already exists or conflicts.");
-        return;
+        return Err("Alias already exists or conflicts.");
     }
 
-    aliases.insert(alias_str.clone(), command_str.clone()).ok();
-    console::write_line(&format!("Alias added: {} -> {}", alias_lower, command_lower));
+    aliases.insert(alias_str, command_str).map_err(|_| "Too many aliases.")?;
+    Ok(())
+}
+
+pub fn add_alias(alias: &str, command: &str) {
+    match insert_alias(alias, command) {
+        Ok(()) => {
+            console::write_line(&format!(
+                "Alias added: {} -> {}",
+                alias.to_ascii_lowercase(),
+                command.to_ascii_lowercase()
+            ));
+            save_aliases();
+        }
+        Err(e) => fail(e),
+    }
 }
 
 pub fn remove_alias(alias: &str) {
@@ -124,9 +150,37 @@ pub fn remove_alias(alias: &str) {
     let _ = alias_str.push_str(&alias_lower);
 
     if aliases.remove(&alias_str).is_some() {
+        drop(aliases);
         console::write_line(&format!("Alias removed: {}", alias_lower));
+        save_aliases();
     } else {
-        console::write_line("Alias not found.");
+        drop(aliases);
+        fail("Alias not found.");
+    }
+}
+
+/// Restores aliases saved in `$HOME\aliases`, one `alias=command` per line, in place of
+/// any the previous user had.
+pub fn load_aliases() {
+    ALIASES.lock().clear();
+    let Some(text) = fs::read_file(&env::home_file("aliases")) else { return; };
+    for line in text.lines() {
+        if let Some((alias, command)) = line.split_once('=') {
+            let _ = insert_alias(alias.trim(), command.trim());
+        }
+    }
+}
+
+fn save_aliases() {
+    if !fs::persist_info().enabled {
+        return;
+    }
+    let mut body = alloc::string::String::new();
+    for (alias, command) in ALIASES.lock().iter() {
+        body.push_str(&format!("{}={}\n", alias, command));
+    }
+    if let Err(e) = fs::write_file(&env::home_file("aliases"), &body) {
+        console::write_line(&format!("alias: could not save: {}", e));
     }
 }
 
@@ -142,6 +196,10 @@ pub fn list_aliases() {
     }
 }
 
+pub fn alias_names() -> alloc::vec::Vec<alloc::string::String> {
+    ALIASES.lock().keys().map(|alias| alias.as_str().to_string()).collect()
+}
+
 pub fn resolve_alias(cmd: &str) -> HString<32> {
     let aliases = ALIASES.lock();
 
@@ -168,31 +226,18 @@ pub fn wait_ticks(ticks: u64) {
     }
 }
 
+/// Reports a failed built-in: prints `msg` and sets a non-zero `$?`.
+fn fail(msg: &str) {
+    console::write_line(msg);
+    env::set_status(1);
+}
+
 pub fn echo(args: &[&str]) {
-    let mut s = HString::<128>::new();
-    for (i, word) in args.iter().enumerate() {
-        if i > 0 {
-            let _ = s.push(' ');
-        }
-        let _ = s.push_str(word);
-    }
-    console::write_line(&s);
+    console::write_line(&args.join(" "));
 }
 
 pub fn secho(args: &[&str]) {
-    let mut s: HString<128> = HString::new();
-
-    for (i, word) in args.iter().enumerate() {
-        if i > 0 {
-            let _ = s.push(' ');
-        }
-
-        if s.push_str(word).is_err() {
-            serial::write("Error: message too long");
-            return;
-        }
-    }
-    serial::write(&s);
+    serial::write(&args.join(" "));
 }
 
 fn parse_rgb_hex(s: &str) -> Option<u32> {
@@ -228,7 +273,7 @@ const SCROLL_USAGE: &str = "Usage: os scroll invert|normal|toggle|status";
 const PERFORMANCE_USAGE: &str = "Usage: os performance winoutline move|resize";
 
 fn os_usage() {
-    console::write_line("Usage: os <font|cursor|hud|text|bg|clipboard|prmpth|scroll|performance> ...");
+    console::write_line("Usage: os <font|cursor|hud|text|bg|clipboard|prmpth|scroll|performance|config> ...");
     console::write_line("  font   default/vga8|terminus|spleen");
     console::write_line("  cursor style underscore|line|block|hidden");
     console::write_line("  cursor blink none|pulse|fade");
@@ -244,6 +289,7 @@ fn os_usage() {
     console::write_line("  prmpth toggle");
     console::write_line("  scroll invert|normal|toggle|status");
     console::write_line("  performance winoutline move|resize");
+    console::write_line("  config show|reset|reload|save  (settings file \\etc\\axiomata.cfg)");
 }
 
 fn handle_cursor_args(args: &[&str]) -> Result<(), &'static str> {
@@ -466,17 +512,17 @@ pub fn os_command(args: &[&str]) {
     match sub.as_str() {
         "font" => {
             if let Err(msg) = handle_font_args(&args[1..]) {
-                console::write_line(msg);
+                fail(msg);
             }
         }
         "cursor" => {
             if let Err(msg) = handle_cursor_args(&args[1..]) {
-                console::write_line(msg);
+                fail(msg);
             }
         }
         "hud" => {
             if let Err(msg) = handle_hud_args(&args[1..]) {
-                console::write_line(msg);
+                fail(msg);
             }
         }
         "clipboard" => {
@@ -489,32 +535,32 @@ pub fn os_command(args: &[&str]) {
         }
         "cmdhistory" => {
             if let Err(msg) = handle_cmdhistory_args(&args[1..]) {
-                console::write_line(msg);
+                fail(msg);
             }
         }
         "time" => {
             if let Err(msg) = handle_time_args(&args[1..]) {
-                console::write_line(msg);
+                fail(msg);
             }
         }
         "cmdcmp" => {
             if let Err(msg) = handle_cmdcmp_args(&args[1..]) {
-                console::write_line(msg);
+                fail(msg);
             }
         }
         "prmpth" => {
             if let Err(msg) = handle_prmpth_args(&args[1..]) {
-                console::write_line(msg);
+                fail(msg);
             }
         }
         "scroll" => {
             if let Err(msg) = handle_scroll_args(&args[1..]) {
-                console::write_line(msg);
+                fail(msg);
             }
         }
         "performance" => {
             if let Err(msg) = handle_performance_args(&args[1..]) {
-                console::write_line(msg);
+                fail(msg);
             }
         }
         "text" => {
@@ -524,7 +570,7 @@ pub fn os_command(args: &[&str]) {
                         console::set_default_fg(v);
                         console::write_line(&format!("Default text color set to #{:06X}.", v));
                     }
-                    _ => console::write_line("os text: invalid hex. Use 3 or 6 hex digits, e.g., FF0000"),
+                    _ => fail("os text: invalid hex. Use 3 or 6 hex digits, e.g., FF0000"),
                 },
                 None => console::write_line(TEXT_USAGE),
             }
@@ -541,37 +587,37 @@ pub fn os_command(args: &[&str]) {
                             console::write_line(&format!("Default background remains #{:06X}.", v));
                         }
                     }
-                    _ => console::write_line("os bg: invalid hex. Use 3 or 6 hex digits, e.g., 000000"),
+                    _ => fail("os bg: invalid hex. Use 3 or 6 hex digits, e.g., 000000"),
                 },
                 None => console::write_line(BG_USAGE),
             }
         }
+        "config" => {
+            if let Err(msg) = config::command(&args[1..]) {
+                fail(msg);
+            }
+        }
         "help" => os_usage(),
         _ => os_usage(),
     }
+    config::sync();
 }
 
 pub fn cecho(args: &[&str]) {
     if args.len() < 2 {
-        console::write_line("Usage: cecho <hex> <text>");
+        fail("Usage: cecho <hex> <text>");
         return;
     }
 
     let fg = match parse_rgb_hex(args[0]) {
         Some(v) if v <= 0xFFFFFF => v,
         _ => {
-            console::write_line("cecho: invalid hex. Use 3 or 6 hex digits, e.g., FF0000");
+            fail("cecho: invalid hex. Use 3 or 6 hex digits, e.g., FF0000");
             return;
         }
     };
 
-    let mut s = HString::<128>::new();
-    for (i, word) in args[1..].iter().enumerate() {
-        if i > 0 { let _ = s.push(' '); }
-        let _ = s.push_str(word);
-    }
-
-    console::cwrite_line(&s, fg, console::default_bg());
+    console::cwrite_line(&args[1..].join(" "), fg, console::default_bg());
 }
 
 fn bytobi(input: &str) -> Option<u32> {
@@ -633,7 +679,7 @@ pub fn help(args: &[&str]) {
         let msg = match topic.as_str() {
             "help" => "help shows available commands. Usage: help [command]",
             "about" => "Prints info about Axiomata and your hardware.",
-            "os" => "Changes system settings (font, cursor, HUD, colors, scroll, cmdhistory, time, prompt, performance). Usage: os <subcommand> ...",
+            "os" => "Changes system settings (font, cursor, HUD, colors, scroll, cmdhistory, time, prompt, performance). Settings are saved to \\etc\\axiomata.cfg; see os config. Usage: os <subcommand> ...",
             "echo" => "Prints text to the console. Usage: echo <text>",
             "cecho" => "Prints colored text. Usage: cecho <hex> <text> (hex in RGB, e.g., FF00FF)",
             "secho" => "Writes text to the serial port. Usage: secho <text>",
@@ -648,22 +694,54 @@ pub fn help(args: &[&str]) {
             "version" => "Prints Axiomata name and build version.",
             "alias" => "Creates an alias. Usage: alias <command> <alias>",
             "unalias" => "Removes an alias. Usage: unalias <alias>",
-            "aliases" => "Lists all defined aliases.",
-            "ls" => "Lists files and folders. Usage: ls [path]",
+            "aliases" => "Lists all defined aliases. Aliases are saved to $HOME\\aliases.",
+            "history" => "Lists numbered command history, or clears it with -c. Recall with !!, !n, !-n or !prefix; Ctrl+R searches. Usage: history [-c]",
+            "ls" => "Lists files and folders. -l adds mode, links, owner, size and modification time; -a includes hidden ones. Usage: ls [-la] [path]",
+            "stat" => "Shows size, blocks, cluster or inode, permissions, FAT attributes and timestamps. Usage: stat <path>...",
             "cd" => "Changes the current directory. Usage: cd <path>",
             "pwd" => "Prints the current directory.",
             "mkdir" => "Creates a folder. Usage: mkdir <path>",
-            "rmdir" => "Removes an empty folder. Usage: rmdir <path>",
+            "rmdir" => "Removes empty folders. Usage: rmdir <path>...",
             "touch" => "Creates an empty file. Usage: touch <path>",
-            "cat" => "Prints file contents. Usage: cat <path>",
-            "rm" => "Deletes a file. Usage: rm <path>",
+            "ln" => "Gives a file a second name; -s makes a symbolic link to target instead. EXT2 only. Usage: ln [-s] <target> <link>",
+            "readlink" => "Prints where a symbolic link points. Usage: readlink <path>...",
+            "truncate" => "Cuts files to a size, or extends them with zeros (sparse on EXT2); missing files are created. Sizes take a K, M or G suffix. Usage: truncate -s <size> <path>...",
+            "whoami" => "Prints the name of the logged-in user.",
+            "su" => "Switches to another user, root by default, after asking for their password. Usage: su [user]",
+            "passwd" => "Sets a password; the first one saved turns on the login prompt. Usage: passwd [user]",
+            "useradd" => "Adds an account with a home folder under \\home (root only). Groups are edited in \\etc\\group. Usage: useradd <name>",
+            "chmod" => "Sets permissions, as an octal mode or clauses like u+x,go-w. FAT32 and exFAT keep only read-only. Usage: chmod <mode> <path>...",
+            "chown" => "Sets the owner and group of files on EXT2. On FAT32 and exFAT, chown root marks a file system, private to root, and chown to yourself gives it back. Usage: chown <user>[:group] <path>...",
+            "cat" => "Prints file contents, or piped input when no path is given. Usage: cat [path...]",
+            "grep" => "Prints lines matching a pattern (. [abc] * + ? ^ $). -i ignores case, -n numbers lines, -r searches folders, -v inverts, -c counts. Usage: grep [-inrvc] <pattern> [path...]",
+            "head" => "Prints the first lines of files or piped input. Usage: head [-n count] [path...]",
+            "tail" => "Prints the last lines of files or piped input. Usage: tail [-n count] [path...]",
+            "wc" => "Counts lines, words and bytes. Usage: wc [-l] [-w] [-c] [path...]",
+            "sort" => "Sorts lines; -r reverses, -n compares numbers, -u drops duplicates. Usage: sort [-rnu] [path...]",
+            "uniq" => "Collapses repeated adjacent lines; -c counts them, -d shows only repeats. Usage: uniq [-cd] [path]",
+            "hexdump" => "Shows the bytes of a file or piped input in hex and ASCII. Usage: hexdump [-s offset] [-n length] [path]",
+            "xxd" => "Like hexdump, in xxd's layout. Usage: xxd [-s offset] [-l length] [path]",
+            "readsec" => "Dumps raw sectors of ATA drive 0-3, or the mounted disk, as root. Usage: readsec <0-3|disk> <lba> [count]",
+            "peek" if debug::is_available() => "Dumps physical memory, as root. Usage: peek <phys-addr> [length]",
+            "poke" if debug::is_available() => "Writes hex bytes to physical memory, as root. Usage: poke <phys-addr> <byte>...",
+            "find" => "Lists paths below a folder, filtered by name pattern or type. Usage: find [dir] [-name pattern] [-type f|d]",
+            "rm" => "Deletes files. Usage: rm <path>...",
             "fsinfo" => "Shows persistent filesystem status.",
-            "fstype" => "Sets preferred filesystem for mounting/formatting. Usage: fstype [auto|fat32|ext2]",
-            "format" => "Formats the selected disk as FAT32 or EXT2. Usage: format [fat32|ext2]",
+            "fstype" => "Sets preferred filesystem for mounting/formatting; fat32 also mounts FAT12/FAT16 and ext2 EXT3/EXT4 volumes, and iso9660 mounts a CD-ROM read-only. Usage: fstype [auto|fat32|ext2|exfat|iso9660]",
+            "format" => "Formats the selected disk as FAT32, EXT2 or exFAT; disks too small for FAT32 get FAT16 (FAT12 when tiny), and EXT2 volumes of 8 MiB or more get a journal, making them EXT3. Usage: format [fat32|ext2|ext3|exfat]",
+            "fsck" => "Checks the mounted volume for damaged chains, bitmaps, link counts and directory entries. -y repairs what it finds. Usage: fsck [-y]",
             "vight" => "Opens the Vight editor (save-as, :find, :status, :reload, :q!). Usage: vight <name>",
+            "set" => "Sets a shell variable, or lists all with no arguments. Usage: set NAME=value",
+            "unset" => "Removes shell variables. Usage: unset <name>...",
+            "export" => "Marks variables for programs started with run. Usage: export NAME[=value]...",
+            "env" => "Lists exported variables.",
+            "run" | "exec" => "Runs an ELF64 program in user mode. Usage: run <file> [args]",
+            "source" | "sh" => "Runs a shell script with $1.. set to args. Supports if/elif/else/fi, while/do/done, for x in ...; do/done, name() { ... }, break, continue, return and exit. \\etc\\autoexec.sh runs at boot; once there are accounts, only if root owns it and its folder (chown root on FAT). Usage: source <file> [args]",
+            "test" | "[" => "Checks a condition: -n/-z s, -e/-f/-d path, a = b, a != b, a -eq|-ne|-lt|-le|-gt|-ge b. Usage: test <expr> or [ <expr> ]",
+            "true" | "false" => "Does nothing, successfully (true) or not (false).",
             "forth" => "Runs Forth. Usage: forth           (REPL) | forth <file.f> (run-and-quit) | forth examples (install demo .f files)",
             _ => {
-                console::write_line("Unknown command for help.");
+                fail("Unknown command for help.");
                 return;
             }
         };
@@ -688,19 +766,46 @@ pub fn help(args: &[&str]) {
     console::write_line("  alias         - Create an alias");
     console::write_line("  unalias       - Remove an alias");
     console::write_line("  aliases       - List all aliases");
-    console::write_line("  ls [path]     - List directory entries");
+    console::write_line("  history       - List or clear command history (!n, !prefix, Ctrl+R)");
+    console::write_line("  ls [-l] [dir] - List directory entries");
     console::write_line("  cd <path>     - Change directory");
     console::write_line("  pwd           - Print current directory");
     console::write_line("  mkdir <path>  - Create a folder");
     console::write_line("  rmdir <path>  - Remove an empty folder");
     console::write_line("  touch <path>  - Create an empty file");
-    console::write_line("  cat <path>    - Print a file");
+    console::write_line("  cat [path]    - Print a file or piped input");
     console::write_line("  rm <path>     - Delete a file");
+    console::write_line("  ln [-s]       - Create a hard or symbolic link");
+    console::write_line("  readlink      - Print a symbolic link's target");
+    console::write_line("  truncate      - Shrink or extend a file to a size");
+    console::write_line("  chmod / chown - Change permissions / owner");
+    console::write_line("  whoami / su   - Show or switch the current user");
+    console::write_line("  passwd        - Set a password (enables login)");
+    console::write_line("  useradd       - Add a user account");
+    console::write_line("  grep          - Search files or input for a pattern");
+    console::write_line("  head / tail   - First or last lines of a file");
+    console::write_line("  stat <path>   - Size, permissions and timestamps of a file");
+    console::write_line("  wc            - Count lines, words and bytes");
+    console::write_line("  sort / uniq   - Sort lines / collapse repeated lines");
+    console::write_line("  find [dir]    - Find files by name (-name, -type)");
+    console::write_line("  hexdump / xxd - Show file bytes in hex");
+    console::write_line("  readsec       - Dump raw disk sectors");
     console::write_line("  fsinfo        - Filesystem persistence status");
-    console::write_line("  fstype        - Set preferred filesystem (auto/fat32/ext2)");
-    console::write_line("  format        - Format selected disk as FAT32 or EXT2");
+    console::write_line("  fstype        - Set preferred filesystem (auto/fat32/ext2/exfat/iso9660)");
+    console::write_line("  format        - Format selected disk as FAT32, EXT2 or exFAT");
+    console::write_line("  fsck          - Check (and with -y repair) the mounted volume");
     console::write_line("  vight <name>  - Edit a file (:w, :q!, :find, :status)");
-    console::write_line("  forth [...]   - Forth REPL (no args), run file.f, or 'examples'\n");
+    console::write_line("  forth [...]   - Forth REPL (no args), run file.f, or 'examples'");
+    console::write_line("  run <file>    - Run an ELF program in user mode");
+    console::write_line("  set/unset     - Define or remove shell variables");
+    console::write_line("  export / env  - Export variables to programs / list them");
+    console::write_line("  source <file> - Run a shell script (also: sh)");
+    console::write_line("  test / [ ]    - Check a condition for if/while");
+    console::write_line("\nCombine commands with a; b, a && b or a || b, pipe with a | b, redirect with < file, > file, >> file.");
+    console::write_line("Use $NAME or ${NAME} to expand variables; $? is the last exit status.");
+    console::write_line("Quote with '...' (literal) or \"...\" (expands $); \\ escapes quotes, $ and operators.");
+    console::write_line("Wildcards *, ? and [abc] expand to the matching paths; quote them to keep them literal.");
+    console::write_line("Tab completes commands, subcommands and paths; press it again to cycle through matches.\n");
 }
 
 pub fn about() {
@@ -889,6 +994,12 @@ pub fn meminfo() {
         format_bytes::<32>(mo.user_arena_free_for_new_regions),
     ));
 
+    console::write_line(&format!(
+        "\nPhysical frames:\n  Total: {}\n  Used by processes: {}",
+        format_bytes::<32>(mo.frames.total.saturating_mul(4096)),
+        format_bytes::<32>(mo.frames.used.saturating_mul(4096)),
+    ));
+
     if let Some(db) = mo.display {
         console::write_line(&format!(
             "\nDisplay buffers:\n  Resolution: {}x{} (stride {} px, {} Bpp)\n  Framebuffer: {} ({} B)\n  Back buffer: {} ({} B)",
@@ -950,17 +1061,33 @@ pub fn cpuinfo() {
 }
 
 fn ramfs_ls(args: &[&str]) {
+    let (mut long, mut all) = (false, false);
+    let mut args = args;
+    while let Some(flags) = args
+        .first()
+        .and_then(|arg| arg.strip_prefix('-'))
+        .filter(|flags| !flags.is_empty() && flags.chars().all(|c| c == 'l' || c == 'a'))
+    {
+        long |= flags.contains('l');
+        all |= flags.contains('a');
+        args = &args[1..];
+    }
+    if long {
+        ls_long(args, all);
+        return;
+    }
     let entries = if let Some(path) = args.get(0) {
         match fs::list_dir(path) {
             Ok(entries) => entries,
             Err(e) => {
-                console::write_line(e);
+                fail(e);
                 return;
             }
         }
     } else {
         fs::list_files()
     };
+    let entries: alloc::vec::Vec<_> = entries.into_iter().filter(|e| all || !e.hidden).collect();
     if entries.is_empty() {
         if args.is_empty() && fs::current_dir() == "\\" {
             console::write_line("Filesystem is empty.");
@@ -980,14 +1107,204 @@ fn ramfs_ls(args: &[&str]) {
     }
 }
 
+fn timestamp_or_dash(secs: Option<u64>) -> HString<16> {
+    match secs {
+        Some(secs) => time::format_timestamp(secs),
+        None => HString::try_from("-").unwrap_or_default(),
+    }
+}
+
+/// `ls -l [path]`: mode, links, owner, size and modification time of every entry.
+fn ls_long(args: &[&str], all: bool) {
+    let path = match args {
+        [] => ".",
+        [path] => path,
+        _ => {
+            fail("Usage: ls [-la] [path]");
+            return;
+        }
+    };
+    let stats = match fs::list_dir_stat(path) {
+        Ok(stats) => stats,
+        Err(e) => {
+            fail(e);
+            return;
+        }
+    };
+    let stats: alloc::vec::Vec<_> = stats.into_iter().filter(|stat| all || !stat.hidden).collect();
+    if stats.is_empty() {
+        console::write_line("Directory is empty.");
+        return;
+    }
+    for stat in stats {
+        let suffix = match &stat.link_target {
+            Some(target) => format!(" -> {}", target),
+            None if stat.is_dir => "\\".to_string(),
+            None => alloc::string::String::new(),
+        };
+        console::write_line(&format!(
+            "{} {:>2} {:<8} {:<8} {:>9} {:>16}  {}{}",
+            fs::mode_string(stat.mode),
+            stat.links,
+            users::user_name(stat.uid).unwrap_or_else(|| format!("{}", stat.uid)),
+            users::group_name(stat.gid).unwrap_or_else(|| format!("{}", stat.gid)),
+            stat.size,
+            timestamp_or_dash(stat.modified),
+            stat.name,
+            suffix
+        ));
+    }
+}
+
+/// `stat <path>...`. Exits 1 if any path could not be read.
+fn stat_command(args: &[&str]) -> i64 {
+    if args.is_empty() {
+        console::write_line("Usage: stat <path>...");
+        return 1;
+    }
+    let id_label = match fs::persist_info().fs_kind {
+        Some(fs::FsKind::Ext2 | fs::FsKind::Ext3 | fs::FsKind::Ext4) => "Inode",
+        _ => "Cluster",
+    };
+    let mut status = 0;
+    for path in args {
+        let stat = match fs::stat(path) {
+            Ok(stat) => stat,
+            Err(e) => {
+                console::write_line(&format!("stat: {}: {}", path, e));
+                status = 1;
+                continue;
+            }
+        };
+        let kind = match stat.mode & 0xF000 {
+            0x4000 => "directory",
+            0xA000 => "symbolic link",
+            0x2000 => "character special file",
+            0x6000 => "block special file",
+            0x1000 => "fifo",
+            0xC000 => "socket",
+            _ if stat.is_dir => "directory",
+            _ => "regular file",
+        };
+        match &stat.link_target {
+            Some(target) => console::write_line(&format!("  File: {} -> {}", stat.name, target)),
+            None => console::write_line(&format!("  File: {}", stat.name)),
+        }
+        console::write_line(&format!(
+            "  Size: {:<10} Blocks: {:<8} {}",
+            stat.size, stat.blocks, kind
+        ));
+        console::write_line(&format!(
+            "{:>7}: {:<10} Links: {}",
+            id_label, stat.id, stat.links
+        ));
+        console::write_line(&format!(
+            "Access: ({:04o}/{})  Uid: ({}/{})  Gid: ({}/{})",
+            stat.mode & 0o7777,
+            fs::mode_string(stat.mode),
+            stat.uid,
+            users::user_name(stat.uid).unwrap_or_default(),
+            stat.gid,
+            users::group_name(stat.gid).unwrap_or_default()
+        ));
+        if id_label == "Cluster" {
+            let flags = [(0x01, 'R'), (0x02, 'H'), (0x04, 'S'), (0x20, 'A')];
+            let attrs: alloc::string::String = flags
+                .iter()
+                .map(|&(bit, flag)| if stat.attributes & bit != 0 { flag } else { '-' })
+                .collect();
+            console::write_line(&format!(" Attrs: {}", attrs));
+        }
+        console::write_line(&format!("Access: {}", timestamp_or_dash(stat.accessed)));
+        console::write_line(&format!("Modify: {}", timestamp_or_dash(stat.modified)));
+        console::write_line(&format!("Change: {}", timestamp_or_dash(stat.changed)));
+        console::write_line(&format!(" Birth: {}", timestamp_or_dash(stat.created)));
+    }
+    status
+}
+
+/// `ln [-s] <target> <link>`.
+fn ln_command(args: &[&str]) -> i64 {
+    let (symbolic, args) = match args {
+        ["-s", rest @ ..] => (true, rest),
+        _ => (false, args),
+    };
+    let [target, link] = args else {
+        console::write_line("Usage: ln [-s] <target> <link>");
+        return 1;
+    };
+    let result = if symbolic {
+        fs::symlink(target, link)
+    } else {
+        fs::hard_link(target, link)
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            console::write_line(&format!("ln: {}: {}", link, e));
+            1
+        }
+    }
+}
+
+/// `readlink <path>...`. Exits 1 if any path is not a symlink.
+fn readlink_command(args: &[&str]) -> i64 {
+    if args.is_empty() {
+        console::write_line("Usage: readlink <path>...");
+        return 1;
+    }
+    let mut status = 0;
+    for path in args {
+        match fs::read_link(path) {
+            Ok(target) => console::write_line(&target),
+            Err(e) => {
+                console::write_line(&format!("readlink: {}: {}", path, e));
+                status = 1;
+            }
+        }
+    }
+    status
+}
+
+/// `truncate -s <size> <path>...`. Exits 1 if any file could not be resized.
+fn truncate_command(args: &[&str]) -> i64 {
+    let (Some(&"-s"), Some(size), Some(_)) = (args.first(), args.get(1), args.get(2)) else {
+        console::write_line("Usage: truncate -s <size> <path>...");
+        return 1;
+    };
+    let Some(len) = parse_size(size) else {
+        console::write_line(&format!("truncate: invalid size '{}'", size));
+        return 1;
+    };
+    let mut status = 0;
+    for path in &args[2..] {
+        if let Err(e) = fs::truncate(path, len) {
+            console::write_line(&format!("truncate: {}: {}", path, e));
+            status = 1;
+        }
+    }
+    status
+}
+
+// A byte count with an optional binary K, M or G suffix.
+fn parse_size(text: &str) -> Option<u64> {
+    let (digits, shift) = match text.chars().last()?.to_ascii_uppercase() {
+        'K' => (&text[..text.len() - 1], 10),
+        'M' => (&text[..text.len() - 1], 20),
+        'G' => (&text[..text.len() - 1], 30),
+        _ => (text, 0),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
 fn ramfs_cd(args: &[&str]) {
     let Some(path) = args.get(0) else {
-        console::write_line("Usage: cd <path>");
+        fail("Usage: cd <path>");
         return;
     };
 
     if let Err(e) = fs::set_current_dir(path) {
-        console::write_line(e);
+        fail(e);
     }
 }
 
@@ -997,70 +1314,83 @@ fn ramfs_pwd() {
 
 fn ramfs_mkdir(args: &[&str]) {
     let Some(path) = args.get(0) else {
-        console::write_line("Usage: mkdir <path>");
+        fail("Usage: mkdir <path>");
         return;
     };
 
     match fs::mkdir(path) {
         Ok(_) => console::write_line(&format!("Directory ready: {}", path)),
-        Err(e) => console::write_line(e),
+        Err(e) => fail(e),
     }
 }
 
 fn ramfs_rmdir(args: &[&str]) {
-    let Some(path) = args.get(0) else {
-        console::write_line("Usage: rmdir <path>");
+    if args.is_empty() {
+        fail("Usage: rmdir <path>...");
         return;
-    };
+    }
 
-    match fs::rmdir(path) {
-        Ok(_) => console::write_line(&format!("Deleted {}", path)),
-        Err(e) => console::write_line(e),
+    for path in args {
+        match fs::rmdir(path) {
+            Ok(_) => console::write_line(&format!("Deleted {}", path)),
+            Err(e) => fail(&format!("{}: {}", path, e)),
+        }
     }
 }
 
 fn ramfs_touch(args: &[&str]) {
     let Some(name) = args.get(0) else {
-        console::write_line("Usage: touch <path>");
+        fail("Usage: touch <path>");
         return;
     };
 
     match fs::touch(name) {
         Ok(_) => console::write_line(&format!("File ready: {}", name)),
         Err("File already exists.") => console::write_line("File already exists."),
-        Err(e) => console::write_line(e),
+        Err(e) => fail(e),
     }
 }
 
 fn ramfs_cat(args: &[&str]) {
     let Some(name) = args.get(0) else {
-        console::write_line("Usage: cat <path>");
-        return;
-    };
-
-    match fs::read_file(name) {
-        Some(body) => {
-            if body.is_empty() {
-                console::write_line("(empty file)");
-            } else {
-                for line in body.lines() {
+        match fd::read_stdin_to_end() {
+            Some(data) => {
+                for line in alloc::string::String::from_utf8_lossy(&data).lines() {
                     console::write_line(line);
                 }
             }
+            None => fail("Usage: cat <path>"),
+        }
+        return;
+    };
+
+    if args.len() == 1 {
+        match fs::read_file(name) {
+            Some(body) if body.is_empty() => console::write_line("(empty file)"),
+            Some(body) => body.lines().for_each(console::write_line),
+            None => fail("File not found."),
+        }
+        return;
+    }
+    for name in args {
+        match fs::read_file(name) {
+            Some(body) => body.lines().for_each(console::write_line),
+            None => fail(&format!("{}: File not found.", name)),
         }
-        None => console::write_line("File not found."),
     }
 }
 
 fn ramfs_rm(args: &[&str]) {
-    let Some(name) = args.get(0) else {
-        console::write_line("Usage: rm <path>");
+    if args.is_empty() {
+        fail("Usage: rm <path>...");
         return;
-    };
+    }
 
-    match fs::delete_file(name) {
-        Ok(_) => console::write_line(&format!("Deleted {}", name)),
-        Err(e) => console::write_line(e),
+    for name in args {
+        match fs::delete_file(name) {
+            Ok(_) => console::write_line(&format!("Deleted {}", name)),
+            Err(e) => fail(&format!("{}: {}", name, e)),
+        }
     }
 }
 
@@ -1068,6 +1398,10 @@ fn fs_kind_label(kind: Option<fs::FsKind>) -> &'static str {
     match kind {
         Some(fs::FsKind::Fat32) => "fat32",
         Some(fs::FsKind::Ext2) => "ext2",
+        Some(fs::FsKind::Ext3) => "ext3",
+        Some(fs::FsKind::Ext4) => "ext4",
+        Some(fs::FsKind::Exfat) => "exfat",
+        Some(fs::FsKind::Iso9660) => "iso9660",
         None => "none",
     }
 }
@@ -1077,6 +1411,8 @@ fn fs_pref_label(pref: fs::FsPreference) -> &'static str {
         fs::FsPreference::Auto => "auto",
         fs::FsPreference::Fat32 => "fat32",
         fs::FsPreference::Ext2 => "ext2",
+        fs::FsPreference::Exfat => "exfat",
+        fs::FsPreference::Iso9660 => "iso9660",
     }
 }
 
@@ -1096,9 +1432,18 @@ fn fs_status() {
     } else {
         console::write_line("Persistent filesystem: disabled.");
     }
+    if info.unclean {
+        console::write_line("Volume state: not cleanly unmounted last time; checked at mount.");
+    }
+    // The FAT driver also mounts FAT12 and FAT16, which the volume itself tells apart.
+    let kind = match info.fat32_info.map(|fat| fat.fat_type) {
+        Some(crate::fat32::FatType::Fat12) => "fat12",
+        Some(crate::fat32::FatType::Fat16) => "fat16",
+        _ => fs_kind_label(info.fs_kind),
+    };
     console::write_line(&format!(
         "Filesystem type: {} (preferred {}).",
-        fs_kind_label(info.fs_kind),
+        kind,
         fs_pref_label(info.preferred_fs)
     ));
 
@@ -1106,15 +1451,33 @@ fn fs_status() {
         let label = core::str::from_utf8(&fat.volume_label)
             .unwrap_or("NO_LABEL")
             .trim();
-        console::write_line(&format!("FAT32 label: '{}'.", label));
+        console::write_line(&format!("{} label: '{}'.", fat.fat_type.label(), label));
     }
     if let Some(ext2) = info.ext2_info {
         let label = core::str::from_utf8(&ext2.volume_name)
             .unwrap_or("NO_LABEL")
             .trim();
         console::write_line(&format!(
-            "EXT2 label: '{}' (block {} bytes).",
-            label, ext2.block_size
+            "EXT{} label: '{}' (block {} bytes{}).",
+            ext2.generation(),
+            label,
+            ext2.block_size,
+            if ext2.read_only { ", read-only" } else { "" }
+        ));
+    }
+    if let Some(exfat) = info.exfat_info {
+        console::write_line(&format!(
+            "exFAT label: '{}' (cluster {} bytes{}).",
+            exfat.label(),
+            exfat.sectors_per_cluster * exfat.bytes_per_sector as u32,
+            if exfat.read_only { ", read-only" } else { "" }
+        ));
+    }
+    if let Some(iso) = info.iso_info {
+        console::write_line(&format!(
+            "ISO9660 label: '{}' (block {} bytes, read-only).",
+            iso.label(),
+            iso.block_size
         ));
     }
 
@@ -1164,13 +1527,16 @@ fn fs_type_cmd(args: &[&str]) {
         "auto" => fs::FsPreference::Auto,
         "fat32" => fs::FsPreference::Fat32,
         "ext2" => fs::FsPreference::Ext2,
+        "exfat" => fs::FsPreference::Exfat,
+        "iso9660" => fs::FsPreference::Iso9660,
         _ => {
-            console::write_line("Usage: fstype [auto|fat32|ext2]");
+            fail("Usage: fstype [auto|fat32|ext2|exfat|iso9660]");
             return;
         }
     };
     fs::set_fs_preference(pref);
     fs::init_persistent();
+    config::sync();
     console::write_line(&format!(
         "Filesystem preference set to {}.",
         fs_pref_label(pref)
@@ -1182,8 +1548,10 @@ fn fs_format(args: &[&str]) {
         None => None,
         Some(name) if name == "fat32" => Some(fs::FsKind::Fat32),
         Some(name) if name == "ext2" => Some(fs::FsKind::Ext2),
+        Some(name) if name == "ext3" => Some(fs::FsKind::Ext3),
+        Some(name) if name == "exfat" => Some(fs::FsKind::Exfat),
         Some(_) => {
-            console::write_line("Usage: format [fat32|ext2]");
+            fail("Usage: format [fat32|ext2|ext3|exfat]");
             return;
         }
     };
@@ -1196,13 +1564,53 @@ fn fs_format(args: &[&str]) {
                 fs_kind_label(info.fs_kind)
             ));
         }
-        Err(e) => console::write_line(e),
+        Err(e) => fail(e),
+    }
+}
+
+/// `fsck [-y]`. Exits 0 when the volume is clean, 1 when problems were repaired, 4 when
+/// they were left alone and 8 when the check itself failed.
+fn fsck_command(args: &[&str]) -> i64 {
+    let repair = match args {
+        [] => false,
+        ["-y"] => true,
+        _ => {
+            console::write_line("Usage: fsck [-y]");
+            return 8;
+        }
+    };
+    let info = fs::persist_info();
+    if !info.enabled {
+        console::write_line("fsck: no volume is mounted.");
+        return 8;
+    }
+    console::write_line(&format!("Checking {} volume...", fs_kind_label(info.fs_kind)));
+    let report = match fs::fsck(repair) {
+        Ok(report) => report,
+        Err(e) => {
+            console::write_line(&format!("fsck: {}", e));
+            return 8;
+        }
+    };
+    for problem in &report.problems {
+        console::write_line(&format!("  {}", problem));
+    }
+    console::write_line(&format!("{} file(s), {} folder(s).", report.files, report.dirs));
+    if report.problems.is_empty() {
+        console::write_line("No problems found.");
+        0
+    } else if report.repaired {
+        console::write_line(&format!("{} problem(s) repaired.", report.problems.len()));
+        1
+    } else {
+        console::write_line(&format!("{} problem(s) found; run fsck -y to repair.", report.problems.len()));
+        4
     }
 }
 
 fn vight_command(args: &[&str]) {
     let Some(name) = args.get(0) else {
-        console::write_line("Usage: vight <file>");
+        fail("Usage: vight <file>");
         return;
     };
     editor::start(name);
@@ -1222,6 +1630,28 @@ fn forth_command(args: &[&str]) {
     forth::start_repl();
 }
 
+fn run_program(args: &[&str]) {
+    let Some(path) = args.get(0) else {
+        console::write_line("Usage: run <file> [args]");
+        env::set_status(2);
+        return;
+    };
+    let resolved = elf::resolve_program(path);
+    let vars = env::exported();
+    let envp: alloc::vec::Vec<&str> = vars.iter().map(|v| v.as_str()).collect();
+    match elf::exec(&resolved, args, &envp) {
+        Ok(0) => {}
+        Ok(code) => {
+            console::write_line(&format!("Process exited with code {}.", code));
+            env::set_status(code);
+        }
+        Err(e) => {
+            console::write_line(&format!("run: {}", e));
+            env::set_status(126);
+        }
+    }
+}
+
 pub fn makel(args: &[&str]) {
     if args.len() == 1 && args[0] == "love" {
         console::write_line("Not war?");
@@ -1355,25 +1785,28 @@ pub fn panic_cmd(args: &[&str]) {
         }
 
         _ => {
-            console::write_line("Usage: panic yes-i-know [controlled|badmem|delidt|nullidt|int3|int3andkill|divby0|ud]");
+            fail("Usage: panic yes-i-know [controlled|badmem|delidt|nullidt|int3|int3andkill|divby0|ud]");
         }
     }
 }
 
-pub fn handle_command(input: &str) {
-    let mut parts: Vec<&str, 16> = Vec::new();
-    for word in input.split_whitespace() {
-        let _ = parts.push(word);
-    }
+/// Runs one built-in, shell function or program from an already tokenized argument list.
+pub fn handle_command(args: &[alloc::string::String]) {
+    let parts: alloc::vec::Vec<&str> = args.iter().map(|a| a.as_str()).collect();
 
     if parts.is_empty() {
         return;
     }
 
+    if script::call_function(&parts) {
+        return;
+    }
+
     let command = resolve_alias(&parts[0]).to_ascii_lowercase();
 
-    if command == "debug" && !debug::is_available() {
+    if matches!(command.as_str(), "debug" | "peek" | "poke") && !debug::is_available() {
         console::write_line(&format!("Unknown command: {}", parts[0]));
+        env::set_status(127);
         return;
     }
 
@@ -1394,9 +1827,15 @@ pub fn handle_command(input: &str) {
         }
         "os" => os_command(&parts[1..]),
         "uptime" => uptime(),
-        "reboot" => reboot(),
+        "reboot" => {
+            fs::unmount();
+            reboot();
+        }
         "fbinfo" => fbtst(),
-        "shutdown" => shutdown(),
+        "shutdown" => {
+            fs::unmount();
+            shutdown();
+        }
         "meminfo" => meminfo(),
         "memtest" => mem_selftest(),
         "cpuinfo" => cpuinfo(),
@@ -1408,80 +1847,75 @@ pub fn handle_command(input: &str) {
         "touch" => ramfs_touch(&parts[1..]),
         "cat" => ramfs_cat(&parts[1..]),
         "rm" => ramfs_rm(&parts[1..]),
+        "grep" => env::set_status(textutil::grep(&parts[1..])),
+        "head" => env::set_status(textutil::head(&parts[1..])),
+        "tail" => env::set_status(textutil::tail(&parts[1..])),
+        "wc" => env::set_status(textutil::wc(&parts[1..])),
+        "sort" => env::set_status(textutil::sort(&parts[1..])),
+        "uniq" => env::set_status(textutil::uniq(&parts[1..])),
+        "find" => env::set_status(textutil::find(&parts[1..])),
+        "hexdump" => env::set_status(inspect::hexdump(&parts[1..])),
+        "xxd" => env::set_status(inspect::xxd(&parts[1..])),
+        "readsec" => env::set_status(inspect::readsec(&parts[1..])),
+        "peek" => env::set_status(inspect::peek(&parts[1..])),
+        "poke" => env::set_status(inspect::poke(&parts[1..])),
         "del" => ramfs_rm(&parts[1..]),
         "fsinfo" => fs_status(),
         "fstype" => fs_type_cmd(&parts[1..]),
         "format" => fs_format(&parts[1..]),
+        "fsck" => env::set_status(fsck_command(&parts[1..])),
+        "stat" => env::set_status(stat_command(&parts[1..])),
+        "ln" => env::set_status(ln_command(&parts[1..])),
+        "readlink" => env::set_status(readlink_command(&parts[1..])),
+        "truncate" => env::set_status(truncate_command(&parts[1..])),
+        "whoami" => env::set_status(users::whoami_command(&parts[1..])),
+        "su" => env::set_status(users::su(&parts[1..])),
+        "passwd" => env::set_status(users::passwd(&parts[1..])),
+        "useradd" => env::set_status(users::useradd(&parts[1..])),
+        "chmod" => env::set_status(users::chmod(&parts[1..])),
+        "chown" => env::set_status(users::chown(&parts[1..])),
         "vight" => vight_command(&parts[1..]),
         "v"=> vight_command(&parts[1..]),
         "forth" => forth_command(&parts[1..]),
+        "run" | "exec" => run_program(&parts[1..]),
         "halt" => halt_cmd(&parts[1..]),
         "panic" => panic_cmd(&parts[1..]),
         "alias" => {
             if parts.len() == 3 {
                 add_alias(parts[2], parts[1]);
             } else {
-                console::write_line("Usage: alias <command> <alias>");
+                fail("Usage: alias <command> <alias>");
             }
         }
         "unalias" => {
             if parts.len() == 2 {
                 remove_alias(parts[1]);
             } else {
-                console::write_line("Usage: unalias <alias>");
+                fail("Usage: unalias <alias>");
             }
         }
         "aliases" => list_aliases(),
-        "debug" => debug::command(&parts[1..]),
-
-        _ => console::write_line(&format!("Unknown command: {}", parts[0])),
-    }
-}
-
-// oui oui cigarette break
-fn split_deuxand(line: &str) -> heapless::Vec<heapless::String<128>, 16> {
-    use heapless::{String as HString, Vec};
-
-    let mut result: Vec<HString<128>, 16> = Vec::new();
-    let mut current = HString::<128>::new();
-
-    let mut in_single = false;
-    let mut in_double = false;
-    let mut chars = line.chars().peekable();
-
-    while let Some(c) = chars.next() {
-        match c {
-            '\'' if !in_double => in_single = !in_single,
-            '"' if !in_single => in_double = !in_double,
-            '&' if !in_single && !in_double => {
-                if chars.peek() == Some(&'&') {
-                    chars.next();
-                    let seg = current.trim();
-                    if !seg.is_empty() {
-                        let mut s = HString::<128>::new();
-                        let _ = s.push_str(seg);
-                        let _ = result.push(s);
-                    }
-                    current.clear();
-                    continue;
-                } else {
-                    let _ = current.push(c);
-                }
-            }
-            other => {
-                let _ = current.push(other);
+        "history" => {
+            if let Err(e) = history::command(&parts[1..]) {
+                fail(e);
             }
         }
-    }
+        "debug" => debug::command(&parts[1..]),
+        "set" => env::set_status(env::set_command(&parts[1..])),
+        "unset" => env::set_status(env::unset_command(&parts[1..])),
+        "export" => env::set_status(env::export_command(&parts[1..])),
+        "env" => env::set_status(env::env_command(&parts[1..])),
+        "source" | "sh" | "." => script::source_command(&parts[1..]),
+        "break" | "continue" | "return" | "exit" => script::control_command(&command, &parts[1..]),
+        "test" | "[" => env::set_status(script::test_command(&command, &parts[1..])),
+        "true" => env::set_status(0),
+        "false" => env::set_status(1),
 
-    let seg = current.trim();
-    if !seg.is_empty() {
-        let mut s = HString::<128>::new();
-        let _ = s.push_str(seg);
-        let _ = result.push(s);
+        _ => {
+            console::write_line(&format!("Unknown command: {}", parts[0]));
+            env::set_status(127);
+        }
     }
-
-    result
 }
 
 pub fn handle_line(input: &str) {
@@ -1493,11 +1927,18 @@ pub fn handle_line(input: &str) {
         return;
     }
 
-    let cmds = split_deuxand(input);
-    for cmd in cmds {
-        handle_command(&cmd);
-        if editor::is_active() || forth::is_active() {
-            break;
+    let line = match history::expand(input) {
+        Ok(Some(expanded)) => {
+            console::write_line(&expanded);
+            expanded
         }
-    }
+        Ok(None) => alloc::string::String::from(input),
+        Err(e) => {
+            console::write_line(&format!("sh: {}: {}", input.trim(), e));
+            env::set_status(1);
+            return;
+        }
+    };
+    history::push(&line);
+    script::run_line(&line);
 }
@@ -0,0 +1,314 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::lexer::{self, Kind};
+use crate::{commands, debug, editor, env, forth, fs, script};
+
+// Arguments of commands with fixed subcommands, keyed by the words that come before them.
+// A command listed here never falls back to file names.
+const SUBCOMMANDS: &[(&str, &[&str])] = &[
+    (
+        "os",
+        &[
+            "font", "cursor", "hud", "text", "bg", "clipboard", "cmdhistory", "time", "cmdcmp",
+            "prmpth", "scroll", "performance", "config", "help",
+        ],
+    ),
+    ("os font", &["default", "vga8", "terminus", "spleen"]),
+    ("os cursor", &["style", "blink", "color"]),
+    ("os cursor style", &["underscore", "line", "block", "hidden"]),
+    ("os cursor blink", &["none", "pulse", "fade"]),
+    ("os hud", &["toggle"]),
+    ("os clipboard", &["clear"]),
+    ("os cmdhistory", &["clear", "toggle"]),
+    ("os time", &["12hr", "24hr", "sync", "log", "source", "help"]),
+    ("os cmdcmp", &["toggle"]),
+    ("os prmpth", &["toggle"]),
+    ("os scroll", &["invert", "normal", "toggle", "status"]),
+    ("os performance", &["winoutline"]),
+    ("os performance winoutline", &["move", "resize"]),
+    ("os config", &["show", "reset", "reload", "save"]),
+    ("debug", &["compdemo", "fbffer", "fbmode", "mode", "compinfo", "filesystem", "usermode", "permissions"]),
+    ("debug fbffer", &["back_buffer", "scene_buffer"]),
+    ("debug fbmode", &["legacy", "layered", "classic"]),
+    ("debug mode", &["toggle", "console", "desktop"]),
+    ("fstype", &["auto", "fat32", "ext2", "exfat", "iso9660"]),
+    ("format", &["fat32", "ext2", "ext3", "exfat"]),
+    ("history", &["-c"]),
+];
+
+// Commands whose arguments must be directories.
+const DIR_COMMANDS: &[&str] = &["cd", "rmdir"];
+
+// Words after which the next word is a command again.
+const KEYWORDS: &[&str] = &["!", "if", "elif", "then", "else", "while", "do"];
+
+struct Candidate {
+    /// The completed word, unquoted.
+    value: String,
+    /// How the candidate is listed when the word is ambiguous.
+    label: String,
+    dir: bool,
+}
+
+impl Candidate {
+    fn word(value: &str) -> Self {
+        Candidate { value: value.to_string(), label: value.to_string(), dir: false }
+    }
+
+    // The name matched against the typed prefix.
+    fn name(&self) -> &str {
+        self.label.trim_end_matches('\\')
+    }
+
+    // The text put on the line: quoted where needed, then `\` after a directory or a space
+    // after anything else so the next word can be typed straight away.
+    fn insert(&self) -> String {
+        let mut text = lexer::quote_path(&self.value);
+        text.push(if self.dir { '\\' } else { ' ' });
+        text
+    }
+}
+
+/// The input line after a Tab press.
+pub struct Completion {
+    pub line: String,
+    /// Cursor position in characters.
+    pub cursor: usize,
+    /// Candidates to list below the prompt when the word was ambiguous; empty otherwise.
+    pub choices: Vec<String>,
+}
+
+// Candidates of the last ambiguous Tab, which further Tabs step through.
+struct Cycle {
+    head: String,
+    tail: String,
+    choices: Vec<String>,
+    index: Option<usize>,
+    // The line and cursor as the last Tab left them; any edit in between ends the cycle.
+    line: String,
+    cursor: usize,
+}
+
+/// Tab completion state of one input line.
+#[derive(Default)]
+pub struct Completer {
+    cycle: Option<Cycle>,
+}
+
+impl Completer {
+    /// Completes the word before `cursor` (in characters). A unique match is inserted whole;
+    /// several are extended to their common prefix and listed, and pressing Tab again
+    /// steps through them. Returns `None` when there is nothing to complete.
+    pub fn tab(&mut self, line: &str, cursor: usize) -> Option<Completion> {
+        if forth::is_active() || editor::is_active() {
+            return None;
+        }
+        if let Some(cycle) = self.cycle.as_mut() {
+            if cycle.line == line && cycle.cursor == cursor {
+                let index = cycle.index.map_or(0, |i| (i + 1) % cycle.choices.len());
+                cycle.index = Some(index);
+                let done = splice(&cycle.head, &cycle.choices[index], &cycle.tail);
+                cycle.line = done.line.clone();
+                cycle.cursor = done.cursor;
+                return Some(done);
+            }
+        }
+        self.cycle = None;
+
+        let split = line.char_indices().nth(cursor).map_or(line.len(), |(i, _)| i);
+        let (before, tail) = line.split_at(split);
+        let (context, start) = word_context(before);
+        let raw = &before[start..];
+        let word = unquote(raw)?;
+        let candidates = candidates(&context, &word);
+
+        let head = &before[..start];
+        match candidates.as_slice() {
+            [] => None,
+            [only] => Some(splice(head, &only.insert(), tail)),
+            _ => {
+                let common = common_prefix(&candidates);
+                let mut done = if common.chars().count() > word.chars().count() {
+                    splice(head, &open_quote(lexer::quote_path(&common)), tail)
+                } else {
+                    splice(head, raw, tail)
+                };
+                done.choices = candidates.iter().map(|c| c.label.clone()).collect();
+                self.cycle = Some(Cycle {
+                    head: head.to_string(),
+                    tail: tail.to_string(),
+                    choices: candidates.iter().map(Candidate::insert).collect(),
+                    index: None,
+                    line: done.line.clone(),
+                    cursor: done.cursor,
+                });
+                Some(done)
+            }
+        }
+    }
+}
+
+fn splice(head: &str, word: &str, tail: &str) -> Completion {
+    let cursor = head.chars().count() + word.chars().count();
+    Completion { line: format!("{}{}{}", head, word, tail), cursor, choices: Vec::new() }
+}
+
+// Finds where the word under completion starts, and the words of its command before it.
+fn word_context(before: &str) -> (Vec<String>, usize) {
+    let mut command_start = 0;
+    let mut word_start = 0;
+    for (i, c, kind) in lexer::scan(before) {
+        if kind != Kind::Live {
+            continue;
+        }
+        if matches!(c, ';' | '&' | '|') {
+            command_start = i + 1;
+            word_start = i + 1;
+        } else if c.is_whitespace() || matches!(c, '<' | '>') {
+            word_start = i + c.len_utf8();
+        }
+    }
+    let mut words = lexer::split_literal(&before[command_start..word_start]).unwrap_or_default();
+    while words.first().is_some_and(|w| KEYWORDS.contains(&w.as_str())) {
+        words.remove(0);
+    }
+    // A redirection target is always a file, whatever the command.
+    if before[command_start..word_start].trim_end().ends_with(['<', '>']) {
+        words = Vec::from([String::from(">")]);
+    }
+    (words, word_start)
+}
+
+// The partial word without its quotes, closing a quote the user has only opened.
+fn unquote(raw: &str) -> Option<String> {
+    ["", "\"", "'"].iter().find_map(|close| {
+        let words = lexer::split_literal(&format!("{}{}", raw, close)).ok()?;
+        Some(words.into_iter().next().unwrap_or_default())
+    })
+}
+
+fn candidates(context: &[String], word: &str) -> Vec<Candidate> {
+    let prefix = word.rsplit('\\').next().unwrap_or(word);
+    let all = match context.first() {
+        None if word.contains('\\') => paths(word, false),
+        None => command_names(),
+        Some(command) => arguments(context, command, word),
+    };
+    // Prefer names spelled as typed; fall back to ignoring case, as FAT32 does.
+    let exact = all.iter().any(|c| c.name().starts_with(prefix));
+    all.into_iter()
+        .filter(|c| if exact { c.name().starts_with(prefix) } else { starts_with_ignore_case(c.name(), prefix) })
+        .collect()
+}
+
+fn arguments(context: &[String], command: &str, word: &str) -> Vec<Candidate> {
+    let command = commands::resolve_alias(command).to_ascii_lowercase();
+    let mut key = command.clone();
+    for arg in &context[1..] {
+        key.push(' ');
+        key.push_str(&arg.to_ascii_lowercase());
+    }
+    if SUBCOMMANDS.iter().any(|(k, _)| k.split(' ').next() == Some(command.as_str())) {
+        if command == "debug" && !debug::is_available() {
+            return Vec::new();
+        }
+        let words = SUBCOMMANDS.iter().find(|(k, _)| *k == key).map_or(&[][..], |(_, words)| words);
+        return words.iter().map(|w| Candidate::word(w)).collect();
+    }
+    match command.as_str() {
+        "help" => command_names(),
+        "unalias" => commands::alias_names().iter().map(|a| Candidate::word(a)).collect(),
+        "run" | "exec" if context.len() == 1 && !word.contains('\\') => {
+            let mut found = paths(word, false);
+            found.extend(programs());
+            found.sort_by(|a, b| a.value.cmp(&b.value));
+            found.dedup_by(|a, b| a.value == b.value);
+            found
+        }
+        _ => paths(word, DIR_COMMANDS.contains(&command.as_str())),
+    }
+}
+
+fn starts_with_ignore_case(text: &str, prefix: &str) -> bool {
+    text.len() >= prefix.len()
+        && text.is_char_boundary(prefix.len())
+        && text[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+// Built-ins, aliases and shell functions.
+fn command_names() -> Vec<Candidate> {
+    let mut names: Vec<String> = commands::COMMANDS.iter().map(|c| c.to_string()).collect();
+    if debug::is_available() {
+        names.extend(["debug", "peek", "poke"].map(String::from));
+    }
+    names.extend(commands::alias_names());
+    names.extend(script::function_names());
+    names.sort();
+    names.dedup();
+    names.iter().map(|n| Candidate::word(n)).collect()
+}
+
+// Entries of the directory named by `word` up to its last `\`.
+fn paths(word: &str, dirs_only: bool) -> Vec<Candidate> {
+    let dir = word.rfind('\\').map_or("", |i| &word[..=i]);
+    let listing = match dir {
+        "" => fs::list_dir("."),
+        "\\" => fs::list_dir("\\"),
+        _ => fs::list_dir(dir.trim_end_matches('\\')),
+    };
+    let Ok(entries) = listing else {
+        return Vec::new();
+    };
+    let mut found: Vec<Candidate> = entries
+        .into_iter()
+        .filter(|e| e.is_dir || !dirs_only)
+        .filter(|e| e.name != "." && e.name != "..")
+        .map(|e| {
+            let label = if e.is_dir { format!("{}\\", e.name) } else { e.name.clone() };
+            Candidate { value: format!("{}{}", dir, e.name), label, dir: e.is_dir }
+        })
+        .collect();
+    found.sort_by(|a, b| a.value.cmp(&b.value));
+    found
+}
+
+// Programs in the `$PATH` directories, offered without their `.elf` extension.
+fn programs() -> Vec<Candidate> {
+    let path = env::get("PATH").unwrap_or_default();
+    let mut found = Vec::new();
+    for dir in path.split(';').map(|d| d.trim_end_matches('\\')).filter(|d| !d.is_empty()) {
+        let Ok(entries) = fs::list_dir(dir) else { continue; };
+        for entry in entries.into_iter().filter(|e| !e.is_dir) {
+            found.push(Candidate::word(entry.name.strip_suffix(".elf").unwrap_or(&entry.name)));
+        }
+    }
+    found
+}
+
+// Longest prefix shared by all candidates, ignoring case, spelled like the first one.
+fn common_prefix(candidates: &[Candidate]) -> String {
+    let first = &candidates[0].value;
+    let mut len = first.len();
+    for other in &candidates[1..] {
+        len = first
+            .char_indices()
+            .zip(other.value.chars())
+            .find(|((_, a), b)| !a.eq_ignore_ascii_case(b))
+            .map_or(len.min(other.value.len()), |((i, _), _)| i.min(len));
+    }
+    while !first.is_char_boundary(len) {
+        len -= 1;
+    }
+    first[..len].to_string()
+}
+
+// A common prefix is not a whole name, so a quote around its last part is left open.
+fn open_quote(mut text: String) -> String {
+    if text.ends_with(['\'', '"']) {
+        text.pop();
+    }
+    text
+}
+
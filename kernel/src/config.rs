@@ -0,0 +1,290 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
+use spin::Mutex;
+
+use crate::console::{self, CursorBlink, CursorStyle, FontKind};
+use crate::{commands, desktop, fs, history, thud, time};
+
+const CONFIG_PATH: &str = "\\etc\\axiomata.cfg";
+const CONFIG_DIR: &str = "\\etc";
+const HEADER: &str = "# Axiomata settings. One `key = value` per line; `os config reload` applies edits.";
+
+type Settings = Vec<(&'static str, String)>;
+
+// Built-in values captured at boot, and what the file held when last loaded or saved.
+static DEFAULTS: Mutex<Option<Settings>> = Mutex::new(None);
+static SAVED: Mutex<Option<Settings>> = Mutex::new(None);
+
+/// Remembers the built-in settings, then applies the config file if there is one.
+/// Call after `fs::init_persistent`.
+pub fn init() {
+    *DEFAULTS.lock() = Some(current());
+    let pref = fs::fs_preference();
+    load();
+    // A different `fstype` names another volume, which holds the config to use.
+    if fs::fs_preference() != pref {
+        fs::init_persistent();
+        load();
+    }
+}
+
+fn on_off(value: bool) -> String {
+    String::from(if value { "on" } else { "off" })
+}
+
+/// Every persisted setting with its current value, in file order.
+fn current() -> Settings {
+    let font = match console::current_font() {
+        FontKind::Vga8 => "vga8",
+        FontKind::Terminus8x16 => "terminus",
+        FontKind::Spleen8x16 => "spleen",
+    };
+    let style = match console::cursor_style() {
+        CursorStyle::Underscore => "underscore",
+        CursorStyle::Line => "line",
+        CursorStyle::Block => "block",
+        CursorStyle::Hidden => "hidden",
+    };
+    let blink = match console::cursor_blink() {
+        CursorBlink::None => "none",
+        CursorBlink::Pulse => "pulse",
+        CursorBlink::Fade => "fade",
+    };
+    let fs_type = match fs::fs_preference() {
+        fs::FsPreference::Auto => "auto",
+        fs::FsPreference::Fat32 => "fat32",
+        fs::FsPreference::Ext2 => "ext2",
+        fs::FsPreference::Exfat => "exfat",
+        fs::FsPreference::Iso9660 => "iso9660",
+    };
+    let time_format = if time::DISPLAY_24H.load(Ordering::Relaxed) { "24hr" } else { "12hr" };
+    let scroll = if commands::is_scroll_inverted() { "inverted" } else { "normal" };
+    let (fg, bg) = console::default_colors();
+    Vec::from([
+        ("font", font.to_string()),
+        ("cursor.style", style.to_string()),
+        ("cursor.blink", blink.to_string()),
+        ("cursor.color", format!("{:06X}", console::cursor_color())),
+        ("text", format!("{:06X}", fg)),
+        ("bg", format!("{:06X}", bg)),
+        ("hud", on_off(thud::is_enabled())),
+        ("scroll", scroll.to_string()),
+        ("prmpth", on_off(commands::is_prompt_path_enabled())),
+        ("cmdcmp", on_off(commands::is_completion_enabled())),
+        ("cmdhistory", on_off(history::is_enabled())),
+        ("time", time_format.to_string()),
+        ("performance.winoutline.move", on_off(desktop::move_outline_enabled())),
+        ("performance.winoutline.resize", on_off(desktop::resize_outline_enabled())),
+        ("fstype", fs_type.to_string()),
+    ])
+}
+
+fn parse_on_off(value: &str) -> Result<bool, &'static str> {
+    match value.to_ascii_lowercase().as_str() {
+        "on" | "true" | "yes" | "1" => Ok(true),
+        "off" | "false" | "no" | "0" => Ok(false),
+        _ => Err("expected on or off"),
+    }
+}
+
+fn parse_color(value: &str) -> Result<u32, &'static str> {
+    let hex = value.trim_start_matches('#');
+    match u32::from_str_radix(hex, 16) {
+        Ok(v) if hex.len() == 6 && v <= 0xFFFFFF => Ok(v),
+        _ => Err("expected a 6-digit hex color"),
+    }
+}
+
+/// Applies one setting. Unchanged values are skipped so a reload does not redraw.
+fn apply(key: &str, value: &str, now: &Settings) -> Result<(), &'static str> {
+    if now.iter().any(|(k, v)| *k == key && v.eq_ignore_ascii_case(value)) {
+        return Ok(());
+    }
+    match key {
+        "font" => console::set_font(match value {
+            "vga8" | "default" => FontKind::Vga8,
+            "terminus" => FontKind::Terminus8x16,
+            "spleen" => FontKind::Spleen8x16,
+            _ => return Err("expected vga8, terminus or spleen"),
+        }),
+        "cursor.style" => console::set_cursor_style(match value {
+            "underscore" => CursorStyle::Underscore,
+            "line" => CursorStyle::Line,
+            "block" => CursorStyle::Block,
+            "hidden" => CursorStyle::Hidden,
+            _ => return Err("expected underscore, line, block or hidden"),
+        }),
+        "cursor.blink" => console::set_cursor_blink(match value {
+            "none" => CursorBlink::None,
+            "pulse" => CursorBlink::Pulse,
+            "fade" => CursorBlink::Fade,
+            _ => return Err("expected none, pulse or fade"),
+        }),
+        "cursor.color" => console::set_cursor_color(parse_color(value)?),
+        "text" => console::set_default_fg(parse_color(value)?),
+        "bg" => console::set_default_bg(parse_color(value)?),
+        "hud" => {
+            if parse_on_off(value)? {
+                thud::enable();
+            } else {
+                thud::disable();
+            }
+        }
+        "scroll" => commands::set_scroll_inverted(match value {
+            "inverted" => true,
+            "normal" => false,
+            _ => return Err("expected inverted or normal"),
+        }),
+        "prmpth" => commands::set_prompt_path_enabled(parse_on_off(value)?),
+        "cmdcmp" => commands::set_completion_enabled(parse_on_off(value)?),
+        "cmdhistory" => history::set_enabled(parse_on_off(value)?),
+        "time" => time::DISPLAY_24H.store(
+            match value {
+                "24hr" => true,
+                "12hr" => false,
+                _ => return Err("expected 12hr or 24hr"),
+            },
+            Ordering::Relaxed,
+        ),
+        "performance.winoutline.move" => desktop::set_move_outline_enabled(parse_on_off(value)?),
+        "performance.winoutline.resize" => desktop::set_resize_outline_enabled(parse_on_off(value)?),
+        "fstype" => {
+            let pref = match value {
+                "auto" => fs::FsPreference::Auto,
+                "fat32" => fs::FsPreference::Fat32,
+                "ext2" => fs::FsPreference::Ext2,
+                "exfat" => fs::FsPreference::Exfat,
+                "iso9660" => fs::FsPreference::Iso9660,
+                _ => return Err("expected auto, fat32, ext2, exfat or iso9660"),
+            };
+            // Takes effect at the next mount; `init` remounts for it at boot.
+            fs::set_fs_preference(pref);
+        }
+        _ => return Err("unknown setting"),
+    }
+    Ok(())
+}
+
+// `key = value` pairs of a config file, with their line numbers.
+fn entries(text: &str) -> impl Iterator<Item = (usize, &str, &str)> {
+    text.lines().enumerate().filter_map(|(i, line)| {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (key, value) = line.split_once('=').unwrap_or((line, ""));
+        Some((i + 1, key.trim(), value.trim()))
+    })
+}
+
+/// Re-reads the config file and applies it. A missing file is not an error.
+pub fn load() {
+    if !fs::persist_info().enabled {
+        return;
+    }
+    let Some(text) = fs::read_file(CONFIG_PATH) else {
+        *SAVED.lock() = Some(current());
+        return;
+    };
+    let now = current();
+    for (line, key, value) in entries(&text) {
+        if let Err(e) = apply(key, &value.to_ascii_lowercase(), &now) {
+            console::write_line(&format!("config: {} line {}: {}: {}", CONFIG_PATH, line, key, e));
+        }
+    }
+    *SAVED.lock() = Some(current());
+}
+
+/// Writes the current settings, keeping comments and unknown lines of a hand-edited file.
+pub fn save() -> Result<(), &'static str> {
+    let settings = current();
+    let existing = fs::read_file(CONFIG_PATH).unwrap_or_default();
+    let mut written: Vec<&str> = Vec::new();
+    let mut out = String::new();
+    if existing.is_empty() {
+        out.push_str(HEADER);
+        out.push('\n');
+    }
+    for line in existing.lines() {
+        let trimmed = line.trim();
+        let key = trimmed.split_once('=').map(|(k, _)| k.trim());
+        match key.and_then(|k| settings.iter().find(|(name, _)| *name == k)) {
+            Some((name, value)) if !trimmed.starts_with('#') && !written.contains(name) => {
+                out.push_str(&format!("{} = {}\n", name, value));
+                written.push(*name);
+            }
+            _ => {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+    }
+    for (name, value) in &settings {
+        if !written.contains(name) {
+            out.push_str(&format!("{} = {}\n", name, value));
+        }
+    }
+
+    if !fs::exists(CONFIG_DIR) {
+        fs::mkdir(CONFIG_DIR)?;
+    }
+    fs::write_file(CONFIG_PATH, &out)?;
+    *SAVED.lock() = Some(settings);
+    Ok(())
+}
+
+/// Saves the config file if a command changed any setting since it was last written.
+pub fn sync() {
+    if !fs::persist_info().enabled {
+        return;
+    }
+    let changed = SAVED.lock().as_ref() != Some(&current());
+    if changed {
+        if let Err(e) = save() {
+            console::write_line(&format!("config: could not save {}: {}", CONFIG_PATH, e));
+        }
+    }
+}
+
+/// `os config show|reset|reload|save`.
+pub fn command(args: &[&str]) -> Result<(), &'static str> {
+    match args.first().map(|a| a.to_ascii_lowercase()).as_deref() {
+        None | Some("show") => {
+            console::write_line(&format!("Settings ({}):", CONFIG_PATH));
+            for (key, value) in current() {
+                console::write_line(&format!("  {} = {}", key, value));
+            }
+            Ok(())
+        }
+        Some("reset") => {
+            let Some(defaults) = DEFAULTS.lock().clone() else {
+                return Err("config: defaults not recorded");
+            };
+            let now = current();
+            for (key, value) in &defaults {
+                apply(key, value, &now)?;
+            }
+            save()?;
+            console::write_line("Settings reset to defaults.");
+            Ok(())
+        }
+        Some("reload") => {
+            if !fs::exists(CONFIG_PATH) {
+                return Err("No config file yet; 'os config save' creates one.");
+            }
+            load();
+            console::write_line(&format!("Settings reloaded from {}.", CONFIG_PATH));
+            Ok(())
+        }
+        Some("save") => {
+            save()?;
+            console::write_line(&format!("Settings saved to {}.", CONFIG_PATH));
+            Ok(())
+        }
+        _ => Err(CONFIG_USAGE),
+    }
+}
+
+const CONFIG_USAGE: &str = "Usage: os config show|reset|reload|save";
@@ -3013,7 +3013,7 @@ impl Console {
 }
 
 pub static CONSOLE: Mutex<Option<Console>> = Mutex::new(None);
-type OutputHook = fn(&str, bool);
+pub type OutputHook = fn(&str, bool);
 static OUTPUT_HOOK: Mutex<Option<OutputHook>> = Mutex::new(None);
 
 pub fn init_console(boot: &'static mut BootInfo) {
@@ -3033,7 +3033,7 @@ where
     })
 }
 
-fn output_hook() -> Option<OutputHook> {
+pub fn output_hook() -> Option<OutputHook> {
     *OUTPUT_HOOK.lock()
 }
 
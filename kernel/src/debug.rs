@@ -1,4 +1,4 @@
-use crate::{cdmo, console, ext2, fat32, fs, run_mode};
+use crate::{cdmo, console, exfat, ext2, fat32, fs, process, run_mode, users};
 use alloc::format;
 use crate::console::CompositorMode;
 
@@ -135,6 +135,26 @@ pub fn command(args: &[&str]) {
             }
             filesystem_info();
         }
+        "usermode" => {
+            if args.len() != 1 {
+                usage();
+                return;
+            }
+            match process::run_selftest() {
+                Ok(code) => console::write_line(&format!("usermode: process exited with code {}", code)),
+                Err(e) => console::write_line(&format!("usermode: {}", e)),
+            }
+        }
+        "permissions" => {
+            if args.len() != 1 {
+                usage();
+                return;
+            }
+            match users::permission_selftest() {
+                Ok(()) => console::write_line("permissions: ok"),
+                Err(e) => console::write_line(&format!("permissions: {}", e)),
+            }
+        }
         _ => usage(),
     }
 }
@@ -146,6 +166,8 @@ fn usage() {
     console::write_line("       debug mode [toggle|console|desktop]");
     console::write_line("       debug compinfo");
     console::write_line("       debug filesystem");
+    console::write_line("       debug usermode");
+    console::write_line("       debug permissions");
 }
 
 fn toggle_back_buffer() {
@@ -209,12 +231,18 @@ fn filesystem_info() {
     let fs_kind = match info.fs_kind {
         Some(fs::FsKind::Fat32) => "fat32",
         Some(fs::FsKind::Ext2) => "ext2",
+        Some(fs::FsKind::Ext3) => "ext3",
+        Some(fs::FsKind::Ext4) => "ext4",
+        Some(fs::FsKind::Exfat) => "exfat",
+        Some(fs::FsKind::Iso9660) => "iso9660",
         None => "none",
     };
     let preferred = match info.preferred_fs {
         fs::FsPreference::Auto => "auto",
         fs::FsPreference::Fat32 => "fat32",
         fs::FsPreference::Ext2 => "ext2",
+        fs::FsPreference::Exfat => "exfat",
+        fs::FsPreference::Iso9660 => "iso9660",
     };
     let drive = match info.drive {
         Some(crate::ata::DriveSelect::PrimaryMaster) => "ATA0 master",
@@ -258,35 +286,42 @@ fn filesystem_info() {
         let label = core::str::from_utf8(&fat.volume_label)
             .unwrap_or("NO_LABEL")
             .trim();
+        let kind = fat.fat_type.label();
         console::write_line(&format!(
-            "FAT32: label '{}' (cluster {} bytes, FAT {} sectors).",
-            label, cluster_bytes, fat.sectors_per_fat
-        ));
-        console::write_line(&format!(
-            "FAT32 layout: reserved {} sectors, {} FATs, root cluster {}.",
-            fat.reserved_sectors, fat.num_fats, fat.root_cluster
+            "{}: label '{}' (cluster {} bytes, FAT {} sectors).",
+            kind, label, cluster_bytes, fat.sectors_per_fat
         ));
-        let total_bytes = fat.total_sectors as u64 * fat.bytes_per_sector as u64;
-        let data_sectors = fat
-            .total_sectors
-            .saturating_sub(fat.reserved_sectors as u32)
-            .saturating_sub(fat.num_fats as u32 * fat.sectors_per_fat);
-        let clusters = if fat.sectors_per_cluster == 0 {
-            0
+        if fat.root_cluster == 0 {
+            console::write_line(&format!(
+                "{} layout: reserved {} sectors, {} FATs, root {} entries.",
+                kind, fat.reserved_sectors, fat.num_fats, fat.root_entries
+            ));
         } else {
-            data_sectors / fat.sectors_per_cluster as u32
-        };
+            console::write_line(&format!(
+                "{} layout: reserved {} sectors, {} FATs, root cluster {}.",
+                kind, fat.reserved_sectors, fat.num_fats, fat.root_cluster
+            ));
+        }
+        let total_bytes = fat.total_sectors as u64 * fat.bytes_per_sector as u64;
         console::write_line(&format!(
-            "FAT32 size: {} sectors ({} bytes), data clusters {}.",
-            fat.total_sectors, total_bytes, clusters
+            "{} size: {} sectors ({} bytes), data clusters {}.",
+            kind, fat.total_sectors, total_bytes, fat.cluster_count()
         ));
         if let Some(part) = info.partition {
             let fat_start = part.lba_start + fat.reserved_sectors as u32;
-            let data_start = fat_start + fat.num_fats as u32 * fat.sectors_per_fat;
-            console::write_line(&format!(
-                "FAT32 LBA: FAT @ {}, data @ {}.",
-                fat_start, data_start
-            ));
+            let root_start = fat_start + fat.num_fats as u32 * fat.sectors_per_fat;
+            let data_start = root_start + fat.root_dir_sectors();
+            if fat.root_cluster == 0 {
+                console::write_line(&format!(
+                    "{} LBA: FAT @ {}, root @ {}, data @ {}.",
+                    kind, fat_start, root_start, data_start
+                ));
+            } else {
+                console::write_line(&format!(
+                    "{} LBA: FAT @ {}, data @ {}.",
+                    kind, fat_start, data_start
+                ));
+            }
         }
     }
     if let Some(ext2_info) = info.ext2_info {
@@ -312,6 +347,52 @@ fn filesystem_info() {
             "EXT2 data: first block {}, partition {} sectors.",
             ext2_info.first_data_block, ext2_info.part_sectors
         ));
+        console::write_line(&format!(
+            "EXT2 features: {}{}.",
+            ext2_info.feature_names(),
+            if ext2_info.read_only { " (read-only)" } else { "" }
+        ));
+    }
+    if let Some(exfat_info) = info.exfat_info {
+        console::write_line(&format!(
+            "exFAT: label '{}' (cluster {} bytes, revision {}.{:02}{}).",
+            exfat_info.label(),
+            exfat_info.sectors_per_cluster * exfat_info.bytes_per_sector as u32,
+            exfat_info.revision >> 8,
+            exfat_info.revision & 0xFF,
+            if exfat_info.read_only { ", read-only" } else { "" }
+        ));
+        console::write_line(&format!(
+            "exFAT layout: FAT @ {} ({} sectors), heap @ {}, {} clusters, root cluster {}.",
+            exfat_info.fat_offset,
+            exfat_info.fat_length,
+            exfat_info.cluster_heap_offset,
+            exfat_info.cluster_count,
+            exfat_info.root_cluster
+        ));
+        console::write_line(&format!(
+            "exFAT size: {} sectors, serial {:08X}.",
+            exfat_info.volume_length, exfat_info.serial
+        ));
+    }
+    if let Some(iso_info) = info.iso_info {
+        let names = if iso_info.rock_ridge {
+            "Rock Ridge"
+        } else if iso_info.joliet {
+            "Joliet"
+        } else {
+            "ISO9660"
+        };
+        console::write_line(&format!(
+            "ISO9660: label '{}' (block {} bytes, {} names, read-only).",
+            iso_info.label(),
+            iso_info.block_size,
+            names
+        ));
+        console::write_line(&format!(
+            "ISO9660 layout: {} blocks, root extent {}.",
+            iso_info.volume_blocks, iso_info.root_extent
+        ));
     }
 
     fn err_label(err: crate::ata::AtaError) -> &'static str {
@@ -319,6 +400,8 @@ fn filesystem_info() {
             crate::ata::AtaError::NoDevice => "no device",
             crate::ata::AtaError::Timeout => "timeout",
             crate::ata::AtaError::Error => "error",
+            crate::ata::AtaError::NoMedium => "no medium",
+            crate::ata::AtaError::ReadOnly => "read-only",
         }
     }
 
@@ -337,16 +420,19 @@ fn filesystem_info() {
                 let bytes = sectors.saturating_mul(512);
                 let has_fat32 = fat32::find_fat32_partition(&mbr).is_some();
                 let has_ext2 = ext2::find_ext2_partition(&mbr).is_some();
+                let has_exfat = exfat::find_exfat_partition(&mbr).is_some();
                 let layout = if mbr.is_empty {
                     "empty"
                 } else if !mbr.signature {
                     "data"
                 } else if has_fat32 && has_ext2 {
-                    "mbr/fat32+ext2"
+                    "mbr/fat+ext2"
                 } else if has_fat32 {
-                    "mbr/fat32"
+                    "mbr/fat"
                 } else if has_ext2 {
                     "mbr/ext2"
+                } else if has_exfat {
+                    "mbr/exfat"
                 } else {
                     "mbr/no-fs"
                 };
@@ -355,6 +441,14 @@ fn filesystem_info() {
                     label, sectors, bytes, layout
                 ));
             }
+            fs::ProbeResult::Optical { sectors, iso } => {
+                let bytes = sectors.saturating_mul(512);
+                let layout = if iso { "cd/iso9660" } else { "cd/no-fs" };
+                console::write_line(&format!(
+                    "{}: ok ({} sectors, {} bytes, {})",
+                    label, sectors, bytes, layout
+                ));
+            }
         }
     }
 
@@ -16,7 +16,7 @@ use crate::windows::{
     draw_scrollbar, AppAction, AppColors, AppContext, AppDescriptor, ContentArea, MouseEvent, MouseEventKind, Rect,
     ScrollMetrics, WindowApp, WindowMetrics,
 };
-use crate::{timer, time};
+use crate::{fs, timer, time};
 
 const DESKTOP_BG: u32 = 0x102028;
 const WINDOW_BG: u32 = 0x1B1E23;
@@ -78,6 +78,10 @@ pub fn resize_outline_enabled() -> bool {
 }
 
 
+pub fn set_resize_outline_enabled(enabled: bool) {
+    RESIZE_OUTLINE_ENABLED.store(enabled, Ordering::Release);
+}
+
 pub fn toggle_resize_outline_enabled() -> bool {
     let prev = RESIZE_OUTLINE_ENABLED.fetch_xor(true, Ordering::AcqRel);
     !prev
@@ -88,6 +92,10 @@ pub fn move_outline_enabled() -> bool {
 }
 
 
+pub fn set_move_outline_enabled(enabled: bool) {
+    MOVE_OUTLINE_ENABLED.store(enabled, Ordering::Release);
+}
+
 pub fn toggle_move_outline_enabled() -> bool {
     let prev = MOVE_OUTLINE_ENABLED.fetch_xor(true, Ordering::AcqRel);
     !prev
@@ -1940,12 +1948,14 @@ impl Desktop {
             }
             StartMenuAction::Restart => {
                 console::set_output_hook(Some(terminal::console_output_hook));
+                fs::unmount();
                 commands::reboot();
                 console::set_output_hook(None);
                 true
             }
             StartMenuAction::Shutdown => {
                 console::set_output_hook(Some(terminal::console_output_hook));
+                fs::unmount();
                 commands::shutdown();
             }
         }
@@ -7,12 +7,15 @@ use heapless::String as HString;
 
 use crate::clipboard;
 use crate::commands;
+use crate::complete;
 use crate::console;
 use crate::debug;
 use crate::fs;
 use crate::history;
 use crate::keyboard::KeyEvent;
+use crate::lexer;
 use crate::terminal;
+use crate::time;
 use crate::windows::{
     apply_intensity, AppAction, AppContext, AppDescriptor, AppEventResult, ContentArea, MouseEvent, MouseEventKind,
     Rect, ScrollMetrics, ScrollbarDraw, WindowApp,
@@ -23,9 +26,11 @@ const NOTES_MAX_COLS: usize = 128;
 const SCROLLBAR_COLS: usize = 1;
 const SCROLLBAR_GAP_COLS: usize = 1;
 const TERMINAL_PROMPT: &str = "> ";
-const FILE_EXPLORER_HEADER_ROWS: usize = 1;
+const FILE_EXPLORER_HEADER_ROWS: usize = 2;
+const FILE_EXPLORER_COLUMN_ROW: usize = 1;
 const FILE_EXPLORER_SIZE_COLS: usize = 8;
 const FILE_EXPLORER_SIZE_GAP: usize = 1;
+const FILE_EXPLORER_DATE_COLS: usize = 16;
 const APP_PICKER_MIN_COLS: usize = 18;
 const APP_PICKER_MAX_COLS: usize = 32;
 const APP_PICKER_MIN_ROWS: usize = 4;
@@ -84,6 +89,10 @@ const BUILTIN_APPS: &[AppDescriptor] = &[
     },
 ];
 
+fn terminal_app_index() -> Option<usize> {
+    builtin_apps().iter().position(|app| app.label == "Terminal")
+}
+
 fn create_welcome_app() -> Box<dyn WindowApp> {
     Box::new(InfoApp { paragraphs: WELCOME_TEXT })
 }
@@ -117,7 +126,7 @@ impl WindowApp for InfoApp {
 
 #[derive(Clone)]
 struct NotesBuffer {
-    lines: Vec<HString<128>>,
+    lines: Vec<String>,
     cursor_row: usize,
     cursor_col: usize,
     scroll: usize,
@@ -127,13 +136,13 @@ struct NotesBuffer {
 impl NotesBuffer {
     fn new() -> Self {
         let mut lines = Vec::new();
-        lines.push(HString::<128>::new());
+        lines.push(String::new());
         Self { lines, cursor_row: 0, cursor_col: 0, scroll: 0, selection_all: false }
     }
 
-    fn current_line_mut(&mut self) -> &mut HString<128> {
+    fn current_line_mut(&mut self) -> &mut String {
         if self.lines.is_empty() {
-            self.lines.push(HString::<128>::new());
+            self.lines.push(String::new());
             self.cursor_row = 0;
             self.cursor_col = 0;
         }
@@ -148,13 +157,13 @@ impl NotesBuffer {
         line
     }
 
-    fn line_len(line: &HString<128>) -> usize {
+    fn line_len(line: &str) -> usize {
         line.chars().count()
     }
 
     fn set_cursor(&mut self, row: usize, col: usize) -> bool {
         if self.lines.is_empty() {
-            self.lines.push(HString::<128>::new());
+            self.lines.push(String::new());
         }
         let row = row.min(self.lines.len().saturating_sub(1));
         let max_col = Self::line_len(&self.lines[row]);
@@ -227,9 +236,7 @@ impl NotesBuffer {
         let curr_len = Self::line_len(&current);
         if prev_len.saturating_add(curr_len) <= NOTES_MAX_COLS {
             let prev = &mut self.lines[prev_idx];
-            for ch in current.chars() {
-                let _ = prev.push(ch);
-            }
+            prev.push_str(&current);
             self.cursor_row = prev_idx;
             self.cursor_col = prev_len;
             return true;
@@ -249,15 +256,8 @@ impl NotesBuffer {
             return false;
         }
         let current = self.current_line_mut().clone();
-        let mut left = HString::<128>::new();
-        let mut right = HString::<128>::new();
-        for (idx, ch) in current.chars().enumerate() {
-            if idx < self.cursor_col {
-                let _ = left.push(ch);
-            } else {
-                let _ = right.push(ch);
-            }
-        }
+        let left: String = current.chars().take(self.cursor_col).collect();
+        let right: String = current.chars().skip(self.cursor_col).collect();
         self.lines[self.cursor_row] = left;
         let insert_at = self.cursor_row + 1;
         self.lines.insert(insert_at, right);
@@ -273,7 +273,7 @@ impl NotesBuffer {
     fn clear_all(&mut self) -> bool {
         let changed = self.lines.len() != 1 || !self.lines.first().map(|line| line.is_empty()).unwrap_or(true);
         self.lines.clear();
-        self.lines.push(HString::<128>::new());
+        self.lines.push(String::new());
         self.cursor_row = 0;
         self.cursor_col = 0;
         self.scroll = 0;
@@ -438,9 +438,7 @@ impl NotesBuffer {
             let curr_len = Self::line_len(&current);
             if prev_len.saturating_add(curr_len) <= NOTES_MAX_COLS {
                 let prev = &mut self.lines[prev_idx];
-                for ch in current.chars() {
-                    let _ = prev.push(ch);
-                }
+                prev.push_str(&current);
                 self.cursor_row = prev_idx;
                 self.cursor_col = prev_len;
                 merged = true;
@@ -607,6 +605,7 @@ impl NotesDialog {
             entries.push(FileEntry {
                 name: String::from(".."),
                 size: 0,
+                modified: None,
                 kind: FileEntryKind::Parent,
             });
         }
@@ -621,10 +620,11 @@ impl NotesDialog {
                         _ => a.name.cmp(&b.name),
                     }
                 });
-                for item in list {
+                for item in list.into_iter().filter(|item| !item.hidden) {
                     entries.push(FileEntry {
                         name: item.name,
                         size: item.size,
+                        modified: item.modified,
                         kind: if item.is_dir { FileEntryKind::Dir } else { FileEntryKind::File },
                     });
                 }
@@ -1469,9 +1469,35 @@ enum FileEntryKind {
 struct FileEntry {
     name: String,
     size: usize,
+    modified: Option<u64>,
     kind: FileEntryKind,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum SortColumn {
+    Name,
+    Modified,
+    Size,
+}
+
+impl SortColumn {
+    fn label(self) -> &'static str {
+        match self {
+            SortColumn::Name => "Name",
+            SortColumn::Modified => "Modified",
+            SortColumn::Size => "Size",
+        }
+    }
+}
+
+/// Where the name, date and size columns start; the date and size are dropped when the
+/// window is too narrow.
+struct FileExplorerColumns {
+    name_cols: usize,
+    date_col: Option<usize>,
+    size_col: Option<usize>,
+}
+
 struct FileExplorerLayout {
     area: ContentArea,
     list_row: usize,
@@ -1507,6 +1533,8 @@ struct FileExplorerApp {
     status: HString<64>,
     app_picker: Option<AppPickerState>,
     pending_action: Option<AppAction>,
+    sort: SortColumn,
+    sort_desc: bool,
 }
 
 impl FileExplorerApp {
@@ -1519,6 +1547,8 @@ impl FileExplorerApp {
             status: HString::new(),
             app_picker: None,
             pending_action: None,
+            sort: SortColumn::Name,
+            sort_desc: false,
         };
         app.refresh_entries();
         app
@@ -1570,6 +1600,7 @@ impl FileExplorerApp {
             entries.push(FileEntry {
                 name: String::from(".."),
                 size: 0,
+                modified: None,
                 kind: FileEntryKind::Parent,
             });
         }
@@ -1577,17 +1608,12 @@ impl FileExplorerApp {
         let path = self.current_path();
         match fs::list_dir(&path) {
             Ok(mut list) => {
-                list.sort_by(|a, b| {
-                    match (a.is_dir, b.is_dir) {
-                        (true, false) => Ordering::Less,
-                        (false, true) => Ordering::Greater,
-                        _ => a.name.cmp(&b.name),
-                    }
-                });
-                for item in list {
+                self.sort_listing(&mut list);
+                for item in list.into_iter().filter(|item| !item.hidden) {
                     entries.push(FileEntry {
                         name: item.name,
                         size: item.size,
+                        modified: item.modified,
                         kind: if item.is_dir { FileEntryKind::Dir } else { FileEntryKind::File },
                     });
                 }
@@ -1605,6 +1631,42 @@ impl FileExplorerApp {
         self.scroll = 0;
     }
 
+    // Folders stay ahead of files whichever column is sorted; ties fall back to the name.
+    fn sort_listing(&self, list: &mut [fs::ListingEntry]) {
+        let column = self.sort;
+        let desc = self.sort_desc;
+        list.sort_by(|a, b| {
+            if a.is_dir != b.is_dir {
+                return if a.is_dir { Ordering::Less } else { Ordering::Greater };
+            }
+            let order = match column {
+                SortColumn::Name => Ordering::Equal,
+                SortColumn::Modified => a.modified.cmp(&b.modified),
+                SortColumn::Size => a.size.cmp(&b.size),
+            }
+            .then_with(|| a.name.cmp(&b.name));
+            if desc { order.reverse() } else { order }
+        });
+    }
+
+    /// Sorts by `column`, or flips the direction if it is already the sort column.
+    fn set_sort(&mut self, column: SortColumn, view_rows: usize) {
+        if self.sort == column {
+            self.sort_desc = !self.sort_desc;
+        } else {
+            self.sort = column;
+            self.sort_desc = false;
+        }
+        let selected = self.entries.get(self.selection).map(|entry| entry.name.clone());
+        self.refresh_entries();
+        if let Some(name) = selected {
+            if let Some(idx) = self.entries.iter().position(|entry| entry.name == name) {
+                self.selection = idx;
+            }
+        }
+        self.ensure_selection_visible(view_rows);
+    }
+
     fn layout(&self, ctx: &AppContext, total_entries: usize) -> Option<FileExplorerLayout> {
         let area = ctx.metrics.content_area?;
         if area.h <= FILE_EXPLORER_HEADER_ROWS {
@@ -1631,9 +1693,10 @@ impl FileExplorerApp {
         })
     }
 
-    fn name_and_size_cols(&self, text_cols: usize) -> (usize, Option<usize>) {
+    fn columns(&self, text_cols: usize) -> FileExplorerColumns {
         let mut name_cols = text_cols;
         let mut size_col = None;
+        let mut date_col = None;
         let needed = FILE_EXPLORER_SIZE_COLS
             .saturating_add(FILE_EXPLORER_SIZE_GAP)
             .saturating_add(4);
@@ -1642,7 +1705,29 @@ impl FileExplorerApp {
             size_col = Some(col);
             name_cols = col.saturating_sub(FILE_EXPLORER_SIZE_GAP).max(1);
         }
-        (name_cols.max(1), size_col)
+        let date_needed = FILE_EXPLORER_DATE_COLS
+            .saturating_add(FILE_EXPLORER_SIZE_GAP)
+            .saturating_add(12);
+        if size_col.is_some() && name_cols >= date_needed {
+            let col = name_cols.saturating_sub(FILE_EXPLORER_DATE_COLS);
+            date_col = Some(col);
+            name_cols = col.saturating_sub(FILE_EXPLORER_SIZE_GAP).max(1);
+        }
+        FileExplorerColumns {
+            name_cols: name_cols.max(1),
+            date_col,
+            size_col,
+        }
+    }
+
+    fn column_at(&self, columns: &FileExplorerColumns, col: usize) -> SortColumn {
+        if columns.size_col.is_some_and(|start| col >= start) {
+            SortColumn::Size
+        } else if columns.date_col.is_some_and(|start| col >= start) {
+            SortColumn::Modified
+        } else {
+            SortColumn::Name
+        }
     }
 
     fn max_scroll(&self, view_rows: usize) -> usize {
@@ -1714,6 +1799,12 @@ impl FileExplorerApp {
             }
             FileEntryKind::File => {
                 let file_path = self.join_path(&entry.name);
+                if entry.name.to_ascii_lowercase().ends_with(".elf") {
+                    if let Some(app_idx) = terminal_app_index() {
+                        self.pending_action = Some(AppAction::OpenFile { app_idx, path: file_path });
+                        return true;
+                    }
+                }
                 self.open_app_picker(file_path);
                 true
             }
@@ -1920,7 +2011,9 @@ impl WindowApp for FileExplorerApp {
         let Some(layout) = self.layout(ctx, total_entries) else { return; };
         let area = layout.area;
         let view_rows = layout.list_rows;
-        let (name_cols, size_col) = self.name_and_size_cols(layout.text_cols);
+        let columns = self.columns(layout.text_cols);
+        let name_cols = columns.name_cols;
+        let size_col = columns.size_col;
         let char_w = ctx.metrics.char_w.max(1);
         let char_h = ctx.metrics.char_h.max(1);
 
@@ -1946,6 +2039,36 @@ impl WindowApp for FileExplorerApp {
         }
         ctx.draw_text_at_char(area.x, header_y, header_trimmed.as_str(), ctx.colors.fg, header_bg);
 
+        let column_row = area.y.saturating_add(FILE_EXPLORER_COLUMN_ROW);
+        let column_bg = apply_intensity(ctx.colors.fg, ctx.colors.bg, 10);
+        ctx.fill_rect(
+            area.x.saturating_mul(char_w),
+            column_row.saturating_mul(char_h),
+            area.w.saturating_mul(char_w),
+            char_h,
+            column_bg,
+        );
+        let headings = [
+            (SortColumn::Name, Some(0), name_cols),
+            (SortColumn::Modified, columns.date_col, FILE_EXPLORER_DATE_COLS),
+            (SortColumn::Size, size_col, FILE_EXPLORER_SIZE_COLS),
+        ];
+        for (column, start, width) in headings {
+            let Some(start) = start else { continue; };
+            let mut heading = HString::<32>::new();
+            let _ = heading.push_str(column.label());
+            if column == self.sort {
+                let _ = heading.push(' ');
+                let _ = heading.push(if self.sort_desc { 'v' } else { '^' });
+            }
+            let mut trimmed = HString::<32>::new();
+            for ch in heading.chars().take(width) {
+                let _ = trimmed.push(ch);
+            }
+            let col = area.x.saturating_add(start);
+            ctx.draw_text_at_char(col, column_row, trimmed.as_str(), ctx.colors.fg, column_bg);
+        }
+
         let highlight = apply_intensity(ctx.colors.fg, ctx.colors.bg, 48);
         let start = self.scroll.min(total_entries.saturating_sub(view_rows));
         for (row, entry) in self.entries.iter().skip(start).take(view_rows).enumerate() {
@@ -1980,6 +2103,11 @@ impl WindowApp for FileExplorerApp {
                 ctx.colors.fg,
                 row_bg,
             );
+            if let (Some(date_col), Some(modified)) = (columns.date_col, entry.modified) {
+                let date = time::format_timestamp(modified);
+                let col = area.x.saturating_add(date_col);
+                ctx.draw_text_at_char(col, abs_row, date.as_str(), ctx.colors.fg, row_bg);
+            }
             if let Some(size_col) = size_col {
                 let size_label = if matches!(entry.kind, FileEntryKind::Dir | FileEntryKind::Parent) {
                     let mut s = HString::<16>::new();
@@ -2156,6 +2284,11 @@ impl WindowApp for FileExplorerApp {
 
         let total_entries = self.entries.len();
         let Some(layout) = self.layout(ctx, total_entries) else { return AppEventResult::Ignored; };
+        if evt.row == FILE_EXPLORER_COLUMN_ROW {
+            let column = self.column_at(&self.columns(layout.text_cols), evt.col);
+            self.set_sort(column, layout.list_rows);
+            return AppEventResult::HandledRedraw;
+        }
         if evt.row < FILE_EXPLORER_HEADER_ROWS {
             return AppEventResult::HandledNoRedraw;
         }
@@ -2320,15 +2453,23 @@ struct TerminalLayout {
     scrollbar_col: Option<usize>,
 }
 
-struct TerminalApp;
+struct TerminalApp {
+    // A Ctrl+R history search in progress; its label replaces the prompt.
+    search: Option<history::Search>,
+    completer: complete::Completer,
+}
 
 impl TerminalApp {
     fn new() -> Self {
-        TerminalApp
+        TerminalApp { search: None, completer: complete::Completer::default() }
     }
 
     fn prompt_text(&self) -> HString<128> {
         let mut prompt = HString::<128>::new();
+        if let Some(search) = &self.search {
+            let _ = prompt.push_str(&search.label());
+            return prompt;
+        }
         if commands::is_prompt_path_enabled() {
             let path = fs::prompt_path();
             let _ = prompt.push_str(&path);
@@ -2580,12 +2721,19 @@ impl TerminalApp {
             return;
         }
         if trimmed.eq_ignore_ascii_case("clear") || trimmed.eq_ignore_ascii_case("cls") {
+            history::push(trimmed);
             terminal::clear_output();
             return;
         }
-        let mut parts = trimmed.split_whitespace();
-        let Some(cmd) = parts.next() else { return; };
+        // The shell reports lexing errors itself, so only intercept what tokenizes cleanly.
+        let words = lexer::split(trimmed).unwrap_or_default();
+        let mut parts = words.iter().map(String::as_str);
+        let Some(cmd) = parts.next() else {
+            self.run_shell(line);
+            return;
+        };
         if cmd.eq_ignore_ascii_case("cecho") {
+            history::push(trimmed);
             let Some(color) = parts.next() else {
                 terminal::push_output("Usage: cecho <hex> <text>", true);
                 return;
@@ -2597,17 +2745,11 @@ impl TerminalApp {
                     return;
                 }
             };
-            let mut text = HString::<128>::new();
-            for (i, word) in parts.enumerate() {
-                if i > 0 {
-                    let _ = text.push(' ');
-                }
-                let _ = text.push_str(word);
-            }
+            let text: Vec<&str> = parts.collect();
             if text.is_empty() {
                 terminal::push_output("Usage: cecho <hex> <text>", true);
             } else {
-                terminal::push_output_colored(text.as_str(), fg, true);
+                terminal::push_output_colored(&text.join(" "), fg, true);
             }
             return;
         }
@@ -2626,6 +2768,10 @@ impl TerminalApp {
                 }
             }
         }
+        self.run_shell(line);
+    }
+
+    fn run_shell(&self, line: &str) {
         console::set_output_hook(Some(terminal::console_output_hook));
         commands::handle_line(line);
         console::set_output_hook(None);
@@ -2637,6 +2783,16 @@ impl WindowApp for TerminalApp {
         true
     }
 
+    fn open_path(&mut self, path: &str) -> bool {
+        let mut line = String::from("run ");
+        line.push_str(&lexer::quote_path(path));
+        let mut echoed = String::from(self.prompt_text().as_str());
+        echoed.push_str(&line);
+        terminal::push_output(&echoed, true);
+        self.run_command(&line);
+        true
+    }
+
     fn draw(&mut self, ctx: &mut AppContext, input_focus: bool) {
         let Some((layout, total_lines)) = self.layout_and_lines(ctx) else { return; };
         let output_rows = layout.output_rows;
@@ -2691,10 +2847,38 @@ impl WindowApp for TerminalApp {
     }
 
     fn handle_key(&mut self, ctx: &mut AppContext, evt: &KeyEvent) -> AppEventResult {
+        if let Some(search) = self.search.as_mut() {
+            match evt {
+                &KeyEvent::Char(ch) => search.push(ch),
+                &KeyEvent::Backspace => search.pop(),
+                &KeyEvent::CtrlR => search.older(),
+                // Any other key keeps the match as input and is then handled as usual.
+                _ => self.search = None,
+            }
+            let found = self.search.as_ref().and_then(|s| s.matched());
+            terminal::with_state_mut(|term| {
+                if let Some(found) = found {
+                    term.input = found;
+                }
+                term.cursor_pos = term.input.chars().count();
+                term.selection_anchor = None;
+                term.history_index = None;
+            });
+            if self.search.is_some() {
+                let _ = self.layout_and_lines(ctx);
+                return AppEventResult::HandledRedraw;
+            }
+        } else if matches!(evt, KeyEvent::CtrlR) {
+            self.search = Some(history::Search::default());
+            let _ = self.layout_and_lines(ctx);
+            return AppEventResult::HandledRedraw;
+        }
+
         let prompt = self.prompt_text();
         let mut handled = false;
         let mut changed = false;
-        let mut entered: Option<HString<128>> = None;
+        let mut entered: Option<String> = None;
+        let mut listed: Option<(String, String)> = None;
 
         terminal::with_state_mut(|term| {
             let len = term.input.chars().count();
@@ -2828,12 +3012,7 @@ impl WindowApp for TerminalApp {
                     if let Some(anchor) = term.selection_anchor {
                         let start = anchor.min(term.cursor_pos);
                         let end = anchor.max(term.cursor_pos);
-                        let mut selected = HString::<128>::new();
-                        for (i, ch) in term.input.chars().enumerate() {
-                            if i >= start && i < end {
-                                let _ = selected.push(ch);
-                            }
-                        }
+                        let selected: String = term.input.chars().skip(start).take(end - start).collect();
                         if !selected.is_empty() {
                             clipboard::set_text(selected.as_str());
                         }
@@ -2844,12 +3023,7 @@ impl WindowApp for TerminalApp {
                     if let Some(anchor) = term.selection_anchor {
                         let start = anchor.min(term.cursor_pos);
                         let end = anchor.max(term.cursor_pos);
-                        let mut selected = HString::<128>::new();
-                        for (i, ch) in term.input.chars().enumerate() {
-                            if i >= start && i < end {
-                                let _ = selected.push(ch);
-                            }
-                        }
+                        let selected: String = term.input.chars().skip(start).take(end - start).collect();
                         if !selected.is_empty() {
                             clipboard::set_text(selected.as_str());
                         }
@@ -2869,8 +3043,6 @@ impl WindowApp for TerminalApp {
                             if insert_char_at(&mut term.input, term.cursor_pos, ch) {
                                 term.cursor_pos += 1;
                                 changed = true;
-                            } else {
-                                break;
                             }
                         }
                     }
@@ -2878,10 +3050,15 @@ impl WindowApp for TerminalApp {
                 }
                 &KeyEvent::Tab => {
                     handled = true;
-                    if let Some(cmd) = commands::suggest_command(term.input.as_str()) {
-                        term.input.clear();
-                        let _ = term.input.push_str(cmd.as_str());
-                        term.cursor_pos = term.input.chars().count();
+                    let done = self.completer.tab(term.input.as_str(), term.cursor_pos);
+                    if let Some(done) = done {
+                        if !done.choices.is_empty() {
+                            let mut echoed = String::from(prompt.as_str());
+                            echoed.push_str(term.input.as_str());
+                            listed = Some((echoed, done.choices.join("  ")));
+                        }
+                        term.input = done.line;
+                        term.cursor_pos = done.cursor;
                         term.selection_anchor = None;
                         term.history_index = None;
                         changed = true;
@@ -2948,15 +3125,18 @@ impl WindowApp for TerminalApp {
             }
         });
 
+        if let Some((echoed, choices)) = listed {
+            terminal::push_output(&echoed, true);
+            terminal::push_output(&choices, true);
+        }
+
         if let Some(line) = entered {
             let trimmed = line.as_str().trim();
             if !trimmed.is_empty() {
-                let mut echoed = HString::<256>::new();
-                let _ = echoed.push_str(prompt.as_str());
-                let _ = echoed.push_str(line.as_str());
+                let mut echoed = String::from(prompt.as_str());
+                echoed.push_str(line.as_str());
                 terminal::push_output(echoed.as_str(), true);
                 self.run_command(line.as_str());
-                history::push(line.as_str());
             } else {
                 terminal::push_output(prompt.as_str(), true);
             }
@@ -3069,68 +3249,46 @@ fn parse_rgb_hex(s: &str) -> Option<u32> {
     None
 }
 
-fn delete_selection(line: &mut HString<128>, cursor_pos: &mut usize, anchor: usize) -> bool {
+// Byte offset of the `idx`th character of `line`, or its end when `idx` is the length.
+fn char_offset(line: &str, idx: usize) -> Option<usize> {
+    line.char_indices().map(|(at, _)| at).chain(core::iter::once(line.len())).nth(idx)
+}
+
+fn delete_selection(line: &mut String, cursor_pos: &mut usize, anchor: usize) -> bool {
     let start = anchor.min(*cursor_pos);
     let end = anchor.max(*cursor_pos);
     if start == end {
         return false;
     }
-    let mut new_line = HString::<128>::new();
-    for (i, ch) in line.chars().enumerate() {
-        if i >= start && i < end {
-            continue;
-        }
-        let _ = new_line.push(ch);
-    }
-    *line = new_line;
+    *line = line
+        .chars()
+        .enumerate()
+        .filter(|(i, _)| *i < start || *i >= end)
+        .map(|(_, ch)| ch)
+        .collect();
     *cursor_pos = start;
     true
 }
 
-fn insert_char_at(line: &mut HString<128>, idx: usize, ch: char) -> bool {
-    let len = line.chars().count();
-    if idx > len {
-        return false;
-    }
-    let mut new_line = HString::<128>::new();
-    let mut inserted = false;
-    for (i, existing) in line.chars().enumerate() {
-        if i == idx {
-            if new_line.push(ch).is_err() {
-                return false;
-            }
-            inserted = true;
-        }
-        if new_line.push(existing).is_err() {
-            return false;
-        }
-    }
-    if !inserted && new_line.push(ch).is_err() {
+fn insert_char_at(line: &mut String, idx: usize, ch: char) -> bool {
+    let Some(at) = char_offset(line, idx) else {
         return false;
-    }
-    *line = new_line;
+    };
+    line.insert(at, ch);
     true
 }
 
-fn remove_char_at(line: &mut HString<128>, idx: usize) -> bool {
-    let len = line.chars().count();
-    if idx >= len {
-        return false;
-    }
-    let mut new_line = HString::<128>::new();
-    for (i, ch) in line.chars().enumerate() {
-        if i == idx {
-            continue;
-        }
-        if new_line.push(ch).is_err() {
-            return false;
+fn remove_char_at(line: &mut String, idx: usize) -> bool {
+    match char_offset(line, idx) {
+        Some(at) if at < line.len() => {
+            line.remove(at);
+            true
         }
+        _ => false,
     }
-    *line = new_line;
-    true
 }
 
-fn delete_prev_word(line: &mut HString<128>, cursor_pos: &mut usize) -> bool {
+fn delete_prev_word(line: &mut String, cursor_pos: &mut usize) -> bool {
     if *cursor_pos == 0 {
         return false;
     }
@@ -3145,19 +3303,13 @@ fn delete_prev_word(line: &mut HString<128>, cursor_pos: &mut usize) -> bool {
     if idx == *cursor_pos {
         return false;
     }
-    let remove_count = *cursor_pos - idx;
-    for _ in 0..remove_count {
-        chars.remove(idx);
-    }
-    line.clear();
-    for ch in chars.iter() {
-        let _ = line.push(*ch);
-    }
+    chars.drain(idx..*cursor_pos);
+    *line = chars.into_iter().collect();
     *cursor_pos = idx;
     true
 }
 
-fn move_cursor_word_left(line: &HString<128>, cursor_pos: &mut usize) -> bool {
+fn move_cursor_word_left(line: &str, cursor_pos: &mut usize) -> bool {
     if *cursor_pos == 0 {
         return false;
     }
@@ -3176,7 +3328,7 @@ fn move_cursor_word_left(line: &HString<128>, cursor_pos: &mut usize) -> bool {
     true
 }
 
-fn move_cursor_word_right(line: &HString<128>, cursor_pos: &mut usize) -> bool {
+fn move_cursor_word_right(line: &str, cursor_pos: &mut usize) -> bool {
     let chars: Vec<char> = line.chars().collect();
     if *cursor_pos >= chars.len() {
         return false;
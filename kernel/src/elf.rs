@@ -0,0 +1,227 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use x86_64::structures::paging::PageTableFlags;
+
+use crate::{env, fs};
+use crate::process::{self, Process, PAGE_SIZE, USER_SPACE_START};
+
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 0x3E;
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const MAX_PHDRS: usize = 64;
+
+pub const AT_NULL: u64 = 0;
+pub const AT_PHDR: u64 = 3;
+pub const AT_PHENT: u64 = 4;
+pub const AT_PHNUM: u64 = 5;
+pub const AT_PAGESZ: u64 = 6;
+pub const AT_ENTRY: u64 = 9;
+
+struct ElfHeader {
+    entry: u64,
+    phoff: u64,
+    phentsize: u16,
+    phnum: u16,
+}
+
+struct ProgramHeader {
+    p_type: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+    memsz: u64,
+}
+
+fn read_u16(data: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes([data[off], data[off + 1]])
+}
+
+fn read_u32(data: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes([data[off], data[off + 1], data[off + 2], data[off + 3]])
+}
+
+fn read_u64(data: &[u8], off: usize) -> u64 {
+    let mut raw = [0u8; 8];
+    raw.copy_from_slice(&data[off..off + 8]);
+    u64::from_le_bytes(raw)
+}
+
+fn parse_header(data: &[u8]) -> Result<ElfHeader, &'static str> {
+    if data.len() < EHDR_SIZE || data[0..4] != ELF_MAGIC {
+        return Err("Not an ELF file.");
+    }
+    if data[4] != ELFCLASS64 || data[5] != ELFDATA2LSB {
+        return Err("Only little-endian ELF64 is supported.");
+    }
+    if read_u16(data, 16) != ET_EXEC {
+        return Err("Only static executables are supported.");
+    }
+    if read_u16(data, 18) != EM_X86_64 {
+        return Err("Not an x86_64 executable.");
+    }
+    let header = ElfHeader {
+        entry: read_u64(data, 24),
+        phoff: read_u64(data, 32),
+        phentsize: read_u16(data, 54),
+        phnum: read_u16(data, 56),
+    };
+    if header.phentsize as usize != PHDR_SIZE || header.phnum as usize > MAX_PHDRS {
+        return Err("Malformed program header table.");
+    }
+    let table_end = (header.phnum as u64)
+        .checked_mul(PHDR_SIZE as u64)
+        .and_then(|len| len.checked_add(header.phoff))
+        .ok_or("Malformed program header table.")?;
+    if table_end > data.len() as u64 {
+        return Err("Truncated program header table.");
+    }
+    Ok(header)
+}
+
+fn program_headers(data: &[u8], header: &ElfHeader) -> Vec<ProgramHeader> {
+    let mut out = Vec::with_capacity(header.phnum as usize);
+    for i in 0..header.phnum as usize {
+        let off = header.phoff as usize + i * PHDR_SIZE;
+        out.push(ProgramHeader {
+            p_type: read_u32(data, off),
+            flags: read_u32(data, off + 4),
+            offset: read_u64(data, off + 8),
+            vaddr: read_u64(data, off + 16),
+            filesz: read_u64(data, off + 32),
+            memsz: read_u64(data, off + 40),
+        });
+    }
+    out
+}
+
+fn page_flags(phdrs: &[ProgramHeader], page: u64) -> PageTableFlags {
+    let mut writable = false;
+    let mut executable = false;
+    for ph in phdrs.iter().filter(|ph| ph.p_type == PT_LOAD && ph.memsz > 0) {
+        let start = ph.vaddr & !(PAGE_SIZE - 1);
+        if page >= start && page < ph.vaddr + ph.memsz {
+            writable |= ph.flags & PF_W != 0;
+            executable |= ph.flags & PF_X != 0;
+        }
+    }
+    let mut flags = PageTableFlags::empty();
+    if writable {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if !executable {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    flags
+}
+
+fn page_align_up(v: u64) -> u64 {
+    (v + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+/// Maps every PT_LOAD segment of `data` into `process` and records the entry point.
+/// Returns the user address of the program header table for AT_PHDR.
+pub fn load(process: &mut Process, data: &[u8]) -> Result<u64, &'static str> {
+    let header = parse_header(data)?;
+    let phdrs = program_headers(data, &header);
+    let mut loaded = false;
+    let mut image_end = 0u64;
+    let mut phdr_addr = 0u64;
+
+    for ph in phdrs.iter().filter(|ph| ph.p_type == PT_LOAD) {
+        if ph.memsz == 0 {
+            continue;
+        }
+        if ph.filesz > ph.memsz {
+            return Err("Malformed segment.");
+        }
+        let seg_end = ph.vaddr.checked_add(ph.memsz).ok_or("Segment out of range.")?;
+        if ph.vaddr < USER_SPACE_START || seg_end > process::USER_MMAP_BASE {
+            return Err("Segment outside the user image area.");
+        }
+        let file_end = ph.offset.checked_add(ph.filesz).ok_or("Malformed segment.")?;
+        if file_end > data.len() as u64 {
+            return Err("Truncated segment.");
+        }
+
+        // Pages start out zeroed, so only the file-backed part needs copying; .bss is free.
+        process.space.map(ph.vaddr, ph.memsz, PageTableFlags::WRITABLE)?;
+        let bytes = &data[ph.offset as usize..file_end as usize];
+        process.space.write(ph.vaddr, bytes)?;
+
+        if header.phoff >= ph.offset && header.phoff < file_end {
+            phdr_addr = ph.vaddr + (header.phoff - ph.offset);
+        }
+        image_end = image_end.max(seg_end);
+        loaded = true;
+    }
+
+    if !loaded {
+        return Err("No loadable segments.");
+    }
+
+    // Segments may share a page at their boundaries, so final permissions are the union
+    // of every segment touching the page.
+    for ph in phdrs.iter().filter(|ph| ph.p_type == PT_LOAD && ph.memsz > 0) {
+        let mut page = ph.vaddr & !(PAGE_SIZE - 1);
+        while page < ph.vaddr + ph.memsz {
+            process.space.protect(page, PAGE_SIZE, page_flags(&phdrs, page))?;
+            page += PAGE_SIZE;
+        }
+    }
+    if header.entry < USER_SPACE_START || header.entry >= image_end {
+        return Err("Entry point outside the image.");
+    }
+    process.entry = header.entry;
+    process.mmap_next = process.mmap_next.max(page_align_up(image_end));
+    Ok(phdr_addr)
+}
+
+/// Bare program names that don't exist in the current directory are looked up in each
+/// `;`-separated directory of `$PATH`, with or without the `.elf` extension.
+pub fn resolve_program(name: &str) -> String {
+    if fs::exists(name) || name.contains('\\') || name.contains('/') {
+        return String::from(name);
+    }
+    let path = env::get("PATH").unwrap_or_default();
+    for dir in path.split(';').map(|d| d.trim_end_matches('\\')).filter(|d| !d.is_empty()) {
+        let candidates = [format!("{}\\{}", dir, name), format!("{}\\{}.elf", dir, name)];
+        for candidate in candidates {
+            if fs::exists(&candidate) {
+                return candidate;
+            }
+        }
+    }
+    String::from(name)
+}
+
+/// Loads the executable at `path`, builds its initial stack and runs it in the foreground.
+pub fn exec(path: &str, argv: &[&str], envp: &[&str]) -> Result<i64, &'static str> {
+    let data = fs::read_executable(path)?;
+    let name = path.rsplit(['\\', '/']).next().unwrap_or(path);
+    let mut process = Process::new(name)?;
+    let phdr = load(&mut process, &data)?;
+    let header = parse_header(&data)?;
+    drop(data);
+
+    let auxv = [
+        (AT_PHDR, phdr),
+        (AT_PHENT, PHDR_SIZE as u64),
+        (AT_PHNUM, header.phnum as u64),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_ENTRY, header.entry),
+        (AT_NULL, 0),
+    ];
+    process.map_stack()?;
+    process.push_args(argv, envp, &auxv)?;
+    process::run(process)
+}
@@ -0,0 +1,243 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicI64, Ordering};
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use crate::{console, fs};
+
+struct Var {
+    value: String,
+    exported: bool,
+}
+
+lazy_static! {
+    static ref VARS: Mutex<BTreeMap<String, Var>> = {
+        let mut vars = BTreeMap::new();
+        vars.insert("PATH".to_string(), Var { value: "\\bin".to_string(), exported: true });
+        vars.insert("HOME".to_string(), Var { value: "\\".to_string(), exported: true });
+        Mutex::new(vars)
+    };
+}
+
+static LAST_STATUS: AtomicI64 = AtomicI64::new(0);
+
+// Positional parameters of the running scripts and shell functions, innermost last.
+// `$0` is the script or function name.
+static ARGS: Mutex<Vec<Vec<String>>> = Mutex::new(Vec::new());
+
+pub fn last_status() -> i64 {
+    LAST_STATUS.load(Ordering::Relaxed)
+}
+
+pub fn set_status(code: i64) {
+    LAST_STATUS.store(code, Ordering::Relaxed);
+}
+
+/// `$?` and the positional parameters of a task, set aside while another task runs.
+pub struct TaskState {
+    status: i64,
+    args: Vec<Vec<String>>,
+}
+
+pub fn switch_out() -> TaskState {
+    TaskState { status: last_status(), args: core::mem::take(&mut *ARGS.lock()) }
+}
+
+pub fn switch_in(state: TaskState) {
+    set_status(state.status);
+    *ARGS.lock() = state.args;
+}
+
+/// The running task's `$?` and parameters, for one it starts.
+pub fn inherit() -> TaskState {
+    TaskState { status: last_status(), args: ARGS.lock().clone() }
+}
+
+pub fn push_args(args: Vec<String>) {
+    ARGS.lock().push(args);
+}
+
+pub fn pop_args() {
+    ARGS.lock().pop();
+}
+
+fn positional(name: &str) -> Option<String> {
+    let args = ARGS.lock();
+    let current = args.last();
+    match name {
+        "#" => Some(format!("{}", current.map_or(0, |a| a.len().saturating_sub(1)))),
+        "@" | "*" => Some(current.map(|a| a.get(1..).unwrap_or_default().join(" ")).unwrap_or_default()),
+        _ => {
+            let index: usize = name.parse().ok()?;
+            Some(current.and_then(|a| a.get(index)).cloned().unwrap_or_default())
+        }
+    }
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Looks up a variable, including the dynamic ones (`?`, `PWD`, `#`, `@` and `0`-`9`).
+pub fn get(name: &str) -> Option<String> {
+    match name {
+        "?" => return Some(format!("{}", last_status())),
+        "PWD" => return Some(fs::current_dir()),
+        "#" | "@" | "*" => return positional(name),
+        _ if name.bytes().all(|b| b.is_ascii_digit()) && !name.is_empty() => return positional(name),
+        _ => {}
+    }
+    VARS.lock().get(name).map(|v| v.value.clone())
+}
+
+pub fn set(name: &str, value: &str, export: bool) -> Result<(), &'static str> {
+    if !is_valid_name(name) || name == "PWD" {
+        return Err("Invalid variable name.");
+    }
+    let mut vars = VARS.lock();
+    let exported = export || vars.get(name).is_some_and(|v| v.exported);
+    vars.insert(name.to_string(), Var { value: value.to_string(), exported });
+    Ok(())
+}
+
+/// Path of `name` inside the user's `$HOME`.
+pub fn home_file(name: &str) -> String {
+    let home = get("HOME").unwrap_or_else(|| String::from("\\"));
+    if home.ends_with('\\') {
+        format!("{}{}", home, name)
+    } else {
+        format!("{}\\{}", home, name)
+    }
+}
+
+pub fn unset(name: &str) -> bool {
+    VARS.lock().remove(name).is_some()
+}
+
+/// `NAME=value` strings handed to launched programs as their environment.
+pub fn exported() -> Vec<String> {
+    let mut out: Vec<String> = VARS
+        .lock()
+        .iter()
+        .filter(|(_, v)| v.exported)
+        .map(|(k, v)| format!("{}={}", k, v.value))
+        .collect();
+    out.push(format!("PWD={}", fs::current_dir()));
+    out
+}
+
+/// Expands the `$` reference at the start of `rest` (the text just after the dollar):
+/// `NAME`, `{NAME}`, `?` or a positional `0`-`9`, `#`, `@`. Returns the replacement and
+/// how many bytes of `rest` it used; a dollar that starts no reference stays literal.
+pub fn expand_var(rest: &str) -> (String, usize) {
+    match rest.chars().next() {
+        Some('?') => (format!("{}", last_status()), 1),
+        Some(c) if c.is_ascii_digit() || c == '#' || c == '@' || c == '*' => {
+            (get(&rest[..1]).unwrap_or_default(), 1)
+        }
+        Some('{') => match rest.find('}') {
+            Some(end) => (get(&rest[1..end]).unwrap_or_default(), end + 1),
+            None => (String::from("$"), 0),
+        },
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (get(&rest[..len]).unwrap_or_default(), len)
+        }
+        _ => (String::from("$"), 0),
+    }
+}
+
+fn print_vars(only_exported: bool) {
+    let vars = VARS.lock();
+    for (name, var) in vars.iter() {
+        if only_exported && !var.exported {
+            continue;
+        }
+        console::write_line(&format!("{}={}", name, var.value));
+    }
+    drop(vars);
+    if only_exported {
+        console::write_line(&format!("PWD={}", fs::current_dir()));
+    }
+}
+
+// Accepts `NAME=value`, or `NAME value...` for `set`.
+fn parse_assignment<'a>(args: &[&'a str]) -> Option<(&'a str, String)> {
+    let first = args.first()?;
+    if let Some((name, value)) = first.split_once('=') {
+        let mut value = value.to_string();
+        for extra in &args[1..] {
+            value.push(' ');
+            value.push_str(extra);
+        }
+        return Some((name, value));
+    }
+    Some((first, args[1..].join(" ")))
+}
+
+pub fn set_command(args: &[&str]) -> i64 {
+    if args.is_empty() {
+        print_vars(false);
+        return 0;
+    }
+    let Some((name, value)) = parse_assignment(args) else { return 1; };
+    match set(name, &value, false) {
+        Ok(()) => 0,
+        Err(e) => {
+            console::write_line(&format!("set: {}", e));
+            1
+        }
+    }
+}
+
+pub fn export_command(args: &[&str]) -> i64 {
+    if args.is_empty() {
+        print_vars(true);
+        return 0;
+    }
+    let mut status = 0;
+    for arg in args {
+        let result = match arg.split_once('=') {
+            Some((name, value)) => set(name, value, true),
+            None => {
+                let value = get(arg).unwrap_or_default();
+                set(arg, &value, true)
+            }
+        };
+        if let Err(e) = result {
+            console::write_line(&format!("export: {}: {}", arg, e));
+            status = 1;
+        }
+    }
+    status
+}
+
+pub fn unset_command(args: &[&str]) -> i64 {
+    if args.is_empty() {
+        console::write_line("Usage: unset <name>...");
+        return 1;
+    }
+    for name in args {
+        unset(name);
+    }
+    0
+}
+
+pub fn env_command(args: &[&str]) -> i64 {
+    if !args.is_empty() {
+        console::write_line("Usage: env");
+        return 1;
+    }
+    print_vars(true);
+    0
+}
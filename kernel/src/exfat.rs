@@ -0,0 +1,1513 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::{format, string::String, string::ToString, vec, vec::Vec};
+use core::cmp;
+
+use crate::{
+    block::{BlockDevice, BlockDeviceError},
+    fat32::{self, FatUsage, FsckReport, MbrInfo, PartitionInfo},
+    memory, time,
+};
+
+const SECTOR_SIZE: usize = 512;
+const DIR_ENTRY_SIZE: usize = 32;
+const ENTRIES_PER_SECTOR: u32 = (SECTOR_SIZE / DIR_ENTRY_SIZE) as u32;
+/// Type code MBR partition tables give exFAT (shared with NTFS).
+pub const EXFAT_PART_TYPE: u8 = 0x07;
+const EXFAT_SIGNATURE: &[u8; 8] = b"EXFAT   ";
+// Boot sector, eight extended boot sectors, OEM parameters, a reserved sector and the
+// checksum sector; the backup copy follows straight after.
+const BOOT_REGION_SECTORS: u32 = 12;
+const BOOT_CHECKSUM_SECTOR: usize = 11;
+// VolumeFlags and PercentInUse are left out of the boot checksum so they can change in place.
+const VOLUME_FLAGS_OFFSET: usize = 106;
+const PERCENT_IN_USE_OFFSET: usize = 112;
+const VOLUME_FLAG_ACTIVE_FAT: u16 = 0x0001;
+const VOLUME_FLAG_DIRTY: u16 = 0x0002;
+const MAX_CLUSTER_SHIFT: u8 = 25;
+
+const FAT_EOC: u32 = 0xFFFF_FFFF;
+const FAT_BAD: u32 = 0xFFFF_FFF7;
+const FAT_MEDIA: u32 = 0xFFFF_FFF8;
+
+const ENTRY_END: u8 = 0x00;
+const ENTRY_IN_USE: u8 = 0x80;
+const ENTRY_BITMAP: u8 = 0x81;
+const ENTRY_UPCASE: u8 = 0x82;
+const ENTRY_LABEL: u8 = 0x83;
+const ENTRY_FILE: u8 = 0x85;
+const ENTRY_STREAM: u8 = 0xC0;
+const ENTRY_NAME: u8 = 0xC1;
+const NAME_UNITS_PER_ENTRY: usize = 15;
+const MAX_LABEL_UNITS: usize = 11;
+// General secondary flags of a stream extension entry.
+const ALLOCATION_POSSIBLE: u8 = 0x01;
+const NO_FAT_CHAIN: u8 = 0x02;
+
+const ATTR_DIRECTORY: u16 = 0x10;
+const ATTR_ARCHIVE: u16 = 0x20;
+// Characters exFAT refuses in file names, besides control characters.
+const INVALID_NAME_CHARS: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+
+// Volumes we format start their FAT 64 KiB in and must hold at least 4 MiB.
+const FORMAT_FAT_OFFSET: u32 = 128;
+const FORMAT_MIN_SECTORS: u32 = 8192;
+
+#[derive(Copy, Clone)]
+pub struct ExfatInfo {
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u32,
+    pub fat_offset: u32,
+    pub fat_length: u32,
+    pub cluster_heap_offset: u32,
+    pub cluster_count: u32,
+    pub root_cluster: u32,
+    pub volume_length: u64,
+    pub serial: u32,
+    pub revision: u16,
+    pub volume_label: [u16; MAX_LABEL_UNITS],
+    pub label_len: u8,
+    /// Set for TexFAT volumes, whose second FAT and bitmap this driver does not keep.
+    pub read_only: bool,
+}
+
+impl ExfatInfo {
+    pub fn label(&self) -> String {
+        decode_utf16(&self.volume_label[..self.label_len as usize])
+    }
+}
+
+pub fn find_exfat_partition(mbr: &MbrInfo) -> Option<PartitionInfo> {
+    for part in mbr.partitions.iter().flatten() {
+        if part.type_code == EXFAT_PART_TYPE {
+            return Some(*part);
+        }
+    }
+    None
+}
+
+/// A file or directory, as its file, stream extension and name entries describe it.
+#[derive(Clone)]
+pub struct DirEntryInfo {
+    pub name: String,
+    pub is_dir: bool,
+    pub attr: u16,
+    pub cluster: u32,
+    pub size: u64,
+    /// Bytes actually written; the rest up to `size` reads as zeros.
+    pub valid_size: u64,
+    /// NoFatChain: the clusters are one contiguous run and the FAT is not consulted.
+    pub contiguous: bool,
+    /// Slot of the file entry in its directory, and how many entries follow it.
+    pub entry_index: u32,
+    pub secondary_count: u8,
+    /// Creation, last-write and last-access times in Unix seconds, when recorded.
+    pub created: Option<u64>,
+    pub modified: Option<u64>,
+    pub accessed: Option<u64>,
+}
+
+// Where a directory's own entry set lives. Directories may be NoFatChain runs, so their
+// first cluster alone does not say how long they are.
+struct DirRecord {
+    parent: u32,
+    entry: DirEntryInfo,
+}
+
+struct FatCache {
+    lba: Option<u32>,
+    buf: [u8; SECTOR_SIZE],
+    dirty: bool,
+}
+
+impl FatCache {
+    fn new() -> Self {
+        Self {
+            lba: None,
+            buf: [0u8; SECTOR_SIZE],
+            dirty: false,
+        }
+    }
+}
+
+pub struct ExfatVolume<D: BlockDevice> {
+    device: D,
+    part_start: u32,
+    volume_length: u64,
+    fat_offset: u32,
+    fat_length: u32,
+    // First sector of the FAT in use, past any inactive TexFAT copy.
+    fat_start: u32,
+    cluster_heap_offset: u32,
+    cluster_count: u32,
+    sectors_per_cluster: u32,
+    root_cluster: u32,
+    serial: u32,
+    revision: u16,
+    volume_label: [u16; MAX_LABEL_UNITS],
+    label_len: u8,
+    read_only: bool,
+    fat_cache: FatCache,
+    bitmap: Vec<u8>,
+    bitmap_clusters: Vec<u32>,
+    // Sectors of the bitmap changed since the last flush.
+    bitmap_dirty: BTreeSet<u32>,
+    free_count: u32,
+    next_free: u32,
+    upcase: Vec<u16>,
+    upcase_clusters: Vec<u32>,
+    // Directories seen so far, by first cluster.
+    dirs: BTreeMap<u32, DirRecord>,
+}
+
+impl<D: BlockDevice> ExfatVolume<D> {
+    pub fn info(&self) -> ExfatInfo {
+        ExfatInfo {
+            bytes_per_sector: SECTOR_SIZE as u16,
+            sectors_per_cluster: self.sectors_per_cluster,
+            fat_offset: self.fat_offset,
+            fat_length: self.fat_length,
+            cluster_heap_offset: self.cluster_heap_offset,
+            cluster_count: self.cluster_count,
+            root_cluster: self.root_cluster,
+            volume_length: self.volume_length,
+            serial: self.serial,
+            revision: self.revision,
+            volume_label: self.volume_label,
+            label_len: self.label_len,
+            read_only: self.read_only,
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn root_cluster(&self) -> u32 {
+        self.root_cluster
+    }
+
+    pub fn cluster_size(&self) -> u32 {
+        self.sectors_per_cluster * SECTOR_SIZE as u32
+    }
+
+    pub fn usage(&mut self) -> Result<FatUsage, &'static str> {
+        Ok(FatUsage {
+            total_clusters: self.cluster_count,
+            free_clusters: self.free_count,
+            cluster_size: self.cluster_size(),
+        })
+    }
+
+    /// Mounts the volume at `part`. A main boot region that fails its checksum is passed
+    /// over for the backup; `fsck -y` rewrites it.
+    pub fn open(dev: D, part: PartitionInfo) -> Result<Self, &'static str> {
+        let main = read_boot_region(&dev, part.lba_start)?;
+        let boot = if boot_region_valid(&main) {
+            main[0]
+        } else {
+            let backup = read_boot_region(&dev, part.lba_start + BOOT_REGION_SECTORS)?;
+            if &main[0][3..11] != EXFAT_SIGNATURE && &backup[0][3..11] != EXFAT_SIGNATURE {
+                return Err("Not an exFAT volume.");
+            }
+            if !boot_region_valid(&backup) {
+                return Err("exFAT boot region checksum mismatch.");
+            }
+            backup[0]
+        };
+        if &boot[3..11] != EXFAT_SIGNATURE {
+            return Err("Not an exFAT volume.");
+        }
+        if boot[510] != 0x55 || boot[511] != 0xAA || boot[11..64].iter().any(|b| *b != 0) {
+            return Err("Invalid exFAT boot sector.");
+        }
+
+        let word = |at: usize| u32::from_le_bytes([boot[at], boot[at + 1], boot[at + 2], boot[at + 3]]);
+        let long = |at: usize| word(at) as u64 | (word(at + 4) as u64) << 32;
+        let revision = u16::from_le_bytes([boot[104], boot[105]]);
+        if revision >> 8 != 1 {
+            return Err("Unsupported exFAT revision.");
+        }
+        let flags = u16::from_le_bytes([boot[VOLUME_FLAGS_OFFSET], boot[VOLUME_FLAGS_OFFSET + 1]]);
+        if boot[108] as usize != SECTOR_SIZE.trailing_zeros() as usize {
+            return Err("Unsupported exFAT sector size.");
+        }
+        if boot[109] > MAX_CLUSTER_SHIFT - boot[108] {
+            return Err("Invalid exFAT cluster size.");
+        }
+        let num_fats = boot[110];
+        if num_fats != 1 && num_fats != 2 {
+            return Err("Invalid exFAT FAT count.");
+        }
+
+        let volume_length = long(72);
+        let fat_offset = word(80);
+        let fat_length = word(84);
+        let cluster_heap_offset = word(88);
+        let cluster_count = word(92);
+        let root_cluster = word(96);
+        let sectors_per_cluster = 1u32 << boot[109];
+        if volume_length > part.sectors as u64 {
+            return Err("exFAT volume is larger than its partition.");
+        }
+        let heap_sectors = cluster_count as u64 * sectors_per_cluster as u64;
+        if fat_offset < 2 * BOOT_REGION_SECTORS
+            || cluster_heap_offset < fat_offset + num_fats as u32 * fat_length
+            || (fat_length as u64 * SECTOR_SIZE as u64) < (cluster_count as u64 + 2) * 4
+            || cluster_heap_offset as u64 + heap_sectors > volume_length
+            || root_cluster < 2
+            || root_cluster >= cluster_count + 2
+        {
+            return Err("Invalid exFAT size fields.");
+        }
+        let active_fat = if num_fats == 2 && flags & VOLUME_FLAG_ACTIVE_FAT != 0 { 1 } else { 0 };
+
+        let mut volume = Self {
+            device: dev,
+            part_start: part.lba_start,
+            volume_length,
+            fat_offset,
+            fat_length,
+            fat_start: part.lba_start + fat_offset + active_fat * fat_length,
+            cluster_heap_offset,
+            cluster_count,
+            sectors_per_cluster,
+            root_cluster,
+            serial: word(100),
+            revision,
+            volume_label: [0u16; MAX_LABEL_UNITS],
+            label_len: 0,
+            read_only: num_fats != 1,
+            fat_cache: FatCache::new(),
+            bitmap: Vec::new(),
+            bitmap_clusters: Vec::new(),
+            bitmap_dirty: BTreeSet::new(),
+            free_count: 0,
+            next_free: 2,
+            upcase: Vec::new(),
+            upcase_clusters: Vec::new(),
+            dirs: BTreeMap::new(),
+        };
+        volume.load_metadata()?;
+        Ok(volume)
+    }
+
+    pub fn format(dev: D, part_start: u32, part_sectors: u32, label: &str) -> Result<Self, &'static str> {
+        if part_sectors < FORMAT_MIN_SECTORS {
+            return Err("Disk too small for exFAT.");
+        }
+        let cluster_shift = choose_cluster_shift(part_sectors);
+        let sectors_per_cluster = 1u32 << cluster_shift;
+
+        // Shrinking the cluster count only shrinks the FAT, so one correction settles it.
+        let mut cluster_count = (part_sectors - FORMAT_FAT_OFFSET) / sectors_per_cluster;
+        let (fat_length, heap_offset) = loop {
+            let fat_length = ((cluster_count as u64 + 2) * 4).div_ceil(SECTOR_SIZE as u64) as u32;
+            let heap_offset = (FORMAT_FAT_OFFSET + fat_length).next_multiple_of(sectors_per_cluster);
+            let fits = part_sectors.saturating_sub(heap_offset) / sectors_per_cluster;
+            if fits >= cluster_count {
+                break (fat_length, heap_offset);
+            }
+            cluster_count = fits;
+        };
+
+        let cluster_size = sectors_per_cluster as usize * SECTOR_SIZE;
+        let bitmap_bytes = (cluster_count as usize).div_ceil(8);
+        let bitmap_len = bitmap_bytes.div_ceil(cluster_size) as u32;
+        let upcase = compress_upcase(&default_upcase());
+        let upcase_len = upcase.len().div_ceil(cluster_size) as u32;
+        let bitmap_first = 2u32;
+        let upcase_first = bitmap_first + bitmap_len;
+        let root_cluster = upcase_first + upcase_len;
+        if root_cluster >= cluster_count + 2 {
+            return Err("Disk too small for exFAT.");
+        }
+        let cluster_lba = |cluster: u32| part_start + heap_offset + (cluster - 2) * sectors_per_cluster;
+
+        write_mbr(&dev, part_start, part_sectors)?;
+
+        let serial = time::current_time_secs().unwrap_or(0) as u32;
+        let mut region = vec![[0u8; SECTOR_SIZE]; BOOT_REGION_SECTORS as usize];
+        let boot = &mut region[0];
+        boot[0..3].copy_from_slice(&[0xEB, 0x76, 0x90]);
+        boot[3..11].copy_from_slice(EXFAT_SIGNATURE);
+        boot[64..72].copy_from_slice(&(part_start as u64).to_le_bytes());
+        boot[72..80].copy_from_slice(&(part_sectors as u64).to_le_bytes());
+        boot[80..84].copy_from_slice(&FORMAT_FAT_OFFSET.to_le_bytes());
+        boot[84..88].copy_from_slice(&fat_length.to_le_bytes());
+        boot[88..92].copy_from_slice(&heap_offset.to_le_bytes());
+        boot[92..96].copy_from_slice(&cluster_count.to_le_bytes());
+        boot[96..100].copy_from_slice(&root_cluster.to_le_bytes());
+        boot[100..104].copy_from_slice(&serial.to_le_bytes());
+        boot[104..106].copy_from_slice(&0x0100u16.to_le_bytes());
+        boot[108] = SECTOR_SIZE.trailing_zeros() as u8;
+        boot[109] = cluster_shift;
+        boot[110] = 1;
+        boot[111] = 0x80;
+        boot[510] = 0x55;
+        boot[511] = 0xAA;
+        for sector in region[1..9].iter_mut() {
+            sector[508..512].copy_from_slice(&[0x00, 0x00, 0x55, 0xAA]);
+        }
+        write_boot_region(&dev, part_start, &mut region)?;
+        write_boot_region(&dev, part_start + BOOT_REGION_SECTORS, &mut region)?;
+
+        // The FAT chains the bitmap and the up-case table, and ends the root directory.
+        let mut fat_head = vec![FAT_MEDIA, FAT_EOC];
+        for (first, len) in [(bitmap_first, bitmap_len), (upcase_first, upcase_len), (root_cluster, 1)] {
+            for cluster in first..first + len {
+                fat_head.push(if cluster + 1 == first + len { FAT_EOC } else { cluster + 1 });
+            }
+        }
+        let fat_bytes: Vec<u8> = fat_head.iter().flat_map(|value| value.to_le_bytes()).collect();
+        write_run(&dev, part_start + FORMAT_FAT_OFFSET, &fat_bytes, fat_length)?;
+
+        let mut bitmap = vec![0u8; bitmap_bytes];
+        for index in 0..(root_cluster - 1) as usize {
+            bitmap[index / 8] |= 1 << (index % 8);
+        }
+        write_run(&dev, cluster_lba(bitmap_first), &bitmap, bitmap_len * sectors_per_cluster)?;
+        write_run(&dev, cluster_lba(upcase_first), &upcase, upcase_len * sectors_per_cluster)?;
+
+        let mut root = Vec::new();
+        let label: Vec<u16> = label.encode_utf16().take(MAX_LABEL_UNITS).collect();
+        if !label.is_empty() {
+            let mut entry = [0u8; DIR_ENTRY_SIZE];
+            entry[0] = ENTRY_LABEL;
+            entry[1] = label.len() as u8;
+            for (index, unit) in label.iter().enumerate() {
+                entry[2 + index * 2..4 + index * 2].copy_from_slice(&unit.to_le_bytes());
+            }
+            root.extend_from_slice(&entry);
+        }
+        let mut entry = [0u8; DIR_ENTRY_SIZE];
+        entry[0] = ENTRY_BITMAP;
+        entry[20..24].copy_from_slice(&bitmap_first.to_le_bytes());
+        entry[24..32].copy_from_slice(&(bitmap_bytes as u64).to_le_bytes());
+        root.extend_from_slice(&entry);
+        let mut entry = [0u8; DIR_ENTRY_SIZE];
+        entry[0] = ENTRY_UPCASE;
+        entry[4..8].copy_from_slice(&table_checksum(&upcase).to_le_bytes());
+        entry[20..24].copy_from_slice(&upcase_first.to_le_bytes());
+        entry[24..32].copy_from_slice(&(upcase.len() as u64).to_le_bytes());
+        root.extend_from_slice(&entry);
+        write_run(&dev, cluster_lba(root_cluster), &root, sectors_per_cluster)?;
+
+        let part = PartitionInfo {
+            type_code: EXFAT_PART_TYPE,
+            lba_start: part_start,
+            sectors: part_sectors,
+        };
+        Self::open(dev, part)
+    }
+
+    pub fn read_directory(&mut self, dir: u32) -> Result<Vec<DirEntryInfo>, &'static str> {
+        let clusters = self.dir_clusters(dir)?;
+        let data = self.read_clusters(&clusters)?;
+        let mut entries = Vec::new();
+        // Sets failing their checksum are left alone until fsck repairs them.
+        for (entry, checksum_ok) in parse_entry_sets(&data) {
+            if !checksum_ok {
+                continue;
+            }
+            if entry.is_dir && entry.cluster != 0 {
+                self.dirs.insert(entry.cluster, DirRecord { parent: dir, entry: entry.clone() });
+            }
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    /// Looks `name` up as exFAT does, through the volume's up-case table.
+    pub fn find_entry(&mut self, dir: u32, name: &str) -> Result<Option<DirEntryInfo>, &'static str> {
+        let wanted = self.upcase_name(name);
+        let entries = self.read_directory(dir)?;
+        Ok(entries.into_iter().find(|entry| self.upcase_name(&entry.name) == wanted))
+    }
+
+    pub fn create_entry(&mut self, dir: u32, name: &str, is_dir: bool) -> Result<DirEntryInfo, &'static str> {
+        self.check_writable()?;
+        let trimmed = name.trim();
+        if trimmed.is_empty() || trimmed == "." || trimmed == ".." {
+            return Err("Invalid file name.");
+        }
+        let units: Vec<u16> = trimmed.encode_utf16().collect();
+        if units.len() > 255 {
+            return Err("File name is too long.");
+        }
+        if trimmed.chars().any(|c| c.is_control() || INVALID_NAME_CHARS.contains(&c)) {
+            return Err("File name contains characters exFAT does not allow.");
+        }
+        if self.find_entry(dir, trimmed)?.is_some() {
+            return Err("File already exists.");
+        }
+
+        let stamp = exfat_now();
+        let name_entries = units.len().div_ceil(NAME_UNITS_PER_ENTRY);
+        let mut entry = DirEntryInfo {
+            name: trimmed.to_string(),
+            is_dir,
+            attr: if is_dir { ATTR_DIRECTORY } else { ATTR_ARCHIVE },
+            cluster: 0,
+            size: 0,
+            valid_size: 0,
+            contiguous: false,
+            entry_index: 0,
+            secondary_count: (1 + name_entries) as u8,
+            created: exfat_timestamp(stamp, 0),
+            modified: exfat_timestamp(stamp, 0),
+            accessed: exfat_timestamp(stamp, 0),
+        };
+        if is_dir {
+            let (clusters, contiguous) = self.allocate(1)?;
+            entry.cluster = clusters[0];
+            entry.contiguous = contiguous;
+            entry.size = self.cluster_size() as u64;
+            entry.valid_size = entry.size;
+            self.zero_cluster(entry.cluster)?;
+        }
+
+        let mut set = vec![[0u8; DIR_ENTRY_SIZE]; 2 + name_entries];
+        set[0][0] = ENTRY_FILE;
+        set[0][1] = entry.secondary_count;
+        set[0][4..6].copy_from_slice(&entry.attr.to_le_bytes());
+        for at in [8, 12, 16] {
+            set[0][at..at + 4].copy_from_slice(&stamp.to_le_bytes());
+        }
+        set[1][0] = ENTRY_STREAM;
+        set[1][3] = units.len() as u8;
+        set[1][4..6].copy_from_slice(&self.name_hash(&units).to_le_bytes());
+        patch_stream(&mut set[1], &entry);
+        for (chunk, slot) in units.chunks(NAME_UNITS_PER_ENTRY).zip(set[2..].iter_mut()) {
+            slot[0] = ENTRY_NAME;
+            for (index, unit) in chunk.iter().enumerate() {
+                slot[2 + index * 2..4 + index * 2].copy_from_slice(&unit.to_le_bytes());
+            }
+        }
+        seal_entry_set(&mut set);
+
+        entry.entry_index = self.find_free_slots(dir, set.len() as u32)?;
+        self.write_slots(dir, entry.entry_index, &set)?;
+        if is_dir {
+            self.dirs.insert(entry.cluster, DirRecord { parent: dir, entry: entry.clone() });
+        }
+        self.flush()?;
+        Ok(entry)
+    }
+
+    /// Reads the file's contents; anything past its valid data length reads as zeros.
+    pub fn read_file(&mut self, entry: &DirEntryInfo) -> Result<Vec<u8>, &'static str> {
+        if entry.size == 0 || entry.cluster == 0 {
+            return Ok(Vec::new());
+        }
+        let size = usize::try_from(entry.size).map_err(|_| "File too large.")?;
+        let valid = cmp::min(entry.valid_size, entry.size) as usize;
+        let mut data = vec![0u8; size];
+        let mut offset = 0usize;
+        'clusters: for cluster in self.file_clusters(entry)? {
+            for sector_index in 0..self.sectors_per_cluster {
+                if offset >= valid {
+                    break 'clusters;
+                }
+                let mut sector = [0u8; SECTOR_SIZE];
+                read_sector(&self.device, self.cluster_to_lba(cluster) + sector_index, &mut sector)?;
+                let copy_len = cmp::min(SECTOR_SIZE, valid - offset);
+                data[offset..offset + copy_len].copy_from_slice(&sector[..copy_len]);
+                offset += copy_len;
+            }
+        }
+        if offset < valid {
+            return Err("File is longer than its clusters.");
+        }
+        Ok(data)
+    }
+
+    /// Replaces the file's contents. They go in one contiguous run (NoFatChain) when the
+    /// bitmap has one free, and through a FAT chain otherwise.
+    pub fn write_file(&mut self, dir: u32, entry: &DirEntryInfo, contents: &[u8]) -> Result<(), &'static str> {
+        self.check_writable()?;
+        let mut entry = entry.clone();
+        self.free_clusters(&entry)?;
+        entry.cluster = 0;
+        entry.contiguous = false;
+
+        if !contents.is_empty() {
+            let cluster_size = self.cluster_size() as usize;
+            let needed = contents.len().div_ceil(cluster_size);
+            let (clusters, contiguous) = self.allocate(u32::try_from(needed).map_err(|_| "File too large.")?)?;
+            entry.cluster = clusters[0];
+            entry.contiguous = contiguous;
+            for (cluster, chunk) in clusters.iter().zip(contents.chunks(cluster_size)) {
+                for (sector_index, part) in chunk.chunks(SECTOR_SIZE).enumerate() {
+                    let mut sector = [0u8; SECTOR_SIZE];
+                    sector[..part.len()].copy_from_slice(part);
+                    write_sector(&self.device, self.cluster_to_lba(*cluster) + sector_index as u32, &sector)?;
+                }
+            }
+        }
+
+        entry.size = contents.len() as u64;
+        entry.valid_size = entry.size;
+        let stamp = exfat_now();
+        self.update_set(dir, &entry, |set| {
+            patch_stream(&mut set[1], &entry);
+            let attr = u16::from_le_bytes([set[0][4], set[0][5]]) | ATTR_ARCHIVE;
+            set[0][4..6].copy_from_slice(&attr.to_le_bytes());
+            set[0][12..16].copy_from_slice(&stamp.to_le_bytes());
+            set[0][16..20].copy_from_slice(&stamp.to_le_bytes());
+            set[0][21] = 0;
+        })?;
+        self.flush()
+    }
+
+    pub fn delete_entry(&mut self, dir: u32, entry: &DirEntryInfo) -> Result<(), &'static str> {
+        self.check_writable()?;
+        if entry.is_dir && !self.read_directory(entry.cluster)?.is_empty() {
+            return Err("Directory not empty.");
+        }
+        self.free_clusters(entry)?;
+        let mut set = self.read_slots(dir, entry.entry_index, entry.secondary_count as u32 + 1)?;
+        for slot in set.iter_mut() {
+            slot[0] &= !ENTRY_IN_USE;
+        }
+        self.write_slots(dir, entry.entry_index, &set)?;
+        if entry.is_dir {
+            self.dirs.remove(&entry.cluster);
+        }
+        self.flush()
+    }
+
+    pub fn update_access_date(&mut self, dir: u32, entry: &DirEntryInfo) -> Result<(), &'static str> {
+        if self.read_only {
+            return Ok(());
+        }
+        let stamp = exfat_now();
+        self.update_set(dir, entry, |set| {
+            set[0][16..20].copy_from_slice(&stamp.to_le_bytes());
+        })
+    }
+
+    /// Rewrites the attributes of `entry`. The directory bit is kept.
+    pub fn set_attributes(&mut self, dir: u32, entry: &DirEntryInfo, attr: u16) -> Result<(), &'static str> {
+        self.check_writable()?;
+        self.update_set(dir, entry, |set| {
+            let kept = u16::from_le_bytes([set[0][4], set[0][5]]) & ATTR_DIRECTORY;
+            set[0][4..6].copy_from_slice(&(kept | (attr & !ATTR_DIRECTORY)).to_le_bytes());
+        })
+    }
+
+    /// True when the boot sector's VolumeDirty flag is set.
+    pub fn is_dirty(&mut self) -> Result<bool, &'static str> {
+        let mut boot = [0u8; SECTOR_SIZE];
+        read_sector(&self.device, self.part_start, &mut boot)?;
+        let flags = u16::from_le_bytes([boot[VOLUME_FLAGS_OFFSET], boot[VOLUME_FLAGS_OFFSET + 1]]);
+        Ok(flags & VOLUME_FLAG_DIRTY != 0)
+    }
+
+    /// Clears VolumeDirty and records PercentInUse on unmount; sets it while mounted.
+    pub fn set_clean(&mut self, clean: bool) -> Result<(), &'static str> {
+        if self.read_only {
+            return Ok(());
+        }
+        self.flush()?;
+        let mut boot = [0u8; SECTOR_SIZE];
+        read_sector(&self.device, self.part_start, &mut boot)?;
+        let flags = u16::from_le_bytes([boot[VOLUME_FLAGS_OFFSET], boot[VOLUME_FLAGS_OFFSET + 1]]);
+        let flags = if clean { flags & !VOLUME_FLAG_DIRTY } else { flags | VOLUME_FLAG_DIRTY };
+        boot[VOLUME_FLAGS_OFFSET..VOLUME_FLAGS_OFFSET + 2].copy_from_slice(&flags.to_le_bytes());
+        let used = (self.cluster_count - self.free_count) as u64;
+        boot[PERCENT_IN_USE_OFFSET] = (used * 100 / cmp::max(self.cluster_count, 1) as u64) as u8;
+        write_sector(&self.device, self.part_start, &boot)
+    }
+
+    /// Checks both boot regions, every entry set reachable from the root and the clusters
+    /// each one claims, then compares the allocation bitmap with what was found. With
+    /// `repair`, boot regions and set checksums are rewritten, chains cut at the first bad
+    /// link, sizes fitted to what is left and the bitmap rebuilt.
+    pub fn fsck(&mut self, repair: bool) -> Result<FsckReport, &'static str> {
+        if repair {
+            self.check_writable()?;
+        }
+        let mut report = FsckReport::new(repair);
+        self.flush()?;
+        // Repairs go through the cache, so it must not hold a stale sector.
+        self.fat_cache.lba = None;
+        self.fsck_boot_regions(repair, &mut report)?;
+
+        // A failed allocation would panic the kernel, so a volume whose cluster flags would
+        // crowd the heap is refused.
+        if self.cluster_count as usize > memory::heap_stats().free / 2 {
+            return Err("Volume is too large to check in the kernel heap.");
+        }
+        let mut check = ClusterCheck {
+            used: vec![false; self.cluster_count as usize],
+            repair,
+            report,
+        };
+        for &cluster in self.bitmap_clusters.iter().chain(&self.upcase_clusters) {
+            check.used[(cluster - 2) as usize] = true;
+        }
+        let root = self.cluster_chain(self.root_cluster)?;
+        for &cluster in &root {
+            if check.used[(cluster - 2) as usize] {
+                check.report.problems.push(format!("\\: cluster {} is cross-linked", cluster));
+            }
+            check.used[(cluster - 2) as usize] = true;
+        }
+
+        let cluster_size = self.cluster_size() as u64;
+        let mut pending = vec![(self.root_cluster, root, String::from("\\"))];
+        while let Some((dir, clusters, path)) = pending.pop() {
+            check.report.dirs += 1;
+            let data = self.read_clusters(&clusters)?;
+            for (mut entry, checksum_ok) in parse_entry_sets(&data) {
+                let child = format!("{}{}{}", path, if path.ends_with('\\') { "" } else { "\\" }, entry.name);
+                let mut changed = !checksum_ok;
+                if !checksum_ok {
+                    check.report.problems.push(format!("{}: entry set checksum is wrong", child));
+                }
+                if entry.valid_size > entry.size {
+                    check.report.problems.push(format!("{}: valid data length is past the end", child));
+                    entry.valid_size = entry.size;
+                    changed = true;
+                }
+                if entry.size == 0 && entry.cluster != 0 {
+                    check.report.problems.push(format!("{}: empty file owns cluster {}", child, entry.cluster));
+                    entry.cluster = 0;
+                    entry.contiguous = false;
+                    changed = true;
+                }
+
+                let claimed = self.fsck_claim(&mut check, &entry, &child)?;
+                let needed = entry.size.div_ceil(cluster_size);
+                if (claimed.len() as u64) < needed {
+                    check.report.problems.push(format!(
+                        "{}: size {} does not fit its {} cluster(s)",
+                        child,
+                        entry.size,
+                        claimed.len()
+                    ));
+                    entry.size = claimed.len() as u64 * cluster_size;
+                    entry.valid_size = cmp::min(entry.valid_size, entry.size);
+                    if claimed.is_empty() {
+                        entry.cluster = 0;
+                        entry.contiguous = false;
+                    }
+                    changed = true;
+                }
+
+                if entry.is_dir && claimed.is_empty() {
+                    check.report.problems.push(format!("{}: directory has no clusters", child));
+                    if repair {
+                        let mut set = self.read_slots(dir, entry.entry_index, entry.secondary_count as u32 + 1)?;
+                        for slot in set.iter_mut() {
+                            slot[0] &= !ENTRY_IN_USE;
+                        }
+                        self.write_slots(dir, entry.entry_index, &set)?;
+                    }
+                    continue;
+                }
+                if repair && changed {
+                    self.update_set(dir, &entry, |set| patch_stream(&mut set[1], &entry))?;
+                }
+                if entry.is_dir {
+                    self.dirs.insert(entry.cluster, DirRecord { parent: dir, entry: entry.clone() });
+                    pending.push((entry.cluster, claimed, child));
+                } else {
+                    check.report.files += 1;
+                }
+            }
+        }
+
+        let ClusterCheck { used, mut report, .. } = check;
+        let (mut lost, mut unmarked) = (0u32, 0u32);
+        for cluster in 2..self.cluster_count + 2 {
+            let owned = used[(cluster - 2) as usize];
+            let marked = self.is_allocated(cluster);
+            if marked && !owned {
+                lost += 1;
+            } else if owned && !marked {
+                unmarked += 1;
+            }
+            if repair && owned != marked {
+                self.set_bitmap(cluster, owned);
+            }
+        }
+        if lost > 0 {
+            report.problems.push(format!("{} lost cluster(s) not owned by any file", lost));
+        }
+        if unmarked > 0 {
+            report.problems.push(format!("{} cluster(s) in use but free in the bitmap", unmarked));
+        }
+        if repair {
+            self.flush()?;
+        }
+        Ok(report)
+    }
+
+    fn check_writable(&self) -> Result<(), &'static str> {
+        if self.read_only {
+            return Err("Volume is mounted read-only.");
+        }
+        Ok(())
+    }
+
+    // Finds the allocation bitmap, up-case table and label in the root directory.
+    fn load_metadata(&mut self) -> Result<(), &'static str> {
+        let clusters = self.cluster_chain(self.root_cluster)?;
+        let data = self.read_clusters(&clusters)?;
+        let mut bitmap = None;
+        let mut upcase = None;
+        for slot in data.chunks_exact(DIR_ENTRY_SIZE) {
+            let word = |at: usize| u32::from_le_bytes([slot[at], slot[at + 1], slot[at + 2], slot[at + 3]]);
+            let long = |at: usize| word(at) as u64 | (word(at + 4) as u64) << 32;
+            match slot[0] {
+                ENTRY_END => break,
+                // TexFAT keeps a second bitmap; the first goes with the first FAT.
+                ENTRY_BITMAP if slot[1] & 1 == 0 && bitmap.is_none() => bitmap = Some((word(20), long(24))),
+                ENTRY_UPCASE if upcase.is_none() => upcase = Some((word(4), word(20), long(24))),
+                ENTRY_LABEL => {
+                    let count = cmp::min(slot[1] as usize, MAX_LABEL_UNITS);
+                    for index in 0..count {
+                        self.volume_label[index] = u16::from_le_bytes([slot[2 + index * 2], slot[3 + index * 2]]);
+                    }
+                    self.label_len = count as u8;
+                }
+                _ => {}
+            }
+        }
+
+        let (first, length) = bitmap.ok_or("exFAT allocation bitmap is missing.")?;
+        let bitmap_bytes = (self.cluster_count as usize).div_ceil(8);
+        if length < bitmap_bytes as u64 {
+            return Err("exFAT allocation bitmap is too small.");
+        }
+        self.bitmap_clusters = self.cluster_chain(first)?;
+        self.bitmap = self.read_clusters(&self.bitmap_clusters)?;
+        if self.bitmap.len() < bitmap_bytes {
+            return Err("exFAT allocation bitmap is too small.");
+        }
+        self.bitmap.truncate(bitmap_bytes);
+        self.free_count = (2..self.cluster_count + 2)
+            .filter(|&cluster| !self.is_allocated(cluster))
+            .count() as u32;
+
+        let (checksum, first, length) = upcase.ok_or("exFAT up-case table is missing.")?;
+        self.upcase_clusters = self.cluster_chain(first)?;
+        let mut table = self.read_clusters(&self.upcase_clusters)?;
+        if (table.len() as u64) < length {
+            return Err("exFAT up-case table is truncated.");
+        }
+        table.truncate(length as usize);
+        if table_checksum(&table) != checksum {
+            return Err("exFAT up-case table checksum mismatch.");
+        }
+        self.upcase = expand_upcase(&table);
+        Ok(())
+    }
+
+    fn upcase_name(&self, name: &str) -> Vec<u16> {
+        name.encode_utf16()
+            .map(|unit| self.upcase.get(unit as usize).copied().unwrap_or(unit))
+            .collect()
+    }
+
+    // The stream extension's NameHash: a rotating sum over the up-cased name's bytes.
+    fn name_hash(&self, units: &[u16]) -> u16 {
+        let mut hash = 0u16;
+        for &unit in units {
+            let upper = self.upcase.get(unit as usize).copied().unwrap_or(unit);
+            for byte in upper.to_le_bytes() {
+                hash = hash.rotate_right(1).wrapping_add(byte as u16);
+            }
+        }
+        hash
+    }
+
+    fn cluster_to_lba(&self, cluster: u32) -> u32 {
+        self.part_start + self.cluster_heap_offset + (cluster - 2) * self.sectors_per_cluster
+    }
+
+    fn read_clusters(&self, clusters: &[u32]) -> Result<Vec<u8>, &'static str> {
+        let mut data = Vec::with_capacity(clusters.len() * self.cluster_size() as usize);
+        let mut sector = [0u8; SECTOR_SIZE];
+        for &cluster in clusters {
+            for sector_index in 0..self.sectors_per_cluster {
+                read_sector(&self.device, self.cluster_to_lba(cluster) + sector_index, &mut sector)?;
+                data.extend_from_slice(&sector);
+            }
+        }
+        Ok(data)
+    }
+
+    fn zero_cluster(&mut self, cluster: u32) -> Result<(), &'static str> {
+        let zero = [0u8; SECTOR_SIZE];
+        for sector_index in 0..self.sectors_per_cluster {
+            write_sector(&self.device, self.cluster_to_lba(cluster) + sector_index, &zero)?;
+        }
+        Ok(())
+    }
+
+    // The clusters of a directory: the root and unknown directories follow the FAT.
+    fn dir_clusters(&mut self, dir: u32) -> Result<Vec<u32>, &'static str> {
+        match self.dirs.get(&dir) {
+            Some(record) if record.entry.contiguous => self.extent(record.entry.cluster, record.entry.size),
+            _ => self.cluster_chain(dir),
+        }
+    }
+
+    fn file_clusters(&mut self, entry: &DirEntryInfo) -> Result<Vec<u32>, &'static str> {
+        if entry.cluster == 0 {
+            Ok(Vec::new())
+        } else if entry.contiguous {
+            self.extent(entry.cluster, entry.size)
+        } else {
+            self.cluster_chain(entry.cluster)
+        }
+    }
+
+    // The run of clusters holding `size` bytes from `first`, for NoFatChain files.
+    fn extent(&self, first: u32, size: u64) -> Result<Vec<u32>, &'static str> {
+        let count = size.div_ceil(self.cluster_size() as u64);
+        if first < 2 || first as u64 + count > self.cluster_count as u64 + 2 {
+            return Err("Invalid cluster extent.");
+        }
+        Ok((first..first + count as u32).collect())
+    }
+
+    // Lists the clusters of a chain, refusing ones that leave the volume or loop.
+    fn cluster_chain(&mut self, start: u32) -> Result<Vec<u32>, &'static str> {
+        let limit = self.cluster_count + 2;
+        let mut chain = Vec::new();
+        let mut current = start;
+        loop {
+            if current < 2 || current >= limit || chain.len() >= limit as usize {
+                return Err("Invalid cluster chain.");
+            }
+            let next = self.read_fat_entry(current)?;
+            if next == FAT_BAD {
+                return Err("Bad cluster encountered.");
+            }
+            chain.push(current);
+            if is_eoc(next) {
+                return Ok(chain);
+            }
+            current = next;
+        }
+    }
+
+    fn read_fat_entry(&mut self, cluster: u32) -> Result<u32, &'static str> {
+        let offset = self.load_fat_sector(cluster)?;
+        let buf = &self.fat_cache.buf;
+        Ok(u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]]))
+    }
+
+    fn write_fat_entry(&mut self, cluster: u32, value: u32) -> Result<(), &'static str> {
+        let offset = self.load_fat_sector(cluster)?;
+        self.fat_cache.buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        self.fat_cache.dirty = true;
+        Ok(())
+    }
+
+    // Brings the FAT sector holding `cluster` into the cache and returns its offset there.
+    fn load_fat_sector(&mut self, cluster: u32) -> Result<usize, &'static str> {
+        let fat_offset = cluster as usize * 4;
+        let lba = self.fat_start + (fat_offset / SECTOR_SIZE) as u32;
+        if self.fat_cache.lba != Some(lba) {
+            self.flush_fat_cache()?;
+            read_sector(&self.device, lba, &mut self.fat_cache.buf)?;
+            self.fat_cache.lba = Some(lba);
+        }
+        Ok(fat_offset % SECTOR_SIZE)
+    }
+
+    fn flush_fat_cache(&mut self) -> Result<(), &'static str> {
+        if !self.fat_cache.dirty {
+            return Ok(());
+        }
+        let Some(lba) = self.fat_cache.lba else {
+            return Ok(());
+        };
+        write_sector(&self.device, lba, &self.fat_cache.buf)?;
+        self.fat_cache.dirty = false;
+        Ok(())
+    }
+
+    fn is_allocated(&self, cluster: u32) -> bool {
+        let index = (cluster - 2) as usize;
+        self.bitmap.get(index / 8).is_some_and(|byte| byte & (1 << (index % 8)) != 0)
+    }
+
+    fn is_free(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster < self.cluster_count + 2 && !self.is_allocated(cluster)
+    }
+
+    fn set_bitmap(&mut self, cluster: u32, used: bool) {
+        if self.is_allocated(cluster) == used {
+            return;
+        }
+        let index = (cluster - 2) as usize;
+        self.bitmap[index / 8] ^= 1 << (index % 8);
+        if used {
+            self.free_count -= 1;
+        } else {
+            self.free_count += 1;
+        }
+        self.bitmap_dirty.insert((index / 8 / SECTOR_SIZE) as u32);
+    }
+
+    fn flush_bitmap(&mut self) -> Result<(), &'static str> {
+        for sector_index in core::mem::take(&mut self.bitmap_dirty) {
+            let Some(&cluster) = self.bitmap_clusters.get((sector_index / self.sectors_per_cluster) as usize) else {
+                return Err("Allocation bitmap is shorter than the volume.");
+            };
+            let start = sector_index as usize * SECTOR_SIZE;
+            let end = cmp::min(start + SECTOR_SIZE, self.bitmap.len());
+            let mut sector = [0u8; SECTOR_SIZE];
+            sector[..end - start].copy_from_slice(&self.bitmap[start..end]);
+            let lba = self.cluster_to_lba(cluster) + sector_index % self.sectors_per_cluster;
+            write_sector(&self.device, lba, &sector)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), &'static str> {
+        self.flush_fat_cache()?;
+        self.flush_bitmap()
+    }
+
+    // Claims `count` clusters in the bitmap. A free run is preferred and returned as
+    // contiguous, with the FAT left alone; otherwise scattered clusters are chained.
+    fn allocate(&mut self, count: u32) -> Result<(Vec<u32>, bool), &'static str> {
+        if count == 0 || count > self.free_count {
+            return Err("Disk full.");
+        }
+        if let Some(start) = self.find_free_run(count) {
+            for cluster in start..start + count {
+                self.set_bitmap(cluster, true);
+            }
+            self.next_free = start + count;
+            return Ok(((start..start + count).collect(), true));
+        }
+
+        let limit = self.cluster_count + 2;
+        let mut chain = Vec::new();
+        let mut cluster = self.next_free;
+        while chain.len() < count as usize {
+            if cluster >= limit {
+                cluster = 2;
+            }
+            if self.is_free(cluster) {
+                self.set_bitmap(cluster, true);
+                chain.push(cluster);
+            }
+            cluster += 1;
+        }
+        for pair in chain.windows(2) {
+            self.write_fat_entry(pair[0], pair[1])?;
+        }
+        if let Some(&last) = chain.last() {
+            self.write_fat_entry(last, FAT_EOC)?;
+        }
+        self.next_free = cluster;
+        Ok((chain, false))
+    }
+
+    // The first run of `count` free clusters, searching on from the last allocation.
+    fn find_free_run(&self, count: u32) -> Option<u32> {
+        let limit = self.cluster_count + 2;
+        let hint = self.next_free.clamp(2, limit);
+        [(hint, limit), (2, hint)].into_iter().find_map(|(from, to)| {
+            let mut run = 0u32;
+            for cluster in from..to {
+                if self.is_free(cluster) {
+                    run += 1;
+                    if run == count {
+                        return Some(cluster + 1 - count);
+                    }
+                } else {
+                    run = 0;
+                }
+            }
+            None
+        })
+    }
+
+    fn free_clusters(&mut self, entry: &DirEntryInfo) -> Result<(), &'static str> {
+        for cluster in self.file_clusters(entry)? {
+            self.set_bitmap(cluster, false);
+            if !entry.contiguous {
+                self.write_fat_entry(cluster, 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Grows a directory by one zeroed cluster. A NoFatChain directory stays a run when
+    // the next cluster is free and becomes a FAT chain when it is not.
+    fn extend_dir(&mut self, dir: u32) -> Result<(), &'static str> {
+        let clusters = self.dir_clusters(dir)?;
+        let last = *clusters.last().ok_or("Invalid directory.")?;
+        let record = self.dirs.get(&dir).map(|record| (record.parent, record.entry.clone()));
+        let contiguous = record.as_ref().is_some_and(|(_, entry)| entry.contiguous);
+        let in_place = contiguous && self.is_free(last + 1);
+        let next = if in_place {
+            self.set_bitmap(last + 1, true);
+            last + 1
+        } else {
+            if contiguous {
+                for pair in clusters.windows(2) {
+                    self.write_fat_entry(pair[0], pair[1])?;
+                }
+            }
+            let (new, _) = self.allocate(1)?;
+            self.write_fat_entry(last, new[0])?;
+            self.write_fat_entry(new[0], FAT_EOC)?;
+            new[0]
+        };
+        self.zero_cluster(next)?;
+
+        // The root has no entry set; its FAT chain is all there is to its size.
+        if let Some((parent, mut entry)) = record {
+            entry.contiguous = in_place;
+            entry.size += self.cluster_size() as u64;
+            entry.valid_size = entry.size;
+            self.update_set(parent, &entry, |set| patch_stream(&mut set[1], &entry))?;
+            self.dirs.insert(dir, DirRecord { parent, entry });
+        }
+        Ok(())
+    }
+
+    // Finds `needed` consecutive unused slots in a directory, growing it when it is full.
+    fn find_free_slots(&mut self, dir: u32, needed: u32) -> Result<u32, &'static str> {
+        let clusters = self.dir_clusters(dir)?;
+        let data = self.read_clusters(&clusters)?;
+        let mut run_start = 0u32;
+        let mut run_len = 0u32;
+        for (index, slot) in data.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+            if slot[0] & ENTRY_IN_USE == 0 {
+                if run_len == 0 {
+                    run_start = index as u32;
+                }
+                run_len += 1;
+                if run_len >= needed {
+                    return Ok(run_start);
+                }
+            } else {
+                run_len = 0;
+            }
+        }
+
+        let slots_per_cluster = ENTRIES_PER_SECTOR * self.sectors_per_cluster;
+        let start = if run_len > 0 { run_start } else { (data.len() / DIR_ENTRY_SIZE) as u32 };
+        while run_len < needed {
+            self.extend_dir(dir)?;
+            run_len += slots_per_cluster;
+        }
+        Ok(start)
+    }
+
+    fn slot_location(&self, clusters: &[u32], index: u32) -> Result<(u32, usize), &'static str> {
+        let sector = index / ENTRIES_PER_SECTOR;
+        let cluster = clusters
+            .get((sector / self.sectors_per_cluster) as usize)
+            .ok_or("Directory entry out of range.")?;
+        let lba = self.cluster_to_lba(*cluster) + sector % self.sectors_per_cluster;
+        Ok((lba, (index % ENTRIES_PER_SECTOR) as usize * DIR_ENTRY_SIZE))
+    }
+
+    fn read_slots(&mut self, dir: u32, start: u32, count: u32) -> Result<Vec<[u8; DIR_ENTRY_SIZE]>, &'static str> {
+        let clusters = self.dir_clusters(dir)?;
+        let mut slots = Vec::with_capacity(count as usize);
+        let mut sector = [0u8; SECTOR_SIZE];
+        for index in start..start + count {
+            let (lba, offset) = self.slot_location(&clusters, index)?;
+            read_sector(&self.device, lba, &mut sector)?;
+            let mut slot = [0u8; DIR_ENTRY_SIZE];
+            slot.copy_from_slice(&sector[offset..offset + DIR_ENTRY_SIZE]);
+            slots.push(slot);
+        }
+        Ok(slots)
+    }
+
+    fn write_slots(&mut self, dir: u32, start: u32, slots: &[[u8; DIR_ENTRY_SIZE]]) -> Result<(), &'static str> {
+        let clusters = self.dir_clusters(dir)?;
+        let mut sector = [0u8; SECTOR_SIZE];
+        for (index, slot) in (start..).zip(slots) {
+            let (lba, offset) = self.slot_location(&clusters, index)?;
+            read_sector(&self.device, lba, &mut sector)?;
+            sector[offset..offset + DIR_ENTRY_SIZE].copy_from_slice(slot);
+            write_sector(&self.device, lba, &sector)?;
+        }
+        Ok(())
+    }
+
+    // Applies `change` to the entry set of `entry` and rewrites it with a fresh checksum.
+    fn update_set(
+        &mut self,
+        dir: u32,
+        entry: &DirEntryInfo,
+        change: impl FnOnce(&mut [[u8; DIR_ENTRY_SIZE]]),
+    ) -> Result<(), &'static str> {
+        let mut set = self.read_slots(dir, entry.entry_index, entry.secondary_count as u32 + 1)?;
+        if set[0][0] != ENTRY_FILE || set[1][0] != ENTRY_STREAM {
+            return Err("Directory entry has moved.");
+        }
+        change(&mut set);
+        seal_entry_set(&mut set);
+        self.write_slots(dir, entry.entry_index, &set)
+    }
+
+    // Follows the clusters of `entry`, claiming them. It stops at a cluster that is out of
+    // range or already claimed, or where a FAT chain ends before the size does; when
+    // repairing, the chain is ended at the last good cluster.
+    fn fsck_claim(&mut self, check: &mut ClusterCheck, entry: &DirEntryInfo, path: &str) -> Result<Vec<u32>, &'static str> {
+        let needed = entry.size.div_ceil(self.cluster_size() as u64) as usize;
+        let mut clusters: Vec<u32> = Vec::new();
+        if entry.cluster == 0 || needed == 0 {
+            return Ok(clusters);
+        }
+        let limit = self.cluster_count + 2;
+        let mut current = entry.cluster;
+        let problem = loop {
+            if current < 2 || current >= limit {
+                break Some(format!("{}: cluster {} is out of range", path, current));
+            }
+            if check.used[(current - 2) as usize] {
+                break Some(format!("{}: cluster {} is cross-linked or loops", path, current));
+            }
+            check.used[(current - 2) as usize] = true;
+            clusters.push(current);
+            if clusters.len() == needed {
+                let longer = !entry.contiguous && !is_eoc(self.read_fat_entry(current)?);
+                break longer.then(|| format!("{}: cluster chain is longer than the file", path));
+            }
+            if entry.contiguous {
+                current += 1;
+                continue;
+            }
+            let next = self.read_fat_entry(current)?;
+            if is_eoc(next) || next < 2 || next == FAT_BAD {
+                break Some(format!("{}: cluster chain ends early at cluster {}", path, current));
+            }
+            current = next;
+        };
+
+        if let Some(problem) = problem {
+            check.report.problems.push(problem);
+            if let (true, false, Some(&last)) = (check.repair, entry.contiguous, clusters.last()) {
+                self.write_fat_entry(last, FAT_EOC)?;
+            }
+        }
+        Ok(clusters)
+    }
+
+    // Checks the main and backup boot regions against their checksums. A bad main region
+    // is restored from a good backup, and a bad backup from the main region.
+    fn fsck_boot_regions(&mut self, repair: bool, report: &mut FsckReport) -> Result<(), &'static str> {
+        let main_lba = self.part_start;
+        let backup_lba = self.part_start + BOOT_REGION_SECTORS;
+        let main = read_boot_region(&self.device, main_lba)?;
+        let backup = read_boot_region(&self.device, backup_lba)?;
+        let main_ok = boot_region_valid(&main);
+        let backup_ok = boot_region_valid(&backup);
+        if !main_ok {
+            report.problems.push(String::from("Main boot region checksum is wrong"));
+        }
+        if !backup_ok {
+            report.problems.push(String::from("Backup boot region checksum is wrong"));
+        }
+        if !repair {
+            return Ok(());
+        }
+        let mut good = if main_ok || !backup_ok { main } else { backup };
+        if !main_ok {
+            write_boot_region(&self.device, main_lba, &mut good)?;
+        }
+        if !backup_ok {
+            write_boot_region(&self.device, backup_lba, &mut good)?;
+        }
+        Ok(())
+    }
+}
+
+// State carried through one `fsck` pass: the clusters claimed so far, by cluster - 2.
+struct ClusterCheck {
+    used: Vec<bool>,
+    repair: bool,
+    report: FsckReport,
+}
+
+fn read_sector<D: BlockDevice>(dev: &D, lba: u32, buf: &mut [u8; SECTOR_SIZE]) -> Result<(), &'static str> {
+    dev.read_block(lba as u64, buf).map_err(|err| err.as_str())
+}
+
+fn write_sector<D: BlockDevice>(dev: &D, lba: u32, buf: &[u8; SECTOR_SIZE]) -> Result<(), &'static str> {
+    dev.write_block(lba as u64, buf).map_err(|err| err.as_str())
+}
+
+// Writes `data` from `lba` on, zero-filling the rest of `sectors` sectors.
+fn write_run<D: BlockDevice>(dev: &D, lba: u32, data: &[u8], sectors: u32) -> Result<(), &'static str> {
+    for index in 0..sectors {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let start = cmp::min(index as usize * SECTOR_SIZE, data.len());
+        let end = cmp::min(start + SECTOR_SIZE, data.len());
+        sector[..end - start].copy_from_slice(&data[start..end]);
+        write_sector(dev, lba + index, &sector)?;
+    }
+    Ok(())
+}
+
+fn is_eoc(value: u32) -> bool {
+    value >= FAT_MEDIA
+}
+
+fn read_boot_region<D: BlockDevice>(dev: &D, lba: u32) -> Result<Vec<[u8; SECTOR_SIZE]>, &'static str> {
+    let mut region = vec![[0u8; SECTOR_SIZE]; BOOT_REGION_SECTORS as usize];
+    for (index, sector) in region.iter_mut().enumerate() {
+        read_sector(dev, lba + index as u32, sector)?;
+    }
+    Ok(region)
+}
+
+// Fills in the checksum sector of a boot region and writes all twelve sectors.
+fn write_boot_region<D: BlockDevice>(dev: &D, lba: u32, region: &mut [[u8; SECTOR_SIZE]]) -> Result<(), &'static str> {
+    let checksum = boot_checksum(&region[..BOOT_CHECKSUM_SECTOR]);
+    for word in region[BOOT_CHECKSUM_SECTOR].chunks_exact_mut(4) {
+        word.copy_from_slice(&checksum.to_le_bytes());
+    }
+    for (index, sector) in region.iter().enumerate() {
+        write_sector(dev, lba + index as u32, sector)?;
+    }
+    Ok(())
+}
+
+fn boot_region_valid(region: &[[u8; SECTOR_SIZE]]) -> bool {
+    let checksum = boot_checksum(&region[..BOOT_CHECKSUM_SECTOR]).to_le_bytes();
+    &region[0][3..11] == EXFAT_SIGNATURE && region[BOOT_CHECKSUM_SECTOR].chunks_exact(4).all(|word| word == checksum)
+}
+
+// Rotating sum over the first eleven boot sectors, skipping the fields that change in place.
+fn boot_checksum(sectors: &[[u8; SECTOR_SIZE]]) -> u32 {
+    let mut sum = 0u32;
+    for (index, sector) in sectors.iter().enumerate() {
+        for (offset, &byte) in sector.iter().enumerate() {
+            if index == 0 && matches!(offset, 106 | 107 | PERCENT_IN_USE_OFFSET) {
+                continue;
+            }
+            sum = sum.rotate_right(1).wrapping_add(byte as u32);
+        }
+    }
+    sum
+}
+
+fn table_checksum(data: &[u8]) -> u32 {
+    data.iter().fold(0u32, |sum, &byte| sum.rotate_right(1).wrapping_add(byte as u32))
+}
+
+// SetChecksum covers the whole set but its own two bytes.
+fn entry_set_checksum(set: &[[u8; DIR_ENTRY_SIZE]]) -> u16 {
+    let mut sum = 0u16;
+    for (index, &byte) in set.iter().flatten().enumerate() {
+        if index == 2 || index == 3 {
+            continue;
+        }
+        sum = sum.rotate_right(1).wrapping_add(byte as u16);
+    }
+    sum
+}
+
+fn seal_entry_set(set: &mut [[u8; DIR_ENTRY_SIZE]]) {
+    let checksum = entry_set_checksum(set);
+    set[0][2..4].copy_from_slice(&checksum.to_le_bytes());
+}
+
+// Writes where the data of `entry` is into its stream extension entry.
+fn patch_stream(stream: &mut [u8; DIR_ENTRY_SIZE], entry: &DirEntryInfo) {
+    stream[1] = ALLOCATION_POSSIBLE | if entry.contiguous { NO_FAT_CHAIN } else { 0 };
+    stream[8..16].copy_from_slice(&entry.valid_size.to_le_bytes());
+    stream[20..24].copy_from_slice(&entry.cluster.to_le_bytes());
+    stream[24..32].copy_from_slice(&entry.size.to_le_bytes());
+}
+
+// Decodes the file entry sets in a directory's data, in order, each with whether its
+// checksum holds. Malformed sets are skipped.
+fn parse_entry_sets(data: &[u8]) -> Vec<(DirEntryInfo, bool)> {
+    let slots: Vec<[u8; DIR_ENTRY_SIZE]> = data
+        .chunks_exact(DIR_ENTRY_SIZE)
+        .map(|slot| {
+            let mut bytes = [0u8; DIR_ENTRY_SIZE];
+            bytes.copy_from_slice(slot);
+            bytes
+        })
+        .collect();
+    let mut entries = Vec::new();
+    let mut index = 0usize;
+    while index < slots.len() {
+        match slots[index][0] {
+            ENTRY_END => break,
+            ENTRY_FILE => {
+                let secondary = slots[index][1] as usize;
+                let Some(set) = slots.get(index..index + 1 + secondary) else {
+                    break;
+                };
+                if let Some(entry) = decode_entry_set(set, index as u32) {
+                    let stored = u16::from_le_bytes([set[0][2], set[0][3]]);
+                    entries.push((entry, stored == entry_set_checksum(set)));
+                    index += 1 + secondary;
+                } else {
+                    index += 1;
+                }
+            }
+            _ => index += 1,
+        }
+    }
+    entries
+}
+
+fn decode_entry_set(set: &[[u8; DIR_ENTRY_SIZE]], entry_index: u32) -> Option<DirEntryInfo> {
+    let (file, stream) = (set.first()?, set.get(1)?);
+    if stream[0] != ENTRY_STREAM {
+        return None;
+    }
+    let name_len = stream[3] as usize;
+    let mut units = Vec::with_capacity(name_len);
+    for slot in set[2..].iter().take_while(|slot| slot[0] == ENTRY_NAME) {
+        for pair in slot[2..].chunks_exact(2) {
+            if units.len() < name_len {
+                units.push(u16::from_le_bytes([pair[0], pair[1]]));
+            }
+        }
+    }
+    if units.len() < name_len || name_len == 0 {
+        return None;
+    }
+
+    let word = |slot: &[u8; DIR_ENTRY_SIZE], at: usize| u32::from_le_bytes([slot[at], slot[at + 1], slot[at + 2], slot[at + 3]]);
+    let long = |slot: &[u8; DIR_ENTRY_SIZE], at: usize| word(slot, at) as u64 | (word(slot, at + 4) as u64) << 32;
+    let attr = u16::from_le_bytes([file[4], file[5]]);
+    Some(DirEntryInfo {
+        name: decode_utf16(&units),
+        is_dir: attr & ATTR_DIRECTORY != 0,
+        attr,
+        cluster: word(stream, 20),
+        size: long(stream, 24),
+        valid_size: long(stream, 8),
+        contiguous: stream[1] & NO_FAT_CHAIN != 0,
+        entry_index,
+        secondary_count: file[1],
+        created: exfat_timestamp(word(file, 8), file[20]),
+        modified: exfat_timestamp(word(file, 12), file[21]),
+        accessed: exfat_timestamp(word(file, 16), 0),
+    })
+}
+
+fn decode_utf16(units: &[u16]) -> String {
+    core::char::decode_utf16(units.iter().copied())
+        .map(|ch| ch.unwrap_or('\u{FFFD}'))
+        .collect()
+}
+
+// exFAT timestamps are a FAT date and time packed into one word, plus a count of 10 ms
+// units past the even second. The UTC offset byte is left unset, as FAT32 has none.
+fn exfat_now() -> u32 {
+    let (date, time, _tenth) = fat32::fat_date_time();
+    (date as u32) << 16 | time as u32
+}
+
+fn exfat_timestamp(stamp: u32, ten_ms: u8) -> Option<u64> {
+    fat32::fat_timestamp((stamp >> 16) as u16, stamp as u16).map(|secs| secs + ten_ms as u64 / 100)
+}
+
+// The up-case table `format` writes: each character of the BMP mapped to its single
+// upper-case form, where Unicode gives one.
+fn default_upcase() -> Vec<u16> {
+    (0..=u16::MAX)
+        .map(|unit| {
+            let Some(ch) = char::from_u32(unit as u32) else {
+                return unit;
+            };
+            let mut upper = ch.to_uppercase();
+            match (upper.next(), upper.next()) {
+                (Some(single), None) if (single as u32) <= u16::MAX as u32 => single as u16,
+                _ => unit,
+            }
+        })
+        .collect()
+}
+
+// Stores runs of characters that map to themselves as 0xFFFF and the run length, as the
+// spec allows.
+fn compress_upcase(table: &[u16]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut index = 0usize;
+    while index < table.len() {
+        let run = table[index..]
+            .iter()
+            .enumerate()
+            .take_while(|&(offset, &upper)| upper as usize == index + offset && offset < u16::MAX as usize)
+            .count();
+        if run > 2 || (run > 0 && table[index] == 0xFFFF) {
+            out.extend_from_slice(&0xFFFFu16.to_le_bytes());
+            out.extend_from_slice(&(run as u16).to_le_bytes());
+            index += run;
+        } else {
+            out.extend_from_slice(&table[index].to_le_bytes());
+            index += 1;
+        }
+    }
+    out
+}
+
+// Expands a stored up-case table, compressed or not, to one entry per BMP character.
+fn expand_upcase(data: &[u8]) -> Vec<u16> {
+    let mut table: Vec<u16> = Vec::with_capacity(0x10000);
+    let mut units = data.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]]));
+    while table.len() < 0x10000 {
+        let Some(unit) = units.next() else {
+            break;
+        };
+        if unit == 0xFFFF {
+            let Some(run) = units.next() else {
+                break;
+            };
+            for _ in 0..run {
+                if table.len() >= 0x10000 {
+                    break;
+                }
+                table.push(table.len() as u16);
+            }
+        } else {
+            table.push(unit);
+        }
+    }
+    // Characters past the end of the table map to themselves.
+    while table.len() < 0x10000 {
+        table.push(table.len() as u16);
+    }
+    table
+}
+
+// Cluster sizes as Windows picks them: 4 KiB up to 256 MiB, 32 KiB up to 32 GiB, then 128 KiB.
+fn choose_cluster_shift(total_sectors: u32) -> u8 {
+    let bytes = total_sectors as u64 * SECTOR_SIZE as u64;
+    if bytes <= 256 * 1024 * 1024 {
+        3
+    } else if bytes <= 32 * 1024 * 1024 * 1024 {
+        6
+    } else {
+        8
+    }
+}
+
+fn write_mbr<D: BlockDevice>(dev: &D, part_start: u32, part_sectors: u32) -> Result<(), &'static str> {
+    let mut sector = [0u8; SECTOR_SIZE];
+    let signature = time::current_time_secs().unwrap_or(0) as u32;
+    sector[440..444].copy_from_slice(&signature.to_le_bytes());
+    let base = 446;
+    sector[base + 4] = EXFAT_PART_TYPE;
+    sector[base + 8..base + 12].copy_from_slice(&part_start.to_le_bytes());
+    sector[base + 12..base + 16].copy_from_slice(&part_sectors.to_le_bytes());
+    sector[510] = 0x55;
+    sector[511] = 0xAA;
+    write_sector(dev, 0, &sector)
+}
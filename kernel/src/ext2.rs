@@ -1,23 +1,144 @@
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::{format, string::String, string::ToString, vec, vec::Vec};
 use core::cmp;
 
 use crate::block::{BlockDevice, BlockDeviceError};
 use crate::console;
-use crate::fat32::{MbrInfo, PartitionInfo};
+use crate::memory;
+use crate::fat32::{FsckReport, MbrInfo, PartitionInfo};
+use crate::jbd2;
 use crate::time;
 
 const SECTOR_SIZE: usize = 512;
-const BLOCK_SIZE: usize = 4096;
-const BLOCK_SECTORS: u32 = (BLOCK_SIZE / SECTOR_SIZE) as u32;
+// Volumes we format use 4 KiB blocks; existing ones may use 1, 2 or 4 KiB.
+const FORMAT_BLOCK_SIZE: usize = 4096;
+const MAX_LOG_BLOCK_SIZE: u32 = 2;
+const SUPERBLOCK_OFFSET: u32 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const SB_CHECKSUM_OFFSET: usize = 0x3FC;
+const EXT4_CRC32C_CHKSUM: u8 = 1;
+const GROUP_DESC_SIZE: usize = 32;
+const GROUP_DESC_SIZE_64BIT: usize = 64;
 const EXT2_SUPER_MAGIC: u16 = 0xEF53;
+const EXT2_GOOD_OLD_REV: u32 = 0;
 const EXT2_REV_DYNAMIC: u32 = 1;
 const EXT2_FIRST_INO: u32 = 11;
 const INODE_SIZE: u16 = 128;
 const ROOT_INODE: u32 = 2;
+const EXT3_JOURNAL_INO: u32 = 8;
+// s_jnl_backup_type: the superblock keeps a copy of the journal inode's block map.
+const EXT3_JNL_BACKUP_BLOCKS: u8 = 1;
+const EXT2_VALID_FS: u16 = 1;
+const EXT2_ERROR_FS: u16 = 2;
 pub const EXT2_PART_TYPE: u8 = 0x83;
 
 const EXT2_FT_REG_FILE: u8 = 1;
 const EXT2_FT_DIR: u8 = 2;
+const EXT2_FT_CHRDEV: u8 = 3;
+const EXT2_FT_BLKDEV: u8 = 4;
+const EXT2_FT_FIFO: u8 = 5;
+const EXT2_FT_SOCK: u8 = 6;
+const EXT2_FT_SYMLINK: u8 = 7;
+// Most links a file may have, as Linux limits ext2.
+const EXT2_LINK_MAX: u16 = 32000;
+// Symlink targets shorter than the block array are kept in it ("fast" symlinks).
+const FAST_SYMLINK_LEN: usize = 60;
+
+const EXT2_INDEX_FL: u32 = 0x1000;
+const EXT4_EXTENTS_FL: u32 = 0x80000;
+
+const EXT4_EXT_MAGIC: u16 = 0xF30A;
+const EXT4_EXT_NODE_HEADER: usize = 12;
+const EXT4_EXT_ENTRY_SIZE: usize = 12;
+const EXT4_EXT_MAX_DEPTH: u16 = 5;
+// Extents longer than this are unwritten (preallocated) and read as zeros.
+const EXT4_EXT_INIT_MAX_LEN: u32 = 32768;
+
+const EXT4_BG_INODE_UNINIT: u16 = 0x0001;
+const EXT4_BG_BLOCK_UNINIT: u16 = 0x0002;
+
+const EXT3_FEATURE_COMPAT_HAS_JOURNAL: u32 = 0x0004;
+const EXT2_FEATURE_COMPAT_RESIZE_INODE: u32 = 0x0010;
+const EXT2_FEATURE_INCOMPAT_FILETYPE: u32 = 0x0002;
+const EXT3_FEATURE_INCOMPAT_RECOVER: u32 = 0x0004;
+const EXT4_FEATURE_INCOMPAT_EXTENTS: u32 = 0x0040;
+const EXT4_FEATURE_INCOMPAT_64BIT: u32 = 0x0080;
+const EXT4_FEATURE_INCOMPAT_FLEX_BG: u32 = 0x0200;
+const EXT4_FEATURE_INCOMPAT_CSUM_SEED: u32 = 0x2000;
+const EXT4_FEATURE_INCOMPAT_LARGEDIR: u32 = 0x4000;
+const EXT2_FEATURE_RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+const EXT2_FEATURE_RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+const EXT4_FEATURE_RO_COMPAT_HUGE_FILE: u32 = 0x0008;
+const EXT4_FEATURE_RO_COMPAT_GDT_CSUM: u32 = 0x0010;
+const EXT4_FEATURE_RO_COMPAT_DIR_NLINK: u32 = 0x0020;
+const EXT4_FEATURE_RO_COMPAT_EXTRA_ISIZE: u32 = 0x0040;
+const EXT4_FEATURE_RO_COMPAT_BIGALLOC: u32 = 0x0200;
+const EXT4_FEATURE_RO_COMPAT_METADATA_CSUM: u32 = 0x0400;
+
+// Anything outside these sets is refused (incompat) or mounted read-only (ro_compat).
+// Compat features are safe to ignore by definition. The ext4 incompat features below
+// can be read but not written, so they mount read-only too.
+const SUPPORTED_INCOMPAT: u32 = EXT2_FEATURE_INCOMPAT_FILETYPE | EXT3_FEATURE_INCOMPAT_RECOVER | READ_ONLY_INCOMPAT;
+const READ_ONLY_INCOMPAT: u32 = EXT4_FEATURE_INCOMPAT_EXTENTS
+    | EXT4_FEATURE_INCOMPAT_64BIT
+    | EXT4_FEATURE_INCOMPAT_FLEX_BG
+    | EXT4_FEATURE_INCOMPAT_CSUM_SEED
+    | EXT4_FEATURE_INCOMPAT_LARGEDIR;
+const SUPPORTED_RO_COMPAT: u32 = EXT2_FEATURE_RO_COMPAT_SPARSE_SUPER | EXT2_FEATURE_RO_COMPAT_LARGE_FILE;
+// Features only ext4 sets, for telling ext4 volumes from ext3 ones.
+const EXT4_INCOMPAT: u32 = READ_ONLY_INCOMPAT;
+const EXT4_RO_COMPAT: u32 = EXT4_FEATURE_RO_COMPAT_HUGE_FILE
+    | EXT4_FEATURE_RO_COMPAT_GDT_CSUM
+    | EXT4_FEATURE_RO_COMPAT_DIR_NLINK
+    | EXT4_FEATURE_RO_COMPAT_EXTRA_ISIZE
+    | EXT4_FEATURE_RO_COMPAT_METADATA_CSUM;
+
+const INCOMPAT_ERRORS: &[(u32, &str)] = &[
+    (0x0001, "EXT2 volume uses compression, which is not supported."),
+    (0x0008, "EXT2 volume is an external journal device."),
+    (0x0010, "EXT2 volume uses meta_bg, which is not supported."),
+    (0x0100, "EXT2 volume uses multi-mount protection, which is not supported."),
+    (0x0400, "EXT2 volume uses extended attribute inodes, which are not supported."),
+    (0x1000, "EXT2 volume uses inline directory data, which is not supported."),
+    (0x8000, "EXT2 volume uses inline data, which is not supported."),
+    (0x10000, "EXT2 volume is encrypted, which is not supported."),
+    (0x20000, "EXT2 volume uses case folding, which is not supported."),
+];
+
+/// Names of the compat, incompat and ro_compat feature bits.
+const FEATURE_NAMES: [&[(u32, &str)]; 3] = [
+    &[
+        (0x0001, "dir_prealloc"),
+        (0x0002, "imagic_inodes"),
+        (0x0004, "has_journal"),
+        (0x0008, "ext_attr"),
+        (0x0010, "resize_inode"),
+        (0x0020, "dir_index"),
+    ],
+    &[
+        (0x0001, "compression"),
+        (0x0002, "filetype"),
+        (0x0004, "needs_recovery"),
+        (0x0008, "journal_dev"),
+        (0x0010, "meta_bg"),
+        (0x0040, "extents"),
+        (0x0080, "64bit"),
+        (0x0100, "mmp"),
+        (0x0200, "flex_bg"),
+        (0x2000, "metadata_csum_seed"),
+        (0x4000, "large_dir"),
+    ],
+    &[
+        (0x0001, "sparse_super"),
+        (0x0002, "large_file"),
+        (0x0008, "huge_file"),
+        (0x0010, "uninit_bg"),
+        (0x0020, "dir_nlink"),
+        (0x0040, "extra_isize"),
+        (0x0200, "bigalloc"),
+        (0x0400, "metadata_csum"),
+    ],
+];
 
 struct FormatProgress {
     label: &'static str,
@@ -95,6 +216,52 @@ pub struct Ext2Info {
     pub first_data_block: u32,
     pub part_sectors: u32,
     pub volume_name: [u8; 16],
+    pub feature_compat: u32,
+    pub feature_incompat: u32,
+    pub feature_ro_compat: u32,
+    /// Set when the volume has features this driver can read but not write.
+    pub read_only: bool,
+}
+
+impl Ext2Info {
+    /// 4 for volumes using ext4 features, 3 for other journalled ones, 2 otherwise.
+    pub fn generation(&self) -> u8 {
+        if self.feature_incompat & EXT4_INCOMPAT != 0 || self.feature_ro_compat & EXT4_RO_COMPAT != 0 {
+            4
+        } else if self.feature_compat & EXT3_FEATURE_COMPAT_HAS_JOURNAL != 0 {
+            3
+        } else {
+            2
+        }
+    }
+
+    /// Space-separated names of the feature flags that are set.
+    pub fn feature_names(&self) -> String {
+        let flags = [self.feature_compat, self.feature_incompat, self.feature_ro_compat];
+        let mut out = String::new();
+        for (set, names) in flags.iter().zip(FEATURE_NAMES.iter()) {
+            let mut known = 0u32;
+            for &(bit, name) in names.iter() {
+                known |= bit;
+                if set & bit != 0 {
+                    if !out.is_empty() {
+                        out.push(' ');
+                    }
+                    out.push_str(name);
+                }
+            }
+            if set & !known != 0 {
+                if !out.is_empty() {
+                    out.push(' ');
+                }
+                out.push_str(&format!("{:#x}", set & !known));
+            }
+        }
+        if out.is_empty() {
+            out.push_str("none");
+        }
+        out
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -113,16 +280,60 @@ pub fn find_ext2_partition(mbr: &MbrInfo) -> Option<PartitionInfo> {
     None
 }
 
+/// What an inode holds, from the type bits of its mode.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Directory,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+}
+
+impl FileKind {
+    // The type byte directory entries store with the filetype feature.
+    fn dirent_type(self) -> u8 {
+        match self {
+            FileKind::File => EXT2_FT_REG_FILE,
+            FileKind::Directory => EXT2_FT_DIR,
+            FileKind::Symlink => EXT2_FT_SYMLINK,
+            FileKind::CharDevice => EXT2_FT_CHRDEV,
+            FileKind::BlockDevice => EXT2_FT_BLKDEV,
+            FileKind::Fifo => EXT2_FT_FIFO,
+            FileKind::Socket => EXT2_FT_SOCK,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct DirEntryInfo {
     pub name: String,
     pub inode: u32,
     pub is_dir: bool,
+    pub kind: FileKind,
     pub size: u64,
     pub block: u32,
     pub offset: u16,
     #[allow(dead_code)]
     pub rec_len: u16,
+    pub mtime: u32,
+}
+
+/// The parts of an inode `stat` reports.
+#[derive(Copy, Clone)]
+pub struct InodeStat {
+    pub mode: u16,
+    pub uid: u16,
+    pub gid: u16,
+    pub links: u16,
+    pub size: u64,
+    /// 512-byte sectors allocated, indirect blocks included.
+    pub blocks: u32,
+    pub atime: u32,
+    pub ctime: u32,
+    pub mtime: u32,
 }
 
 #[derive(Clone, Copy)]
@@ -145,7 +356,15 @@ struct Superblock {
     rev_level: u32,
     first_ino: u32,
     inode_size: u16,
+    block_group_nr: u16,
+    feature_compat: u32,
+    feature_incompat: u32,
+    feature_ro_compat: u32,
     volume_name: [u8; 16],
+    reserved_gdt_blocks: u16,
+    journal_inum: u32,
+    desc_size: u16,
+    blocks_count_hi: u32,
 }
 
 #[derive(Clone, Copy)]
@@ -156,6 +375,7 @@ struct GroupDesc {
     free_blocks_count: u16,
     free_inodes_count: u16,
     used_dirs_count: u16,
+    flags: u16,
 }
 
 #[derive(Clone, Copy)]
@@ -172,6 +392,10 @@ struct Inode {
     blocks: u32,
     flags: u32,
     block: [u32; 15],
+    generation: u32,
+    file_acl: u32,
+    // High half of a regular file's size with large_file; `dir_acl` otherwise.
+    size_high: u32,
 }
 
 impl Inode {
@@ -182,6 +406,30 @@ impl Inode {
     fn is_file(&self) -> bool {
         self.mode & 0xF000 == 0x8000
     }
+
+    fn is_symlink(&self) -> bool {
+        self.mode & 0xF000 == 0xA000
+    }
+
+    fn kind(&self) -> FileKind {
+        match self.mode & 0xF000 {
+            0x4000 => FileKind::Directory,
+            0xA000 => FileKind::Symlink,
+            0x2000 => FileKind::CharDevice,
+            0x6000 => FileKind::BlockDevice,
+            0x1000 => FileKind::Fifo,
+            0xC000 => FileKind::Socket,
+            _ => FileKind::File,
+        }
+    }
+
+    fn len(&self) -> u64 {
+        if self.is_file() {
+            (self.size_high as u64) << 32 | self.size as u64
+        } else {
+            self.size as u64
+        }
+    }
 }
 
 pub struct Ext2Volume<D: BlockDevice> {
@@ -201,6 +449,23 @@ pub struct Ext2Volume<D: BlockDevice> {
     groups_count: u32,
     group_desc: Vec<GroupDesc>,
     volume_name: [u8; 16],
+    state: u16,
+    first_ino: u32,
+    read_only: bool,
+    desc_size: u32,
+    // Seed for metadata_csum checksums; None without the feature.
+    csum_seed: Option<u32>,
+    // The superblock as read at mount; fields the driver does not maintain are written
+    // back unchanged.
+    sb: Superblock,
+    journal: Option<Journal>,
+    // Metadata blocks written by the operation in progress, held back until it commits.
+    txn: Option<BTreeMap<u32, Vec<u8>>>,
+    // Blocks the operation in progress freed. Until it commits the old metadata still
+    // points at them, so they are not handed out again.
+    txn_freed: BTreeSet<u32>,
+    // Owner given to the files and directories created from now on.
+    creator: (u16, u16),
 }
 
 impl<D: BlockDevice> Ext2Volume<D> {
@@ -218,9 +483,18 @@ impl<D: BlockDevice> Ext2Volume<D> {
             first_data_block: self.first_data_block,
             part_sectors: self.part_sectors,
             volume_name: self.volume_name,
+            feature_compat: self.sb.feature_compat,
+            feature_incompat: self.sb.feature_incompat,
+            feature_ro_compat: self.sb.feature_ro_compat,
+            read_only: self.read_only,
         }
     }
 
+    /// True when features the driver cannot write keep it from writing.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
     pub fn usage(&mut self) -> Result<Ext2Usage, &'static str> {
         Ok(Ext2Usage {
             total_blocks: self.blocks_count,
@@ -230,25 +504,74 @@ impl<D: BlockDevice> Ext2Volume<D> {
     }
 
     pub fn open(dev: D, part: PartitionInfo) -> Result<Self, &'static str> {
-        let mut block0 = vec![0u8; BLOCK_SIZE];
-        read_block_raw(&dev, part.lba_start as u32, 0, &mut block0)?;
-        let sb = parse_superblock(&block0)?;
+        let raw = read_superblock_raw(&dev, part.lba_start + SUPERBLOCK_OFFSET / SECTOR_SIZE as u32)?;
+        let mut sb = parse_superblock(&raw)?;
 
         if sb.magic != EXT2_SUPER_MAGIC {
             return Err("Invalid EXT2 superblock.");
         }
-        let block_size = 1024u32 << sb.log_block_size;
-        if block_size != BLOCK_SIZE as u32 {
+        if sb.log_block_size > MAX_LOG_BLOCK_SIZE {
             return Err("Unsupported EXT2 block size.");
         }
-        if sb.rev_level < EXT2_REV_DYNAMIC {
-            return Err("Unsupported EXT2 revision.");
+        let block_size = 1024u32 << sb.log_block_size;
+        match sb.rev_level {
+            // Revision 0 predates feature flags and variable inode sizes.
+            EXT2_GOOD_OLD_REV => {
+                sb.first_ino = EXT2_FIRST_INO;
+                sb.inode_size = INODE_SIZE;
+                sb.feature_compat = 0;
+                sb.feature_incompat = 0;
+                sb.feature_ro_compat = 0;
+            }
+            EXT2_REV_DYNAMIC => {}
+            _ => return Err("Unsupported EXT2 revision."),
+        }
+        for &(bit, message) in INCOMPAT_ERRORS {
+            if sb.feature_incompat & bit != 0 {
+                return Err(message);
+            }
         }
+        if sb.feature_incompat & !SUPPORTED_INCOMPAT != 0 {
+            return Err("EXT2 volume uses an unknown incompatible feature.");
+        }
+        if sb.feature_ro_compat & EXT4_FEATURE_RO_COMPAT_BIGALLOC != 0 {
+            return Err("EXT4 volume uses bigalloc, which is not supported.");
+        }
+        let read_only = sb.feature_ro_compat & !SUPPORTED_RO_COMPAT != 0 || sb.feature_incompat & READ_ONLY_INCOMPAT != 0;
+        let csum_seed = metadata_csum_seed(&raw, &sb)?;
+
+        let inode_size = sb.inode_size as u32;
+        if inode_size < INODE_SIZE as u32 || !inode_size.is_power_of_two() || inode_size > block_size {
+            return Err("Unsupported EXT2 inode size.");
+        }
+        let expected_first_block = if block_size == 1024 { 1 } else { 0 };
+        if sb.blocks_per_group == 0
+            || sb.blocks_per_group > block_size * 8
+            || sb.inodes_per_group == 0
+            || sb.inodes_per_group > block_size * 8
+            || sb.first_data_block != expected_first_block
+            || sb.blocks_count <= sb.first_data_block
+        {
+            return Err("Invalid EXT2 geometry.");
+        }
+        let desc_size = if sb.feature_incompat & EXT4_FEATURE_INCOMPAT_64BIT != 0 {
+            let size = sb.desc_size as u32;
+            if (size as usize) < GROUP_DESC_SIZE_64BIT || !size.is_power_of_two() || size > block_size {
+                return Err("Invalid EXT4 group descriptor size.");
+            }
+            if sb.blocks_count_hi != 0 {
+                return Err("EXT4 volume is too large for this driver.");
+            }
+            size
+        } else {
+            GROUP_DESC_SIZE as u32
+        };
 
-        let groups_count = (sb.blocks_count + sb.blocks_per_group - 1) / sb.blocks_per_group;
-        let group_desc = read_group_descs(&dev, part.lba_start as u32, groups_count)?;
+        let groups_count = (sb.blocks_count - sb.first_data_block).div_ceil(sb.blocks_per_group);
+        let layout = DescLayout { part_start: part.lba_start, block_size, desc_size, csum_seed };
+        let group_desc = read_group_descs(&dev, &layout, sb.first_data_block + 1, groups_count)?;
 
-        Ok(Self {
+        let mut volume = Self {
             device: dev,
             part_start: part.lba_start as u32,
             part_sectors: part.sectors as u32,
@@ -264,27 +587,56 @@ impl<D: BlockDevice> Ext2Volume<D> {
             groups_count,
             group_desc,
             volume_name: sb.volume_name,
-        })
+            state: sb.state,
+            first_ino: sb.first_ino,
+            read_only,
+            desc_size,
+            csum_seed,
+            sb,
+            journal: None,
+            txn: None,
+            txn_freed: BTreeSet::new(),
+            creator: (0, 0),
+        };
+        // Recovery may rewrite the superblock and descriptors too, so the volume is
+        // opened again afterwards.
+        if sb.feature_incompat & EXT3_FEATURE_INCOMPAT_RECOVER != 0 && volume.recover_journal()? {
+            return Self::open(volume.device, part);
+        }
+        volume.open_journal()?;
+        Ok(volume)
     }
 
     pub fn format(dev: D, part_start: u32, part_sectors: u32, label: &str) -> Result<Self, &'static str> {
-        let total_blocks = (part_sectors as u64 * SECTOR_SIZE as u64 / BLOCK_SIZE as u64) as u32;
+        let block_size = FORMAT_BLOCK_SIZE as u32;
+        let mut total_blocks = (part_sectors as u64 * SECTOR_SIZE as u64 / block_size as u64) as u32;
         if total_blocks < 256 {
             return Err("Disk too small for EXT2.");
         }
 
         write_mbr(&dev, part_start, part_sectors)?;
 
-        let blocks_per_group = cmp::min(32768u32, total_blocks);
+        let blocks_per_group = cmp::min(block_size * 8, total_blocks);
         let inodes_per_group = compute_inodes_per_group(blocks_per_group);
-        let groups_count = (total_blocks + blocks_per_group - 1) / blocks_per_group;
         let inode_table_blocks = inode_table_blocks(inodes_per_group);
-        let group_desc_blocks = ((groups_count as u64 * 32 + (BLOCK_SIZE as u64 - 1))
-            / BLOCK_SIZE as u64) as u64;
+        // A last group too small for its own bitmaps and inode table is left off.
+        let mut groups_count = total_blocks.div_ceil(blocks_per_group);
+        let mut desc_blocks = group_desc_blocks(groups_count, block_size, GROUP_DESC_SIZE as u32);
+        let last_group_blocks = blocks_in_group(total_blocks, 0, blocks_per_group, groups_count - 1);
+        let last_group_meta = group_meta_blocks(groups_count - 1, true, desc_blocks) + 2 + inode_table_blocks;
+        if groups_count > 1 && last_group_blocks < last_group_meta + 64 {
+            groups_count -= 1;
+            total_blocks = groups_count * blocks_per_group;
+            desc_blocks = group_desc_blocks(groups_count, block_size, GROUP_DESC_SIZE as u32);
+        }
+        // The journal follows the root directory's block in group 0.
+        let journal_len = format_journal_len(total_blocks, groups_count, desc_blocks);
+
         let total_steps = 1
-            + group_desc_blocks
+            + desc_blocks as u64
             + (groups_count as u64 * (2 + inode_table_blocks as u64))
-            + 2;
+            + 2
+            + journal_len as u64;
         let mut progress = FormatProgress::new("Formatting EXT2:", total_steps);
 
         let mut group_desc = Vec::new();
@@ -293,45 +645,38 @@ impl<D: BlockDevice> Ext2Volume<D> {
 
         for group in 0..groups_count {
             let group_start = group * blocks_per_group;
-            let blocks_in_group = blocks_in_group(total_blocks, blocks_per_group, group);
-            let (block_bitmap, inode_bitmap, inode_table) = if group == 0 {
-                (2u32, 3u32, 4u32)
-            } else {
-                (group_start, group_start + 1, group_start + 2)
-            };
-            let reserved = if group == 0 {
-                2 + 2 + inode_table_blocks
-            } else {
-                2 + inode_table_blocks
-            };
-            let mut free_blocks = blocks_in_group.saturating_sub(reserved);
-            if group == 0 && free_blocks > 0 {
-                free_blocks = free_blocks.saturating_sub(1);
-            }
-
-            let used_inodes = if group == 0 { 10 } else { 0 };
+            let blocks_in_group = blocks_in_group(total_blocks, 0, blocks_per_group, group);
+            let meta = group_meta_blocks(group, true, desc_blocks);
+            let block_bitmap = group_start + meta;
+            // Bitmaps and the inode table, plus the root directory's block and the journal
+            // in group 0.
+            let reserved = meta + 2 + inode_table_blocks + if group == 0 { 1 + journal_len } else { 0 };
+            let free_blocks = blocks_in_group.saturating_sub(reserved);
+
+            let used_inodes = if group == 0 { EXT2_FIRST_INO - 1 } else { 0 };
             let free_inodes = inodes_per_group.saturating_sub(used_inodes);
 
             group_desc.push(GroupDesc {
                 block_bitmap,
-                inode_bitmap,
-                inode_table,
+                inode_bitmap: block_bitmap + 1,
+                inode_table: block_bitmap + 2,
                 free_blocks_count: free_blocks as u16,
                 free_inodes_count: free_inodes as u16,
                 used_dirs_count: if group == 0 { 1 } else { 0 },
+                flags: 0,
             });
 
             free_blocks_total = free_blocks_total.saturating_add(free_blocks);
             free_inodes_total = free_inodes_total.saturating_add(free_inodes);
         }
 
-        let sb = Superblock {
+        let mut sb = Superblock {
             inodes_count: inodes_per_group.saturating_mul(groups_count),
             blocks_count: total_blocks,
             free_blocks_count: free_blocks_total,
             free_inodes_count: free_inodes_total,
             first_data_block: 0,
-            log_block_size: 2,
+            log_block_size: block_size.trailing_zeros() - 10,
             blocks_per_group,
             inodes_per_group,
             mtime: 0,
@@ -339,58 +684,83 @@ impl<D: BlockDevice> Ext2Volume<D> {
             mnt_count: 0,
             max_mnt_count: 0xFFFF,
             magic: EXT2_SUPER_MAGIC,
-            state: 1,
+            state: EXT2_VALID_FS,
             errors: 1,
             rev_level: EXT2_REV_DYNAMIC,
             first_ino: EXT2_FIRST_INO,
             inode_size: INODE_SIZE,
+            block_group_nr: 0,
+            feature_compat: 0,
+            feature_incompat: EXT2_FEATURE_INCOMPAT_FILETYPE,
+            feature_ro_compat: EXT2_FEATURE_RO_COMPAT_SPARSE_SUPER,
             volume_name: build_volume_name(label),
+            reserved_gdt_blocks: 0,
+            journal_inum: 0,
+            desc_size: 0,
+            blocks_count_hi: 0,
         };
 
-        write_superblock(&dev, part_start, &sb)?;
-        progress.advance(1);
-        write_group_descs(&dev, part_start, groups_count, &group_desc)?;
-        progress.advance(group_desc_blocks);
+        // Whatever an earlier filesystem left in the superblocks and descriptor tables is
+        // cleared first.
+        let zero = vec![0u8; block_size as usize];
+        for group in 0..groups_count {
+            let start = group * blocks_per_group;
+            for block in start..start + group_meta_blocks(group, true, desc_blocks) {
+                write_block_raw(&dev, part_start, block_size, block, &zero)?;
+            }
+        }
+        for group in 0..groups_count {
+            if !group_has_super(group, true) {
+                continue;
+            }
+            sb.block_group_nr = group as u16;
+            let block = group * blocks_per_group;
+            let lba = if group == 0 { part_start + SUPERBLOCK_OFFSET / SECTOR_SIZE as u32 } else { block_lba(part_start, block_size, block) };
+            write_superblock_at(&dev, lba, &sb)?;
+            let layout = DescLayout { part_start, block_size, desc_size: GROUP_DESC_SIZE as u32, csum_seed: None };
+            write_group_descs(&dev, &layout, block + 1, &group_desc)?;
+        }
+        progress.advance(1 + desc_blocks as u64);
 
         for group in 0..groups_count {
-            let blocks_in_group = blocks_in_group(total_blocks, blocks_per_group, group);
-            let reserved = if group == 0 {
-                2 + 2 + inode_table_blocks
-            } else {
-                2 + inode_table_blocks
-            };
-            let mut bitmap = vec![0u8; BLOCK_SIZE];
+            let blocks_in_group = blocks_in_group(total_blocks, 0, blocks_per_group, group);
+            let desc = group_desc[group as usize];
+            let reserved = desc.inode_table - group * blocks_per_group + inode_table_blocks;
+            let mut bitmap = vec![0u8; block_size as usize];
             for bit in 0..reserved {
                 set_bit(&mut bitmap, bit);
             }
 
             if group == 0 {
-                let root_block = root_dir_block(inode_table_blocks);
-                set_bit(&mut bitmap, root_block);
+                for bit in reserved..reserved + 1 + journal_len {
+                    set_bit(&mut bitmap, bit);
+                }
             }
 
-            for bit in blocks_in_group..blocks_per_group {
+            for bit in blocks_in_group..block_size * 8 {
                 set_bit(&mut bitmap, bit);
             }
 
-            let desc = group_desc[group as usize];
-            write_block_raw(&dev, part_start, desc.block_bitmap, &bitmap)?;
+            write_block_raw(&dev, part_start, block_size, desc.block_bitmap, &bitmap)?;
             progress.advance(1);
 
-            let mut inode_bitmap = vec![0u8; BLOCK_SIZE];
+            let mut inode_bitmap = vec![0u8; block_size as usize];
             if group == 0 {
-                for inode_idx in 0..10u32 {
+                for inode_idx in 0..EXT2_FIRST_INO - 1 {
                     set_bit(&mut inode_bitmap, inode_idx);
                 }
             }
-            write_block_raw(&dev, part_start, desc.inode_bitmap, &inode_bitmap)?;
+            for bit in inodes_per_group..block_size * 8 {
+                set_bit(&mut inode_bitmap, bit);
+            }
+            write_block_raw(&dev, part_start, block_size, desc.inode_bitmap, &inode_bitmap)?;
             progress.advance(1);
 
-            let zero = vec![0u8; BLOCK_SIZE];
             for offset in 0..inode_table_blocks {
                 write_block_raw(
                     &dev,
                     part_start,
+                    block_size,
                     desc.inode_table + offset,
                     &zero,
                 )?;
@@ -405,32 +775,38 @@ impl<D: BlockDevice> Ext2Volume<D> {
         };
         let mut volume = Self::open(dev, part)?;
 
-        let root_block = root_dir_block(inode_table_blocks);
-        volume.write_root_dir(root_block)?;
+        let root_block = group_desc[0].inode_table + inode_table_blocks;
+        volume.write_directory_block(root_block, ROOT_INODE, ROOT_INODE)?;
         progress.advance(1);
 
         let now = time::current_time_secs().unwrap_or(0) as u32;
         let root_inode = Inode {
             mode: 0x41ED,
             uid: 0,
-            size: BLOCK_SIZE as u32,
+            size: block_size,
             atime: now,
             ctime: now,
             mtime: now,
             dtime: 0,
             gid: 0,
             links_count: 2,
-            blocks: BLOCK_SECTORS,
+            blocks: volume.block_sectors(),
             flags: 0,
             block: {
                 let mut b = [0u32; 15];
                 b[0] = root_block;
                 b
             },
+            generation: 0,
+            file_acl: 0,
+            size_high: 0,
         };
         volume.write_inode(ROOT_INODE, &root_inode)?;
         progress.advance(1);
 
+        if journal_len > 0 {
+            volume.create_journal(root_block + 1, journal_len, &mut progress)?;
+        }
         progress.finish();
 
         Ok(volume)
@@ -440,6 +816,8 @@ impl<D: BlockDevice> Ext2Volume<D> {
         ROOT_INODE
     }
 
+    /// Hashed (dir_index) directories are read through their leaf blocks; index blocks
+    /// look like empty entries to a linear reader.
     pub fn read_directory(&mut self, inode: u32) -> Result<Vec<DirEntryInfo>, &'static str> {
         let dir_inode = self.read_inode(inode)?;
         if !dir_inode.is_dir() {
@@ -447,62 +825,74 @@ impl<D: BlockDevice> Ext2Volume<D> {
         }
 
         let mut entries = Vec::new();
-        let mut block_idx = 0u32;
-        let mut remaining = dir_inode.size as u64;
-        while remaining > 0 {
-            let block = self.inode_block(&dir_inode, block_idx)?;
+        let seed = self.csum_seed.map(|seed| inode_csum_seed(seed, inode, dir_inode.generation));
+        let blocks = self.block_map(inode, &dir_inode, dir_inode.size.div_ceil(self.block_size))?;
+        for block in blocks {
             if block == 0 {
                 break;
             }
-            let mut buf = vec![0u8; BLOCK_SIZE];
+            let mut buf = self.block_buf();
             self.read_block(block, &mut buf)?;
+            if seed.is_some_and(|seed| !dir_block_checksum_ok(seed, &buf)) {
+                return Err("EXT4 directory block checksum mismatch.");
+            }
             let mut offset = 0usize;
-            while offset + 8 <= BLOCK_SIZE {
+            while offset + 8 <= buf.len() {
                 let inode_num = read_u32(&buf, offset);
                 let rec_len = read_u16(&buf, offset + 4) as usize;
                 let name_len = buf[offset + 6] as usize;
-                let file_type = buf[offset + 7];
 
-                if rec_len == 0 || offset + rec_len > BLOCK_SIZE {
+                if rec_len == 0 || offset + rec_len > buf.len() {
                     return Err("Invalid EXT2 directory entry.");
                 }
 
-                if inode_num != 0 && name_len > 0 && offset + 8 + name_len <= BLOCK_SIZE {
+                if inode_num != 0 && name_len > 0 && offset + 8 + name_len <= buf.len() {
                     let name_bytes = &buf[offset + 8..offset + 8 + name_len];
                     let name = core::str::from_utf8(name_bytes)
                         .map_err(|_| "Invalid EXT2 filename.")?
                         .to_string();
+                    // The inode's mode is authoritative; the entry's type byte is only a hint.
                     let entry_inode = self.read_inode(inode_num)?;
-                    let is_dir = if file_type == 0 {
-                        entry_inode.is_dir()
-                    } else {
-                        file_type == EXT2_FT_DIR
-                    };
+                    let kind = entry_inode.kind();
 
                     entries.push(DirEntryInfo {
                         name,
                         inode: inode_num,
-                        is_dir,
-                        size: entry_inode.size as u64,
+                        is_dir: kind == FileKind::Directory,
+                        kind,
+                        size: entry_inode.len(),
                         block,
                         offset: offset as u16,
                         rec_len: rec_len as u16,
+                        mtime: entry_inode.mtime,
                     });
                 }
 
                 offset += rec_len;
-                if offset >= BLOCK_SIZE {
+                if offset >= buf.len() {
                     break;
                 }
             }
-
-            block_idx += 1;
-            remaining = remaining.saturating_sub(BLOCK_SIZE as u64);
         }
 
         Ok(entries)
     }
 
+    pub fn stat(&mut self, inode: u32) -> Result<InodeStat, &'static str> {
+        let entry = self.read_inode(inode)?;
+        Ok(InodeStat {
+            mode: entry.mode,
+            uid: entry.uid,
+            gid: entry.gid,
+            links: entry.links_count,
+            size: entry.len(),
+            blocks: entry.blocks,
+            atime: entry.atime,
+            ctime: entry.ctime,
+            mtime: entry.mtime,
+        })
+    }
+
     pub fn find_entry(&mut self, dir_inode: u32, name: &str) -> Result<Option<DirEntryInfo>, &'static str> {
         let entries = self.read_directory(dir_inode)?;
         for entry in entries {
@@ -514,42 +904,39 @@ impl<D: BlockDevice> Ext2Volume<D> {
     }
 
     pub fn create_entry(&mut self, dir_inode: u32, name: &str, is_dir: bool) -> Result<DirEntryInfo, &'static str> {
-        let trimmed = name.trim();
-        if trimmed.is_empty() || trimmed == "." || trimmed == ".." {
-            return Err("Invalid file name.");
-        }
-        if trimmed.len() > 255 {
-            return Err("File name is too long.");
-        }
-        if trimmed.chars().any(|c| c.is_control() || c == '/' || c == '\\') {
-            return Err("Path segment may not contain control characters or slashes or backslashes.");
-        }
-        if self.find_entry(dir_inode, trimmed)?.is_some() {
-            return Err("File already exists.");
-        }
+        self.journaled(|volume| volume.create_entry_internal(dir_inode, name, is_dir))
+    }
+
+    fn create_entry_internal(&mut self, dir_inode: u32, name: &str, is_dir: bool) -> Result<DirEntryInfo, &'static str> {
+        self.check_writable()?;
+        let trimmed = self.check_new_name(dir_inode, name)?;
+        let trimmed = trimmed.as_str();
 
         let inode_num = self.allocate_inode(is_dir)?;
         let now = time::current_time_secs().unwrap_or(0) as u32;
         let mut inode = Inode {
             mode: if is_dir { 0x41ED } else { 0x81A4 },
-            uid: 0,
+            uid: self.creator.0,
             size: 0,
             atime: now,
             ctime: now,
             mtime: now,
             dtime: 0,
-            gid: 0,
+            gid: self.creator.1,
             links_count: if is_dir { 2 } else { 1 },
             blocks: 0,
             flags: 0,
             block: [0u32; 15],
+            generation: 0,
+            file_acl: 0,
+            size_high: 0,
         };
 
         if is_dir {
             let data_block = self.allocate_block()?;
             inode.block[0] = data_block;
-            inode.size = BLOCK_SIZE as u32;
-            inode.blocks = BLOCK_SECTORS;
+            inode.size = self.block_size;
+            inode.blocks = self.block_sectors();
             self.write_directory_block(data_block, inode_num, dir_inode)?;
         }
 
@@ -574,38 +961,151 @@ impl<D: BlockDevice> Ext2Volume<D> {
         self.find_entry(dir_inode, trimmed)?.ok_or("Failed to create entry.")
     }
 
+    /// Creates `name` in `dir_inode` as a symbolic link to `target`. Short targets are kept
+    /// in the inode itself, longer ones in a block of their own.
+    pub fn create_symlink(&mut self, dir_inode: u32, name: &str, target: &str) -> Result<DirEntryInfo, &'static str> {
+        self.journaled(|volume| volume.create_symlink_internal(dir_inode, name, target))
+    }
+
+    fn create_symlink_internal(&mut self, dir_inode: u32, name: &str, target: &str) -> Result<DirEntryInfo, &'static str> {
+        self.check_writable()?;
+        let name = self.check_new_name(dir_inode, name)?;
+        if target.is_empty() {
+            return Err("Symbolic link target cannot be empty.");
+        }
+        // Linux caps targets at a block, terminating NUL included.
+        if target.len() >= self.block_size as usize {
+            return Err("Symbolic link target is too long.");
+        }
+
+        let inode_num = self.allocate_inode(false)?;
+        let now = time::current_time_secs().unwrap_or(0) as u32;
+        let mut inode = Inode {
+            mode: 0xA1FF,
+            uid: self.creator.0,
+            size: target.len() as u32,
+            atime: now,
+            ctime: now,
+            mtime: now,
+            dtime: 0,
+            gid: self.creator.1,
+            links_count: 1,
+            blocks: 0,
+            flags: 0,
+            block: [0u32; 15],
+            generation: 0,
+            file_acl: 0,
+            size_high: 0,
+        };
+        if target.len() < FAST_SYMLINK_LEN {
+            let mut bytes = [0u8; FAST_SYMLINK_LEN];
+            bytes[..target.len()].copy_from_slice(target.as_bytes());
+            for (slot, chunk) in inode.block.iter_mut().zip(bytes.chunks(4)) {
+                *slot = read_u32(chunk, 0);
+            }
+        } else {
+            let block = self.allocate_block()?;
+            let mut buf = self.block_buf();
+            buf[..target.len()].copy_from_slice(target.as_bytes());
+            self.write_data_block(block, &buf)?;
+            inode.block[0] = block;
+            inode.blocks = self.block_sectors();
+        }
+
+        self.write_inode(inode_num, &inode)?;
+        if let Err(err) = self.insert_dir_entry(dir_inode, &name, inode_num, EXT2_FT_SYMLINK) {
+            if self.has_block_map(&inode) {
+                let _ = self.free_inode_blocks(&inode);
+            }
+            let _ = self.free_inode(inode_num);
+            return Err(err);
+        }
+        self.find_entry(dir_inode, &name)?.ok_or("Failed to create entry.")
+    }
+
+    /// The target of symbolic link `inode`, as stored.
+    pub fn read_link(&mut self, ino: u32) -> Result<String, &'static str> {
+        let inode = self.read_inode(ino)?;
+        if !inode.is_symlink() {
+            return Err("Not a symbolic link.");
+        }
+        let len = inode.len() as usize;
+        let bytes = if self.has_block_map(&inode) {
+            if len >= self.block_size as usize {
+                return Err("Invalid EXT2 symbolic link.");
+            }
+            let block = self.block_map(ino, &inode, 1)?[0];
+            if block == 0 {
+                return Err("Invalid EXT2 symbolic link.");
+            }
+            let mut buf = self.block_buf();
+            self.read_block(block, &mut buf)?;
+            buf[..len].to_vec()
+        } else {
+            if len >= FAST_SYMLINK_LEN {
+                return Err("Invalid EXT2 symbolic link.");
+            }
+            inode.block.iter().flat_map(|slot| slot.to_le_bytes()).take(len).collect()
+        };
+        String::from_utf8(bytes).map_err(|_| "Symbolic link target is not valid UTF-8.")
+    }
+
+    /// Adds `name` in `dir_inode` as another link to `inode`, which may not be a directory.
+    pub fn link(&mut self, dir_inode: u32, name: &str, inode: u32) -> Result<DirEntryInfo, &'static str> {
+        self.journaled(|volume| volume.link_internal(dir_inode, name, inode))
+    }
+
+    fn link_internal(&mut self, dir_inode: u32, name: &str, ino: u32) -> Result<DirEntryInfo, &'static str> {
+        self.check_writable()?;
+        let name = self.check_new_name(dir_inode, name)?;
+        let mut inode = self.read_inode(ino)?;
+        if inode.is_dir() {
+            return Err("Hard links to directories are not allowed.");
+        }
+        if inode.links_count >= EXT2_LINK_MAX {
+            return Err("Too many links.");
+        }
+        self.insert_dir_entry(dir_inode, &name, ino, inode.kind().dirent_type())?;
+        inode.links_count += 1;
+        inode.ctime = time::current_time_secs().unwrap_or(0) as u32;
+        self.write_inode(ino, &inode)?;
+        self.find_entry(dir_inode, &name)?.ok_or("Failed to create entry.")
+    }
+
     pub fn read_file(&mut self, entry: &DirEntryInfo) -> Result<Vec<u8>, &'static str> {
         let inode = self.read_inode(entry.inode)?;
         if !inode.is_file() {
             return Err("Not a file.");
         }
-        if inode.size == 0 {
+        if inode.len() == 0 {
             return Ok(Vec::new());
         }
+        if inode.len() > u32::MAX as u64 {
+            return Err("File too large for EXT2 driver.");
+        }
 
-        let mut data = vec![0u8; inode.size as usize];
-        let mut remaining = inode.size as usize;
-        let mut offset = 0usize;
-        let mut block_idx = 0u32;
-
-        while remaining > 0 {
-            let block = self.inode_block(&inode, block_idx)?;
+        let mut data = vec![0u8; inode.len() as usize];
+        let count = inode.len().div_ceil(self.block_size as u64) as u32;
+        let blocks = self.block_map(entry.inode, &inode, count)?;
+        let mut buf = self.block_buf();
+        for (chunk, block) in data.chunks_mut(self.block_size as usize).zip(blocks) {
+            // Holes read as zeros.
             if block == 0 {
-                break;
+                continue;
             }
-            let mut buf = vec![0u8; BLOCK_SIZE];
             self.read_block(block, &mut buf)?;
-            let copy_len = cmp::min(BLOCK_SIZE, remaining);
-            data[offset..offset + copy_len].copy_from_slice(&buf[..copy_len]);
-            offset += copy_len;
-            remaining -= copy_len;
-            block_idx += 1;
+            chunk.copy_from_slice(&buf[..chunk.len()]);
         }
 
         Ok(data)
     }
 
     pub fn write_file(&mut self, _dir_inode: u32, entry: &DirEntryInfo, contents: &[u8]) -> Result<(), &'static str> {
+        self.journaled(|volume| volume.write_file_internal(entry, contents))
+    }
+
+    fn write_file_internal(&mut self, entry: &DirEntryInfo, contents: &[u8]) -> Result<(), &'static str> {
+        self.check_writable()?;
         let mut inode = self.read_inode(entry.inode)?;
         if !inode.is_file() {
             return Err("Not a file.");
@@ -616,7 +1116,7 @@ impl<D: BlockDevice> Ext2Volume<D> {
         let needed = if contents.is_empty() {
             0
         } else {
-            (contents.len() + BLOCK_SIZE - 1) / BLOCK_SIZE
+            contents.len().div_ceil(self.block_size as usize)
         };
         if needed > max_blocks {
             return Err("File too large for EXT2 driver.");
@@ -637,21 +1137,22 @@ impl<D: BlockDevice> Ext2Volume<D> {
         let mut remaining = contents.len();
         let mut offset = 0usize;
         for block in data_blocks {
-            let mut buf = vec![0u8; BLOCK_SIZE];
-            let copy_len = cmp::min(BLOCK_SIZE, remaining);
+            let mut buf = self.block_buf();
+            let copy_len = cmp::min(buf.len(), remaining);
             if copy_len > 0 {
                 buf[..copy_len].copy_from_slice(&contents[offset..offset + copy_len]);
                 offset += copy_len;
                 remaining -= copy_len;
             }
-            self.write_block(block, &buf)?;
+            self.write_data_block(block, &buf)?;
             if remaining == 0 {
                 break;
             }
         }
 
         inode.size = contents.len() as u32;
-        let block_units = (needed as u32 + indirect_blocks) * BLOCK_SECTORS;
+        inode.size_high = 0;
+        let block_units = (needed as u32 + indirect_blocks) * self.block_sectors();
         inode.blocks = block_units;
         let now = time::current_time_secs().unwrap_or(0) as u32;
         inode.mtime = now;
@@ -660,18 +1161,141 @@ impl<D: BlockDevice> Ext2Volume<D> {
         self.write_inode(entry.inode, &inode)
     }
 
+    /// Writes `data` at `offset` in place, allocating only the blocks it lands in. Writing
+    /// past the end leaves a hole, which reads as zeros and takes no space.
+    pub fn write_at(&mut self, entry: &DirEntryInfo, offset: u64, data: &[u8]) -> Result<(), &'static str> {
+        self.journaled(|volume| volume.write_at_internal(entry, offset, data))
+    }
+
+    fn write_at_internal(&mut self, entry: &DirEntryInfo, offset: u64, data: &[u8]) -> Result<(), &'static str> {
+        self.check_writable()?;
+        let mut inode = self.read_inode(entry.inode)?;
+        if !inode.is_file() {
+            return Err("Not a file.");
+        }
+        let end = offset.checked_add(data.len() as u64).ok_or("File too large for EXT2 driver.")?;
+        if end > self.max_file_size() {
+            return Err("File too large for EXT2 driver.");
+        }
+
+        let block_size = self.block_size as u64;
+        let mut pos = offset;
+        while pos < end {
+            let index = (pos / block_size) as u32;
+            let start = (pos % block_size) as usize;
+            let len = cmp::min(block_size - start as u64, end - pos) as usize;
+            let mut buf = self.block_buf();
+            let mut block = self.inode_block(&inode, index)?;
+            if block == 0 {
+                block = self.allocate_block()?;
+                self.set_inode_block(&mut inode, index, block)?;
+                inode.blocks = inode.blocks.saturating_add(self.block_sectors());
+            } else if len < buf.len() {
+                self.read_block(block, &mut buf)?;
+            }
+            let from = (pos - offset) as usize;
+            buf[start..start + len].copy_from_slice(&data[from..from + len]);
+            self.write_data_block(block, &buf)?;
+            pos += len as u64;
+        }
+
+        if end > inode.len() {
+            inode.size = end as u32;
+            inode.size_high = 0;
+        }
+        let now = time::current_time_secs().unwrap_or(0) as u32;
+        inode.mtime = now;
+        inode.ctime = now;
+        self.write_inode(entry.inode, &inode)
+    }
+
+    /// Cuts a file to `len` bytes, freeing the blocks past it, or extends it with a hole.
+    pub fn truncate(&mut self, entry: &DirEntryInfo, len: u64) -> Result<(), &'static str> {
+        self.journaled(|volume| volume.truncate_internal(entry, len))
+    }
+
+    fn truncate_internal(&mut self, entry: &DirEntryInfo, len: u64) -> Result<(), &'static str> {
+        self.check_writable()?;
+        let mut inode = self.read_inode(entry.inode)?;
+        if !inode.is_file() {
+            return Err("Not a file.");
+        }
+        if len > self.max_file_size() {
+            return Err("File too large for EXT2 driver.");
+        }
+
+        if len < inode.len() {
+            let block_size = self.block_size as u64;
+            let keep = len.div_ceil(block_size);
+            let per_block = block_size / 4;
+            let mut freed = 0u32;
+            for index in keep..12 {
+                let block = inode.block[index as usize];
+                if block != 0 {
+                    self.free_block(block)?;
+                    inode.block[index as usize] = 0;
+                    freed += 1;
+                }
+            }
+            let mut tree_start = 12u64;
+            for (slot, depth) in [(12, 1u8), (13, 2), (14, 3)] {
+                let head = inode.block[slot];
+                inode.block[slot] = self.truncate_indirect(head, depth, keep.saturating_sub(tree_start), &mut freed)?;
+                tree_start += per_block.pow(depth as u32);
+            }
+            inode.blocks = inode.blocks.saturating_sub(freed * self.block_sectors());
+
+            // The rest of the new last block must read as zeros if the file grows again.
+            let tail = (len % block_size) as usize;
+            if tail != 0 {
+                let block = self.inode_block(&inode, (len / block_size) as u32)?;
+                if block != 0 {
+                    let mut buf = self.block_buf();
+                    self.read_block(block, &mut buf)?;
+                    buf[tail..].fill(0);
+                    self.write_data_block(block, &buf)?;
+                }
+            }
+        }
+
+        inode.size = len as u32;
+        inode.size_high = 0;
+        let now = time::current_time_secs().unwrap_or(0) as u32;
+        inode.mtime = now;
+        inode.ctime = now;
+        self.write_inode(entry.inode, &inode)
+    }
+
     pub fn delete_entry(&mut self, dir_inode: u32, entry: &DirEntryInfo) -> Result<(), &'static str> {
-        let inode = self.read_inode(entry.inode)?;
+        self.journaled(|volume| volume.delete_entry_internal(dir_inode, entry))
+    }
+
+    fn delete_entry_internal(&mut self, dir_inode: u32, entry: &DirEntryInfo) -> Result<(), &'static str> {
+        self.check_writable()?;
+        let mut inode = self.read_inode(entry.inode)?;
         if inode.is_dir() {
             return Err("Not a file.");
         }
 
-        self.free_inode_blocks(&inode)?;
-        self.free_inode(entry.inode)?;
-        self.clear_dir_entry(dir_inode, entry)
+        self.clear_dir_entry(dir_inode, entry)?;
+        // The inode lives on while other names link to it.
+        if inode.links_count > 1 {
+            inode.links_count -= 1;
+            inode.ctime = time::current_time_secs().unwrap_or(0) as u32;
+            return self.write_inode(entry.inode, &inode);
+        }
+        if self.has_block_map(&inode) {
+            self.free_inode_blocks(&inode)?;
+        }
+        self.free_inode(entry.inode)
     }
 
     pub fn delete_dir(&mut self, dir_inode: u32, entry: &DirEntryInfo) -> Result<(), &'static str> {
+        self.journaled(|volume| volume.delete_dir_internal(dir_inode, entry))
+    }
+
+    fn delete_dir_internal(&mut self, dir_inode: u32, entry: &DirEntryInfo) -> Result<(), &'static str> {
+        self.check_writable()?;
         let inode = self.read_inode(entry.inode)?;
         if !inode.is_dir() {
             return Err("Not a directory.");
@@ -689,57 +1313,274 @@ impl<D: BlockDevice> Ext2Volume<D> {
         self.write_inode(dir_inode, &parent)
     }
 
+    /// Sets the owner and group recorded on files and directories created after this.
+    pub fn set_creator(&mut self, uid: u16, gid: u16) {
+        self.creator = (uid, gid);
+    }
+
+    /// Replaces the permission bits of `ino` (the low twelve mode bits), keeping its type.
+    pub fn set_mode(&mut self, ino: u32, permissions: u16) -> Result<(), &'static str> {
+        self.journaled(|volume| {
+            volume.update_inode(ino, |inode| inode.mode = (inode.mode & 0xF000) | (permissions & 0o7777))
+        })
+    }
+
+    pub fn set_owner(&mut self, ino: u32, uid: u16, gid: u16) -> Result<(), &'static str> {
+        self.journaled(|volume| {
+            volume.update_inode(ino, |inode| {
+                inode.uid = uid;
+                inode.gid = gid;
+            })
+        })
+    }
+
+    // Applies `change` to inode `ino` and stamps its change time.
+    fn update_inode(&mut self, ino: u32, change: impl FnOnce(&mut Inode)) -> Result<(), &'static str> {
+        self.check_writable()?;
+        let mut inode = self.read_inode(ino)?;
+        change(&mut inode);
+        inode.ctime = time::current_time_secs().unwrap_or(0) as u32;
+        self.write_inode(ino, &inode)
+    }
+
     pub fn update_access_date(&mut self, entry: &DirEntryInfo) -> Result<(), &'static str> {
+        if self.read_only {
+            return Ok(());
+        }
         let mut inode = self.read_inode(entry.inode)?;
         let now = time::current_time_secs().unwrap_or(0) as u32;
         inode.atime = now;
         self.write_inode(entry.inode, &inode)
     }
 
-    fn read_block(&self, block: u32, buf: &mut [u8]) -> Result<(), &'static str> {
-        if buf.len() != BLOCK_SIZE {
-            return Err("Invalid block buffer.");
-        }
-        read_block_raw(&self.device, self.part_start, block, buf)
+    /// True when the superblock says the volume was not cleanly unmounted or has errors.
+    pub fn is_dirty(&self) -> bool {
+        self.state & EXT2_VALID_FS == 0 || self.state & EXT2_ERROR_FS != 0
     }
 
-    fn write_block(&self, block: u32, buf: &[u8]) -> Result<(), &'static str> {
-        if buf.len() != BLOCK_SIZE {
-            return Err("Invalid block buffer.");
+    /// Marks the superblock valid on unmount, and not valid while mounted. A volume with a
+    /// journal is marked as needing recovery instead, since replaying the journal is all
+    /// a crash calls for.
+    pub fn set_clean(&mut self, clean: bool) -> Result<(), &'static str> {
+        if self.read_only {
+            return Ok(());
         }
-        write_block_raw(&self.device, self.part_start, block, buf)
-    }
-
-    fn read_inode(&mut self, inode: u32) -> Result<Inode, &'static str> {
-        if inode == 0 || inode > self.inodes_count {
-            return Err("Invalid inode.");
+        if self.journal.is_some() {
+            if clean {
+                self.sb.feature_incompat &= !EXT3_FEATURE_INCOMPAT_RECOVER;
+            } else {
+                self.sb.feature_incompat |= EXT3_FEATURE_INCOMPAT_RECOVER;
+            }
+        } else if clean {
+            self.state |= EXT2_VALID_FS;
+        } else {
+            self.state &= !EXT2_VALID_FS;
         }
-        let group = self.group_index_for_inode(inode);
-        let index = (inode - 1) % self.inodes_per_group;
-        let desc = self.group_desc[group as usize];
-        let inode_size = self.inode_size as u32;
-        let block_offset = (index * inode_size) / self.block_size;
-        let offset = (index * inode_size) % self.block_size;
-        let mut buf = vec![0u8; BLOCK_SIZE];
-        self.read_block(desc.inode_table + block_offset, &mut buf)?;
-        parse_inode(&buf, offset as usize)
+        self.write_superblock()
     }
 
-    fn write_inode(&mut self, inode: u32, entry: &Inode) -> Result<(), &'static str> {
-        if inode == 0 || inode > self.inodes_count {
-            return Err("Invalid inode.");
+    /// Walks the directory tree from the root, checking entries, the blocks each inode
+    /// claims and link counts, then compares the block and inode bitmaps and the free
+    /// counts with what was found. With `repair`, bad entries and block pointers are
+    /// cleared, link counts fixed, unreferenced inodes released and the bitmaps rewritten.
+    pub fn fsck(&mut self, repair: bool) -> Result<FsckReport, &'static str> {
+        if repair {
+            self.check_writable()?;
+        }
+        // A failed allocation would panic the kernel, so a volume whose tables would
+        // crowd the heap is refused instead; mount then carries on without the check.
+        let (block_bytes, inodes) = ((self.blocks_count as usize).div_ceil(8), self.inodes_count as usize + 1);
+        if block_bytes + inodes * 2 + inodes.div_ceil(8) > memory::heap_stats().free / 2 {
+            return Err("Volume is too large to check in the kernel heap.");
+        }
+        let mut check = Ext2Check {
+            report: FsckReport::new(repair),
+            repair,
+            claimed: vec![0; block_bytes],
+            refs: vec![0; inodes],
+            dirs: vec![0; inodes.div_ceil(8)],
+        };
+        self.claim_metadata(&mut check.claimed);
+
+        // Reserved inodes (the resize inode in particular) may point into metadata, so
+        // their blocks are claimed without complaint.
+        for ino in 1..self.first_ino {
+            if ino == ROOT_INODE || ino > self.inodes_count {
+                continue;
+            }
+            let mut inode = self.read_inode(ino)?;
+            self.fsck_blocks(&mut check, ino, &mut inode)?;
         }
-        let group = self.group_index_for_inode(inode);
-        let index = (inode - 1) % self.inodes_per_group;
-        let desc = self.group_desc[group as usize];
-        let inode_size = self.inode_size as u32;
-        let block_offset = (index * inode_size) / self.block_size;
-        let offset = (index * inode_size) % self.block_size;
-        let mut buf = vec![0u8; BLOCK_SIZE];
-        self.read_block(desc.inode_table + block_offset, &mut buf)?;
-        write_inode(&mut buf, offset as usize, entry);
-        self.write_block(desc.inode_table + block_offset, &buf)
-    }
+
+        let mut root = self.read_inode(ROOT_INODE)?;
+        if !root.is_dir() || root.links_count == 0 {
+            return Err("Root inode is not a directory.");
+        }
+        set_bit(&mut check.dirs, ROOT_INODE);
+        let blocks = self.fsck_blocks(&mut check, ROOT_INODE, &mut root)?;
+        let mut pending = vec![(ROOT_INODE, ROOT_INODE, blocks, String::from("\\"))];
+        while let Some((dir, parent, blocks, path)) = pending.pop() {
+            check.report.dirs += 1;
+            for block in blocks {
+                self.fsck_dir_block(&mut check, dir, parent, block, &path, &mut pending)?;
+            }
+        }
+
+        for ino in 1..=self.inodes_count {
+            let refs = check.refs[ino as usize];
+            if refs == 0 || !self.is_checked_inode(ino) {
+                continue;
+            }
+            let mut inode = self.read_inode(ino)?;
+            // With dir_nlink a directory with too many subdirectories to count has 1.
+            let uncounted = inode.is_dir()
+                && inode.links_count == 1
+                && self.sb.feature_ro_compat & EXT4_FEATURE_RO_COMPAT_DIR_NLINK != 0;
+            if inode.links_count != refs && !uncounted {
+                check.report.problems.push(format!(
+                    "inode {}: link count is {}, should be {}",
+                    ino, inode.links_count, refs
+                ));
+                if repair {
+                    inode.links_count = refs;
+                    self.write_inode(ino, &inode)?;
+                }
+            }
+        }
+
+        self.fsck_bitmaps(&mut check)?;
+        self.fsck_backups(&mut check)?;
+        if repair {
+            self.state &= !EXT2_ERROR_FS;
+            self.write_group_descs()?;
+            self.write_superblock()?;
+        }
+        Ok(check.report)
+    }
+
+    // Checks `name` for a new entry in `dir_inode`, returning it trimmed.
+    fn check_new_name(&mut self, dir_inode: u32, name: &str) -> Result<String, &'static str> {
+        let trimmed = name.trim();
+        if trimmed.is_empty() || trimmed == "." || trimmed == ".." {
+            return Err("Invalid file name.");
+        }
+        if trimmed.len() > 255 {
+            return Err("File name is too long.");
+        }
+        if trimmed.chars().any(|c| c.is_control() || c == '/' || c == '\\') {
+            return Err("Path segment may not contain control characters or slashes or backslashes.");
+        }
+        if self.find_entry(dir_inode, trimmed)?.is_some() {
+            return Err("File already exists.");
+        }
+        Ok(trimmed.to_string())
+    }
+
+    // Fast symlinks and device nodes keep other things in the block array. As on Linux, a
+    // symlink is fast when it has no blocks besides an extended attribute block.
+    fn has_block_map(&self, inode: &Inode) -> bool {
+        match inode.kind() {
+            FileKind::File | FileKind::Directory => true,
+            FileKind::Symlink => inode.blocks > if inode.file_acl != 0 { self.block_sectors() } else { 0 },
+            _ => false,
+        }
+    }
+
+    // Files are written through direct and double-indirect blocks, with 32-bit sizes.
+    fn max_file_size(&self) -> u64 {
+        let per_block = (self.block_size / 4) as u64;
+        let blocks = 12 + per_block + per_block * per_block;
+        cmp::min(blocks * self.block_size as u64, u32::MAX as u64)
+    }
+
+    fn check_writable(&self) -> Result<(), &'static str> {
+        if self.read_only {
+            return Err("Volume is mounted read-only.");
+        }
+        Ok(())
+    }
+
+    // Sees the blocks the transaction in progress has written.
+    fn read_block(&self, block: u32, buf: &mut [u8]) -> Result<(), &'static str> {
+        if buf.len() != self.block_size as usize {
+            return Err("Invalid block buffer.");
+        }
+        if let Some(staged) = self.txn.as_ref().and_then(|txn| txn.get(&block)) {
+            buf.copy_from_slice(staged);
+            return Ok(());
+        }
+        read_block_raw(&self.device, self.part_start, self.block_size, block, buf)
+    }
+
+    // Metadata writes go into the transaction in progress, if there is one.
+    fn write_block(&mut self, block: u32, buf: &[u8]) -> Result<(), &'static str> {
+        if self.txn.is_none() {
+            return self.write_block_direct(block, buf);
+        }
+        self.check_writable()?;
+        if buf.len() != self.block_size as usize {
+            return Err("Invalid block buffer.");
+        }
+        if let Some(txn) = self.txn.as_mut() {
+            txn.insert(block, buf.to_vec());
+        }
+        Ok(())
+    }
+
+    // File contents skip the journal and land before the transaction that points at them
+    // commits, as in ext3's ordered mode.
+    fn write_data_block(&mut self, block: u32, buf: &[u8]) -> Result<(), &'static str> {
+        if let Some(txn) = self.txn.as_mut() {
+            txn.remove(&block);
+        }
+        self.write_block_direct(block, buf)
+    }
+
+    fn write_block_direct(&self, block: u32, buf: &[u8]) -> Result<(), &'static str> {
+        self.check_writable()?;
+        if buf.len() != self.block_size as usize {
+            return Err("Invalid block buffer.");
+        }
+        write_block_raw(&self.device, self.part_start, self.block_size, block, buf)
+    }
+
+    fn read_inode(&mut self, inode: u32) -> Result<Inode, &'static str> {
+        if inode == 0 || inode > self.inodes_count {
+            return Err("Invalid inode.");
+        }
+        let group = self.group_index_for_inode(inode);
+        let index = (inode - 1) % self.inodes_per_group;
+        let desc = self.group_desc[group as usize];
+        let inode_size = self.inode_size as u32;
+        let block_offset = (index * inode_size) / self.block_size;
+        let offset = (index * inode_size) % self.block_size;
+        let mut buf = self.block_buf();
+        self.read_block(desc.inode_table + block_offset, &mut buf)?;
+        if let Some(seed) = self.csum_seed {
+            let raw = &buf[offset as usize..(offset + inode_size) as usize];
+            // Never-used inodes in a lazily initialised table are all zeros.
+            if !inode_checksum_ok(seed, inode, raw) && raw.iter().any(|&b| b != 0) {
+                return Err("EXT4 inode checksum mismatch.");
+            }
+        }
+        parse_inode(&buf, offset as usize)
+    }
+
+    fn write_inode(&mut self, inode: u32, entry: &Inode) -> Result<(), &'static str> {
+        if inode == 0 || inode > self.inodes_count {
+            return Err("Invalid inode.");
+        }
+        let group = self.group_index_for_inode(inode);
+        let index = (inode - 1) % self.inodes_per_group;
+        let desc = self.group_desc[group as usize];
+        let inode_size = self.inode_size as u32;
+        let block_offset = (index * inode_size) / self.block_size;
+        let offset = (index * inode_size) % self.block_size;
+        let mut buf = self.block_buf();
+        self.read_block(desc.inode_table + block_offset, &mut buf)?;
+        write_inode(&mut buf, offset as usize, entry);
+        self.write_block(desc.inode_table + block_offset, &buf)
+    }
 
     fn inode_block(&mut self, inode: &Inode, block_index: u32) -> Result<u32, &'static str> {
         let per_block = self.block_size / 4;
@@ -758,9 +1599,252 @@ impl<D: BlockDevice> Ext2Volume<D> {
             let first = self.read_indirect_block(inode.block[13], outer)?;
             return self.read_indirect_block(first, inner);
         }
+        // Only reached on small-block volumes made elsewhere; files we write stop at the
+        // double-indirect block.
+        idx -= per_double;
+        if idx < per_double * per_block {
+            let first = self.read_indirect_block(inode.block[14], idx / per_double)?;
+            let second = self.read_indirect_block(first, (idx / per_block) % per_block)?;
+            return self.read_indirect_block(second, idx % per_block);
+        }
         Err("File too large for EXT2 driver.")
     }
 
+    // The physical block behind each of the first `count` logical blocks of an inode,
+    // zero for holes and unwritten extents.
+    fn block_map(&mut self, ino: u32, inode: &Inode, count: u32) -> Result<Vec<u32>, &'static str> {
+        if inode.flags & EXT4_EXTENTS_FL == 0 {
+            return (0..count).map(|idx| self.inode_block(inode, idx)).collect();
+        }
+        let mut map = vec![0u32; count as usize];
+        for extent in self.extents(ino, inode, &mut Vec::new())? {
+            if extent.unwritten {
+                continue;
+            }
+            for i in 0..extent.len {
+                if let Some(slot) = map.get_mut(extent.logical.saturating_add(i) as usize) {
+                    *slot = extent.start + i;
+                }
+            }
+        }
+        Ok(map)
+    }
+
+    // Walks an inode's extent tree, returning its extents in logical order. The blocks
+    // holding index and leaf nodes below the root are added to `nodes`.
+    fn extents(&mut self, ino: u32, inode: &Inode, nodes: &mut Vec<u32>) -> Result<Vec<Extent>, &'static str> {
+        let mut root = Vec::with_capacity(60);
+        for word in inode.block.iter() {
+            root.extend_from_slice(&word.to_le_bytes());
+        }
+        let seed = self.csum_seed.map(|seed| inode_csum_seed(seed, ino, inode.generation));
+        let mut extents = Vec::new();
+        let mut pending = vec![(root, None)];
+        while let Some((node, expected_depth)) = pending.pop() {
+            let entries = read_u16(&node, 2) as usize;
+            let depth = read_u16(&node, 6);
+            if read_u16(&node, 0) != EXT4_EXT_MAGIC
+                || EXT4_EXT_NODE_HEADER + entries * EXT4_EXT_ENTRY_SIZE > node.len()
+                || depth > EXT4_EXT_MAX_DEPTH
+                || expected_depth.is_some_and(|expected| expected != depth)
+            {
+                return Err("Invalid EXT4 extent tree.");
+            }
+            let entry = |i: usize| EXT4_EXT_NODE_HEADER + i * EXT4_EXT_ENTRY_SIZE;
+            if depth == 0 {
+                for i in 0..entries {
+                    let raw_len = read_u16(&node, entry(i) + 4) as u32;
+                    if read_u16(&node, entry(i) + 6) != 0 {
+                        return Err("EXT4 extent lies past the first 2^32 blocks.");
+                    }
+                    extents.push(Extent {
+                        logical: read_u32(&node, entry(i)),
+                        len: if raw_len > EXT4_EXT_INIT_MAX_LEN { raw_len - EXT4_EXT_INIT_MAX_LEN } else { raw_len },
+                        start: read_u32(&node, entry(i) + 8),
+                        unwritten: raw_len > EXT4_EXT_INIT_MAX_LEN,
+                    });
+                }
+                continue;
+            }
+            // Children are pushed last first so they come off the stack in order.
+            for i in (0..entries).rev() {
+                if read_u16(&node, entry(i) + 8) != 0 {
+                    return Err("EXT4 extent lies past the first 2^32 blocks.");
+                }
+                let child = read_u32(&node, entry(i) + 4);
+                if child >= self.blocks_count {
+                    return Err("Invalid EXT4 extent tree.");
+                }
+                let mut buf = self.block_buf();
+                self.read_block(child, &mut buf)?;
+                if let Some(seed) = seed {
+                    let tail = EXT4_EXT_NODE_HEADER + read_u16(&buf, 4) as usize * EXT4_EXT_ENTRY_SIZE;
+                    if tail + 4 > buf.len() || read_u32(&buf, tail) != crc32c(seed, &buf[..tail]) {
+                        return Err("EXT4 extent block checksum mismatch.");
+                    }
+                }
+                nodes.push(child);
+                pending.push((buf, Some(depth - 1)));
+            }
+        }
+        Ok(extents)
+    }
+
+    // Replays the journal when it holds committed transactions and clears needs_recovery,
+    // returning whether the disk changed. Replay writes, so a journal needing it on a
+    // volume this driver only reads is refused instead.
+    fn recover_journal(&mut self) -> Result<bool, &'static str> {
+        let ino = self.sb.journal_inum;
+        if self.sb.feature_compat & EXT3_FEATURE_COMPAT_HAS_JOURNAL == 0 || ino == 0 {
+            return Err("EXT3 journal needs recovery, but it is on another device.");
+        }
+        let blocks = self.journal_blocks(ino)?;
+        let mut journal = JournalFile { volume: self, blocks: &blocks };
+        let jsb = jbd2::read_superblock(&mut journal, self.block_size)?;
+        if self.read_only {
+            if jsb.needs_recovery() {
+                return Err("Journal needs recovery, which this driver cannot write here; mount it on Linux first.");
+            }
+            return Ok(false);
+        }
+        let replayed = jbd2::recover(&mut journal, self.block_size)?;
+        if replayed > 0 {
+            console::write_line(&format!("Journal: replayed {} transaction(s).", replayed));
+        }
+        let mut sb = read_superblock(&self.device, self.part_start)?;
+        sb.feature_incompat &= !EXT3_FEATURE_INCOMPAT_RECOVER;
+        write_superblock_at(&self.device, self.part_start + SUPERBLOCK_OFFSET / SECTOR_SIZE as u32, &sb)?;
+        Ok(true)
+    }
+
+    // Takes up the volume's own journal so updates go through it. A journal this driver
+    // cannot log into leaves the volume read-only; an external one is left alone.
+    fn open_journal(&mut self) -> Result<(), &'static str> {
+        let ino = self.sb.journal_inum;
+        if self.read_only || self.sb.feature_compat & EXT3_FEATURE_COMPAT_HAS_JOURNAL == 0 || ino == 0 {
+            return Ok(());
+        }
+        let blocks = self.journal_blocks(ino)?;
+        let jsb = jbd2::read_superblock(&mut JournalFile { volume: self, blocks: &blocks }, self.block_size)?;
+        if jsb.can_log() {
+            self.journal = Some(Journal { blocks, jsb });
+        } else {
+            self.read_only = true;
+        }
+        Ok(())
+    }
+
+    fn journal_blocks(&mut self, ino: u32) -> Result<Vec<u32>, &'static str> {
+        let inode = self.read_inode(ino)?;
+        let blocks = self.block_map(ino, &inode, (inode.len() / self.block_size as u64) as u32)?;
+        if blocks.contains(&0) {
+            return Err("EXT3 journal inode has holes.");
+        }
+        Ok(blocks)
+    }
+
+    // Makes the `len` blocks from `start`, already marked in use, the journal inode as mke2fs
+    // would, and turns on has_journal.
+    fn create_journal(&mut self, start: u32, len: u32, progress: &mut FormatProgress) -> Result<(), &'static str> {
+        let blocks: Vec<u32> = (start..start + len).collect();
+        let zero = self.block_buf();
+        for &block in &blocks {
+            self.write_block(block, &zero)?;
+            progress.advance(1);
+        }
+        jbd2::format(&mut JournalFile { volume: self, blocks: &blocks }, self.block_size, len)?;
+
+        let now = time::current_time_secs().unwrap_or(0) as u32;
+        let mut inode = Inode {
+            mode: 0x8180,
+            uid: 0,
+            size: len * self.block_size,
+            atime: now,
+            ctime: now,
+            mtime: now,
+            dtime: 0,
+            gid: 0,
+            links_count: 1,
+            blocks: 0,
+            flags: 0,
+            block: [0u32; 15],
+            generation: 0,
+            file_acl: 0,
+            size_high: 0,
+        };
+        let mut indirect_blocks = 0u32;
+        self.assign_blocks(&mut inode, &blocks, &mut indirect_blocks)?;
+        inode.blocks = (len + indirect_blocks) * self.block_sectors();
+        self.write_inode(EXT3_JOURNAL_INO, &inode)?;
+
+        // e2fsck can rebuild a damaged journal inode from this copy of its block map.
+        let (block, offset) = self.superblock_location();
+        let mut buf = self.block_buf();
+        self.read_block(block, &mut buf)?;
+        for (i, &pointer) in inode.block.iter().enumerate() {
+            write_u32(&mut buf, offset + 0x10C + i * 4, pointer);
+        }
+        write_u32(&mut buf, offset + 0x148, inode.size_high);
+        write_u32(&mut buf, offset + 0x14C, inode.size);
+        buf[offset + 0xFD] = EXT3_JNL_BACKUP_BLOCKS;
+        self.write_block(block, &buf)?;
+
+        self.sb.feature_compat |= EXT3_FEATURE_COMPAT_HAS_JOURNAL;
+        self.sb.journal_inum = EXT3_JOURNAL_INO;
+        self.write_superblock()?;
+        for group in 1..self.groups_count {
+            if group_has_super(group, self.sparse_super()) {
+                let lba = block_lba(self.part_start, self.block_size, self.group_start(group));
+                write_superblock_at(&self.device, lba, &Superblock { block_group_nr: group as u16, ..self.superblock_state() })?;
+            }
+        }
+        self.open_journal()
+    }
+
+    // Runs `op` as one transaction: the metadata blocks it writes are held back and go
+    // through the journal together when it returns. If `op` fails, or what it wrote does
+    // not fit in the journal, none of it reaches the disk. Without a journal, or inside
+    // another transaction, `op` just runs.
+    fn journaled<T>(&mut self, op: impl FnOnce(&mut Self) -> Result<T, &'static str>) -> Result<T, &'static str> {
+        if self.journal.is_none() || self.txn.is_some() {
+            return op(self);
+        }
+        self.txn = Some(BTreeMap::new());
+        let result = op(self).and_then(|value| self.commit().map(|()| value));
+        if result.is_err() {
+            self.discard()?;
+        }
+        result
+    }
+
+    fn commit(&mut self) -> Result<(), &'static str> {
+        self.txn_freed.clear();
+        let (Some(blocks), Some(journal)) = (self.txn.take(), self.journal.as_ref()) else {
+            return Ok(());
+        };
+        // A transaction too large for the log is refused rather than written in place, so
+        // the change is dropped whole. Volumes formatted here have room for the largest.
+        let mut jsb = journal.jsb;
+        jbd2::commit(&mut JournalFile { volume: self, blocks: &journal.blocks }, &mut jsb, &blocks)?;
+        if let Some(journal) = self.journal.as_mut() {
+            journal.jsb = jsb;
+        }
+        Ok(())
+    }
+
+    // Drops the transaction in progress. Every change to the free counts also rewrote the
+    // superblock and group descriptors, so reading them back undoes what the dropped
+    // blocks had counted.
+    fn discard(&mut self) -> Result<(), &'static str> {
+        self.txn = None;
+        self.txn_freed.clear();
+        let sb = read_superblock(&self.device, self.part_start)?;
+        self.free_blocks_count = sb.free_blocks_count;
+        self.free_inodes_count = sb.free_inodes_count;
+        self.group_desc = read_group_descs(&self.device, &self.desc_layout(), self.first_data_block + 1, self.groups_count)?;
+        Ok(())
+    }
+
     fn assign_blocks(&mut self, inode: &mut Inode, blocks: &[u32], indirect_blocks: &mut u32) -> Result<(), &'static str> {
         for slot in inode.block.iter_mut() {
             *slot = 0;
@@ -777,7 +1861,7 @@ impl<D: BlockDevice> Ext2Volume<D> {
         }
         remaining = &remaining[12..];
 
-        let mut single = vec![0u8; BLOCK_SIZE];
+        let mut single = self.block_buf();
         let single_block = self.allocate_block()?;
         *indirect_blocks = indirect_blocks.saturating_add(1);
         for (idx, &block) in remaining.iter().take(per_block as usize).enumerate() {
@@ -793,12 +1877,12 @@ impl<D: BlockDevice> Ext2Volume<D> {
 
         let double_block = self.allocate_block()?;
         *indirect_blocks = indirect_blocks.saturating_add(1);
-        let mut double = vec![0u8; BLOCK_SIZE];
+        let mut double = self.block_buf();
         let mut used = 0usize;
 
         while used < remaining.len() {
             let chunk = cmp::min(per_block as usize, remaining.len() - used);
-            let mut indirect = vec![0u8; BLOCK_SIZE];
+            let mut indirect = self.block_buf();
             let indirect_block = self.allocate_block()?;
             *indirect_blocks = indirect_blocks.saturating_add(1);
             for (idx, &block) in remaining[used..used + chunk].iter().enumerate() {
@@ -823,10 +1907,10 @@ impl<D: BlockDevice> Ext2Volume<D> {
         if block == 0 {
             return Ok(0);
         }
-        let mut buf = vec![0u8; BLOCK_SIZE];
+        let mut buf = self.block_buf();
         self.read_block(block, &mut buf)?;
         let offset = (index * 4) as usize;
-        if offset + 4 > BLOCK_SIZE {
+        if offset + 4 > buf.len() {
             return Err("Invalid EXT2 block index.");
         }
         Ok(read_u32(&buf, offset))
@@ -838,20 +1922,20 @@ impl<D: BlockDevice> Ext2Volume<D> {
                 continue;
             }
             let desc = self.group_desc[group as usize];
-            let mut bitmap = vec![0u8; BLOCK_SIZE];
+            let mut bitmap = self.block_buf();
             self.read_block(desc.block_bitmap, &mut bitmap)?;
-            let blocks_in_group = blocks_in_group(self.blocks_count, self.blocks_per_group, group);
+            let blocks_in_group = blocks_in_group(self.blocks_count, self.first_data_block, self.blocks_per_group, group);
             for bit in 0..blocks_in_group {
-                if !test_bit(&bitmap, bit) {
+                if !test_bit(&bitmap, bit) && !self.txn_freed.contains(&(self.group_start(group) + bit)) {
                     set_bit(&mut bitmap, bit);
                     self.write_block(desc.block_bitmap, &bitmap)?;
                     self.group_desc[group as usize].free_blocks_count = self.group_desc[group as usize]
                         .free_blocks_count
                         .saturating_sub(1);
                     self.free_blocks_count = self.free_blocks_count.saturating_sub(1);
-                    write_group_descs(&self.device, self.part_start, self.groups_count, &self.group_desc)?;
-                    write_superblock(&self.device, self.part_start, &self.superblock_state())?;
-                    return Ok(group * self.blocks_per_group + bit);
+                    self.write_group_descs()?;
+                    self.write_superblock()?;
+                    return Ok(self.group_start(group) + bit);
                 }
             }
         }
@@ -859,23 +1943,29 @@ impl<D: BlockDevice> Ext2Volume<D> {
     }
 
     fn free_block(&mut self, block: u32) -> Result<(), &'static str> {
-        let group = block / self.blocks_per_group;
-        let bit = block % self.blocks_per_group;
+        if block < self.first_data_block {
+            return Err("Invalid block.");
+        }
+        let group = (block - self.first_data_block) / self.blocks_per_group;
+        let bit = (block - self.first_data_block) % self.blocks_per_group;
         if group >= self.groups_count {
             return Err("Invalid block.");
         }
         let desc = self.group_desc[group as usize];
-        let mut bitmap = vec![0u8; BLOCK_SIZE];
+        let mut bitmap = self.block_buf();
         self.read_block(desc.block_bitmap, &mut bitmap)?;
         if test_bit(&bitmap, bit) {
             clear_bit(&mut bitmap, bit);
             self.write_block(desc.block_bitmap, &bitmap)?;
+            if self.txn.is_some() {
+                self.txn_freed.insert(block);
+            }
             self.group_desc[group as usize].free_blocks_count = self.group_desc[group as usize]
                 .free_blocks_count
                 .saturating_add(1);
             self.free_blocks_count = self.free_blocks_count.saturating_add(1);
-            write_group_descs(&self.device, self.part_start, self.groups_count, &self.group_desc)?;
-            write_superblock(&self.device, self.part_start, &self.superblock_state())?;
+            self.write_group_descs()?;
+            self.write_superblock()?;
         }
         Ok(())
     }
@@ -886,11 +1976,11 @@ impl<D: BlockDevice> Ext2Volume<D> {
                 continue;
             }
             let desc = self.group_desc[group as usize];
-            let mut bitmap = vec![0u8; BLOCK_SIZE];
+            let mut bitmap = self.block_buf();
             self.read_block(desc.inode_bitmap, &mut bitmap)?;
             for bit in 0..self.inodes_per_group {
                 let inode = group * self.inodes_per_group + bit + 1;
-                if inode < EXT2_FIRST_INO {
+                if inode < self.first_ino {
                     continue;
                 }
                 if !test_bit(&bitmap, bit) {
@@ -905,8 +1995,8 @@ impl<D: BlockDevice> Ext2Volume<D> {
                             .saturating_add(1);
                     }
                     self.free_inodes_count = self.free_inodes_count.saturating_sub(1);
-                    write_group_descs(&self.device, self.part_start, self.groups_count, &self.group_desc)?;
-                    write_superblock(&self.device, self.part_start, &self.superblock_state())?;
+                    self.write_group_descs()?;
+                    self.write_superblock()?;
                     return Ok(inode);
                 }
             }
@@ -915,10 +2005,15 @@ impl<D: BlockDevice> Ext2Volume<D> {
     }
 
     fn free_inode(&mut self, inode: u32) -> Result<(), &'static str> {
+        // Other implementations go by the link count and deletion time, not just the bitmap.
+        let mut entry = self.read_inode(inode)?;
+        entry.links_count = 0;
+        entry.dtime = time::current_time_secs().unwrap_or(0) as u32;
+        self.write_inode(inode, &entry)?;
         let group = self.group_index_for_inode(inode);
         let bit = (inode - 1) % self.inodes_per_group;
         let desc = self.group_desc[group as usize];
-        let mut bitmap = vec![0u8; BLOCK_SIZE];
+        let mut bitmap = self.block_buf();
         self.read_block(desc.inode_bitmap, &mut bitmap)?;
         if test_bit(&bitmap, bit) {
             clear_bit(&mut bitmap, bit);
@@ -927,8 +2022,8 @@ impl<D: BlockDevice> Ext2Volume<D> {
                 .free_inodes_count
                 .saturating_add(1);
             self.free_inodes_count = self.free_inodes_count.saturating_add(1);
-            write_group_descs(&self.device, self.part_start, self.groups_count, &self.group_desc)?;
-            write_superblock(&self.device, self.part_start, &self.superblock_state())?;
+            self.write_group_descs()?;
+            self.write_superblock()?;
         }
         Ok(())
     }
@@ -951,6 +2046,51 @@ impl<D: BlockDevice> Ext2Volume<D> {
         Ok(())
     }
 
+    // Frees what the indirect tree headed by `block` maps from its logical block `keep` on,
+    // along with any indirect block left empty, counting the blocks freed. Returns the
+    // block still heading the tree, zero once it maps nothing.
+    fn truncate_indirect(&mut self, block: u32, depth: u8, keep: u64, freed: &mut u32) -> Result<u32, &'static str> {
+        if block == 0 {
+            return Ok(0);
+        }
+        let per_block = (self.block_size / 4) as u64;
+        let span = per_block.pow(depth as u32 - 1);
+        let mut buf = self.block_buf();
+        self.read_block(block, &mut buf)?;
+        let mut changed = false;
+        let mut mapped = false;
+        for idx in 0..per_block as usize {
+            let ptr = read_u32(&buf, idx * 4);
+            if ptr == 0 {
+                continue;
+            }
+            let first = idx as u64 * span;
+            let kept = if keep >= first + span {
+                ptr
+            } else if depth == 1 {
+                self.free_block(ptr)?;
+                *freed += 1;
+                0
+            } else {
+                self.truncate_indirect(ptr, depth - 1, keep.saturating_sub(first), freed)?
+            };
+            if kept != ptr {
+                write_u32(&mut buf, idx * 4, kept);
+                changed = true;
+            }
+            mapped |= kept != 0;
+        }
+        if !mapped {
+            self.free_block(block)?;
+            *freed += 1;
+            return Ok(0);
+        }
+        if changed {
+            self.write_block(block, &buf)?;
+        }
+        Ok(block)
+    }
+
     fn free_indirect_chain(&mut self, block: u32, depth: u8) -> Result<(), &'static str> {
         if block == 0 {
             return Ok(());
@@ -959,7 +2099,7 @@ impl<D: BlockDevice> Ext2Volume<D> {
             self.free_block(block)?;
             return Ok(());
         }
-        let mut buf = vec![0u8; BLOCK_SIZE];
+        let mut buf = self.block_buf();
         self.read_block(block, &mut buf)?;
         let entries = (self.block_size / 4) as usize;
         for idx in 0..entries {
@@ -973,6 +2113,8 @@ impl<D: BlockDevice> Ext2Volume<D> {
 
     fn insert_dir_entry(&mut self, dir_inode: u32, name: &str, inode: u32, file_type: u8) -> Result<(), &'static str> {
         let mut dir = self.read_inode(dir_inode)?;
+        self.drop_dir_index(dir_inode, &mut dir)?;
+        let file_type = self.dirent_type(file_type);
         let entry_size = align4(8 + name.len());
         let mut block_idx = 0u32;
         let mut remaining = dir.size as u64;
@@ -982,13 +2124,13 @@ impl<D: BlockDevice> Ext2Volume<D> {
             if block == 0 {
                 break;
             }
-            let mut buf = vec![0u8; BLOCK_SIZE];
+            let mut buf = self.block_buf();
             self.read_block(block, &mut buf)?;
             let mut offset = 0usize;
-            while offset + 8 <= BLOCK_SIZE {
+            while offset + 8 <= buf.len() {
                 let rec_len = read_u16(&buf, offset + 4) as usize;
                 let name_len = buf[offset + 6] as usize;
-                if rec_len == 0 || offset + rec_len > BLOCK_SIZE {
+                if rec_len == 0 || offset + rec_len > buf.len() {
                     break;
                 }
                 let used = align4(8 + name_len);
@@ -1007,13 +2149,13 @@ impl<D: BlockDevice> Ext2Volume<D> {
                 offset += rec_len;
             }
             block_idx += 1;
-            remaining = remaining.saturating_sub(BLOCK_SIZE as u64);
+            remaining = remaining.saturating_sub(self.block_size as u64);
         }
 
         let new_block = self.allocate_block()?;
-        let mut buf = vec![0u8; BLOCK_SIZE];
+        let mut buf = self.block_buf();
         write_u32(&mut buf, 0, inode);
-        write_u16(&mut buf, 4, BLOCK_SIZE as u16);
+        write_u16(&mut buf, 4, self.block_size as u16);
         buf[6] = name.len() as u8;
         buf[7] = file_type;
         let name_bytes = name.as_bytes();
@@ -1021,11 +2163,13 @@ impl<D: BlockDevice> Ext2Volume<D> {
         self.write_block(new_block, &buf)?;
 
         self.set_inode_block(&mut dir, block_idx, new_block)?;
-        dir.size = dir.size.saturating_add(BLOCK_SIZE as u32);
-        dir.blocks = dir.blocks.saturating_add(BLOCK_SECTORS);
+        dir.size = dir.size.saturating_add(self.block_size);
+        dir.blocks = dir.blocks.saturating_add(self.block_sectors());
         self.write_inode(dir_inode, &dir)
     }
 
+    // Maps logical block `block_index` of an inode to `block`, allocating zeroed indirect
+    // blocks on the way; they are added to the inode's block count.
     fn set_inode_block(&mut self, inode: &mut Inode, block_index: u32, block: u32) -> Result<(), &'static str> {
         let per_block = self.block_size / 4;
         if block_index < 12 {
@@ -1035,7 +2179,7 @@ impl<D: BlockDevice> Ext2Volume<D> {
         let mut idx = block_index - 12;
         if idx < per_block {
             if inode.block[12] == 0 {
-                inode.block[12] = self.allocate_block()?;
+                inode.block[12] = self.allocate_indirect_block(inode)?;
             }
             self.write_indirect_entry(inode.block[12], idx, block)?;
             return Ok(());
@@ -1044,13 +2188,13 @@ impl<D: BlockDevice> Ext2Volume<D> {
         let per_double = per_block * per_block;
         if idx < per_double {
             if inode.block[13] == 0 {
-                inode.block[13] = self.allocate_block()?;
+                inode.block[13] = self.allocate_indirect_block(inode)?;
             }
             let outer = idx / per_block;
             let inner = idx % per_block;
             let first = self.read_indirect_block(inode.block[13], outer)?;
             let indirect = if first == 0 {
-                let new_block = self.allocate_block()?;
+                let new_block = self.allocate_indirect_block(inode)?;
                 self.write_indirect_entry(inode.block[13], outer, new_block)?;
                 new_block
             } else {
@@ -1059,14 +2203,22 @@ impl<D: BlockDevice> Ext2Volume<D> {
             self.write_indirect_entry(indirect, inner, block)?;
             return Ok(());
         }
-        Err("Directory too large for EXT2 driver.")
+        Err("File too large for EXT2 driver.")
+    }
+
+    fn allocate_indirect_block(&mut self, inode: &mut Inode) -> Result<u32, &'static str> {
+        let block = self.allocate_block()?;
+        let zero = self.block_buf();
+        self.write_block(block, &zero)?;
+        inode.blocks = inode.blocks.saturating_add(self.block_sectors());
+        Ok(block)
     }
 
     fn write_indirect_entry(&mut self, block: u32, index: u32, value: u32) -> Result<(), &'static str> {
-        let mut buf = vec![0u8; BLOCK_SIZE];
+        let mut buf = self.block_buf();
         self.read_block(block, &mut buf)?;
         let offset = (index * 4) as usize;
-        if offset + 4 > BLOCK_SIZE {
+        if offset + 4 > buf.len() {
             return Err("Invalid EXT2 block index.");
         }
         write_u32(&mut buf, offset, value);
@@ -1074,12 +2226,35 @@ impl<D: BlockDevice> Ext2Volume<D> {
     }
 
     fn clear_dir_entry(&mut self, dir_inode: u32, entry: &DirEntryInfo) -> Result<(), &'static str> {
-        let mut buf = vec![0u8; BLOCK_SIZE];
+        let mut buf = self.block_buf();
         self.read_block(entry.block, &mut buf)?;
         write_u32(&mut buf, entry.offset as usize, 0);
         self.write_block(entry.block, &buf)?;
-        let _ = dir_inode;
-        Ok(())
+        let mut dir = self.read_inode(dir_inode)?;
+        self.drop_dir_index(dir_inode, &mut dir)
+    }
+
+    // Hashed (dir_index) directories are kept as plain linear ones once we change them, so
+    // other systems stop trusting an index we did not update.
+    fn drop_dir_index(&mut self, dir_inode: u32, dir: &mut Inode) -> Result<(), &'static str> {
+        if dir.flags & EXT2_INDEX_FL == 0 {
+            return Ok(());
+        }
+        dir.flags &= !EXT2_INDEX_FL;
+        self.write_inode(dir_inode, dir)
+    }
+
+    fn has_filetype(&self) -> bool {
+        self.sb.feature_incompat & EXT2_FEATURE_INCOMPAT_FILETYPE != 0
+    }
+
+    // The type byte to store in a directory entry.
+    fn dirent_type(&self, file_type: u8) -> u8 {
+        if self.has_filetype() {
+            file_type
+        } else {
+            0
+        }
     }
 
     fn dir_is_empty(&mut self, inode: u32) -> Result<bool, &'static str> {
@@ -1093,25 +2268,335 @@ impl<D: BlockDevice> Ext2Volume<D> {
     }
 
     fn write_directory_block(&mut self, block: u32, inode: u32, parent: u32) -> Result<(), &'static str> {
-        let mut buf = vec![0u8; BLOCK_SIZE];
+        let mut buf = self.block_buf();
         write_u32(&mut buf, 0, inode);
         write_u16(&mut buf, 4, 12);
         buf[6] = 1;
-        buf[7] = EXT2_FT_DIR;
+        buf[7] = self.dirent_type(EXT2_FT_DIR);
         buf[8] = b'.';
 
         write_u32(&mut buf, 12, parent);
-        write_u16(&mut buf, 16, (BLOCK_SIZE - 12) as u16);
+        write_u16(&mut buf, 16, (self.block_size - 12) as u16);
         buf[18] = 2;
-        buf[19] = EXT2_FT_DIR;
+        buf[19] = self.dirent_type(EXT2_FT_DIR);
         buf[20] = b'.';
         buf[21] = b'.';
 
         self.write_block(block, &buf)
     }
 
-    fn write_root_dir(&mut self, block: u32) -> Result<(), &'static str> {
-        self.write_directory_block(block, ROOT_INODE, ROOT_INODE)
+    // Marks the superblock and its backups, group descriptors, bitmaps and inode tables
+    // as in use.
+    fn claim_metadata(&self, claimed: &mut [u8]) {
+        let table_blocks = (self.inodes_per_group * self.inode_size as u32).div_ceil(self.block_size);
+        let mut mark = |start: u32, end: u32| {
+            for block in start..end.min(self.blocks_count) {
+                set_bit(claimed, block);
+            }
+        };
+        mark(0, self.first_data_block);
+        for (group, desc) in self.group_desc.iter().enumerate() {
+            let start = self.group_start(group as u32);
+            mark(start, start + self.group_meta_blocks(group as u32));
+            mark(desc.block_bitmap, desc.block_bitmap + 1);
+            mark(desc.inode_bitmap, desc.inode_bitmap + 1);
+            mark(desc.inode_table, desc.inode_table + table_blocks);
+        }
+    }
+
+    // Claims every block an inode maps and returns its data blocks in order. Pointers
+    // that are out of range or already claimed are reported, and cleared when repairing.
+    fn fsck_blocks(&mut self, check: &mut Ext2Check, ino: u32, inode: &mut Inode) -> Result<Vec<u32>, &'static str> {
+        let mut data = Vec::new();
+        if !self.has_block_map(inode) {
+            return Ok(data);
+        }
+        // Extent-mapped inodes only occur on volumes mounted read-only, so nothing is cleared.
+        if inode.flags & EXT4_EXTENTS_FL != 0 {
+            let mut nodes = Vec::new();
+            let extents = match self.extents(ino, inode, &mut nodes) {
+                Ok(extents) => extents,
+                Err(err) => {
+                    check.report.problems.push(format!("inode {}: {}", ino, err));
+                    return Ok(data);
+                }
+            };
+            for node in nodes {
+                self.fsck_claim(check, ino, node, 0, &mut Vec::new())?;
+            }
+            for extent in extents {
+                for block in extent.start..extent.start.saturating_add(extent.len) {
+                    self.fsck_claim(check, ino, block, 0, &mut data)?;
+                }
+            }
+            return Ok(data);
+        }
+        let mut changed = false;
+        for slot in 0..15 {
+            let block = inode.block[slot];
+            let depth = slot.saturating_sub(11) as u8;
+            if block != 0 && !self.fsck_claim(check, ino, block, depth, &mut data)? && check.repair {
+                inode.block[slot] = 0;
+                changed = true;
+            }
+        }
+        if changed {
+            self.write_inode(ino, inode)?;
+        }
+        Ok(data)
+    }
+
+    fn fsck_claim(
+        &mut self,
+        check: &mut Ext2Check,
+        ino: u32,
+        block: u32,
+        depth: u8,
+        data: &mut Vec<u32>,
+    ) -> Result<bool, &'static str> {
+        let problem = if block >= self.blocks_count {
+            Some("is out of range")
+        } else if test_bit(&check.claimed, block) {
+            Some("is already in use")
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            if self.is_checked_inode(ino) {
+                check.report.problems.push(format!("inode {}: block {} {}", ino, block, problem));
+            }
+            return Ok(!self.is_checked_inode(ino));
+        }
+        set_bit(&mut check.claimed, block);
+        if depth == 0 {
+            data.push(block);
+            return Ok(true);
+        }
+        let mut buf = self.block_buf();
+        self.read_block(block, &mut buf)?;
+        let mut changed = false;
+        for idx in 0..buf.len() / 4 {
+            let ptr = read_u32(&buf, idx * 4);
+            if ptr != 0 && !self.fsck_claim(check, ino, ptr, depth - 1, data)? && check.repair {
+                write_u32(&mut buf, idx * 4, 0);
+                changed = true;
+            }
+        }
+        if changed {
+            self.write_block(block, &buf)?;
+        }
+        Ok(true)
+    }
+
+    // Checks one block of directory `dir`: record lengths, the inodes entries name, and
+    // `.` and `..`. Subdirectories met for the first time are queued on `pending`.
+    fn fsck_dir_block(
+        &mut self,
+        check: &mut Ext2Check,
+        dir: u32,
+        parent: u32,
+        block: u32,
+        path: &str,
+        pending: &mut Vec<(u32, u32, Vec<u32>, String)>,
+    ) -> Result<(), &'static str> {
+        let mut buf = self.block_buf();
+        self.read_block(block, &mut buf)?;
+        let mut changed = false;
+        let mut offset = 0usize;
+        while offset + 8 <= buf.len() {
+            let ino = read_u32(&buf, offset);
+            let rec_len = read_u16(&buf, offset + 4) as usize;
+            let name_len = buf[offset + 6] as usize;
+            if rec_len < 8 + name_len || rec_len & 3 != 0 || offset + rec_len > buf.len() {
+                check.report.problems.push(format!("{}: corrupt directory entry in block {}", path, block));
+                if check.repair {
+                    // Whatever followed is lost; one empty entry covers the rest of the block.
+                    write_u32(&mut buf, offset, 0);
+                    write_u16(&mut buf, offset + 4, (self.block_size as usize - offset) as u16);
+                    buf[offset + 6] = 0;
+                    changed = true;
+                }
+                break;
+            }
+            if ino != 0 {
+                let name = String::from_utf8_lossy(&buf[offset + 8..offset + 8 + name_len]).into_owned();
+                let child = format!("{}{}{}", path, if path.ends_with('\\') { "" } else { "\\" }, name);
+                let expected = match name.as_str() {
+                    "." => Some(dir),
+                    ".." => Some(parent),
+                    _ => None,
+                };
+                let mut clear = false;
+                match expected {
+                    Some(expected) => {
+                        if expected != ino {
+                            check.report.problems.push(format!("{}: '{}' points to inode {}", path, name, ino));
+                            if check.repair {
+                                write_u32(&mut buf, offset, expected);
+                                changed = true;
+                            }
+                        }
+                        check.refs[expected as usize] = check.refs[expected as usize].saturating_add(1);
+                    }
+                    None if ino > self.inodes_count => {
+                        check.report.problems.push(format!("{}: inode {} is out of range", child, ino));
+                        clear = true;
+                    }
+                    None => {
+                        let mut inode = self.read_inode(ino)?;
+                        if inode.links_count == 0 || inode.mode == 0 {
+                            check.report.problems.push(format!("{}: inode {} is not in use", child, ino));
+                            clear = true;
+                        } else if inode.is_dir() && test_bit(&check.dirs, ino) {
+                            check.report.problems.push(format!("{}: directory inode {} is linked twice", child, ino));
+                            clear = true;
+                        } else {
+                            let first = check.refs[ino as usize] == 0;
+                            check.refs[ino as usize] = check.refs[ino as usize].saturating_add(1);
+                            if inode.is_dir() {
+                                set_bit(&mut check.dirs, ino);
+                                let blocks = self.fsck_blocks(check, ino, &mut inode)?;
+                                pending.push((ino, dir, blocks, child));
+                            } else {
+                                check.report.files += 1;
+                                if first {
+                                    self.fsck_blocks(check, ino, &mut inode)?;
+                                }
+                            }
+                        }
+                    }
+                }
+                if clear && check.repair {
+                    write_u32(&mut buf, offset, 0);
+                    changed = true;
+                }
+            }
+            offset += rec_len;
+        }
+        if changed {
+            self.write_block(block, &buf)?;
+        }
+        Ok(())
+    }
+
+    // Compares each group's bitmaps and counts with the blocks and inodes found in use,
+    // rewriting them when repairing. Inodes that are marked but unreferenced are released.
+    fn fsck_bitmaps(&mut self, check: &mut Ext2Check) -> Result<(), &'static str> {
+        let (mut leaked_blocks, mut missing_blocks) = (0u32, 0u32);
+        let (mut leaked_inodes, mut missing_inodes) = (0u32, 0u32);
+        let mut counts_wrong = false;
+        let (mut free_blocks_total, mut free_inodes_total) = (0u32, 0u32);
+        let now = time::current_time_secs().unwrap_or(0) as u32;
+
+        for group in 0..self.groups_count {
+            let desc = self.group_desc[group as usize];
+
+            // Groups flagged uninitialised have no bitmaps on disk: no inodes are in use, and
+            // the blocks in use are just the metadata, which the walk claimed already.
+            let mut bitmap = self.block_buf();
+            if desc.flags & EXT4_BG_BLOCK_UNINIT == 0 {
+                self.read_block(desc.block_bitmap, &mut bitmap)?;
+            }
+            // Padding bits past the end of the group are kept as they are.
+            let mut expected = bitmap.clone();
+            let in_group = blocks_in_group(self.blocks_count, self.first_data_block, self.blocks_per_group, group);
+            let group_start = self.group_start(group);
+            let mut free_blocks = 0u32;
+            for bit in 0..self.blocks_per_group {
+                let used = bit >= in_group || test_bit(&check.claimed, group_start + bit);
+                if used {
+                    set_bit(&mut expected, bit);
+                } else {
+                    clear_bit(&mut expected, bit);
+                    free_blocks += 1;
+                }
+                let marked = if desc.flags & EXT4_BG_BLOCK_UNINIT != 0 { used } else { test_bit(&bitmap, bit) };
+                match (used, marked) {
+                    (false, true) => leaked_blocks += 1,
+                    (true, false) => missing_blocks += 1,
+                    _ => {}
+                }
+            }
+
+            let mut inode_bitmap = self.block_buf();
+            if desc.flags & EXT4_BG_INODE_UNINIT == 0 {
+                self.read_block(desc.inode_bitmap, &mut inode_bitmap)?;
+            }
+            let mut expected_inodes = inode_bitmap.clone();
+            let (mut free_inodes, mut used_dirs) = (0u32, 0u32);
+            for bit in 0..self.inodes_per_group {
+                let ino = group * self.inodes_per_group + bit + 1;
+                let used = ino < self.first_ino || check.refs[ino as usize] > 0;
+                if used {
+                    set_bit(&mut expected_inodes, bit);
+                } else {
+                    clear_bit(&mut expected_inodes, bit);
+                    free_inodes += 1;
+                }
+                if test_bit(&check.dirs, ino) {
+                    used_dirs += 1;
+                }
+                match (used, test_bit(&inode_bitmap, bit)) {
+                    (false, true) => {
+                        leaked_inodes += 1;
+                        if check.repair {
+                            let mut inode = self.read_inode(ino)?;
+                            inode.links_count = 0;
+                            inode.dtime = now;
+                            self.write_inode(ino, &inode)?;
+                        }
+                    }
+                    (true, false) => missing_inodes += 1,
+                    _ => {}
+                }
+            }
+
+            if check.repair {
+                if expected != bitmap {
+                    self.write_block(desc.block_bitmap, &expected)?;
+                }
+                if expected_inodes != inode_bitmap {
+                    self.write_block(desc.inode_bitmap, &expected_inodes)?;
+                }
+            }
+            let counted = (free_blocks as u16, free_inodes as u16, used_dirs as u16);
+            if (desc.free_blocks_count, desc.free_inodes_count, desc.used_dirs_count) != counted {
+                counts_wrong = true;
+                if check.repair {
+                    let desc = &mut self.group_desc[group as usize];
+                    (desc.free_blocks_count, desc.free_inodes_count, desc.used_dirs_count) = counted;
+                }
+            }
+            free_blocks_total += free_blocks;
+            free_inodes_total += free_inodes;
+        }
+
+        let report = &mut check.report;
+        if leaked_blocks > 0 {
+            report.problems.push(format!("{} block(s) marked in use but not owned by any inode", leaked_blocks));
+        }
+        if missing_blocks > 0 {
+            report.problems.push(format!("{} block(s) in use but marked free", missing_blocks));
+        }
+        if leaked_inodes > 0 {
+            report.problems.push(format!("{} inode(s) marked in use but not in any directory", leaked_inodes));
+        }
+        if missing_inodes > 0 {
+            report.problems.push(format!("{} inode(s) in use but marked free", missing_inodes));
+        }
+        if counts_wrong || (self.free_blocks_count, self.free_inodes_count) != (free_blocks_total, free_inodes_total) {
+            report.problems.push(String::from("free block and inode counts are wrong"));
+            if check.repair {
+                self.free_blocks_count = free_blocks_total;
+                self.free_inodes_count = free_inodes_total;
+            }
+        }
+        Ok(())
+    }
+
+    // Reserved inodes other than the root are left to the tools that made them.
+    fn is_checked_inode(&self, ino: u32) -> bool {
+        ino == ROOT_INODE || ino >= self.first_ino
     }
 
     fn group_index_for_inode(&self, inode: u32) -> u32 {
@@ -1120,127 +2605,322 @@ impl<D: BlockDevice> Ext2Volume<D> {
 
     fn superblock_state(&self) -> Superblock {
         Superblock {
-            inodes_count: self.inodes_count,
-            blocks_count: self.blocks_count,
             free_blocks_count: self.free_blocks_count,
             free_inodes_count: self.free_inodes_count,
-            first_data_block: self.first_data_block,
-            log_block_size: 2,
-            blocks_per_group: self.blocks_per_group,
-            inodes_per_group: self.inodes_per_group,
-            mtime: 0,
             wtime: time::current_time_secs().unwrap_or(0) as u32,
-            mnt_count: 0,
-            max_mnt_count: 0xFFFF,
-            magic: EXT2_SUPER_MAGIC,
-            state: 1,
-            errors: 1,
-            rev_level: EXT2_REV_DYNAMIC,
-            first_ino: EXT2_FIRST_INO,
-            inode_size: INODE_SIZE,
-            volume_name: self.volume_name,
+            state: self.state,
+            ..self.sb
         }
     }
+
+    // Both go through `write_block` so that a transaction picks them up.
+    fn write_superblock(&mut self) -> Result<(), &'static str> {
+        let (block, offset) = self.superblock_location();
+        let mut buf = self.block_buf();
+        self.read_block(block, &mut buf)?;
+        encode_superblock(&mut buf[offset..offset + SUPERBLOCK_SIZE], &self.superblock_state());
+        self.write_block(block, &buf)
+    }
+
+    fn write_group_descs(&mut self) -> Result<(), &'static str> {
+        let first_block = self.first_data_block + 1;
+        let blocks = group_desc_blocks(self.groups_count, self.block_size, self.desc_size);
+        let mut buf = vec![0u8; (blocks * self.block_size) as usize];
+        for (i, chunk) in buf.chunks_mut(self.block_size as usize).enumerate() {
+            self.read_block(first_block + i as u32, chunk)?;
+        }
+        encode_group_descs(&mut buf, &self.desc_layout(), &self.group_desc);
+        for (i, chunk) in buf.chunks(self.block_size as usize).enumerate() {
+            self.write_block(first_block + i as u32, chunk)?;
+        }
+        Ok(())
+    }
+
+    // The block holding the primary superblock and the superblock's offset in it.
+    fn superblock_location(&self) -> (u32, usize) {
+        (SUPERBLOCK_OFFSET / self.block_size, (SUPERBLOCK_OFFSET % self.block_size) as usize)
+    }
+
+    fn desc_layout(&self) -> DescLayout {
+        DescLayout {
+            part_start: self.part_start,
+            block_size: self.block_size,
+            desc_size: self.desc_size,
+            csum_seed: self.csum_seed,
+        }
+    }
+
+    fn block_buf(&self) -> Vec<u8> {
+        vec![0u8; self.block_size as usize]
+    }
+
+    // `i_blocks` counts 512-byte sectors.
+    fn block_sectors(&self) -> u32 {
+        self.block_size / SECTOR_SIZE as u32
+    }
+
+    fn group_start(&self, group: u32) -> u32 {
+        self.first_data_block + group * self.blocks_per_group
+    }
+
+    fn sparse_super(&self) -> bool {
+        self.sb.feature_ro_compat & EXT2_FEATURE_RO_COMPAT_SPARSE_SUPER != 0
+    }
+
+    // Blocks at the start of `group` taken by a superblock backup, the descriptor table
+    // copy and the descriptor blocks reserved for online resizing.
+    fn group_meta_blocks(&self, group: u32) -> u32 {
+        let reserved = if self.sb.feature_compat & EXT2_FEATURE_COMPAT_RESIZE_INODE != 0 {
+            self.sb.reserved_gdt_blocks as u32
+        } else {
+            0
+        };
+        let desc_blocks = group_desc_blocks(self.groups_count, self.block_size, self.desc_size);
+        group_meta_blocks(group, self.sparse_super(), desc_blocks + reserved)
+    }
+
+    // Compares each backup superblock and descriptor table with the primary, rewriting
+    // the ones that differ when repairing.
+    fn fsck_backups(&mut self, check: &mut Ext2Check) -> Result<(), &'static str> {
+        let primary = self.superblock_state();
+        let mut stale = 0u32;
+        for group in 1..self.groups_count {
+            if !group_has_super(group, self.sparse_super()) {
+                continue;
+            }
+            let block = self.group_start(group);
+            let lba = block_lba(self.part_start, self.block_size, block);
+            let backup = read_superblock_at(&self.device, lba)?;
+            // Only the primary table's checksums matter; backups are compared by layout.
+            let layout = DescLayout { csum_seed: None, ..self.desc_layout() };
+            let descs = read_group_descs(&self.device, &layout, block + 1, self.groups_count)?;
+            let same_geometry = backup.magic == EXT2_SUPER_MAGIC
+                && backup.blocks_count == primary.blocks_count
+                && backup.inodes_count == primary.inodes_count
+                && backup.blocks_per_group == primary.blocks_per_group
+                && backup.log_block_size == primary.log_block_size
+                && backup.feature_incompat | EXT3_FEATURE_INCOMPAT_RECOVER
+                    == primary.feature_incompat | EXT3_FEATURE_INCOMPAT_RECOVER;
+            let same_layout = descs.iter().zip(self.group_desc.iter()).all(|(a, b)| {
+                (a.block_bitmap, a.inode_bitmap, a.inode_table) == (b.block_bitmap, b.inode_bitmap, b.inode_table)
+            });
+            if same_geometry && same_layout {
+                continue;
+            }
+            stale += 1;
+            if check.repair {
+                let copy = Superblock { block_group_nr: group as u16, ..primary };
+                write_superblock_at(&self.device, lba, &copy)?;
+                write_group_descs(&self.device, &self.desc_layout(), block + 1, &self.group_desc)?;
+            }
+        }
+        if stale > 0 {
+            check.report.problems.push(format!("{} backup superblock(s) do not match the primary", stale));
+        }
+        Ok(())
+    }
+}
+
+// State carried through one `fsck` pass: a bitmap of the blocks claimed so far, how many
+// directory entries name each inode, and a bitmap of the directories reached from the root.
+struct Ext2Check {
+    report: FsckReport,
+    repair: bool,
+    claimed: Vec<u8>,
+    refs: Vec<u16>,
+    dirs: Vec<u8>,
+}
+
+// A run of `len` blocks from logical block `logical` of a file, stored from `start`.
+struct Extent {
+    logical: u32,
+    len: u32,
+    start: u32,
+    unwritten: bool,
+}
+
+// A journal this driver writes transactions into: its inode's blocks and its superblock
+// as last written.
+struct Journal {
+    blocks: Vec<u32>,
+    jsb: jbd2::JournalSuperblock,
+}
+
+// The journal as the blocks of its inode. Writes bypass any transaction.
+struct JournalFile<'a, D: BlockDevice> {
+    volume: &'a Ext2Volume<D>,
+    blocks: &'a [u32],
+}
+
+impl<D: BlockDevice> jbd2::JournalIo for JournalFile<'_, D> {
+    fn journal_len(&self) -> u32 {
+        self.blocks.len() as u32
+    }
+
+    fn read_journal(&mut self, block: u32, buf: &mut [u8]) -> Result<(), &'static str> {
+        let block = *self.blocks.get(block as usize).ok_or("Journal block out of range.")?;
+        self.volume.read_block(block, buf)
+    }
+
+    fn write_journal(&mut self, block: u32, buf: &[u8]) -> Result<(), &'static str> {
+        let block = *self.blocks.get(block as usize).ok_or("Journal block out of range.")?;
+        self.volume.write_block_direct(block, buf)
+    }
+
+    fn write_fs(&mut self, block: u32, buf: &[u8]) -> Result<(), &'static str> {
+        if block >= self.volume.blocks_count {
+            return Err("Journal refers to a block outside the volume.");
+        }
+        self.volume.write_block_direct(block, buf)
+    }
+}
+
+// Where the group descriptor table lives and how its entries are laid out.
+struct DescLayout {
+    part_start: u32,
+    block_size: u32,
+    desc_size: u32,
+    csum_seed: Option<u32>,
 }
 
 fn parse_superblock(block: &[u8]) -> Result<Superblock, &'static str> {
-    if block.len() < 1024 + 200 {
+    if block.len() < SUPERBLOCK_SIZE {
         return Err("Invalid EXT2 superblock buffer.");
     }
-    let base = 1024;
-    let inodes_count = read_u32(block, base + 0);
-    let blocks_count = read_u32(block, base + 4);
-    let free_blocks_count = read_u32(block, base + 12);
-    let free_inodes_count = read_u32(block, base + 16);
-    let first_data_block = read_u32(block, base + 20);
-    let log_block_size = read_u32(block, base + 24);
-    let blocks_per_group = read_u32(block, base + 32);
-    let inodes_per_group = read_u32(block, base + 40);
-    let mtime = read_u32(block, base + 44);
-    let wtime = read_u32(block, base + 48);
-    let mnt_count = read_u16(block, base + 52);
-    let max_mnt_count = read_u16(block, base + 54);
-    let magic = read_u16(block, base + 56);
-    let state = read_u16(block, base + 58);
-    let errors = read_u16(block, base + 60);
-    let rev_level = read_u32(block, base + 76);
-    let first_ino = read_u32(block, base + 84);
-    let inode_size = read_u16(block, base + 88);
     let mut volume_name = [0u8; 16];
-    volume_name.copy_from_slice(&block[base + 120..base + 136]);
+    volume_name.copy_from_slice(&block[120..136]);
 
     Ok(Superblock {
-        inodes_count,
-        blocks_count,
-        free_blocks_count,
-        free_inodes_count,
-        first_data_block,
-        log_block_size,
-        blocks_per_group,
-        inodes_per_group,
-        mtime,
-        wtime,
-        mnt_count,
-        max_mnt_count,
-        magic,
-        state,
-        errors,
-        rev_level,
-        first_ino,
-        inode_size,
+        inodes_count: read_u32(block, 0),
+        blocks_count: read_u32(block, 4),
+        free_blocks_count: read_u32(block, 12),
+        free_inodes_count: read_u32(block, 16),
+        first_data_block: read_u32(block, 20),
+        log_block_size: read_u32(block, 24),
+        blocks_per_group: read_u32(block, 32),
+        inodes_per_group: read_u32(block, 40),
+        mtime: read_u32(block, 44),
+        wtime: read_u32(block, 48),
+        mnt_count: read_u16(block, 52),
+        max_mnt_count: read_u16(block, 54),
+        magic: read_u16(block, 56),
+        state: read_u16(block, 58),
+        errors: read_u16(block, 60),
+        rev_level: read_u32(block, 76),
+        first_ino: read_u32(block, 84),
+        inode_size: read_u16(block, 88),
+        block_group_nr: read_u16(block, 90),
+        feature_compat: read_u32(block, 92),
+        feature_incompat: read_u32(block, 96),
+        feature_ro_compat: read_u32(block, 100),
         volume_name,
+        reserved_gdt_blocks: read_u16(block, 206),
+        journal_inum: read_u32(block, 224),
+        desc_size: read_u16(block, 254),
+        blocks_count_hi: read_u32(block, 336),
     })
 }
 
-fn write_superblock<D: BlockDevice>(dev: &D, part_start: u32, sb: &Superblock) -> Result<(), &'static str> {
-    let mut block0 = vec![0u8; BLOCK_SIZE];
-    read_block_raw(dev, part_start, 0, &mut block0)?;
-    let base = 1024;
-    write_u32(&mut block0, base + 0, sb.inodes_count);
-    write_u32(&mut block0, base + 4, sb.blocks_count);
-    write_u32(&mut block0, base + 12, sb.free_blocks_count);
-    write_u32(&mut block0, base + 16, sb.free_inodes_count);
-    write_u32(&mut block0, base + 20, sb.first_data_block);
-    write_u32(&mut block0, base + 24, sb.log_block_size);
-    write_u32(&mut block0, base + 32, sb.blocks_per_group);
-    write_u32(&mut block0, base + 40, sb.inodes_per_group);
-    write_u32(&mut block0, base + 44, sb.mtime);
-    write_u32(&mut block0, base + 48, sb.wtime);
-    write_u16(&mut block0, base + 52, sb.mnt_count);
-    write_u16(&mut block0, base + 54, sb.max_mnt_count);
-    write_u16(&mut block0, base + 56, sb.magic);
-    write_u16(&mut block0, base + 58, sb.state);
-    write_u16(&mut block0, base + 60, sb.errors);
-    write_u32(&mut block0, base + 76, sb.rev_level);
-    write_u32(&mut block0, base + 84, sb.first_ino);
-    write_u16(&mut block0, base + 88, sb.inode_size);
-    block0[base + 120..base + 136].copy_from_slice(&sb.volume_name);
-    write_block_raw(dev, part_start, 0, &block0)
+// The primary superblock sits 1024 bytes into the partition whatever the block size.
+fn read_superblock<D: BlockDevice>(dev: &D, part_start: u32) -> Result<Superblock, &'static str> {
+    read_superblock_at(dev, part_start + SUPERBLOCK_OFFSET / SECTOR_SIZE as u32)
+}
+
+fn read_superblock_at<D: BlockDevice>(dev: &D, lba: u32) -> Result<Superblock, &'static str> {
+    parse_superblock(&read_superblock_raw(dev, lba)?)
+}
+
+fn read_superblock_raw<D: BlockDevice>(dev: &D, lba: u32) -> Result<[u8; SUPERBLOCK_SIZE], &'static str> {
+    let mut buf = [0u8; SUPERBLOCK_SIZE];
+    for (idx, chunk) in buf.chunks_mut(SECTOR_SIZE).enumerate() {
+        dev.read_block(lba as u64 + idx as u64, chunk).map_err(|err| err.as_str())?;
+    }
+    Ok(buf)
+}
+
+fn write_superblock_at<D: BlockDevice>(dev: &D, lba: u32, sb: &Superblock) -> Result<(), &'static str> {
+    let mut buf = read_superblock_raw(dev, lba)?;
+    encode_superblock(&mut buf, sb);
+    for (idx, chunk) in buf.chunks(SECTOR_SIZE).enumerate() {
+        dev.write_block(lba as u64 + idx as u64, chunk).map_err(|err| err.as_str())?;
+    }
+    Ok(())
 }
 
-fn read_group_descs<D: BlockDevice>(dev: &D, part_start: u32, groups: u32) -> Result<Vec<GroupDesc>, &'static str> {
-    let total_bytes = groups as usize * 32;
-    let mut buf = vec![0u8; ((total_bytes + BLOCK_SIZE - 1) / BLOCK_SIZE) * BLOCK_SIZE];
-    for (i, chunk) in buf.chunks_mut(BLOCK_SIZE).enumerate() {
-        read_block_raw(dev, part_start, 1 + i as u32, chunk)?;
+// Rewrites the fields this driver knows, leaving the rest of the superblock as it was.
+fn encode_superblock(buf: &mut [u8], sb: &Superblock) {
+    write_u32(buf, 0, sb.inodes_count);
+    write_u32(buf, 4, sb.blocks_count);
+    write_u32(buf, 12, sb.free_blocks_count);
+    write_u32(buf, 16, sb.free_inodes_count);
+    write_u32(buf, 20, sb.first_data_block);
+    write_u32(buf, 24, sb.log_block_size);
+    // The fragment size mirrors the block size; fragments were never implemented.
+    write_u32(buf, 28, sb.log_block_size);
+    write_u32(buf, 32, sb.blocks_per_group);
+    write_u32(buf, 36, sb.blocks_per_group);
+    write_u32(buf, 40, sb.inodes_per_group);
+    write_u32(buf, 44, sb.mtime);
+    write_u32(buf, 48, sb.wtime);
+    write_u16(buf, 52, sb.mnt_count);
+    write_u16(buf, 54, sb.max_mnt_count);
+    write_u16(buf, 56, sb.magic);
+    write_u16(buf, 58, sb.state);
+    write_u16(buf, 60, sb.errors);
+    write_u32(buf, 76, sb.rev_level);
+    if sb.rev_level >= EXT2_REV_DYNAMIC {
+        write_u32(buf, 84, sb.first_ino);
+        write_u16(buf, 88, sb.inode_size);
+        write_u16(buf, 90, sb.block_group_nr);
+        write_u32(buf, 92, sb.feature_compat);
+        write_u32(buf, 96, sb.feature_incompat);
+        write_u32(buf, 100, sb.feature_ro_compat);
+        write_u16(buf, 206, sb.reserved_gdt_blocks);
+        write_u32(buf, 224, sb.journal_inum);
+    }
+    buf[120..136].copy_from_slice(&sb.volume_name);
+    if sb.feature_ro_compat & EXT4_FEATURE_RO_COMPAT_METADATA_CSUM != 0 {
+        let checksum = crc32c(!0, &buf[..SB_CHECKSUM_OFFSET]);
+        write_u32(buf, SB_CHECKSUM_OFFSET, checksum);
+    }
+}
+
+// Reads `groups` descriptors from the table starting at `first_block`, checking their
+// checksums when the layout has a seed.
+fn read_group_descs<D: BlockDevice>(
+    dev: &D,
+    layout: &DescLayout,
+    first_block: u32,
+    groups: u32,
+) -> Result<Vec<GroupDesc>, &'static str> {
+    let (block_size, desc_size) = (layout.block_size, layout.desc_size as usize);
+    let blocks = group_desc_blocks(groups, block_size, desc_size as u32);
+    let mut buf = vec![0u8; (blocks * block_size) as usize];
+    for (i, chunk) in buf.chunks_mut(block_size as usize).enumerate() {
+        read_block_raw(dev, layout.part_start, block_size, first_block + i as u32, chunk)?;
     }
 
     let mut descs = Vec::new();
     for i in 0..groups as usize {
-        let base = i * 32;
-        let block_bitmap = read_u32(&buf, base);
-        let inode_bitmap = read_u32(&buf, base + 4);
-        let inode_table = read_u32(&buf, base + 8);
-        let free_blocks_count = read_u16(&buf, base + 12);
-        let free_inodes_count = read_u16(&buf, base + 14);
-        let used_dirs_count = read_u16(&buf, base + 16);
+        let base = i * desc_size;
+        let raw = &buf[base..base + desc_size];
+        if let Some(seed) = layout.csum_seed {
+            if read_u16(raw, 0x1E) != group_desc_checksum(seed, i as u32, raw) {
+                return Err("EXT4 group descriptor checksum mismatch.");
+            }
+        }
+        // The high halves of 64-bit descriptors must be zero for block numbers to fit.
+        if desc_size >= GROUP_DESC_SIZE_64BIT && raw[0x20..0x2C].iter().any(|&b| b != 0) {
+            return Err("EXT4 volume is too large for this driver.");
+        }
         descs.push(GroupDesc {
-            block_bitmap,
-            inode_bitmap,
-            inode_table,
-            free_blocks_count,
-            free_inodes_count,
-            used_dirs_count,
+            block_bitmap: read_u32(raw, 0),
+            inode_bitmap: read_u32(raw, 4),
+            inode_table: read_u32(raw, 8),
+            free_blocks_count: read_u16(raw, 12),
+            free_inodes_count: read_u16(raw, 14),
+            used_dirs_count: read_u16(raw, 16),
+            flags: read_u16(raw, 18),
         });
     }
     Ok(descs)
@@ -1248,29 +2928,43 @@ fn read_group_descs<D: BlockDevice>(dev: &D, part_start: u32, groups: u32) -> Re
 
 fn write_group_descs<D: BlockDevice>(
     dev: &D,
-    part_start: u32,
-    groups: u32,
+    layout: &DescLayout,
+    first_block: u32,
     descs: &[GroupDesc],
 ) -> Result<(), &'static str> {
-    let total_bytes = groups as usize * 32;
-    let mut buf = vec![0u8; ((total_bytes + BLOCK_SIZE - 1) / BLOCK_SIZE) * BLOCK_SIZE];
-    for (i, desc) in descs.iter().enumerate() {
-        let base = i * 32;
-        write_u32(&mut buf, base, desc.block_bitmap);
-        write_u32(&mut buf, base + 4, desc.inode_bitmap);
-        write_u32(&mut buf, base + 8, desc.inode_table);
-        write_u16(&mut buf, base + 12, desc.free_blocks_count);
-        write_u16(&mut buf, base + 14, desc.free_inodes_count);
-        write_u16(&mut buf, base + 16, desc.used_dirs_count);
+    let (block_size, desc_size) = (layout.block_size, layout.desc_size as usize);
+    let blocks = group_desc_blocks(descs.len() as u32, block_size, desc_size as u32);
+    let mut buf = vec![0u8; (blocks * block_size) as usize];
+    for (i, chunk) in buf.chunks_mut(block_size as usize).enumerate() {
+        read_block_raw(dev, layout.part_start, block_size, first_block + i as u32, chunk)?;
     }
-
-    for (i, chunk) in buf.chunks(BLOCK_SIZE).enumerate() {
-        write_block_raw(dev, part_start, 1 + i as u32, chunk)?;
+    encode_group_descs(&mut buf, layout, descs);
+    for (i, chunk) in buf.chunks(block_size as usize).enumerate() {
+        write_block_raw(dev, layout.part_start, block_size, first_block + i as u32, chunk)?;
     }
-
     Ok(())
 }
 
+// Fills in `descs` over the descriptor table in `buf`, keeping the fields this driver
+// does not know.
+fn encode_group_descs(buf: &mut [u8], layout: &DescLayout, descs: &[GroupDesc]) {
+    let desc_size = layout.desc_size as usize;
+    for (i, desc) in descs.iter().enumerate() {
+        let base = i * desc_size;
+        write_u32(buf, base, desc.block_bitmap);
+        write_u32(buf, base + 4, desc.inode_bitmap);
+        write_u32(buf, base + 8, desc.inode_table);
+        write_u16(buf, base + 12, desc.free_blocks_count);
+        write_u16(buf, base + 14, desc.free_inodes_count);
+        write_u16(buf, base + 16, desc.used_dirs_count);
+        write_u16(buf, base + 18, desc.flags);
+        if let Some(seed) = layout.csum_seed {
+            let checksum = group_desc_checksum(seed, i as u32, &buf[base..base + desc_size]);
+            write_u16(buf, base + 0x1E, checksum);
+        }
+    }
+}
+
 fn parse_inode(buf: &[u8], offset: usize) -> Result<Inode, &'static str> {
     if offset + INODE_SIZE as usize > buf.len() {
         return Err("Invalid inode offset.");
@@ -1290,6 +2984,9 @@ fn parse_inode(buf: &[u8], offset: usize) -> Result<Inode, &'static str> {
     for i in 0..15 {
         block[i] = read_u32(buf, offset + 40 + i * 4);
     }
+    let generation = read_u32(buf, offset + 100);
+    let file_acl = read_u32(buf, offset + 104);
+    let size_high = read_u32(buf, offset + 108);
 
     Ok(Inode {
         mode,
@@ -1304,6 +3001,9 @@ fn parse_inode(buf: &[u8], offset: usize) -> Result<Inode, &'static str> {
         blocks,
         flags,
         block,
+        generation,
+        file_acl,
+        size_high,
     })
 }
 
@@ -1322,11 +3022,22 @@ fn write_inode(buf: &mut [u8], offset: usize, inode: &Inode) {
     for i in 0..15 {
         write_u32(buf, offset + 40 + i * 4, inode.block[i]);
     }
+    write_u32(buf, offset + 104, inode.file_acl);
+    write_u32(buf, offset + 108, inode.size_high);
 }
 
-fn read_block_raw<D: BlockDevice>(dev: &D, part_start: u32, block: u32, buf: &mut [u8]) -> Result<(), &'static str> {
-    let lba_start = part_start
-        .saturating_add(block.saturating_mul(BLOCK_SECTORS));
+fn block_lba(part_start: u32, block_size: u32, block: u32) -> u32 {
+    part_start.saturating_add(block.saturating_mul(block_size / SECTOR_SIZE as u32))
+}
+
+fn read_block_raw<D: BlockDevice>(
+    dev: &D,
+    part_start: u32,
+    block_size: u32,
+    block: u32,
+    buf: &mut [u8],
+) -> Result<(), &'static str> {
+    let lba_start = block_lba(part_start, block_size, block);
     for (idx, chunk) in buf.chunks_mut(SECTOR_SIZE).enumerate() {
         let lba = lba_start.saturating_add(idx as u32);
         dev.read_block(lba as u64, chunk).map_err(|err| err.as_str())?;
@@ -1334,9 +3045,14 @@ fn read_block_raw<D: BlockDevice>(dev: &D, part_start: u32, block: u32, buf: &mu
     Ok(())
 }
 
-fn write_block_raw<D: BlockDevice>(dev: &D, part_start: u32, block: u32, buf: &[u8]) -> Result<(), &'static str> {
-    let lba_start = part_start
-        .saturating_add(block.saturating_mul(BLOCK_SECTORS));
+fn write_block_raw<D: BlockDevice>(
+    dev: &D,
+    part_start: u32,
+    block_size: u32,
+    block: u32,
+    buf: &[u8],
+) -> Result<(), &'static str> {
+    let lba_start = block_lba(part_start, block_size, block);
     for (idx, chunk) in buf.chunks(SECTOR_SIZE).enumerate() {
         let lba = lba_start.saturating_add(idx as u32);
         dev.write_block(lba as u64, chunk).map_err(|err| err.as_str())?;
@@ -1357,6 +3073,27 @@ fn write_mbr<D: BlockDevice>(dev: &D, part_start: u32, part_sectors: u32) -> Res
     dev.write_block(0, &sector).map_err(|err| err.as_str())
 }
 
+// Journal blocks for a new volume of `total_blocks`, scaled as mke2fs does but never too
+// small for the largest transaction; volumes too small to spare the minimum go without one.
+fn format_journal_len(total_blocks: u32, groups_count: u32, desc_blocks: u32) -> u32 {
+    let scaled = match total_blocks {
+        0..=2047 => return 0,
+        2048..=32767 => jbd2::JBD2_MIN_JOURNAL_BLOCKS,
+        32768..=262143 => 4096,
+        262144..=524287 => 8192,
+        _ => 16384,
+    };
+    // Rewriting the largest file that fits logs its indirect blocks, every group's block
+    // bitmap, the descriptors, the superblock and a block of the inode table.
+    let per_block = FORMAT_BLOCK_SIZE as u32 / 4;
+    let file_blocks = cmp::min(total_blocks, 12 + per_block + per_block * per_block);
+    let indirect = file_blocks.div_ceil(per_block) + file_blocks.div_ceil(per_block * per_block) + 3;
+    let logged = indirect + groups_count + desc_blocks + 2;
+    // Plus a descriptor block per batch of tags, the commit block and the journal superblock.
+    let tags_per_descriptor = (FORMAT_BLOCK_SIZE as u32 - 28) / 8;
+    cmp::max(scaled, logged + logged.div_ceil(tags_per_descriptor) + 2)
+}
+
 fn build_volume_name(label: &str) -> [u8; 16] {
     let mut out = [0u8; 16];
     let trimmed = label.trim();
@@ -1367,7 +3104,7 @@ fn build_volume_name(label: &str) -> [u8; 16] {
 }
 
 fn compute_inodes_per_group(blocks_per_group: u32) -> u32 {
-    let bytes = blocks_per_group as u64 * BLOCK_SIZE as u64;
+    let bytes = blocks_per_group as u64 * FORMAT_BLOCK_SIZE as u64;
     let mut inodes = (bytes / 16384) as u32;
     if inodes < 128 {
         inodes = 128;
@@ -1377,19 +3114,135 @@ fn compute_inodes_per_group(blocks_per_group: u32) -> u32 {
 
 fn inode_table_blocks(inodes_per_group: u32) -> u32 {
     let bytes = inodes_per_group as u64 * INODE_SIZE as u64;
-    ((bytes + BLOCK_SIZE as u64 - 1) / BLOCK_SIZE as u64) as u32
-}
-
-fn root_dir_block(inode_table_blocks: u32) -> u32 {
-    4 + inode_table_blocks
+    bytes.div_ceil(FORMAT_BLOCK_SIZE as u64) as u32
 }
 
-fn blocks_in_group(total_blocks: u32, blocks_per_group: u32, group: u32) -> u32 {
-    let start = group * blocks_per_group;
+fn blocks_in_group(total_blocks: u32, first_data_block: u32, blocks_per_group: u32, group: u32) -> u32 {
+    let start = first_data_block + group * blocks_per_group;
     let remaining = total_blocks.saturating_sub(start);
     cmp::min(remaining, blocks_per_group)
 }
 
+fn group_desc_blocks(groups: u32, block_size: u32, desc_size: u32) -> u32 {
+    (groups * desc_size).div_ceil(block_size)
+}
+
+// With sparse_super only groups 0, 1 and powers of 3, 5 and 7 keep a superblock backup.
+fn group_has_super(group: u32, sparse: bool) -> bool {
+    if !sparse || group <= 1 {
+        return true;
+    }
+    [3u32, 5, 7].iter().any(|&base| {
+        let mut power = base;
+        while power < group {
+            power = power.saturating_mul(base);
+        }
+        power == group
+    })
+}
+
+fn group_meta_blocks(group: u32, sparse: bool, desc_blocks: u32) -> u32 {
+    if group_has_super(group, sparse) {
+        1 + desc_blocks
+    } else {
+        0
+    }
+}
+
+const CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+const fn crc32c_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0x82F6_3B78 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// CRC32C (Castagnoli) continuing from `crc`, without the usual final inversion; the
+/// form ext4 and JBD2 checksums use.
+pub fn crc32c(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc = CRC32C_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+// Checks the superblock checksum and returns the seed the other metadata_csum checksums
+// start from, or None without the feature.
+fn metadata_csum_seed(raw: &[u8], sb: &Superblock) -> Result<Option<u32>, &'static str> {
+    if sb.feature_ro_compat & EXT4_FEATURE_RO_COMPAT_METADATA_CSUM == 0 {
+        return Ok(None);
+    }
+    if raw[0x175] != EXT4_CRC32C_CHKSUM {
+        return Err("EXT4 volume uses an unknown checksum type.");
+    }
+    if read_u32(raw, SB_CHECKSUM_OFFSET) != crc32c(!0, &raw[..SB_CHECKSUM_OFFSET]) {
+        return Err("EXT4 superblock checksum mismatch.");
+    }
+    if sb.feature_incompat & EXT4_FEATURE_INCOMPAT_CSUM_SEED != 0 {
+        Ok(Some(read_u32(raw, 0x270)))
+    } else {
+        Ok(Some(crc32c(!0, &raw[0x68..0x78])))
+    }
+}
+
+// Inode, extent and directory block checksums also cover the inode number and
+// generation.
+fn inode_csum_seed(seed: u32, ino: u32, generation: u32) -> u32 {
+    crc32c(crc32c(seed, &ino.to_le_bytes()), &generation.to_le_bytes())
+}
+
+// The checksum is split between offsets 0x7C and 0x82, and is only 16 bits when the
+// inode has no room for the high half.
+fn inode_checksum_ok(seed: u32, ino: u32, raw: &[u8]) -> bool {
+    let mut crc = inode_csum_seed(seed, ino, read_u32(raw, 100));
+    crc = crc32c(crc, &raw[..0x7C]);
+    crc = crc32c(crc, &[0, 0]);
+    crc = crc32c(crc, &raw[0x7E..INODE_SIZE as usize]);
+    let has_high = raw.len() > INODE_SIZE as usize && read_u16(raw, INODE_SIZE as usize) >= 4;
+    if raw.len() > INODE_SIZE as usize {
+        crc = crc32c(crc, &raw[INODE_SIZE as usize..0x82]);
+        if has_high {
+            crc = crc32c(crc, &[0, 0]);
+            crc = crc32c(crc, &raw[0x84..]);
+        } else {
+            crc = crc32c(crc, &raw[0x82..]);
+        }
+    }
+    let stored = read_u16(raw, 0x7C) as u32;
+    if has_high {
+        stored | (read_u16(raw, 0x82) as u32) << 16 == crc
+    } else {
+        stored == crc & 0xFFFF
+    }
+}
+
+fn group_desc_checksum(seed: u32, group: u32, raw: &[u8]) -> u16 {
+    let mut crc = crc32c(seed, &group.to_le_bytes());
+    crc = crc32c(crc, &raw[..0x1E]);
+    crc = crc32c(crc, &[0, 0]);
+    crc = crc32c(crc, &raw[0x20..]);
+    crc as u16
+}
+
+// Directory leaf blocks end in a fake entry holding their checksum; blocks without one
+// (htree index blocks) are not checked.
+fn dir_block_checksum_ok(seed: u32, buf: &[u8]) -> bool {
+    let tail = buf.len() - 12;
+    let has_tail =
+        read_u32(buf, tail) == 0 && read_u16(buf, tail + 4) == 12 && buf[tail + 6] == 0 && buf[tail + 7] == 0xDE;
+    !has_tail || read_u32(buf, tail + 8) == crc32c(seed, &buf[..tail])
+}
+
 fn align4(value: usize) -> usize {
     (value + 3) & !3
 }
@@ -3,15 +3,87 @@ use core::cmp;
 
 use crate::{
     block::{BlockDevice, BlockDeviceError},
-    time,
+    memory, time,
 };
 
 const SECTOR_SIZE: usize = 512;
 const DIR_ENTRY_SIZE: usize = 32;
 const ENTRIES_PER_SECTOR: usize = SECTOR_SIZE / DIR_ENTRY_SIZE;
-const FAT32_PART_TYPES: [u8; 2] = [0x0B, 0x0C];
+const FAT_PART_TYPES: [u8; 6] = [0x01, 0x04, 0x06, 0x0B, 0x0C, 0x0E];
 const FAT_EOC: u32 = 0x0FFFFFF8;
 const FAT_BAD: u32 = 0x0FFFFFF7;
+/// Bits of FAT[1] that are set once the volume has been cleanly unmounted (FAT12 has none).
+const FAT32_CLEAN_SHUTDOWN: u32 = 0x0800_0000;
+const FAT16_CLEAN_SHUTDOWN: u32 = 0x8000;
+/// Volumes with fewer clusters than these are FAT12 and FAT16 respectively.
+const FAT12_MAX_CLUSTERS: u32 = 4085;
+const FAT16_MAX_CLUSTERS: u32 = 65525;
+/// Root directory size given to FAT12 and FAT16 volumes by `format`.
+const FORMAT_ROOT_ENTRIES: u16 = 512;
+
+/// The FAT entry width, which also decides where the root directory lives.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    pub fn label(self) -> &'static str {
+        match self {
+            FatType::Fat12 => "FAT12",
+            FatType::Fat16 => "FAT16",
+            FatType::Fat32 => "FAT32",
+        }
+    }
+
+    /// The MBR partition type for a volume of this kind and size.
+    pub fn partition_type(self, sectors: u32) -> u8 {
+        match self {
+            FatType::Fat12 => 0x01,
+            FatType::Fat16 if sectors < 65536 => 0x04,
+            FatType::Fat16 => 0x0E,
+            FatType::Fat32 => 0x0C,
+        }
+    }
+
+    fn mask(self) -> u32 {
+        match self {
+            FatType::Fat12 => 0x0FFF,
+            FatType::Fat16 => 0xFFFF,
+            FatType::Fat32 => 0x0FFF_FFFF,
+        }
+    }
+
+    // Extends the bad-cluster and end-of-chain markers of narrow FATs to their FAT32
+    // values, so the rest of the driver only deals with one set of markers.
+    fn widen(self, value: u32) -> u32 {
+        let mask = self.mask();
+        if value >= FAT_BAD & mask {
+            value | (0x0FFF_FFFF & !mask)
+        } else {
+            value
+        }
+    }
+
+    fn clean_bit(self) -> u32 {
+        match self {
+            FatType::Fat12 => 0,
+            FatType::Fat16 => FAT16_CLEAN_SHUTDOWN,
+            FatType::Fat32 => FAT32_CLEAN_SHUTDOWN,
+        }
+    }
+
+    // Bytes taken by the first `entries` FAT entries.
+    fn fat_bytes(self, entries: u32) -> u32 {
+        match self {
+            FatType::Fat12 => (entries * 3).div_ceil(2),
+            FatType::Fat16 => entries * 2,
+            FatType::Fat32 => entries * 4,
+        }
+    }
+}
 
 #[derive(Copy, Clone)]
 pub struct PartitionInfo {
@@ -29,20 +101,35 @@ pub struct MbrInfo {
 
 #[derive(Copy, Clone)]
 pub struct Fat32Info {
+    pub fat_type: FatType,
     pub bytes_per_sector: u16,
     pub sectors_per_cluster: u8,
-    #[allow(dead_code)]
     pub reserved_sectors: u16,
-    #[allow(dead_code)]
     pub num_fats: u8,
     pub sectors_per_fat: u32,
-    #[allow(dead_code)]
+    /// Zero on FAT12 and FAT16, whose root directory has a fixed region instead.
     pub root_cluster: u32,
-    #[allow(dead_code)]
+    pub root_entries: u16,
     pub total_sectors: u32,
     pub volume_label: [u8; 11],
 }
 
+impl Fat32Info {
+    /// Sectors taken by the fixed root directory of FAT12 and FAT16 volumes.
+    pub fn root_dir_sectors(&self) -> u32 {
+        (self.root_entries as u32 * DIR_ENTRY_SIZE as u32).div_ceil(SECTOR_SIZE as u32)
+    }
+
+    pub fn cluster_count(&self) -> u32 {
+        let data_sectors = self
+            .total_sectors
+            .saturating_sub(self.reserved_sectors as u32)
+            .saturating_sub(self.num_fats as u32 * self.sectors_per_fat)
+            .saturating_sub(self.root_dir_sectors());
+        data_sectors / cmp::max(self.sectors_per_cluster as u32, 1)
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct FatUsage {
     pub total_clusters: u32,
@@ -50,6 +137,25 @@ pub struct FatUsage {
     pub cluster_size: u32,
 }
 
+/// What `fsck` found on a volume. `repaired` is set when the problems were also fixed.
+pub struct FsckReport {
+    pub problems: Vec<String>,
+    pub repaired: bool,
+    pub files: u32,
+    pub dirs: u32,
+}
+
+impl FsckReport {
+    pub fn new(repair: bool) -> Self {
+        Self {
+            problems: Vec::new(),
+            repaired: repair,
+            files: 0,
+            dirs: 0,
+        }
+    }
+}
+
 pub fn read_mbr<D: BlockDevice>(dev: &D) -> Result<MbrInfo, D::Error> {
     let mut sector = [0u8; SECTOR_SIZE];
     dev.read_block(0, &mut sector)?;
@@ -91,9 +197,10 @@ pub fn read_mbr<D: BlockDevice>(dev: &D) -> Result<MbrInfo, D::Error> {
     })
 }
 
+/// Finds the first FAT12, FAT16 or FAT32 partition.
 pub fn find_fat32_partition(mbr: &MbrInfo) -> Option<PartitionInfo> {
     for part in mbr.partitions.iter().flatten() {
-        if FAT32_PART_TYPES.contains(&part.type_code) {
+        if FAT_PART_TYPES.contains(&part.type_code) {
             return Some(*part);
         }
     }
@@ -116,7 +223,8 @@ fn is_bad_cluster(value: u32) -> bool {
     value == FAT_BAD
 }
 
-fn fat_date_time() -> (u16, u16, u8) {
+/// The current time as a FAT date, time and tenth-of-second byte; zeros without a clock.
+pub fn fat_date_time() -> (u16, u16, u8) {
     let Some(secs) = time::current_time_secs() else {
         return (0, 0, 0);
     };
@@ -131,6 +239,22 @@ fn fat_date_time() -> (u16, u16, u8) {
     (date, time, 0)
 }
 
+/// Decodes a FAT date and time (two-second resolution); a zero date means unset.
+pub fn fat_timestamp(date: u16, clock: u16) -> Option<u64> {
+    let (month, day) = ((date >> 5) & 0x0F, date & 0x1F);
+    if date == 0 || !(1..=12).contains(&month) || day == 0 {
+        return None;
+    }
+    Some(time::ymd_hms_to_secs(
+        1980 + (date >> 9) as u64,
+        month as u64,
+        day as u64,
+        (clock >> 11) as u64,
+        ((clock >> 5) & 0x3F) as u64,
+        (clock & 0x1F) as u64 * 2,
+    ))
+}
+
 fn is_short_name_char(ch: char) -> bool {
     if ch.is_ascii_alphanumeric() {
         return true;
@@ -385,7 +509,6 @@ fn decode_lfn_name(units: &[u16]) -> String {
 pub struct DirEntryInfo {
     pub name: String,
     pub is_dir: bool,
-    #[allow(dead_code)]
     pub attr: u8,
     pub cluster: u32,
     pub size: u32,
@@ -394,6 +517,10 @@ pub struct DirEntryInfo {
     pub short_name: [u8; 11],
     #[allow(dead_code)]
     pub nt_reserved: u8,
+    /// Creation, last-write and last-access times in Unix seconds, when recorded.
+    pub created: Option<u64>,
+    pub modified: Option<u64>,
+    pub accessed: Option<u64>,
 }
 
 struct FatCache {
@@ -417,34 +544,41 @@ pub struct Fat32Volume<D: BlockDevice> {
     pub part_start: u32,
     #[allow(dead_code)]
     pub part_sectors: u32,
+    fat_type: FatType,
     bytes_per_sector: u16,
     sectors_per_cluster: u8,
     reserved_sectors: u16,
     num_fats: u8,
     sectors_per_fat: u32,
     root_cluster: u32,
+    root_entries: u16,
     fsinfo_sector: u16,
     total_sectors: u32,
     volume_label: [u8; 11],
     fat_cache: FatCache,
     free_count: Option<u32>,
     next_free: u32,
+    // The free count or next-free hint changed since FSInfo was last written.
+    fsinfo_dirty: bool,
 }
 
 impl<D: BlockDevice> Fat32Volume<D> {
     pub fn info(&self) -> Fat32Info {
         Fat32Info {
+            fat_type: self.fat_type,
             bytes_per_sector: self.bytes_per_sector,
             sectors_per_cluster: self.sectors_per_cluster,
             reserved_sectors: self.reserved_sectors,
             num_fats: self.num_fats,
             sectors_per_fat: self.sectors_per_fat,
             root_cluster: self.root_cluster,
+            root_entries: self.root_entries,
             total_sectors: self.total_sectors,
             volume_label: self.volume_label,
         }
     }
 
+    /// The root directory's first cluster, or zero when it has a fixed region.
     pub fn root_cluster(&self) -> u32 {
         self.root_cluster
     }
@@ -460,27 +594,27 @@ impl<D: BlockDevice> Fat32Volume<D> {
         })
     }
 
+    /// Mounts a FAT volume. The FAT type is decided by the cluster count, except that a
+    /// boot sector with FAT32's layout (no fixed root, no 16-bit FAT size) is taken as
+    /// FAT32 whatever its size, as older releases formatted small disks that way.
     pub fn open(dev: D, part: PartitionInfo) -> Result<Self, &'static str> {
         let mut sector = [0u8; SECTOR_SIZE];
         read_sector(&dev, part.lba_start, &mut sector)?;
         if sector[510] != 0x55 || sector[511] != 0xAA {
-            return Err("Invalid FAT32 boot sector.");
+            return Err("Invalid FAT boot sector.");
         }
 
         let bytes_per_sector = u16::from_le_bytes([sector[11], sector[12]]);
         if bytes_per_sector != SECTOR_SIZE as u16 {
-            return Err("Unsupported FAT32 sector size.");
+            return Err("Unsupported FAT sector size.");
         }
         let sectors_per_cluster = sector[13];
-        if sectors_per_cluster == 0 {
-            return Err("Invalid FAT32 cluster size.");
+        if sectors_per_cluster == 0 || !sectors_per_cluster.is_power_of_two() {
+            return Err("Invalid FAT cluster size.");
         }
         let reserved_sectors = u16::from_le_bytes([sector[14], sector[15]]);
         let num_fats = sector[16];
         let root_entries = u16::from_le_bytes([sector[17], sector[18]]);
-        if root_entries != 0 {
-            return Err("Not a FAT32 volume.");
-        }
         let total_sectors_16 = u16::from_le_bytes([sector[19], sector[20]]);
         let total_sectors_32 = u32::from_le_bytes([sector[32], sector[33], sector[34], sector[35]]);
         let total_sectors = if total_sectors_16 != 0 {
@@ -488,117 +622,164 @@ impl<D: BlockDevice> Fat32Volume<D> {
         } else {
             total_sectors_32
         };
-        let sectors_per_fat = u32::from_le_bytes([sector[36], sector[37], sector[38], sector[39]]);
-        let root_cluster = u32::from_le_bytes([sector[44], sector[45], sector[46], sector[47]]);
-        let fsinfo_sector = u16::from_le_bytes([sector[48], sector[49]]);
-        let mut volume_label = [b' '; 11];
-        volume_label.copy_from_slice(&sector[71..82]);
+        let sectors_per_fat_16 = u16::from_le_bytes([sector[22], sector[23]]);
+        let fat32_layout = root_entries == 0 && sectors_per_fat_16 == 0;
+        let sectors_per_fat = if fat32_layout {
+            u32::from_le_bytes([sector[36], sector[37], sector[38], sector[39]])
+        } else {
+            sectors_per_fat_16 as u32
+        };
+
+        if total_sectors == 0 || sectors_per_fat == 0 || reserved_sectors == 0 || num_fats == 0 {
+            return Err("Invalid FAT size fields.");
+        }
 
-        if total_sectors == 0 || sectors_per_fat == 0 {
-            return Err("Invalid FAT32 size fields.");
+        let mut info = Fat32Info {
+            fat_type: FatType::Fat32,
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors,
+            num_fats,
+            sectors_per_fat,
+            root_cluster: 0,
+            root_entries,
+            total_sectors,
+            volume_label: [b' '; 11],
+        };
+        let clusters = info.cluster_count();
+        info.fat_type = if fat32_layout {
+            FatType::Fat32
+        } else if clusters < FAT12_MAX_CLUSTERS {
+            FatType::Fat12
+        } else if clusters < FAT16_MAX_CLUSTERS {
+            FatType::Fat16
+        } else {
+            return Err("FAT32 volume has a fixed root directory.");
+        };
+        if info.fat_type.fat_bytes(clusters + 2) > sectors_per_fat.saturating_mul(SECTOR_SIZE as u32) {
+            return Err("FAT is smaller than the volume.");
         }
 
+        // The extended boot record follows the BPB, which is longer on FAT32.
+        let (root_cluster, fsinfo_sector, label_at) = if fat32_layout {
+            let root_cluster = u32::from_le_bytes([sector[44], sector[45], sector[46], sector[47]]);
+            (root_cluster, u16::from_le_bytes([sector[48], sector[49]]), 71)
+        } else {
+            (0, 0, 43)
+        };
+        let mut volume_label = [b' '; 11];
+        volume_label.copy_from_slice(&sector[label_at..label_at + 11]);
+
         let mut volume = Self {
             device: dev,
             part_start: part.lba_start,
             part_sectors: part.sectors,
+            fat_type: info.fat_type,
             bytes_per_sector,
             sectors_per_cluster,
             reserved_sectors,
             num_fats,
             sectors_per_fat,
             root_cluster,
+            root_entries,
             fsinfo_sector,
             total_sectors,
             volume_label,
             fat_cache: FatCache::new(),
             free_count: None,
             next_free: 2,
+            fsinfo_dirty: false,
         };
 
-        volume.load_fsinfo().ok();
+        if volume.fat_type == FatType::Fat32 {
+            volume.load_fsinfo().ok();
+        }
+        if volume.free_count.is_none() {
+            // Only FAT32 keeps a free count on disk; a missing one is rebuilt and written
+            // back with the next update.
+            volume.free_count = Some(volume.count_free_clusters(clusters)?);
+            volume.fsinfo_dirty = volume.fat_type == FatType::Fat32;
+        }
         Ok(volume)
     }
 
+    /// Formats the partition as FAT32, or as FAT16 (FAT12 for the smallest) when it is
+    /// too small to hold the minimum number of FAT32 clusters.
     pub fn format(dev: D, part_start: u32, part_sectors: u32, label: &str) -> Result<Self, &'static str> {
-        if part_sectors < 8192 {
-            return Err("Disk too small for FAT32.");
-        }
-
         let bytes_per_sector = SECTOR_SIZE as u16;
-        let sectors_per_cluster = choose_sectors_per_cluster(part_sectors);
-        let reserved_sectors = 32u16;
         let num_fats = 2u8;
-
         let total_sectors = part_sectors;
-        let mut sectors_per_fat = 1u32;
-        loop {
-            let data_sectors = total_sectors
-                .saturating_sub(reserved_sectors as u32)
-                .saturating_sub(num_fats as u32 * sectors_per_fat);
-            let clusters = data_sectors / sectors_per_cluster as u32;
-            let fat_bytes = clusters.saturating_mul(4);
-            let needed = (fat_bytes + (bytes_per_sector as u32 - 1)) / bytes_per_sector as u32;
-            if needed == sectors_per_fat {
-                break;
-            }
-            sectors_per_fat = needed;
-        }
+        let (fat_type, sectors_per_cluster, sectors_per_fat) = choose_fat_layout(total_sectors, num_fats)?;
+        let is_fat32 = fat_type == FatType::Fat32;
+        let reserved_sectors = if is_fat32 { 32u16 } else { 1u16 };
+        let root_entries = if is_fat32 { 0 } else { FORMAT_ROOT_ENTRIES };
+        let root_dir_sectors = (root_entries as u32 * DIR_ENTRY_SIZE as u32).div_ceil(SECTOR_SIZE as u32);
 
-        let root_cluster = 2u32;
+        let root_cluster = if is_fat32 { 2u32 } else { 0 };
         let fsinfo_sector = 1u16;
         let backup_boot = 6u16;
         let volume_label = build_volume_label(label);
 
-        write_mbr(&dev, part_start, part_sectors)?;
+        write_mbr(&dev, part_start, part_sectors, fat_type.partition_type(part_sectors))?;
 
         let mut boot = [0u8; SECTOR_SIZE];
         boot[0] = 0xEB;
-        boot[1] = 0x58;
+        boot[1] = if is_fat32 { 0x58 } else { 0x3C };
         boot[2] = 0x90;
         boot[3..11].copy_from_slice(b"AXIOMATA");
         boot[11..13].copy_from_slice(&bytes_per_sector.to_le_bytes());
         boot[13] = sectors_per_cluster;
         boot[14..16].copy_from_slice(&reserved_sectors.to_le_bytes());
         boot[16] = num_fats;
-        boot[17..19].copy_from_slice(&0u16.to_le_bytes());
-        boot[19..21].copy_from_slice(&0u16.to_le_bytes());
+        boot[17..19].copy_from_slice(&root_entries.to_le_bytes());
         boot[21] = 0xF8;
-        boot[22..24].copy_from_slice(&0u16.to_le_bytes());
         boot[24..26].copy_from_slice(&63u16.to_le_bytes());
         boot[26..28].copy_from_slice(&255u16.to_le_bytes());
         boot[28..32].copy_from_slice(&part_start.to_le_bytes());
-        boot[32..36].copy_from_slice(&total_sectors.to_le_bytes());
-        boot[36..40].copy_from_slice(&sectors_per_fat.to_le_bytes());
-        boot[40..42].copy_from_slice(&0u16.to_le_bytes());
-        boot[42..44].copy_from_slice(&0u16.to_le_bytes());
-        boot[44..48].copy_from_slice(&root_cluster.to_le_bytes());
-        boot[48..50].copy_from_slice(&fsinfo_sector.to_le_bytes());
-        boot[50..52].copy_from_slice(&backup_boot.to_le_bytes());
-        boot[64] = 0x80;
-        boot[66] = 0x29;
+        if !is_fat32 && total_sectors < 0x10000 {
+            boot[19..21].copy_from_slice(&(total_sectors as u16).to_le_bytes());
+        } else {
+            boot[32..36].copy_from_slice(&total_sectors.to_le_bytes());
+        }
         let serial = volume_id_from_time();
-        boot[67..71].copy_from_slice(&serial.to_le_bytes());
-        boot[71..82].copy_from_slice(&volume_label);
-        boot[82..90].copy_from_slice(b"FAT32   ");
+        if is_fat32 {
+            boot[36..40].copy_from_slice(&sectors_per_fat.to_le_bytes());
+            boot[44..48].copy_from_slice(&root_cluster.to_le_bytes());
+            boot[48..50].copy_from_slice(&fsinfo_sector.to_le_bytes());
+            boot[50..52].copy_from_slice(&backup_boot.to_le_bytes());
+            boot[64] = 0x80;
+            boot[66] = 0x29;
+            boot[67..71].copy_from_slice(&serial.to_le_bytes());
+            boot[71..82].copy_from_slice(&volume_label);
+            boot[82..90].copy_from_slice(b"FAT32   ");
+        } else {
+            boot[22..24].copy_from_slice(&(sectors_per_fat as u16).to_le_bytes());
+            boot[36] = 0x80;
+            boot[38] = 0x29;
+            boot[39..43].copy_from_slice(&serial.to_le_bytes());
+            boot[43..54].copy_from_slice(&volume_label);
+            boot[54..62].copy_from_slice(if fat_type == FatType::Fat12 { b"FAT12   " } else { b"FAT16   " });
+        }
         boot[510] = 0x55;
         boot[511] = 0xAA;
 
         write_sector(&dev, part_start, &boot)?;
 
-        let fsinfo = build_fsinfo(total_sectors, reserved_sectors as u32, sectors_per_cluster as u32, num_fats as u32, sectors_per_fat);
-        write_sector(&dev, part_start + fsinfo_sector as u32, &fsinfo)?;
-        write_sector(&dev, part_start + backup_boot as u32, &boot)?;
-
         let zero = [0u8; SECTOR_SIZE];
-        for offset in 2..reserved_sectors {
-            if offset == backup_boot {
-                continue;
-            }
-            if offset == fsinfo_sector {
-                continue;
+        if is_fat32 {
+            let fsinfo = build_fsinfo(total_sectors, reserved_sectors as u32, sectors_per_cluster as u32, num_fats as u32, sectors_per_fat);
+            write_sector(&dev, part_start + fsinfo_sector as u32, &fsinfo)?;
+            write_sector(&dev, part_start + backup_boot as u32, &boot)?;
+
+            for offset in 2..reserved_sectors {
+                if offset == backup_boot {
+                    continue;
+                }
+                if offset == fsinfo_sector {
+                    continue;
+                }
+                write_sector(&dev, part_start + offset as u32, &zero)?;
             }
-            write_sector(&dev, part_start + offset as u32, &zero)?;
         }
 
         let fat_start = part_start + reserved_sectors as u32;
@@ -607,23 +788,31 @@ impl<D: BlockDevice> Fat32Volume<D> {
             write_sector(&dev, fat_start + i, &zero)?;
         }
 
+        // FAT[0] holds the media byte, FAT[1] the end-of-chain marker with the clean
+        // bit set; FAT32 also ends the root directory's chain in FAT[2].
         let mut fat_sector = [0u8; SECTOR_SIZE];
-        fat_sector[0..4].copy_from_slice(&0x0FFFFFF8u32.to_le_bytes());
-        fat_sector[4..8].copy_from_slice(&0x0FFFFFFFu32.to_le_bytes());
-        fat_sector[8..12].copy_from_slice(&0x0FFFFFFFu32.to_le_bytes());
-        write_sector(&dev, fat_start, &fat_sector)?;
-        write_sector(&dev, fat_start + sectors_per_fat, &fat_sector)?;
+        match fat_type {
+            FatType::Fat12 => fat_sector[0..3].copy_from_slice(&[0xF8, 0xFF, 0xFF]),
+            FatType::Fat16 => fat_sector[0..4].copy_from_slice(&[0xF8, 0xFF, 0xFF, 0xFF]),
+            FatType::Fat32 => {
+                fat_sector[0..4].copy_from_slice(&0x0FFFFFF8u32.to_le_bytes());
+                fat_sector[4..8].copy_from_slice(&0x0FFFFFFFu32.to_le_bytes());
+                fat_sector[8..12].copy_from_slice(&0x0FFFFFFFu32.to_le_bytes());
+            }
+        }
+        for fat_index in 0..num_fats as u32 {
+            write_sector(&dev, fat_start + fat_index * sectors_per_fat, &fat_sector)?;
+        }
 
+        // The root directory: the fixed region on FAT12 and FAT16, cluster 2 on FAT32.
         let data_start = fat_start + fat_total;
-        let cluster_buf = vec![0u8; SECTOR_SIZE * sectors_per_cluster as usize];
-        for i in 0..sectors_per_cluster as u32 {
-            let mut sector_buf = [0u8; SECTOR_SIZE];
-            sector_buf.copy_from_slice(&cluster_buf[i as usize * SECTOR_SIZE..(i as usize + 1) * SECTOR_SIZE]);
-            write_sector(&dev, data_start + i, &sector_buf)?;
+        let root_sectors = if is_fat32 { sectors_per_cluster as u32 } else { root_dir_sectors };
+        for i in 0..root_sectors {
+            write_sector(&dev, data_start + i, &zero)?;
         }
 
         let part = PartitionInfo {
-            type_code: 0x0C,
+            type_code: fat_type.partition_type(part_sectors),
             lba_start: part_start,
             sectors: part_sectors,
         };
@@ -631,120 +820,117 @@ impl<D: BlockDevice> Fat32Volume<D> {
     }
 
     pub fn read_directory(&mut self, cluster: u32) -> Result<Vec<DirEntryInfo>, &'static str> {
+        let sectors = self.dir_sectors(cluster)?;
+        self.parse_directory(&sectors)
+    }
+
+    // Decodes the entries held in a directory's sectors, in order.
+    fn parse_directory(&mut self, sectors: &[u32]) -> Result<Vec<DirEntryInfo>, &'static str> {
         let mut entries = Vec::new();
         let mut lfn_parts: Vec<(u8, Vec<u16>, u8)> = Vec::new();
         let mut lfn_count = 0u8;
-
-        let mut current = if cluster == 0 { self.root_cluster } else { cluster };
         let mut entry_index = 0u32;
+        for &lba in sectors {
+            let mut sector = [0u8; SECTOR_SIZE];
+            read_sector(&self.device, lba, &mut sector)?;
+            for slot in 0..ENTRIES_PER_SECTOR {
+                let offset = slot * DIR_ENTRY_SIZE;
+                let first = sector[offset];
+                if first == 0x00 {
+                    return Ok(entries);
+                }
+                if first == 0xE5 {
+                    lfn_parts.clear();
+                    lfn_count = 0;
+                    entry_index += 1;
+                    continue;
+                }
 
-        loop {
-            let next = self.read_fat_entry(current)?;
-            if is_bad_cluster(next) {
-                return Err("Bad cluster encountered.");
-            }
-            for sector_index in 0..self.sectors_per_cluster {
-                let lba = self.cluster_to_lba(current) + sector_index as u32;
-                let mut sector = [0u8; SECTOR_SIZE];
-                read_sector(&self.device, lba, &mut sector)?;
-                for slot in 0..ENTRIES_PER_SECTOR {
-                    let offset = slot * DIR_ENTRY_SIZE;
-                    let first = sector[offset];
-                    if first == 0x00 {
-                        return Ok(entries);
-                    }
-                    if first == 0xE5 {
-                        lfn_parts.clear();
-                        lfn_count = 0;
-                        entry_index += 1;
-                        continue;
+                let attr = sector[offset + 11];
+                if attr == 0x0F {
+                    if let Some(part) = decode_lfn_entry(&sector[offset..offset + 32]) {
+                        lfn_parts.push(part);
+                        lfn_count = lfn_count.saturating_add(1);
                     }
+                    entry_index += 1;
+                    continue;
+                }
 
-                    let attr = sector[offset + 11];
-                    if attr == 0x0F {
-                        if let Some(part) = decode_lfn_entry(&sector[offset..offset + 32]) {
-                            lfn_parts.push(part);
-                            lfn_count = lfn_count.saturating_add(1);
+                if attr & 0x08 != 0 {
+                    lfn_parts.clear();
+                    lfn_count = 0;
+                    entry_index += 1;
+                    continue;
+                }
+
+                let name = if !lfn_parts.is_empty() {
+                    let short = &sector[offset..offset + 11];
+                    let mut short_name = [0u8; 11];
+                    short_name.copy_from_slice(short);
+                    let checksum = lfn_checksum(&short_name);
+                    let mut collected: Vec<(u8, Vec<u16>)> = Vec::new();
+                    for (seq, chars, sum) in lfn_parts.drain(..) {
+                        if sum == checksum {
+                            collected.push((seq, chars));
                         }
-                        entry_index += 1;
-                        continue;
                     }
-
-                    if attr & 0x08 != 0 {
-                        lfn_parts.clear();
-                        lfn_count = 0;
-                        entry_index += 1;
-                        continue;
+                    collected.sort_by(|a, b| a.0.cmp(&b.0));
+                    let mut utf16: Vec<u16> = Vec::new();
+                    for (_, chunk) in collected {
+                        utf16.extend(chunk);
                     }
-
-                    let name = if !lfn_parts.is_empty() {
-                        let short = &sector[offset..offset + 11];
-                        let mut short_name = [0u8; 11];
-                        short_name.copy_from_slice(short);
-                        let checksum = lfn_checksum(&short_name);
-                        let mut collected: Vec<(u8, Vec<u16>)> = Vec::new();
-                        for (seq, chars, sum) in lfn_parts.drain(..) {
-                            if sum == checksum {
-                                collected.push((seq, chars));
-                            }
-                        }
-                        collected.sort_by(|a, b| a.0.cmp(&b.0));
-                        let mut utf16: Vec<u16> = Vec::new();
-                        for (_, chunk) in collected {
-                            utf16.extend(chunk);
-                        }
-                        let name = decode_lfn_name(&utf16);
-                        if name.is_empty() {
-                            let mut short_name = [0u8; 11];
-                            short_name.copy_from_slice(&sector[offset..offset + 11]);
-                            decode_short_name(&short_name, sector[offset + 12])
-                        } else {
-                            name
-                        }
-                    } else {
+                    let name = decode_lfn_name(&utf16);
+                    if name.is_empty() {
                         let mut short_name = [0u8; 11];
                         short_name.copy_from_slice(&sector[offset..offset + 11]);
                         decode_short_name(&short_name, sector[offset + 12])
-                    };
-
+                    } else {
+                        name
+                    }
+                } else {
                     let mut short_name = [0u8; 11];
                     short_name.copy_from_slice(&sector[offset..offset + 11]);
-                    let nt_reserved = sector[offset + 12];
-
-                    let cluster_lo = u16::from_le_bytes([sector[offset + 26], sector[offset + 27]]);
-                    let cluster_hi = u16::from_le_bytes([sector[offset + 20], sector[offset + 21]]);
-                    let cluster = ((cluster_hi as u32) << 16) | cluster_lo as u32;
-                    let size = u32::from_le_bytes([
-                        sector[offset + 28],
-                        sector[offset + 29],
-                        sector[offset + 30],
-                        sector[offset + 31],
-                    ]);
-
-                    let is_dir = attr & 0x10 != 0;
-
-                    entries.push(DirEntryInfo {
-                        name,
-                        is_dir,
-                        attr,
-                        cluster,
-                        size,
-                        entry_index,
-                        lfn_entries: lfn_count,
-                        short_name,
-                        nt_reserved,
-                    });
-
-                    lfn_parts.clear();
-                    lfn_count = 0;
-                    entry_index += 1;
-                }
-            }
+                    decode_short_name(&short_name, sector[offset + 12])
+                };
+
+                let mut short_name = [0u8; 11];
+                short_name.copy_from_slice(&sector[offset..offset + 11]);
+                let nt_reserved = sector[offset + 12];
+
+                let cluster_lo = u16::from_le_bytes([sector[offset + 26], sector[offset + 27]]);
+                let cluster_hi = u16::from_le_bytes([sector[offset + 20], sector[offset + 21]]);
+                let cluster = ((cluster_hi as u32) << 16) | cluster_lo as u32;
+                let size = u32::from_le_bytes([
+                    sector[offset + 28],
+                    sector[offset + 29],
+                    sector[offset + 30],
+                    sector[offset + 31],
+                ]);
+
+                let is_dir = attr & 0x10 != 0;
+                let word = |at: usize| u16::from_le_bytes([sector[offset + at], sector[offset + at + 1]]);
+                let created = fat_timestamp(word(16), word(14))
+                    .map(|secs| secs + sector[offset + 13] as u64 / 100);
+
+                entries.push(DirEntryInfo {
+                    name,
+                    is_dir,
+                    attr,
+                    cluster,
+                    size,
+                    entry_index,
+                    lfn_entries: lfn_count,
+                    short_name,
+                    nt_reserved,
+                    created,
+                    modified: fat_timestamp(word(24), word(22)),
+                    accessed: fat_timestamp(word(18), 0),
+                });
 
-            if is_eoc(next) {
-                break;
+                lfn_parts.clear();
+                lfn_count = 0;
+                entry_index += 1;
             }
-            current = next;
         }
 
         Ok(entries)
@@ -818,10 +1004,13 @@ impl<D: BlockDevice> Fat32Volume<D> {
             self.init_directory_cluster(entry_cluster, dir_cluster)?;
         }
 
+        // Clusters for the new directory, or for growing this one, must be in the FAT
+        // before an entry can point at them.
+        self.flush_fat_cache()?;
         let mut entries_bytes = lfn_entries;
         entries_bytes.push(short_entry);
         self.write_dir_entries(dir_cluster, start_index, &entries_bytes)?;
-        self.flush_fat_cache()?;
+        self.commit()?;
 
         Ok(DirEntryInfo {
             name: trimmed.to_string(),
@@ -833,6 +1022,9 @@ impl<D: BlockDevice> Fat32Volume<D> {
             lfn_entries: (entries_bytes.len() as u8).saturating_sub(1),
             short_name,
             nt_reserved,
+            created: fat_timestamp(date, time),
+            modified: fat_timestamp(date, time),
+            accessed: fat_timestamp(date, 0),
         })
     }
 
@@ -872,45 +1064,126 @@ impl<D: BlockDevice> Fat32Volume<D> {
         Ok(data)
     }
 
+    /// Replaces the contents of a file copy-on-write: the new chain is written and committed
+    /// to the FAT before the entry points at it, and the old chain is freed last. A crash in
+    /// between leaves either the old or the new contents, plus lost clusters for fsck. Only
+    /// when the disk cannot hold both copies is the old chain freed first.
     pub fn write_file(&mut self, dir_cluster: u32, entry: &DirEntryInfo, contents: &[u8]) -> Result<(), &'static str> {
         let mut entry = entry.clone();
         if contents.len() > u32::MAX as usize {
             return Err("File too large.");
         }
-        if entry.cluster != 0 {
-            self.free_cluster_chain(entry.cluster)?;
-            entry.cluster = 0;
+        let cluster_size = self.sectors_per_cluster as usize * SECTOR_SIZE;
+        let needed = contents.len().div_ceil(cluster_size) as u32;
+        let mut stale = entry.cluster;
+        if stale != 0 && self.free_count.is_some_and(|free| free < needed) {
+            self.free_cluster_chain(stale)?;
+            self.commit()?;
+            stale = 0;
+        }
+
+        entry.cluster = 0;
+        if needed > 0 {
+            let chain = self.allocate_cluster_chain(needed)?;
+            if let Err(err) = self.write_chain_data(&chain, contents) {
+                self.free_cluster_chain(chain[0])?;
+                self.commit()?;
+                return Err(err);
+            }
+            entry.cluster = chain[0];
         }
+        self.flush_fat_cache()?;
 
-        if !contents.is_empty() {
-            let cluster_size = self.sectors_per_cluster as usize * SECTOR_SIZE;
-            let needed = (contents.len() + cluster_size - 1) / cluster_size;
-            let chain = self.allocate_cluster_chain(needed as u32)?;
-            entry.cluster = chain[0];
+        entry.size = contents.len() as u32;
+        self.update_entry(dir_cluster, &entry)?;
+        if stale != 0 {
+            self.free_cluster_chain(stale)?;
+        }
+        self.commit()
+    }
 
-            let mut remaining = contents.len();
-            let mut offset = 0usize;
-            for cluster in chain {
-                for sector_index in 0..self.sectors_per_cluster {
-                    let lba = self.cluster_to_lba(cluster) + sector_index as u32;
-                    let mut sector = [0u8; SECTOR_SIZE];
-                    let copy_len = cmp::min(SECTOR_SIZE, remaining);
-                    if copy_len > 0 {
-                        sector[..copy_len].copy_from_slice(&contents[offset..offset + copy_len]);
-                        offset += copy_len;
-                        remaining -= copy_len;
-                    }
-                    write_sector(&self.device, lba, &sector)?;
-                }
-                if remaining == 0 {
-                    break;
+    /// Writes `data` at `offset` in place, growing the chain when it runs past the end. FAT
+    /// has no holes, so any gap between the old end and `offset` is filled with zeros. New
+    /// clusters reach the FAT before the entry's size covers them.
+    pub fn write_at(&mut self, dir_cluster: u32, entry: &DirEntryInfo, offset: u64, data: &[u8]) -> Result<(), &'static str> {
+        let mut entry = entry.clone();
+        let end = offset.saturating_add(data.len() as u64);
+        if end > u32::MAX as u64 {
+            return Err("File too large.");
+        }
+        let size = entry.size as u64;
+        let new_size = cmp::max(size, end);
+        let cluster_size = self.sectors_per_cluster as u64 * SECTOR_SIZE as u64;
+
+        let mut chain = if entry.cluster == 0 { Vec::new() } else { self.cluster_chain(entry.cluster)? };
+        let needed = new_size.div_ceil(cluster_size) as usize;
+        if chain.len() < needed {
+            let extra = self.allocate_cluster_chain((needed - chain.len()) as u32)?;
+            match chain.last() {
+                Some(&last) => self.write_fat_entry(last, extra[0])?,
+                None => entry.cluster = extra[0],
+            }
+            chain.extend(extra);
+        }
+
+        // Sectors from wherever the zero fill or the data begins, up to the new end.
+        let first = cmp::min(size, offset) / SECTOR_SIZE as u64;
+        let last = end.div_ceil(SECTOR_SIZE as u64);
+        let per_cluster = self.sectors_per_cluster as u64;
+        for index in first..last {
+            let base = index * SECTOR_SIZE as u64;
+            let top = base + SECTOR_SIZE as u64;
+            let lba = self.cluster_to_lba(chain[(index / per_cluster) as usize]) + (index % per_cluster) as u32;
+            let mut sector = [0u8; SECTOR_SIZE];
+            // Keep whatever of the old contents this write does not cover.
+            if base < size && (base < offset || top > end) {
+                read_sector(&self.device, lba, &mut sector)?;
+                let stale = (size - base) as usize;
+                if stale < SECTOR_SIZE {
+                    sector[stale..].fill(0);
                 }
             }
+            let from = cmp::max(base, offset);
+            let to = cmp::min(top, end);
+            if from < to {
+                let src = (from - offset) as usize..(to - offset) as usize;
+                sector[(from - base) as usize..(to - base) as usize].copy_from_slice(&data[src]);
+            }
+            write_sector(&self.device, lba, &sector)?;
         }
+        self.flush_fat_cache()?;
 
-        entry.size = contents.len() as u32;
+        entry.size = new_size as u32;
+        self.update_entry(dir_cluster, &entry)?;
+        self.commit()
+    }
+
+    /// Cuts a file to `len` bytes, freeing the clusters past it, or extends it with zeros.
+    pub fn truncate(&mut self, dir_cluster: u32, entry: &DirEntryInfo, len: u64) -> Result<(), &'static str> {
+        if len > u32::MAX as u64 {
+            return Err("File too large.");
+        }
+        if len >= entry.size as u64 {
+            return self.write_at(dir_cluster, entry, len, &[]);
+        }
+        let mut entry = entry.clone();
+        let cluster_size = self.sectors_per_cluster as u64 * SECTOR_SIZE as u64;
+        let keep = len.div_ceil(cluster_size) as usize;
+        let chain = if entry.cluster == 0 { Vec::new() } else { self.cluster_chain(entry.cluster)? };
+        if keep == 0 {
+            entry.cluster = 0;
+        }
+        // The entry shrinks first, so a crash leaves lost clusters rather than a size its
+        // chain no longer covers.
+        entry.size = len as u32;
         self.update_entry(dir_cluster, &entry)?;
-        self.flush_fat_cache()
+        if let Some(&cut) = chain.get(keep) {
+            if keep > 0 {
+                self.write_fat_entry(chain[keep - 1], 0x0FFFFFFF)?;
+            }
+            self.free_cluster_chain(cut)?;
+        }
+        self.commit()
     }
 
     pub fn delete_entry(&mut self, dir_cluster: u32, entry: &DirEntryInfo) -> Result<(), &'static str> {
@@ -923,11 +1196,13 @@ impl<D: BlockDevice> Fat32Volume<D> {
             }
         }
 
+        // The entry goes first, so a crash leaves lost clusters rather than a file whose
+        // clusters may be handed out again.
+        self.mark_entry_deleted(dir_cluster, entry)?;
         if entry.cluster != 0 {
             self.free_cluster_chain(entry.cluster)?;
         }
-        self.mark_entry_deleted(dir_cluster, entry)?;
-        self.flush_fat_cache()
+        self.commit()
     }
 
     pub fn update_entry(&mut self, dir_cluster: u32, entry: &DirEntryInfo) -> Result<(), &'static str> {
@@ -956,6 +1231,16 @@ impl<D: BlockDevice> Fat32Volume<D> {
         write_sector(&self.device, loc.lba, &sector)
     }
 
+    /// Rewrites the attribute byte of `entry`. The directory and volume-label bits are kept.
+    pub fn set_attributes(&mut self, dir_cluster: u32, entry: &DirEntryInfo, attr: u8) -> Result<(), &'static str> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let loc = self.entry_location(dir_cluster, entry.entry_index)?;
+        read_sector(&self.device, loc.lba, &mut sector)?;
+        let kept = sector[loc.offset + 11] & 0x18;
+        sector[loc.offset + 11] = kept | (attr & !0x18);
+        write_sector(&self.device, loc.lba, &sector)
+    }
+
     pub fn mark_entry_deleted(&mut self, dir_cluster: u32, entry: &DirEntryInfo) -> Result<(), &'static str> {
         let start = entry.entry_index.saturating_sub(entry.lfn_entries as u32);
         let total = entry.lfn_entries as u32 + 1;
@@ -969,6 +1254,147 @@ impl<D: BlockDevice> Fat32Volume<D> {
         Ok(())
     }
 
+    /// True when the volume was not cleanly unmounted, going by FAT[1]. FAT12 keeps no
+    /// such bit, so it is always taken as clean.
+    pub fn is_dirty(&mut self) -> Result<bool, &'static str> {
+        let bit = self.fat_type.clean_bit();
+        Ok(bit != 0 && self.read_fat_entry(1)? & bit == 0)
+    }
+
+    /// Sets the clean-shutdown bit on unmount, and clears it while mounted.
+    pub fn set_clean(&mut self, clean: bool) -> Result<(), &'static str> {
+        let bit = self.fat_type.clean_bit();
+        if bit == 0 {
+            return Ok(());
+        }
+        let value = self.read_fat_entry(1)?;
+        let value = if clean { value | bit } else { value & !bit };
+        self.write_fat_entry(1, value)?;
+        self.commit()
+    }
+
+    /// Checks the FAT copies, every cluster chain reachable from the root, lost clusters
+    /// and, on FAT32, the FSInfo free count. With `repair`, chains are cut at the first bad link,
+    /// sizes are fitted to what is left, lost clusters are freed and the count is fixed.
+    pub fn fsck(&mut self, repair: bool) -> Result<FsckReport, &'static str> {
+        let mut report = FsckReport::new(repair);
+        self.flush_fat_cache()?;
+        // Repairs go through the cache, so it must not hold a stale sector.
+        self.fat_cache.lba = None;
+        self.fsck_fat_copies(repair, &mut report)?;
+
+        let limit = self.cluster_count() + 2;
+        // The FAT is held in memory as 32-bit entries with a flag per cluster; a failed
+        // allocation would panic the kernel, so a volume that would crowd the heap is refused.
+        if limit as usize * 5 > memory::heap_stats().free / 2 {
+            return Err("Volume is too large to check in the kernel heap.");
+        }
+        let cluster_size = self.sectors_per_cluster as u64 * SECTOR_SIZE as u64;
+        let mut check = FatCheck {
+            fat: self.load_fat(limit)?,
+            used: vec![false; limit as usize],
+            repair,
+            report,
+        };
+
+        let root = self.root_cluster;
+        let root_sectors = if root == 0 {
+            self.root_dir_lbas()
+        } else {
+            let root_chain = self.fsck_chain(&mut check, root, None, "\\")?;
+            if root_chain.is_empty() {
+                return Err("Root directory cluster is invalid.");
+            }
+            self.chain_sectors(&root_chain)
+        };
+        let mut pending = vec![(root, root_sectors, String::from("\\"))];
+        while let Some((dir, sectors, path)) = pending.pop() {
+            check.report.dirs += 1;
+            for mut entry in self.parse_directory(&sectors)? {
+                if entry.name == "." || entry.name == ".." {
+                    continue;
+                }
+                let child = format!("{}{}{}", path, if path.ends_with('\\') { "" } else { "\\" }, entry.name);
+                if entry.is_dir {
+                    let chain = if entry.cluster == 0 {
+                        check.report.problems.push(format!("{}: directory has no clusters", child));
+                        Vec::new()
+                    } else {
+                        self.fsck_chain(&mut check, entry.cluster, None, &child)?
+                    };
+                    if chain.is_empty() {
+                        if repair {
+                            self.mark_entry_deleted(dir, &entry)?;
+                        }
+                    } else {
+                        pending.push((entry.cluster, self.chain_sectors(&chain), child));
+                    }
+                    continue;
+                }
+
+                check.report.files += 1;
+                let needed = (entry.size as u64).div_ceil(cluster_size) as usize;
+                let chain = if entry.cluster == 0 {
+                    Vec::new()
+                } else {
+                    self.fsck_chain(&mut check, entry.cluster, Some(needed), &child)?
+                };
+                let capacity = chain.len() as u64 * cluster_size;
+                if entry.size as u64 > capacity {
+                    check.report.problems.push(format!(
+                        "{}: size {} does not fit its {} cluster(s)",
+                        child,
+                        entry.size,
+                        chain.len()
+                    ));
+                }
+                if repair && (entry.size as u64 > capacity || (chain.is_empty() && entry.cluster != 0)) {
+                    entry.size = cmp::min(entry.size as u64, capacity) as u32;
+                    if chain.is_empty() {
+                        entry.cluster = 0;
+                    }
+                    self.set_entry_extent(dir, &entry)?;
+                }
+            }
+        }
+
+        let FatCheck { mut fat, used, mut report, .. } = check;
+        let mut lost = 0u32;
+        for cluster in 2..limit {
+            let value = fat[cluster as usize];
+            if value != 0 && !is_bad_cluster(value) && !used[cluster as usize] {
+                lost += 1;
+                if repair {
+                    self.write_fat_entry(cluster, 0)?;
+                    fat[cluster as usize] = 0;
+                }
+            }
+        }
+        if lost > 0 {
+            report.problems.push(format!("{} lost cluster(s) not owned by any file", lost));
+        }
+
+        let free = fat[2..].iter().filter(|&&value| value == 0).count() as u32;
+        if self.fat_type != FatType::Fat32 {
+            // There is no FSInfo sector; the count is only kept in memory.
+            self.free_count = Some(free);
+        } else {
+            match self.free_count {
+                Some(count) if count == free => {}
+                Some(count) => report
+                    .problems
+                    .push(format!("FSInfo free count is {}, should be {}", count, free)),
+                None => report.problems.push(String::from("FSInfo free count is missing")),
+            }
+        }
+        if repair {
+            self.free_count = Some(free);
+            self.fsinfo_dirty = true;
+            self.commit()?;
+        }
+        Ok(report)
+    }
+
     fn init_directory_cluster(&mut self, cluster: u32, parent: u32) -> Result<(), &'static str> {
         let mut buf = vec![0u8; self.sectors_per_cluster as usize * SECTOR_SIZE];
 
@@ -991,10 +1417,10 @@ impl<D: BlockDevice> Fat32Volume<D> {
         Ok(())
     }
 
-    fn read_fat_entry(&mut self, cluster: u32) -> Result<u32, &'static str> {
-        let fat_offset = cluster * 4;
+    // Brings the sector holding byte `fat_offset` of the first FAT into the cache and
+    // returns the byte's position in it.
+    fn load_fat_sector(&mut self, fat_offset: u32) -> Result<usize, &'static str> {
         let sector_idx = fat_offset as usize / SECTOR_SIZE;
-        let offset = fat_offset as usize % SECTOR_SIZE;
         let lba = self.part_start + self.reserved_sectors as u32 + sector_idx as u32;
 
         if self.fat_cache.lba != Some(lba) {
@@ -1002,31 +1428,71 @@ impl<D: BlockDevice> Fat32Volume<D> {
             read_sector(&self.device, lba, &mut self.fat_cache.buf)?;
             self.fat_cache.lba = Some(lba);
         }
+        Ok(fat_offset as usize % SECTOR_SIZE)
+    }
 
-        let val = u32::from_le_bytes([
-            self.fat_cache.buf[offset],
-            self.fat_cache.buf[offset + 1],
-            self.fat_cache.buf[offset + 2],
-            self.fat_cache.buf[offset + 3],
-        ]) & 0x0FFF_FFFF;
-        Ok(val)
+    // Reads a FAT entry, with FAT12 and FAT16 markers widened to their FAT32 values.
+    fn read_fat_entry(&mut self, cluster: u32) -> Result<u32, &'static str> {
+        let val = match self.fat_type {
+            FatType::Fat32 => {
+                let offset = self.load_fat_sector(cluster * 4)?;
+                let buf = &self.fat_cache.buf;
+                u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+            }
+            FatType::Fat16 => {
+                let offset = self.load_fat_sector(cluster * 2)?;
+                u16::from_le_bytes([self.fat_cache.buf[offset], self.fat_cache.buf[offset + 1]]) as u32
+            }
+            FatType::Fat12 => {
+                // Entries are a byte and a half, so one may straddle two sectors.
+                let fat_offset = cluster + cluster / 2;
+                let offset = self.load_fat_sector(fat_offset)?;
+                let low = self.fat_cache.buf[offset];
+                let offset = self.load_fat_sector(fat_offset + 1)?;
+                let pair = u16::from_le_bytes([low, self.fat_cache.buf[offset]]) as u32;
+                if cluster & 1 == 0 { pair & 0x0FFF } else { pair >> 4 }
+            }
+        };
+        Ok(self.fat_type.widen(val & self.fat_type.mask()))
     }
 
     fn write_fat_entry(&mut self, cluster: u32, value: u32) -> Result<(), &'static str> {
-        let fat_offset = cluster * 4;
-        let sector_idx = fat_offset as usize / SECTOR_SIZE;
-        let offset = fat_offset as usize % SECTOR_SIZE;
-        let lba = self.part_start + self.reserved_sectors as u32 + sector_idx as u32;
-
-        if self.fat_cache.lba != Some(lba) {
-            self.flush_fat_cache()?;
-            read_sector(&self.device, lba, &mut self.fat_cache.buf)?;
-            self.fat_cache.lba = Some(lba);
+        let value = value & self.fat_type.mask();
+        match self.fat_type {
+            FatType::Fat32 => {
+                let offset = self.load_fat_sector(cluster * 4)?;
+                self.fat_cache.buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+                self.fat_cache.dirty = true;
+            }
+            FatType::Fat16 => {
+                let offset = self.load_fat_sector(cluster * 2)?;
+                self.fat_cache.buf[offset..offset + 2].copy_from_slice(&(value as u16).to_le_bytes());
+                self.fat_cache.dirty = true;
+            }
+            FatType::Fat12 => {
+                let fat_offset = cluster + cluster / 2;
+                let odd = cluster & 1 == 1;
+                let offset = self.load_fat_sector(fat_offset)?;
+                let low = &mut self.fat_cache.buf[offset];
+                *low = if odd { (*low & 0x0F) | ((value as u8 & 0x0F) << 4) } else { value as u8 };
+                self.fat_cache.dirty = true;
+                let offset = self.load_fat_sector(fat_offset + 1)?;
+                let high = &mut self.fat_cache.buf[offset];
+                *high = if odd { (value >> 4) as u8 } else { (*high & 0xF0) | (value >> 8) as u8 };
+                self.fat_cache.dirty = true;
+            }
         }
+        Ok(())
+    }
 
-        let bytes = (value & 0x0FFF_FFFF).to_le_bytes();
-        self.fat_cache.buf[offset..offset + 4].copy_from_slice(&bytes);
-        self.fat_cache.dirty = true;
+    // Ends an update: the cached FAT sector goes to every FAT copy, then FSInfo is brought
+    // in line with the free count and next-free hint.
+    fn commit(&mut self) -> Result<(), &'static str> {
+        self.flush_fat_cache()?;
+        if self.fsinfo_dirty {
+            self.write_fsinfo()?;
+            self.fsinfo_dirty = false;
+        }
         Ok(())
     }
 
@@ -1047,10 +1513,44 @@ impl<D: BlockDevice> Fat32Volume<D> {
     }
 
     fn cluster_to_lba(&self, cluster: u32) -> u32 {
-        let first_data = self.part_start + self.reserved_sectors as u32 + self.num_fats as u32 * self.sectors_per_fat;
+        let first_data = self.root_dir_start() + self.info().root_dir_sectors();
         first_data + (cluster - 2) * self.sectors_per_cluster as u32
     }
 
+    // First sector after the FATs: the fixed root directory on FAT12 and FAT16.
+    fn root_dir_start(&self) -> u32 {
+        self.part_start + self.reserved_sectors as u32 + self.num_fats as u32 * self.sectors_per_fat
+    }
+
+    fn root_dir_lbas(&self) -> Vec<u32> {
+        let start = self.root_dir_start();
+        (start..start + self.info().root_dir_sectors()).collect()
+    }
+
+    // True for the fixed root directory region, which cannot grow.
+    fn is_fixed_root(&self, dir_cluster: u32) -> bool {
+        self.root_cluster == 0 && dir_cluster == 0
+    }
+
+    fn chain_sectors(&self, chain: &[u32]) -> Vec<u32> {
+        let mut sectors = Vec::with_capacity(chain.len() * self.sectors_per_cluster as usize);
+        for &cluster in chain {
+            let lba = self.cluster_to_lba(cluster);
+            sectors.extend(lba..lba + self.sectors_per_cluster as u32);
+        }
+        sectors
+    }
+
+    // Lists the sectors of a directory, in order; zero names the root.
+    fn dir_sectors(&mut self, dir_cluster: u32) -> Result<Vec<u32>, &'static str> {
+        if self.is_fixed_root(dir_cluster) {
+            return Ok(self.root_dir_lbas());
+        }
+        let start = if dir_cluster == 0 { self.root_cluster } else { dir_cluster };
+        let chain = self.cluster_chain(start)?;
+        Ok(self.chain_sectors(&chain))
+    }
+
     fn allocate_cluster(&mut self) -> Result<u32, &'static str> {
         let start = if self.next_free < 2 { 2 } else { self.next_free };
         let max_clusters = self.cluster_count();
@@ -1067,7 +1567,7 @@ impl<D: BlockDevice> Fat32Volume<D> {
                 if let Some(count) = self.free_count.as_mut() {
                     *count = count.saturating_sub(1);
                 }
-                self.write_fsinfo().ok();
+                self.fsinfo_dirty = true;
                 return Ok(cluster);
             }
             cluster += 1;
@@ -1079,10 +1579,19 @@ impl<D: BlockDevice> Fat32Volume<D> {
         Err("Disk full.")
     }
 
+    // Allocates `count` linked clusters, handing back any already taken if the disk fills.
     fn allocate_cluster_chain(&mut self, count: u32) -> Result<Vec<u32>, &'static str> {
         let mut chain = Vec::new();
         for _ in 0..count {
-            let cluster = self.allocate_cluster()?;
+            let cluster = match self.allocate_cluster() {
+                Ok(cluster) => cluster,
+                Err(err) => {
+                    if let Some(&first) = chain.first() {
+                        self.free_cluster_chain(first)?;
+                    }
+                    return Err(err);
+                }
+            };
             if let Some(prev) = chain.last() {
                 self.write_fat_entry(*prev, cluster)?;
             }
@@ -1110,18 +1619,129 @@ impl<D: BlockDevice> Fat32Volume<D> {
             }
             current = next;
         }
-        self.write_fsinfo().ok();
+        self.fsinfo_dirty = true;
         Ok(())
     }
 
     fn cluster_count(&self) -> u32 {
-        let data_sectors = self.total_sectors
-            .saturating_sub(self.reserved_sectors as u32)
-            .saturating_sub(self.num_fats as u32 * self.sectors_per_fat);
-        data_sectors / self.sectors_per_cluster as u32
+        self.info().cluster_count()
+    }
+
+    // Follows a chain through the in-memory FAT, claiming its clusters. It stops at a
+    // cluster that is out of range, free, bad or already claimed, or after `max` clusters;
+    // when repairing, the chain is ended at the last good cluster.
+    fn fsck_chain(&mut self, check: &mut FatCheck, start: u32, max: Option<usize>, path: &str) -> Result<Vec<u32>, &'static str> {
+        let mut chain: Vec<u32> = Vec::new();
+        let mut current = start;
+        let problem = loop {
+            if max.is_some_and(|max| chain.len() >= max) {
+                break Some(format!("{}: cluster chain is longer than the file", path));
+            }
+            if current < 2 || current as usize >= check.fat.len() {
+                break Some(format!("{}: cluster {} is out of range", path, current));
+            }
+            if check.used[current as usize] {
+                break Some(format!("{}: cluster {} is cross-linked or loops", path, current));
+            }
+            let next = check.fat[current as usize];
+            if next == 0 {
+                break Some(format!("{}: chain runs into free cluster {}", path, current));
+            }
+            if is_bad_cluster(next) {
+                break Some(format!("{}: chain runs into bad cluster {}", path, current));
+            }
+            check.used[current as usize] = true;
+            chain.push(current);
+            if is_eoc(next) {
+                break None;
+            }
+            current = next;
+        };
+
+        if let Some(problem) = problem {
+            check.report.problems.push(problem);
+            if let (true, Some(&last)) = (check.repair, chain.last()) {
+                self.write_fat_entry(last, 0x0FFFFFFF)?;
+                check.fat[last as usize] = 0x0FFFFFFF;
+            }
+        }
+        Ok(chain)
+    }
+
+    // Compares each further FAT copy with the first, overwriting it when repairing.
+    fn fsck_fat_copies(&mut self, repair: bool, report: &mut FsckReport) -> Result<(), &'static str> {
+        let first = self.part_start + self.reserved_sectors as u32;
+        let mut primary = [0u8; SECTOR_SIZE];
+        let mut copy = [0u8; SECTOR_SIZE];
+        for fat_index in 1..self.num_fats as u32 {
+            let mut differing = 0u32;
+            for sector in 0..self.sectors_per_fat {
+                read_sector(&self.device, first + sector, &mut primary)?;
+                let target = first + fat_index * self.sectors_per_fat + sector;
+                read_sector(&self.device, target, &mut copy)?;
+                if primary != copy {
+                    differing += 1;
+                    if repair {
+                        write_sector(&self.device, target, &primary)?;
+                    }
+                }
+            }
+            if differing > 0 {
+                report.problems.push(format!(
+                    "FAT copy {} differs from the first in {} sector(s)",
+                    fat_index + 1,
+                    differing
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Reads the first `entries` entries of the first FAT.
+    fn load_fat(&mut self, entries: u32) -> Result<Vec<u32>, &'static str> {
+        if self.fat_type.fat_bytes(entries) > self.sectors_per_fat.saturating_mul(SECTOR_SIZE as u32) {
+            return Err("FAT is smaller than the volume.");
+        }
+        let mut fat = Vec::with_capacity(entries as usize);
+        for cluster in 0..entries {
+            fat.push(self.read_fat_entry(cluster)?);
+        }
+        Ok(fat)
+    }
+
+    // Rewrites only the first cluster and size of an entry, leaving its dates alone.
+    fn set_entry_extent(&mut self, dir_cluster: u32, entry: &DirEntryInfo) -> Result<(), &'static str> {
+        let mut sector = [0u8; SECTOR_SIZE];
+        let loc = self.entry_location(dir_cluster, entry.entry_index)?;
+        read_sector(&self.device, loc.lba, &mut sector)?;
+        let offset = loc.offset;
+        sector[offset + 20..offset + 22].copy_from_slice(&((entry.cluster >> 16) as u16).to_le_bytes());
+        sector[offset + 26..offset + 28].copy_from_slice(&((entry.cluster & 0xFFFF) as u16).to_le_bytes());
+        sector[offset + 28..offset + 32].copy_from_slice(&entry.size.to_le_bytes());
+        write_sector(&self.device, loc.lba, &sector)
+    }
+
+    // Lists the clusters of a chain, refusing ones that leave the volume or loop.
+    fn cluster_chain(&mut self, start: u32) -> Result<Vec<u32>, &'static str> {
+        let limit = self.cluster_count() + 2;
+        let mut chain = Vec::new();
+        let mut current = start;
+        loop {
+            if current < 2 || current >= limit || chain.len() >= limit as usize {
+                return Err("Invalid cluster chain.");
+            }
+            let next = self.read_fat_entry(current)?;
+            if is_bad_cluster(next) {
+                return Err("Bad cluster encountered.");
+            }
+            chain.push(current);
+            if is_eoc(next) {
+                return Ok(chain);
+            }
+            current = next;
+        }
     }
 
-    #[allow(dead_code)]
     fn count_free_clusters(&mut self, total_clusters: u32) -> Result<u32, &'static str> {
         let mut free = 0u32;
         let max_cluster = 2u32.saturating_add(total_clusters);
@@ -1135,48 +1755,40 @@ impl<D: BlockDevice> Fat32Volume<D> {
     }
 
     fn find_free_dir_slots(&mut self, dir_cluster: u32, needed: u32) -> Result<u32, &'static str> {
-        let mut current = if dir_cluster == 0 { self.root_cluster } else { dir_cluster };
+        let sectors = self.dir_sectors(dir_cluster)?;
         let mut entry_index = 0u32;
         let mut run_start = 0u32;
         let mut run_len = 0u32;
 
-        loop {
-            for sector_index in 0..self.sectors_per_cluster {
-                let lba = self.cluster_to_lba(current) + sector_index as u32;
-                let mut sector = [0u8; SECTOR_SIZE];
-                read_sector(&self.device, lba, &mut sector)?;
-                for slot in 0..ENTRIES_PER_SECTOR {
-                    let offset = slot * DIR_ENTRY_SIZE;
-                    let first = sector[offset];
-                    if first == 0x00 || first == 0xE5 {
-                        if run_len == 0 {
-                            run_start = entry_index;
-                        }
-                        run_len += 1;
-                        if run_len >= needed {
-                            return Ok(run_start);
-                        }
-                    } else {
-                        run_len = 0;
+        for lba in sectors {
+            let mut sector = [0u8; SECTOR_SIZE];
+            read_sector(&self.device, lba, &mut sector)?;
+            for slot in 0..ENTRIES_PER_SECTOR {
+                let offset = slot * DIR_ENTRY_SIZE;
+                let first = sector[offset];
+                if first == 0x00 || first == 0xE5 {
+                    if run_len == 0 {
+                        run_start = entry_index;
                     }
-                    entry_index += 1;
+                    run_len += 1;
+                    if run_len >= needed {
+                        return Ok(run_start);
+                    }
+                } else {
+                    run_len = 0;
                 }
+                entry_index += 1;
             }
-
-            let next = self.read_fat_entry(current)?;
-            if is_bad_cluster(next) {
-                return Err("Bad cluster encountered.");
-            }
-            if is_eoc(next) {
-                break;
-            }
-            current = next;
         }
 
+        if self.is_fixed_root(dir_cluster) {
+            return Err("Root directory is full.");
+        }
+        let start = if dir_cluster == 0 { self.root_cluster } else { dir_cluster };
+        let last = *self.cluster_chain(start)?.last().ok_or("Invalid cluster chain.")?;
         let new_cluster = self.allocate_cluster()?;
-        self.write_fat_entry(current, new_cluster)?;
-        self.write_fat_entry(new_cluster, 0x0FFFFFFF)?;
         self.zero_cluster(new_cluster)?;
+        self.write_fat_entry(last, new_cluster)?;
         Ok(entry_index)
     }
 
@@ -1192,6 +1804,14 @@ impl<D: BlockDevice> Fat32Volume<D> {
     }
 
     fn entry_location(&mut self, dir_cluster: u32, entry_index: u32) -> Result<EntryLocation, &'static str> {
+        if self.is_fixed_root(dir_cluster) {
+            if entry_index >= self.root_entries as u32 {
+                return Err("Directory entry out of range.");
+            }
+            let lba = self.root_dir_start() + entry_index / ENTRIES_PER_SECTOR as u32;
+            let offset = (entry_index % ENTRIES_PER_SECTOR as u32) as usize * DIR_ENTRY_SIZE;
+            return Ok(EntryLocation { lba, offset });
+        }
         let entries_per_cluster = ENTRIES_PER_SECTOR as u32 * self.sectors_per_cluster as u32;
         let mut current = if dir_cluster == 0 { self.root_cluster } else { dir_cluster };
         let mut cluster_offset = entry_index / entries_per_cluster;
@@ -1217,6 +1837,22 @@ impl<D: BlockDevice> Fat32Volume<D> {
         Ok(EntryLocation { lba, offset })
     }
 
+    // Writes `contents` across `chain`, zero-filling the tail of the last cluster.
+    fn write_chain_data(&mut self, chain: &[u32], contents: &[u8]) -> Result<(), &'static str> {
+        let mut offset = 0usize;
+        for &cluster in chain {
+            for sector_index in 0..self.sectors_per_cluster {
+                let lba = self.cluster_to_lba(cluster) + sector_index as u32;
+                let mut sector = [0u8; SECTOR_SIZE];
+                let copy_len = cmp::min(SECTOR_SIZE, contents.len() - offset);
+                sector[..copy_len].copy_from_slice(&contents[offset..offset + copy_len]);
+                offset += copy_len;
+                write_sector(&self.device, lba, &sector)?;
+            }
+        }
+        Ok(())
+    }
+
     fn zero_cluster(&mut self, cluster: u32) -> Result<(), &'static str> {
         let zero = [0u8; SECTOR_SIZE];
         for sector_index in 0..self.sectors_per_cluster {
@@ -1247,6 +1883,9 @@ impl<D: BlockDevice> Fat32Volume<D> {
     }
 
     fn write_fsinfo(&mut self) -> Result<(), &'static str> {
+        if self.fat_type != FatType::Fat32 {
+            return Ok(());
+        }
         let mut sector = [0u8; SECTOR_SIZE];
         let lba = self.part_start + self.fsinfo_sector as u32;
         read_sector(&self.device, lba, &mut sector)?;
@@ -1264,6 +1903,14 @@ impl<D: BlockDevice> Fat32Volume<D> {
     }
 }
 
+// State carried through one `fsck` pass: a copy of the FAT and the clusters claimed so far.
+struct FatCheck {
+    fat: Vec<u32>,
+    used: Vec<bool>,
+    repair: bool,
+    report: FsckReport,
+}
+
 struct EntryLocation {
     lba: u32,
     offset: usize,
@@ -1349,11 +1996,11 @@ fn build_fsinfo(total_sectors: u32, reserved: u32, spc: u32, fats: u32, fatsz: u
     sector
 }
 
-fn write_mbr<D: BlockDevice>(dev: &D, part_start: u32, part_sectors: u32) -> Result<(), &'static str> {
+fn write_mbr<D: BlockDevice>(dev: &D, part_start: u32, part_sectors: u32, type_code: u8) -> Result<(), &'static str> {
     let mut sector = [0u8; SECTOR_SIZE];
     sector[440..444].copy_from_slice(&volume_id_from_time().to_le_bytes());
     let base = 446;
-    sector[base + 4] = 0x0C;
+    sector[base + 4] = type_code;
     sector[base + 8..base + 12].copy_from_slice(&part_start.to_le_bytes());
     sector[base + 12..base + 16].copy_from_slice(&part_sectors.to_le_bytes());
     sector[510] = 0x55;
@@ -1361,6 +2008,55 @@ fn write_mbr<D: BlockDevice>(dev: &D, part_start: u32, part_sectors: u32) -> Res
     write_sector(dev, 0, &sector)
 }
 
+// Works out the FAT size and cluster count for a layout, sizing the FAT to the clusters
+// that are left once it is in place.
+fn fat_geometry(fat_type: FatType, total_sectors: u32, fixed_sectors: u32, num_fats: u8, sectors_per_cluster: u8) -> (u32, u32) {
+    let mut sectors_per_fat = 1u32;
+    loop {
+        let data_sectors = total_sectors
+            .saturating_sub(fixed_sectors)
+            .saturating_sub(num_fats as u32 * sectors_per_fat);
+        let clusters = data_sectors / sectors_per_cluster as u32;
+        let needed = fat_type.fat_bytes(clusters + 2).div_ceil(SECTOR_SIZE as u32);
+        if needed <= sectors_per_fat {
+            return (sectors_per_fat, clusters);
+        }
+        sectors_per_fat = needed;
+    }
+}
+
+// Picks FAT32 when the volume reaches its minimum cluster count, otherwise the smallest
+// FAT16 (or, for tiny volumes, FAT12) cluster size that keeps within the type's limits.
+fn choose_fat_layout(total_sectors: u32, num_fats: u8) -> Result<(FatType, u8, u32), &'static str> {
+    let sectors_per_cluster = choose_sectors_per_cluster(total_sectors);
+    let (sectors_per_fat, clusters) = fat_geometry(FatType::Fat32, total_sectors, 32, num_fats, sectors_per_cluster);
+    if clusters >= FAT16_MAX_CLUSTERS {
+        return Ok((FatType::Fat32, sectors_per_cluster, sectors_per_fat));
+    }
+
+    let fixed = 1 + (FORMAT_ROOT_ENTRIES as u32 * DIR_ENTRY_SIZE as u32).div_ceil(SECTOR_SIZE as u32);
+    for (fat_type, min_clusters, max_clusters) in [
+        (FatType::Fat16, FAT12_MAX_CLUSTERS, FAT16_MAX_CLUSTERS),
+        (FatType::Fat12, 16, FAT12_MAX_CLUSTERS),
+    ] {
+        let mut sectors_per_cluster = 1u8;
+        loop {
+            let (sectors_per_fat, clusters) = fat_geometry(fat_type, total_sectors, fixed, num_fats, sectors_per_cluster);
+            if clusters < min_clusters {
+                break;
+            }
+            if clusters < max_clusters {
+                return Ok((fat_type, sectors_per_cluster, sectors_per_fat));
+            }
+            if sectors_per_cluster == 128 {
+                break;
+            }
+            sectors_per_cluster *= 2;
+        }
+    }
+    Err("Disk too small for FAT.")
+}
+
 fn choose_sectors_per_cluster(total_sectors: u32) -> u8 {
     let bytes = total_sectors as u64 * SECTOR_SIZE as u64;
     if bytes < 8 * 1024 * 1024 {
@@ -0,0 +1,258 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::console::{self, OutputHook};
+use crate::fs;
+use crate::pipe::{PipeReader, PipeWriter};
+
+pub const ERR_BAD_FD: &str = "Bad file descriptor.";
+
+/// A file opened through a descriptor. The contents are loaded on open and written
+/// back to the volume when the last handle is closed; an append-only handle writes just
+/// what it added.
+pub struct OpenFile {
+    pub path: String,
+    pub data: Vec<u8>,
+    pub pos: usize,
+    pub readable: bool,
+    pub writable: bool,
+    pub append: bool,
+    pub dirty: bool,
+    // How much of `data` is already on the volume as it stands; None after truncating.
+    saved_len: Option<usize>,
+}
+
+pub struct OpenOptions {
+    pub read: bool,
+    pub write: bool,
+    pub create: bool,
+    pub truncate: bool,
+    pub append: bool,
+}
+
+impl OpenFile {
+    pub fn open(path: &str, opts: &OpenOptions) -> Result<Self, &'static str> {
+        let data = match fs::read_file_bytes(path) {
+            Ok(data) => {
+                if opts.write && opts.truncate {
+                    Vec::new()
+                } else {
+                    data
+                }
+            }
+            Err(_) if opts.create && opts.write => {
+                fs::ensure_file(path)?;
+                Vec::new()
+            }
+            Err(e) => return Err(e),
+        };
+        let data_len = data.len();
+        Ok(OpenFile {
+            path: String::from(path),
+            data,
+            pos: 0,
+            readable: opts.read,
+            writable: opts.write,
+            append: opts.append,
+            dirty: opts.write && opts.truncate,
+            saved_len: if opts.write && opts.truncate { None } else { Some(data_len) },
+        })
+    }
+
+    pub fn read(&mut self, max: usize) -> Result<Vec<u8>, &'static str> {
+        if !self.readable {
+            return Err(ERR_BAD_FD);
+        }
+        let start = self.pos.min(self.data.len());
+        let end = (start + max).min(self.data.len());
+        self.pos = end;
+        Ok(self.data[start..end].to_vec())
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<usize, &'static str> {
+        if !self.writable {
+            return Err(ERR_BAD_FD);
+        }
+        if self.append {
+            self.pos = self.data.len();
+        }
+        let end = self.pos + data.len();
+        if self.data.len() < end {
+            self.data.resize(end, 0);
+        }
+        self.data[self.pos..end].copy_from_slice(data);
+        self.pos = end;
+        self.dirty = true;
+        Ok(data.len())
+    }
+
+    pub fn flush(&mut self) -> Result<(), &'static str> {
+        if !self.dirty {
+            return Ok(());
+        }
+        match self.saved_len {
+            Some(saved) if self.append => fs::write_at(&self.path, saved as u64, &self.data[saved..])?,
+            _ => fs::write_file_bytes(&self.path, &self.data)?,
+        }
+        self.saved_len = Some(self.data.len());
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// One slot of a descriptor table. Clones share the underlying file or pipe, which is
+/// how programs inherit the shell's redirections.
+#[derive(Clone)]
+pub enum FileDesc {
+    Console,
+    File(Arc<Mutex<OpenFile>>),
+    PipeRead(PipeReader),
+    PipeWrite(PipeWriter),
+}
+
+impl FileDesc {
+    pub fn file(file: OpenFile) -> Self {
+        FileDesc::File(Arc::new(Mutex::new(file)))
+    }
+
+    pub fn is_console(&self) -> bool {
+        matches!(self, FileDesc::Console)
+    }
+
+    /// Reads up to `max` bytes. Console input is line-buffered per process and handled by
+    /// the syscall layer, so it is rejected here.
+    pub fn read(&self, max: usize) -> Result<Vec<u8>, &'static str> {
+        match self {
+            FileDesc::File(file) => file.lock().read(max),
+            FileDesc::PipeRead(pipe) => Ok(pipe.read(max)),
+            FileDesc::Console | FileDesc::PipeWrite(_) => Err(ERR_BAD_FD),
+        }
+    }
+
+    pub fn write(&self, data: &[u8]) -> Result<usize, &'static str> {
+        match self {
+            FileDesc::Console => {
+                write_console(&String::from_utf8_lossy(data));
+                Ok(data.len())
+            }
+            FileDesc::File(file) => file.lock().write(data),
+            FileDesc::PipeWrite(pipe) => pipe.write(data),
+            FileDesc::PipeRead(_) => Err(ERR_BAD_FD),
+        }
+    }
+
+    pub fn close(self) -> Result<(), &'static str> {
+        if let FileDesc::File(file) = self {
+            if Arc::strong_count(&file) == 1 {
+                return file.lock().flush();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct TaskIo {
+    stdin: FileDesc,
+    stdout: FileDesc,
+}
+
+// Standard streams of whatever the shell is running right now. Built-in commands print
+// through `console`, so stdout redirection swaps in an output hook that feeds `stdout`.
+static TASK_IO: Mutex<Option<TaskIo>> = Mutex::new(None);
+static BASE_HOOK: Mutex<Option<OutputHook>> = Mutex::new(None);
+
+/// Runs `f` with stdin/stdout bound to the given descriptors, then closes them.
+pub fn with_stdio<R>(stdin: FileDesc, stdout: FileDesc, f: impl FnOnce() -> R) -> (R, Result<(), &'static str>) {
+    let prev_hook = console::output_hook();
+    let redirect = !stdout.is_console();
+    let prev = TASK_IO.lock().replace(TaskIo { stdin, stdout });
+    if prev.is_none() {
+        *BASE_HOOK.lock() = prev_hook;
+    }
+    if redirect {
+        console::set_output_hook(Some(redirect_hook));
+    } else {
+        console::set_output_hook(*BASE_HOOK.lock());
+    }
+
+    let result = f();
+
+    console::set_output_hook(prev_hook);
+    let ours = core::mem::replace(&mut *TASK_IO.lock(), prev);
+    let closed = match ours {
+        Some(io) => io.stdin.close().and(io.stdout.close()),
+        None => Ok(()),
+    };
+    (result, closed)
+}
+
+/// A task's standard streams and output hooks, set aside while another task runs.
+pub struct TaskState {
+    io: Option<TaskIo>,
+    base_hook: Option<OutputHook>,
+    hook: Option<OutputHook>,
+}
+
+pub fn switch_out() -> TaskState {
+    TaskState { io: TASK_IO.lock().take(), base_hook: BASE_HOOK.lock().take(), hook: console::output_hook() }
+}
+
+pub fn switch_in(state: TaskState) {
+    *TASK_IO.lock() = state.io;
+    *BASE_HOOK.lock() = state.base_hook;
+    console::set_output_hook(state.hook);
+}
+
+/// The same streams as the running task, for one it starts.
+pub fn inherit() -> TaskState {
+    TaskState { io: TASK_IO.lock().clone(), base_hook: *BASE_HOOK.lock(), hook: console::output_hook() }
+}
+
+/// Descriptors 0, 1 and 2 for a program started from the shell.
+pub fn inherited_stdio() -> [FileDesc; 3] {
+    match TASK_IO.lock().as_ref() {
+        Some(io) => [io.stdin.clone(), io.stdout.clone(), FileDesc::Console],
+        None => [FileDesc::Console, FileDesc::Console, FileDesc::Console],
+    }
+}
+
+/// Everything waiting on a redirected stdin, or `None` when stdin is the keyboard.
+pub fn read_stdin_to_end() -> Option<Vec<u8>> {
+    let stdin = TASK_IO.lock().as_ref().map(|io| io.stdin.clone())?;
+    if stdin.is_console() {
+        return None;
+    }
+    let mut out = Vec::new();
+    loop {
+        match stdin.read(4096) {
+            Ok(chunk) if !chunk.is_empty() => out.extend_from_slice(&chunk),
+            _ => break,
+        }
+    }
+    Some(out)
+}
+
+fn redirect_hook(text: &str, newline: bool) {
+    let stdout = TASK_IO.lock().as_ref().map(|io| io.stdout.clone());
+    let Some(stdout) = stdout else { return; };
+    let _ = stdout.write(text.as_bytes());
+    if newline {
+        let _ = stdout.write(b"\n");
+    }
+}
+
+/// Writes to the terminal underneath any redirection that is in effect.
+fn write_console(text: &str) {
+    if TASK_IO.lock().is_none() {
+        console::write(text);
+        return;
+    }
+    let base = *BASE_HOOK.lock();
+    match base {
+        Some(hook) => hook(text, false),
+        None => console::with_console(|c| c.write(text)),
+    }
+}
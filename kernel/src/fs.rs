@@ -3,19 +3,33 @@ use core::cmp;
 use lazy_static::lazy_static;
 use spin::Mutex;
 
-use crate::{ata, ext2, fat32};
+use crate::{ata, console, exfat, ext2, fat32, iso9660, users};
 
 const ROOT_DIR: &str = "\\";
 const SEP: char = '\\';
 const SEP_STR: &str = "\\";
 const ALT_SEP: char = '/';
+// Symlinks followed while resolving one path before giving up, as on Linux.
+const MAX_SYMLINK_FOLLOWS: u32 = 40;
+// Access asked of a file, as the rwx bits of its mode.
+const MAY_READ: u16 = 4;
+const MAY_WRITE: u16 = 2;
+const MAY_EXEC: u16 = 1;
+const READ_ONLY: &str = "Volume is mounted read-only.";
+pub const NO_OWNERS: &str = "FAT and exFAT have no file owners.";
 type AtaFat32Volume = fat32::Fat32Volume<ata::AtaDevice>;
 type AtaExt2Volume = ext2::Ext2Volume<ata::AtaDevice>;
+type AtaExfatVolume = exfat::ExfatVolume<ata::AtaDevice>;
+type AtaIsoVolume = iso9660::IsoVolume<ata::AtaDevice>;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum FsKind {
     Fat32,
     Ext2,
+    Ext3,
+    Ext4,
+    Exfat,
+    Iso9660,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -23,17 +37,23 @@ pub enum FsPreference {
     Auto,
     Fat32,
     Ext2,
+    Exfat,
+    Iso9660,
 }
 
 enum VfsVolume {
     Fat32(AtaFat32Volume),
     Ext2(AtaExt2Volume),
+    Exfat(AtaExfatVolume),
+    Iso9660(AtaIsoVolume),
 }
 
 #[derive(Clone)]
 enum VfsEntry {
     Fat32(fat32::DirEntryInfo),
     Ext2(ext2::DirEntryInfo),
+    Exfat(exfat::DirEntryInfo),
+    Iso9660(iso9660::DirEntryInfo),
 }
 
 impl VfsEntry {
@@ -41,6 +61,8 @@ impl VfsEntry {
         match self {
             VfsEntry::Fat32(entry) => &entry.name,
             VfsEntry::Ext2(entry) => &entry.name,
+            VfsEntry::Exfat(entry) => &entry.name,
+            VfsEntry::Iso9660(entry) => &entry.name,
         }
     }
 
@@ -48,6 +70,26 @@ impl VfsEntry {
         match self {
             VfsEntry::Fat32(entry) => entry.is_dir,
             VfsEntry::Ext2(entry) => entry.is_dir,
+            VfsEntry::Exfat(entry) => entry.is_dir,
+            VfsEntry::Iso9660(entry) => entry.is_dir,
+        }
+    }
+
+    fn is_symlink(&self) -> bool {
+        match self {
+            VfsEntry::Fat32(_) | VfsEntry::Exfat(_) => false,
+            VfsEntry::Ext2(entry) => entry.kind == ext2::FileKind::Symlink,
+            VfsEntry::Iso9660(entry) => entry.link_target.is_some(),
+        }
+    }
+
+    // FAT's hidden attribute stands in for the leading dot of hidden names.
+    fn is_hidden(&self) -> bool {
+        match self {
+            VfsEntry::Fat32(entry) => entry.attr & FAT_ATTR_HIDDEN != 0 || entry.name.starts_with('.'),
+            VfsEntry::Ext2(entry) => entry.name.starts_with('.'),
+            VfsEntry::Exfat(entry) => entry.attr as u8 & FAT_ATTR_HIDDEN != 0 || entry.name.starts_with('.'),
+            VfsEntry::Iso9660(entry) => entry.hidden || entry.name.starts_with('.'),
         }
     }
 
@@ -55,6 +97,8 @@ impl VfsEntry {
         match self {
             VfsEntry::Fat32(entry) => entry.size as u64,
             VfsEntry::Ext2(entry) => entry.size,
+            VfsEntry::Exfat(entry) => entry.size,
+            VfsEntry::Iso9660(entry) => entry.size,
         }
     }
 
@@ -62,15 +106,285 @@ impl VfsEntry {
         match self {
             VfsEntry::Fat32(entry) => entry.cluster,
             VfsEntry::Ext2(entry) => entry.inode,
+            VfsEntry::Exfat(entry) => entry.cluster,
+            VfsEntry::Iso9660(entry) => entry.extent,
+        }
+    }
+
+    fn modified(&self) -> Option<u64> {
+        match self {
+            VfsEntry::Fat32(entry) => entry.modified,
+            VfsEntry::Ext2(entry) => (entry.mtime != 0).then_some(entry.mtime as u64),
+            VfsEntry::Exfat(entry) => entry.modified,
+            VfsEntry::Iso9660(entry) => entry.modified,
         }
     }
 }
 
 impl VfsVolume {
+    fn is_read_only(&self) -> bool {
+        match self {
+            VfsVolume::Fat32(_) => false,
+            VfsVolume::Ext2(volume) => volume.is_read_only(),
+            VfsVolume::Exfat(volume) => volume.is_read_only(),
+            VfsVolume::Iso9660(_) => true,
+        }
+    }
+
+    fn is_dirty(&mut self) -> Result<bool, &'static str> {
+        match self {
+            VfsVolume::Fat32(volume) => volume.is_dirty(),
+            VfsVolume::Ext2(volume) => Ok(volume.is_dirty()),
+            VfsVolume::Exfat(volume) => volume.is_dirty(),
+            VfsVolume::Iso9660(_) => Ok(false),
+        }
+    }
+
+    fn set_clean(&mut self, clean: bool) -> Result<(), &'static str> {
+        match self {
+            VfsVolume::Fat32(volume) => volume.set_clean(clean),
+            VfsVolume::Ext2(volume) => volume.set_clean(clean),
+            VfsVolume::Exfat(volume) => volume.set_clean(clean),
+            VfsVolume::Iso9660(_) => Ok(()),
+        }
+    }
+
+    fn fsck(&mut self, repair: bool) -> Result<fat32::FsckReport, &'static str> {
+        match self {
+            VfsVolume::Fat32(volume) => volume.fsck(repair),
+            VfsVolume::Ext2(volume) => volume.fsck(repair),
+            VfsVolume::Exfat(volume) => volume.fsck(repair),
+            VfsVolume::Iso9660(volume) => volume.fsck(repair),
+        }
+    }
+
+    // Describes `entry`, or the root directory when there is none.
+    fn stat(&mut self, entry: Option<&VfsEntry>) -> Result<FileStat, &'static str> {
+        let name = entry.map_or(ROOT_DIR, |entry| entry.name()).to_string();
+        let hidden = entry.is_some_and(|entry| entry.is_hidden());
+        match (self, entry) {
+            (VfsVolume::Ext2(volume), _) => {
+                let id = entry.map_or(volume.root_inode(), |entry| entry.dir_id());
+                let inode = volume.stat(id)?;
+                let stamp = |secs: u32| (secs != 0).then_some(secs as u64);
+                let link_target = match entry {
+                    Some(entry) if entry.is_symlink() => Some(volume.read_link(id)?),
+                    _ => None,
+                };
+                Ok(FileStat {
+                    name,
+                    link_target,
+                    hidden,
+                    size: inode.size,
+                    is_dir: inode.mode & 0xF000 == 0x4000,
+                    id,
+                    mode: inode.mode,
+                    attributes: 0,
+                    links: inode.links,
+                    uid: inode.uid,
+                    gid: inode.gid,
+                    blocks: inode.blocks as u64,
+                    created: None,
+                    modified: stamp(inode.mtime),
+                    accessed: stamp(inode.atime),
+                    changed: stamp(inode.ctime),
+                })
+            }
+            (VfsVolume::Fat32(volume), Some(VfsEntry::Fat32(entry))) => {
+                let cluster_sectors = volume.info().sectors_per_cluster as u64;
+                let sectors = (entry.size as u64).div_ceil(cluster_sectors * 512) * cluster_sectors;
+                let (uid, gid) = fat_owner(entry.attr);
+                Ok(FileStat {
+                    name,
+                    link_target: None,
+                    hidden,
+                    size: entry.size as u64,
+                    is_dir: entry.is_dir,
+                    id: entry.cluster,
+                    mode: fat_mode(entry.attr, &entry.name),
+                    attributes: entry.attr,
+                    links: 1,
+                    uid,
+                    gid,
+                    blocks: sectors,
+                    created: entry.created,
+                    modified: entry.modified,
+                    accessed: entry.accessed,
+                    changed: None,
+                })
+            }
+            (VfsVolume::Exfat(volume), Some(VfsEntry::Exfat(entry))) => {
+                let cluster_sectors = volume.cluster_size() as u64 / 512;
+                let sectors = entry.size.div_ceil(cluster_sectors * 512) * cluster_sectors;
+                let attr = entry.attr as u8;
+                let (uid, gid) = fat_owner(attr);
+                Ok(FileStat {
+                    name,
+                    link_target: None,
+                    hidden,
+                    size: entry.size,
+                    is_dir: entry.is_dir,
+                    id: entry.cluster,
+                    mode: fat_mode(attr, &entry.name),
+                    attributes: attr,
+                    links: 1,
+                    uid,
+                    gid,
+                    blocks: sectors,
+                    created: entry.created,
+                    modified: entry.modified,
+                    accessed: entry.accessed,
+                    changed: None,
+                })
+            }
+            (VfsVolume::Iso9660(volume), entry) => {
+                let entry = match entry {
+                    Some(VfsEntry::Iso9660(entry)) => entry,
+                    Some(_) => return Err("Filesystem entry mismatch."),
+                    None => {
+                        let (mode, uid, gid) = iso_owner_mode(None);
+                        return Ok(FileStat {
+                            name,
+                            link_target: None,
+                            hidden,
+                            size: 0,
+                            is_dir: true,
+                            id: volume.root_extent(),
+                            mode,
+                            attributes: 0,
+                            links: 1,
+                            uid,
+                            gid,
+                            blocks: 0,
+                            created: None,
+                            modified: None,
+                            accessed: None,
+                            changed: None,
+                        });
+                    }
+                };
+                let (mode, uid, gid) = iso_owner_mode(Some(entry));
+                Ok(FileStat {
+                    name,
+                    link_target: entry.link_target.clone(),
+                    hidden,
+                    size: entry.size,
+                    is_dir: entry.is_dir,
+                    id: entry.extent,
+                    mode,
+                    attributes: 0,
+                    links: cmp::min(entry.links, u16::MAX as u32) as u16,
+                    uid,
+                    gid,
+                    blocks: entry.size.div_ceil(512),
+                    created: entry.created,
+                    modified: entry.modified,
+                    accessed: entry.accessed,
+                    changed: None,
+                })
+            }
+            (volume @ (VfsVolume::Fat32(_) | VfsVolume::Exfat(_)), _) => Ok(FileStat {
+                name,
+                link_target: None,
+                hidden,
+                size: 0,
+                is_dir: true,
+                id: volume.root_id(),
+                mode: fat_mode(FAT_ATTR_DIRECTORY, ""),
+                attributes: FAT_ATTR_DIRECTORY,
+                links: 1,
+                uid: users::current_ids().0,
+                gid: users::current_ids().1,
+                blocks: 0,
+                created: None,
+                modified: None,
+                accessed: None,
+                changed: None,
+            }),
+        }
+    }
+
+    // Mode, owner and group of `entry`, or of directory `dir` when there is none.
+    fn owner_mode(&mut self, dir: u32, entry: Option<&VfsEntry>) -> Result<(u16, u16, u16), &'static str> {
+        match (self, entry) {
+            (VfsVolume::Ext2(volume), _) => {
+                let inode = volume.stat(entry.map_or(dir, |entry| entry.dir_id()))?;
+                Ok((inode.mode, inode.uid, inode.gid))
+            }
+            (VfsVolume::Fat32(_), Some(VfsEntry::Fat32(entry))) => {
+                let (uid, gid) = fat_owner(entry.attr);
+                Ok((fat_mode(entry.attr, &entry.name), uid, gid))
+            }
+            (VfsVolume::Exfat(_), Some(VfsEntry::Exfat(entry))) => {
+                let attr = entry.attr as u8;
+                let (uid, gid) = fat_owner(attr);
+                Ok((fat_mode(attr, &entry.name), uid, gid))
+            }
+            (VfsVolume::Fat32(_) | VfsVolume::Exfat(_), _) => {
+                let (uid, gid) = fat_owner(FAT_ATTR_DIRECTORY);
+                Ok((fat_mode(FAT_ATTR_DIRECTORY, ""), uid, gid))
+            }
+            (VfsVolume::Iso9660(_), Some(VfsEntry::Iso9660(entry))) => Ok(iso_owner_mode(Some(entry))),
+            (VfsVolume::Iso9660(_), _) => Ok(iso_owner_mode(None)),
+        }
+    }
+
+    // FAT and exFAT keep only the read-only attribute, set when no write bit is left.
+    fn set_mode(&mut self, dir: u32, entry: &VfsEntry, permissions: u16) -> Result<(), &'static str> {
+        match (self, entry) {
+            (VfsVolume::Ext2(volume), VfsEntry::Ext2(entry)) => volume.set_mode(entry.inode, permissions),
+            (VfsVolume::Fat32(volume), VfsEntry::Fat32(entry)) => {
+                let attr = if permissions & 0o222 == 0 {
+                    entry.attr | FAT_ATTR_READ_ONLY
+                } else {
+                    entry.attr & !FAT_ATTR_READ_ONLY
+                };
+                volume.set_attributes(dir, entry, attr)
+            }
+            (VfsVolume::Exfat(volume), VfsEntry::Exfat(entry)) => {
+                let read_only = FAT_ATTR_READ_ONLY as u16;
+                let attr = if permissions & 0o222 == 0 { entry.attr | read_only } else { entry.attr & !read_only };
+                volume.set_attributes(dir, entry, attr)
+            }
+            (VfsVolume::Iso9660(_), _) => Err(READ_ONLY),
+            _ => Err("Filesystem entry mismatch."),
+        }
+    }
+
+    // FAT and exFAT can only tell root's files, marked system, from everyone else's, so
+    // other owners are refused unless they are whoever is logged in.
+    fn set_owner(&mut self, dir: u32, entry: &VfsEntry, uid: u16, gid: u16) -> Result<(), &'static str> {
+        let system = if (uid, gid) == (0, 0) {
+            Some(true)
+        } else if (uid, gid) == users::current_ids() {
+            Some(false)
+        } else {
+            None
+        };
+        match (self, entry) {
+            (VfsVolume::Ext2(volume), VfsEntry::Ext2(entry)) => volume.set_owner(entry.inode, uid, gid),
+            (VfsVolume::Fat32(volume), VfsEntry::Fat32(entry)) => {
+                let system = system.ok_or(NO_OWNERS)?;
+                let attr = if system { entry.attr | FAT_ATTR_SYSTEM } else { entry.attr & !FAT_ATTR_SYSTEM };
+                volume.set_attributes(dir, entry, attr)
+            }
+            (VfsVolume::Exfat(volume), VfsEntry::Exfat(entry)) => {
+                let system = system.ok_or(NO_OWNERS)?;
+                let bit = FAT_ATTR_SYSTEM as u16;
+                let attr = if system { entry.attr | bit } else { entry.attr & !bit };
+                volume.set_attributes(dir, entry, attr)
+            }
+            (VfsVolume::Iso9660(_), _) => Err(READ_ONLY),
+            _ => Err("Filesystem entry mismatch."),
+        }
+    }
+
     fn root_id(&self) -> u32 {
         match self {
             VfsVolume::Fat32(volume) => volume.root_cluster(),
             VfsVolume::Ext2(volume) => volume.root_inode(),
+            VfsVolume::Exfat(volume) => volume.root_cluster(),
+            VfsVolume::Iso9660(volume) => volume.root_extent(),
         }
     }
 
@@ -82,6 +396,12 @@ impl VfsVolume {
             VfsVolume::Ext2(volume) => volume
                 .read_directory(dir)
                 .map(|entries| entries.into_iter().map(VfsEntry::Ext2).collect()),
+            VfsVolume::Exfat(volume) => volume
+                .read_directory(dir)
+                .map(|entries| entries.into_iter().map(VfsEntry::Exfat).collect()),
+            VfsVolume::Iso9660(volume) => volume
+                .read_directory(dir)
+                .map(|entries| entries.into_iter().map(VfsEntry::Iso9660).collect()),
         }
     }
 
@@ -93,6 +413,12 @@ impl VfsVolume {
             VfsVolume::Ext2(volume) => Ok(volume
                 .find_entry(dir, name)?
                 .map(VfsEntry::Ext2)),
+            VfsVolume::Exfat(volume) => Ok(volume
+                .find_entry(dir, name)?
+                .map(VfsEntry::Exfat)),
+            VfsVolume::Iso9660(volume) => Ok(volume
+                .find_entry(dir, name)?
+                .map(VfsEntry::Iso9660)),
         }
     }
 
@@ -104,6 +430,37 @@ impl VfsVolume {
             VfsVolume::Ext2(volume) => volume
                 .create_entry(dir, name, is_dir)
                 .map(VfsEntry::Ext2),
+            VfsVolume::Exfat(volume) => volume
+                .create_entry(dir, name, is_dir)
+                .map(VfsEntry::Exfat),
+            VfsVolume::Iso9660(_) => Err(READ_ONLY),
+        }
+    }
+
+    fn create_symlink(&mut self, dir: u32, name: &str, target: &str) -> Result<VfsEntry, &'static str> {
+        match self {
+            VfsVolume::Fat32(_) => Err("FAT32 has no symbolic links."),
+            VfsVolume::Exfat(_) => Err("exFAT has no symbolic links."),
+            VfsVolume::Iso9660(_) => Err(READ_ONLY),
+            VfsVolume::Ext2(volume) => volume.create_symlink(dir, name, target).map(VfsEntry::Ext2),
+        }
+    }
+
+    fn link(&mut self, dir: u32, name: &str, entry: &VfsEntry) -> Result<VfsEntry, &'static str> {
+        match (self, entry) {
+            (VfsVolume::Ext2(volume), VfsEntry::Ext2(entry)) => volume.link(dir, name, entry.inode).map(VfsEntry::Ext2),
+            (VfsVolume::Fat32(_), _) => Err("FAT32 has no hard links."),
+            (VfsVolume::Exfat(_), _) => Err("exFAT has no hard links."),
+            (VfsVolume::Iso9660(_), _) => Err(READ_ONLY),
+            _ => Err("Filesystem entry mismatch."),
+        }
+    }
+
+    fn read_link(&mut self, entry: &VfsEntry) -> Result<String, &'static str> {
+        match (self, entry) {
+            (VfsVolume::Ext2(volume), VfsEntry::Ext2(entry)) => volume.read_link(entry.inode),
+            (VfsVolume::Iso9660(volume), VfsEntry::Iso9660(entry)) => volume.read_link(entry),
+            _ => Err("Not a symbolic link."),
         }
     }
 
@@ -111,6 +468,8 @@ impl VfsVolume {
         match (self, entry) {
             (VfsVolume::Fat32(volume), VfsEntry::Fat32(entry)) => volume.read_file(entry),
             (VfsVolume::Ext2(volume), VfsEntry::Ext2(entry)) => volume.read_file(entry),
+            (VfsVolume::Exfat(volume), VfsEntry::Exfat(entry)) => volume.read_file(entry),
+            (VfsVolume::Iso9660(volume), VfsEntry::Iso9660(entry)) => volume.read_file(entry),
             _ => Err("Filesystem entry mismatch."),
         }
     }
@@ -123,6 +482,44 @@ impl VfsVolume {
             (VfsVolume::Ext2(volume), VfsEntry::Ext2(entry)) => {
                 volume.write_file(dir, entry, contents)
             }
+            (VfsVolume::Exfat(volume), VfsEntry::Exfat(entry)) => {
+                volume.write_file(dir, entry, contents)
+            }
+            (VfsVolume::Iso9660(_), _) => Err(READ_ONLY),
+            _ => Err("Filesystem entry mismatch."),
+        }
+    }
+
+    // exFAT has no partial writes, so it rewrites the whole file.
+    fn write_at(&mut self, dir: u32, entry: &VfsEntry, offset: u64, data: &[u8]) -> Result<(), &'static str> {
+        match (self, entry) {
+            (VfsVolume::Fat32(volume), VfsEntry::Fat32(entry)) => volume.write_at(dir, entry, offset, data),
+            (VfsVolume::Ext2(volume), VfsEntry::Ext2(entry)) => volume.write_at(entry, offset, data),
+            (VfsVolume::Exfat(volume), VfsEntry::Exfat(entry)) => {
+                let mut contents = volume.read_file(entry)?;
+                let start = size_to_usize(offset);
+                let end = start.checked_add(data.len()).ok_or("File too large.")?;
+                if contents.len() < end {
+                    contents.resize(end, 0);
+                }
+                contents[start..end].copy_from_slice(data);
+                volume.write_file(dir, entry, &contents)
+            }
+            (VfsVolume::Iso9660(_), _) => Err(READ_ONLY),
+            _ => Err("Filesystem entry mismatch."),
+        }
+    }
+
+    fn truncate(&mut self, dir: u32, entry: &VfsEntry, len: u64) -> Result<(), &'static str> {
+        match (self, entry) {
+            (VfsVolume::Fat32(volume), VfsEntry::Fat32(entry)) => volume.truncate(dir, entry, len),
+            (VfsVolume::Ext2(volume), VfsEntry::Ext2(entry)) => volume.truncate(entry, len),
+            (VfsVolume::Exfat(volume), VfsEntry::Exfat(entry)) => {
+                let mut contents = volume.read_file(entry)?;
+                contents.resize(size_to_usize(len), 0);
+                volume.write_file(dir, entry, &contents)
+            }
+            (VfsVolume::Iso9660(_), _) => Err(READ_ONLY),
             _ => Err("Filesystem entry mismatch."),
         }
     }
@@ -131,6 +528,8 @@ impl VfsVolume {
         match (self, entry) {
             (VfsVolume::Fat32(volume), VfsEntry::Fat32(entry)) => volume.delete_entry(dir, entry),
             (VfsVolume::Ext2(volume), VfsEntry::Ext2(entry)) => volume.delete_entry(dir, entry),
+            (VfsVolume::Exfat(volume), VfsEntry::Exfat(entry)) => volume.delete_entry(dir, entry),
+            (VfsVolume::Iso9660(_), _) => Err(READ_ONLY),
             _ => Err("Filesystem entry mismatch."),
         }
     }
@@ -139,6 +538,8 @@ impl VfsVolume {
         match (self, entry) {
             (VfsVolume::Fat32(volume), VfsEntry::Fat32(entry)) => volume.delete_entry(dir, entry),
             (VfsVolume::Ext2(volume), VfsEntry::Ext2(entry)) => volume.delete_dir(dir, entry),
+            (VfsVolume::Exfat(volume), VfsEntry::Exfat(entry)) => volume.delete_entry(dir, entry),
+            (VfsVolume::Iso9660(_), _) => Err(READ_ONLY),
             _ => Err("Filesystem entry mismatch."),
         }
     }
@@ -149,6 +550,9 @@ impl VfsVolume {
                 volume.update_access_date(dir, entry)
             }
             (VfsVolume::Ext2(volume), VfsEntry::Ext2(entry)) => volume.update_access_date(entry),
+            (VfsVolume::Exfat(volume), VfsEntry::Exfat(entry)) => volume.update_access_date(dir, entry),
+            // Access times cannot be recorded on a disc.
+            (VfsVolume::Iso9660(_), _) => Ok(()),
             _ => Err("Filesystem entry mismatch."),
         }
     }
@@ -160,17 +564,24 @@ pub enum ProbeResult {
     IdentifyError(ata::AtaError),
     ReadError(ata::AtaError),
     Identified { sectors: u64, mbr: fat32::MbrInfo },
+    /// An ATAPI drive with a disc in it; `iso` when the disc holds an ISO9660 volume.
+    Optical { sectors: u64, iso: bool },
 }
 
 struct PersistState {
     enabled: bool,
     drive: Option<ata::DriveSelect>,
     sectors: u64,
+    // The selected drive is a CD-ROM, which is read with packet commands.
+    atapi: bool,
+    unclean: bool,
     last_error: Option<&'static str>,
     partition: Option<fat32::PartitionInfo>,
     fs_kind: Option<FsKind>,
     fat32_info: Option<fat32::Fat32Info>,
     ext2_info: Option<ext2::Ext2Info>,
+    exfat_info: Option<exfat::ExfatInfo>,
+    iso_info: Option<iso9660::IsoInfo>,
     preferred_fs: FsPreference,
     primary_master_probe: ProbeResult,
     primary_slave_probe: ProbeResult,
@@ -184,11 +595,15 @@ impl PersistState {
             enabled: false,
             drive: None,
             sectors: 0,
+            atapi: false,
+            unclean: false,
             last_error: None,
             partition: None,
             fs_kind: None,
             fat32_info: None,
             ext2_info: None,
+            exfat_info: None,
+            iso_info: None,
             preferred_fs: FsPreference::Auto,
             primary_master_probe: ProbeResult::NotTried,
             primary_slave_probe: ProbeResult::NotTried,
@@ -209,11 +624,15 @@ pub struct PersistInfo {
     pub enabled: bool,
     pub drive: Option<ata::DriveSelect>,
     pub sectors: u64,
+    /// The mounted volume had not been cleanly unmounted and was checked at mount.
+    pub unclean: bool,
     pub last_error: Option<&'static str>,
     pub partition: Option<fat32::PartitionInfo>,
     pub fs_kind: Option<FsKind>,
     pub fat32_info: Option<fat32::Fat32Info>,
     pub ext2_info: Option<ext2::Ext2Info>,
+    pub exfat_info: Option<exfat::ExfatInfo>,
+    pub iso_info: Option<iso9660::IsoInfo>,
     pub preferred_fs: FsPreference,
     pub primary_master_probe: ProbeResult,
     pub primary_slave_probe: ProbeResult,
@@ -234,11 +653,14 @@ pub fn persist_info() -> PersistInfo {
         enabled: state.enabled,
         drive: state.drive,
         sectors: state.sectors,
+        unclean: state.unclean,
         last_error: state.last_error,
         partition: state.partition,
         fs_kind: state.fs_kind,
         fat32_info: state.fat32_info,
         ext2_info: state.ext2_info,
+        exfat_info: state.exfat_info,
+        iso_info: state.iso_info,
         preferred_fs: state.preferred_fs,
         primary_master_probe: state.primary_master_probe,
         primary_slave_probe: state.primary_slave_probe,
@@ -256,6 +678,17 @@ pub fn set_fs_preference(preference: FsPreference) {
     state.preferred_fs = preference;
 }
 
+/// True when the mounted filesystem compares names without regard to case, as FAT32 and
+/// exFAT do, and ISO9660 without Rock Ridge.
+pub fn names_ignore_case() -> bool {
+    let state = PERSIST.lock();
+    match state.fs_kind {
+        Some(FsKind::Fat32 | FsKind::Exfat) => true,
+        Some(FsKind::Iso9660) => state.iso_info.is_some_and(|info| !info.rock_ridge),
+        _ => false,
+    }
+}
+
 pub fn usage_info() -> Result<UsageInfo, &'static str> {
     with_volume(|volume| {
         let (total_bytes, free_bytes) = match volume {
@@ -271,6 +704,13 @@ pub fn usage_info() -> Result<UsageInfo, &'static str> {
                 let free_bytes = usage.free_blocks as u64 * usage.block_size as u64;
                 (total_bytes, free_bytes)
             }
+            VfsVolume::Exfat(volume) => {
+                let usage = volume.usage()?;
+                let total_bytes = usage.total_clusters as u64 * usage.cluster_size as u64;
+                let free_bytes = usage.free_clusters as u64 * usage.cluster_size as u64;
+                (total_bytes, free_bytes)
+            }
+            VfsVolume::Iso9660(volume) => (volume.total_bytes(), 0),
         };
         let used_bytes = total_bytes.saturating_sub(free_bytes);
         let used_percent = if total_bytes == 0 {
@@ -293,6 +733,10 @@ fn with_volume<T>(mut action: impl FnMut(&mut VfsVolume) -> Result<T, &'static s
     let Some(volume) = guard.as_mut() else {
         return Err("Persistent filesystem not available.");
     };
+    if let VfsVolume::Ext2(volume) = volume {
+        let (uid, gid) = users::current_ids();
+        volume.set_creator(uid, gid);
+    }
     action(volume)
 }
 
@@ -388,39 +832,199 @@ fn resolve_parent(path: &str) -> Result<(Vec<String>, String), &'static str> {
     Ok((components, name))
 }
 
-fn resolve_dir(volume: &mut VfsVolume, components: &[String]) -> Result<(u32, Vec<String>), &'static str> {
-    let mut cluster = volume.root_id();
-    let mut stack: Vec<u32> = vec![cluster];
+// A directory a walk ended at, with the entry naming it in its parent; the root has none.
+// Access to the directory is checked against that entry, so on FAT and exFAT a
+// system-marked one stays root's.
+#[derive(Clone)]
+struct Dir {
+    id: u32,
+    entry: Option<VfsEntry>,
+}
+
+// Walks `components` from the root to a directory. Symlinks met on the way are followed,
+// and the display path is where the walk really ended up.
+fn resolve_dir(volume: &mut VfsVolume, components: &[String]) -> Result<(Dir, Vec<String>), &'static str> {
+    resolve_dir_following(volume, components, &mut 0)
+}
+
+fn resolve_dir_following(
+    volume: &mut VfsVolume,
+    components: &[String],
+    follows: &mut u32,
+) -> Result<(Dir, Vec<String>), &'static str> {
+    let mut pending = components.to_vec();
+    let root = Dir { id: volume.root_id(), entry: None };
+    check_access(volume, root.id, None, MAY_EXEC)?;
+    let mut stack: Vec<Dir> = vec![root.clone()];
     let mut display: Vec<String> = Vec::new();
 
-    for comp in components {
+    let mut index = 0;
+    while index < pending.len() {
+        let comp = pending[index].clone();
+        index += 1;
         if comp == "." {
             continue;
         }
         if comp == ".." {
             if stack.len() > 1 {
                 stack.pop();
-                cluster = *stack.last().unwrap_or(&cluster);
                 display.pop();
             }
             continue;
         }
-        let Some(entry) = volume.find_entry(cluster, comp)? else {
+        let cluster = stack.last().map_or(root.id, |dir| dir.id);
+        let Some(entry) = volume.find_entry(cluster, &comp)? else {
             return Err("Directory not found.");
         };
+        if entry.is_symlink() {
+            // The walk starts over from the root with the target spliced in.
+            let mut target = follow_link(volume, &entry, &display, follows)?;
+            target.extend_from_slice(&pending[index..]);
+            pending = target;
+            index = 0;
+            stack = vec![root.clone()];
+            display.clear();
+            continue;
+        }
         if !entry.is_dir() {
             return Err("Not a directory.");
         }
-        cluster = entry.dir_id();
-        stack.push(cluster);
+        check_access(volume, cluster, Some(&entry), MAY_EXEC)?;
         display.push(entry.name().to_string());
+        stack.push(Dir { id: entry.dir_id(), entry: Some(entry) });
+    }
+
+    Ok((stack.pop().unwrap_or(root), display))
+}
+
+// Fails with "Permission denied." unless the current user may access `entry`, or the
+// directory `dir` when there is none, in the ways `want` asks (MAY_* bits).
+fn check_access(volume: &mut VfsVolume, dir: u32, entry: Option<&VfsEntry>, want: u16) -> Result<(), &'static str> {
+    let (mode, uid, gid) = volume.owner_mode(dir, entry)?;
+    let creds = users::credentials();
+    // Root may do anything but execute a file nobody may execute.
+    let allowed = if creds.uid == 0 {
+        want & MAY_EXEC == 0 || mode & 0xF000 == 0x4000 || mode & 0o111 != 0
+    } else {
+        let bits = if creds.uid == uid {
+            mode >> 6
+        } else if creds.in_group(gid) {
+            mode >> 3
+        } else {
+            mode
+        };
+        bits & want == want
+    };
+    if allowed {
+        Ok(())
+    } else {
+        Err("Permission denied.")
+    }
+}
+
+// Reads symlink `entry`, found in the directory at `dir_path`, and resolves its target to
+// components from the root, counting the follow against the limit.
+fn follow_link(
+    volume: &mut VfsVolume,
+    entry: &VfsEntry,
+    dir_path: &[String],
+    follows: &mut u32,
+) -> Result<Vec<String>, &'static str> {
+    *follows += 1;
+    if *follows > MAX_SYMLINK_FOLLOWS {
+        return Err("Too many levels of symbolic links.");
+    }
+    let target = volume.read_link(entry)?;
+    resolve_path(&target, &path_string(dir_path))
+}
+
+// Where a path led: the directory its last component was looked up in and the entry
+// naming that directory, the name, and the entry by that name if there is one.
+struct Lookup {
+    dir: u32,
+    dir_entry: Option<VfsEntry>,
+    name: String,
+    entry: Option<VfsEntry>,
+}
+
+// Finds what `components` names, following a symlink at the end too when `follow` is set.
+// None stands for the root directory itself.
+fn lookup(volume: &mut VfsVolume, components: &[String], follow: bool) -> Result<Option<Lookup>, &'static str> {
+    let mut follows = 0;
+    let mut components = components.to_vec();
+    loop {
+        let Some((name, parent)) = components.split_last() else {
+            return Ok(None);
+        };
+        let (dir, display) = resolve_dir_following(volume, parent, &mut follows)?;
+        match volume.find_entry(dir.id, name)? {
+            Some(entry) if follow && entry.is_symlink() => {
+                components = follow_link(volume, &entry, &display, &mut follows)?;
+            }
+            entry => {
+                return Ok(Some(Lookup {
+                    dir: dir.id,
+                    dir_entry: dir.entry,
+                    name: name.clone(),
+                    entry,
+                }))
+            }
+        }
+    }
+}
+
+fn path_string(components: &[String]) -> String {
+    if components.is_empty() {
+        ROOT_DIR.to_string()
+    } else {
+        format!("{}{}", ROOT_DIR, components.join(SEP_STR))
+    }
+}
+
+/// Checks and repairs the mounted volume when it was not cleanly unmounted, then marks
+/// it in use until `unmount`. Returns whether it was unclean.
+fn mount(volume: &mut VfsVolume) -> bool {
+    let read_only = volume.is_read_only();
+    if read_only && !matches!(volume, VfsVolume::Iso9660(_)) {
+        console::write_line("Volume uses features this driver cannot write; mounted read-only.");
+    }
+    let unclean = volume.is_dirty().unwrap_or(false);
+    if unclean {
+        console::write_line("Volume was not cleanly unmounted; checking it...");
+        match volume.fsck(!read_only) {
+            Ok(report) => {
+                for problem in &report.problems {
+                    console::write_line(&format!("fsck: {}", problem));
+                }
+                let outcome = if read_only { "found" } else { "repaired" };
+                console::write_line(&format!("fsck: {} problem(s) {}.", report.problems.len(), outcome));
+            }
+            Err(err) => console::write_line(&format!("fsck: {}", err)),
+        }
     }
+    volume.set_clean(false).ok();
+    unclean
+}
 
-    Ok((cluster, display))
+/// Marks the mounted volume clean and drops it. Call before rebooting or powering off.
+pub fn unmount() {
+    if let Some(mut volume) = VOLUME.lock().take() {
+        volume.set_clean(true).ok();
+    }
+    PERSIST.lock().enabled = false;
+}
+
+/// Checks the mounted volume, repairing what it finds when `repair` is set.
+/// Repairs are for root only; anyone may check.
+pub fn fsck(repair: bool) -> Result<fat32::FsckReport, &'static str> {
+    if repair && users::current_ids().0 != 0 {
+        return Err("Only root may repair the volume.");
+    }
+    with_volume(|volume| volume.fsck(repair))
 }
 
 pub fn init_persistent() {
-    *VOLUME.lock() = None;
+    unmount();
     let primary_master_probe = probe_drive(ata::DriveSelect::PrimaryMaster);
     let primary_slave_probe = probe_drive(ata::DriveSelect::PrimarySlave);
     let secondary_master_probe = probe_drive(ata::DriveSelect::SecondaryMaster);
@@ -431,11 +1035,15 @@ pub fn init_persistent() {
         state.enabled = false;
         state.drive = None;
         state.sectors = 0;
+        state.atapi = false;
+        state.unclean = false;
         state.last_error = None;
         state.partition = None;
         state.fs_kind = None;
         state.fat32_info = None;
         state.ext2_info = None;
+        state.exfat_info = None;
+        state.iso_info = None;
         state.primary_master_probe = primary_master_probe;
         state.primary_slave_probe = primary_slave_probe;
         state.secondary_master_probe = secondary_master_probe;
@@ -449,20 +1057,33 @@ pub fn init_persistent() {
         (ata::DriveSelect::PrimaryMaster, primary_master_probe),
     ];
 
-    let mut candidates: Vec<(ata::DriveSelect, u64, fat32::MbrInfo, Option<fat32::PartitionInfo>, Option<fat32::PartitionInfo>)> = Vec::new();
+    let mut candidates: Vec<(ata::DriveSelect, u64, fat32::MbrInfo, Option<fat32::PartitionInfo>, Option<fat32::PartitionInfo>, Option<fat32::PartitionInfo>)> = Vec::new();
+    // CD-ROM drives holding an ISO9660 disc, which is mounted whole.
+    let mut discs: Vec<(ata::DriveSelect, u64)> = Vec::new();
     for (drive, probe) in order {
-        if let ProbeResult::Identified { sectors, mbr } = probe {
-            let fat_part = fat32::find_fat32_partition(&mbr);
-            let ext_part = ext2::find_ext2_partition(&mbr);
-            candidates.push((drive, sectors, mbr, fat_part, ext_part));
+        match probe {
+            ProbeResult::Identified { sectors, mbr } => {
+                let fat_part = fat32::find_fat32_partition(&mbr);
+                let ext_part = ext2::find_ext2_partition(&mbr);
+                let exfat_part = exfat::find_exfat_partition(&mbr);
+                candidates.push((drive, sectors, mbr, fat_part, ext_part, exfat_part));
+            }
+            ProbeResult::Optical { sectors, iso: true } => discs.push((drive, sectors)),
+            _ => {}
         }
     }
+    let disc_selection = |(drive, sectors): (ata::DriveSelect, u64)| {
+        let dev = ata::AtaDevice { drive, sectors, atapi: true };
+        let part = iso9660::find_iso_volume(&dev, sectors);
+        let mbr = fat32::MbrInfo { signature: false, is_empty: false, partitions: [None; 4] };
+        (drive, sectors, mbr, part, Some(FsKind::Iso9660))
+    };
 
     let preferred = fs_preference();
     let mut selected: Option<(ata::DriveSelect, u64, fat32::MbrInfo, Option<fat32::PartitionInfo>, Option<FsKind>)> = None;
     match preferred {
         FsPreference::Fat32 => {
-            for (drive, sectors, mbr, fat_part, _ext_part) in candidates.iter() {
+            for (drive, sectors, mbr, fat_part, _ext_part, _exfat_part) in candidates.iter() {
                 if let Some(part) = fat_part {
                     selected = Some((*drive, *sectors, *mbr, Some(*part), Some(FsKind::Fat32)));
                     break;
@@ -470,33 +1091,53 @@ pub fn init_persistent() {
             }
         }
         FsPreference::Ext2 => {
-            for (drive, sectors, mbr, _fat_part, ext_part) in candidates.iter() {
+            for (drive, sectors, mbr, _fat_part, ext_part, _exfat_part) in candidates.iter() {
                 if let Some(part) = ext_part {
                     selected = Some((*drive, *sectors, *mbr, Some(*part), Some(FsKind::Ext2)));
                     break;
                 }
             }
         }
+        FsPreference::Exfat => {
+            for (drive, sectors, mbr, _fat_part, _ext_part, exfat_part) in candidates.iter() {
+                if let Some(part) = exfat_part {
+                    selected = Some((*drive, *sectors, *mbr, Some(*part), Some(FsKind::Exfat)));
+                    break;
+                }
+            }
+        }
+        FsPreference::Iso9660 => selected = discs.first().copied().map(disc_selection),
         FsPreference::Auto => {
-            for (drive, sectors, mbr, fat_part, _ext_part) in candidates.iter() {
+            for (drive, sectors, mbr, fat_part, _ext_part, _exfat_part) in candidates.iter() {
                 if let Some(part) = fat_part {
                     selected = Some((*drive, *sectors, *mbr, Some(*part), Some(FsKind::Fat32)));
                     break;
                 }
             }
             if selected.is_none() {
-                for (drive, sectors, mbr, _fat_part, ext_part) in candidates.iter() {
+                for (drive, sectors, mbr, _fat_part, ext_part, _exfat_part) in candidates.iter() {
                     if let Some(part) = ext_part {
                         selected = Some((*drive, *sectors, *mbr, Some(*part), Some(FsKind::Ext2)));
                         break;
                     }
                 }
             }
+            if selected.is_none() {
+                for (drive, sectors, mbr, _fat_part, _ext_part, exfat_part) in candidates.iter() {
+                    if let Some(part) = exfat_part {
+                        selected = Some((*drive, *sectors, *mbr, Some(*part), Some(FsKind::Exfat)));
+                        break;
+                    }
+                }
+            }
+            if selected.is_none() {
+                selected = discs.first().copied().map(disc_selection);
+            }
         }
     }
 
     if selected.is_none() {
-        if let Some((drive, sectors, mbr, _fat_part, _ext_part)) = candidates.first().copied() {
+        if let Some((drive, sectors, mbr, _fat_part, _ext_part, _exfat_part)) = candidates.first().copied() {
             selected = Some((drive, sectors, mbr, None, None));
         }
     }
@@ -507,14 +1148,18 @@ pub fn init_persistent() {
         return;
     };
 
-    let dev = ata::AtaDevice { drive, sectors };
+    let atapi = kind == Some(FsKind::Iso9660);
+    let dev = ata::AtaDevice { drive, sectors, atapi };
     let mut state = PERSIST.lock();
     state.drive = Some(drive);
     state.sectors = sectors;
+    state.atapi = atapi;
     state.partition = part;
     state.fs_kind = kind;
     state.fat32_info = None;
     state.ext2_info = None;
+    state.exfat_info = None;
+    state.iso_info = None;
 
     drop(state);
     *CWD.lock() = ROOT_DIR.to_string();
@@ -522,20 +1167,23 @@ pub fn init_persistent() {
     if let (Some(part), Some(kind)) = (part, kind) {
         let open_result = match kind {
             FsKind::Fat32 => fat32::Fat32Volume::open(dev, part).map(VfsVolume::Fat32),
-            FsKind::Ext2 => ext2::Ext2Volume::open(dev, part).map(VfsVolume::Ext2),
+            FsKind::Ext2 | FsKind::Ext3 | FsKind::Ext4 => ext2::Ext2Volume::open(dev, part).map(VfsVolume::Ext2),
+            FsKind::Exfat => exfat::ExfatVolume::open(dev, part).map(VfsVolume::Exfat),
+            FsKind::Iso9660 => iso9660::IsoVolume::open(dev, part).map(VfsVolume::Iso9660),
         };
         match open_result {
-            Ok(volume) => {
-                let (fat32_info, ext2_info) = match &volume {
-                    VfsVolume::Fat32(vol) => (Some(vol.info()), None),
-                    VfsVolume::Ext2(vol) => (None, Some(vol.info())),
-                };
+            Ok(mut volume) => {
+                let unclean = mount(&mut volume);
+                let (fat32_info, ext2_info, exfat_info, iso_info) = volume_infos(&volume);
                 *VOLUME.lock() = Some(volume);
                 let mut state = PERSIST.lock();
                 state.enabled = true;
-                state.fs_kind = Some(kind);
+                state.unclean = unclean;
+                state.fs_kind = Some(ext2_info.as_ref().map_or(kind, ext_kind));
                 state.fat32_info = fat32_info;
                 state.ext2_info = ext2_info;
+                state.exfat_info = exfat_info;
+                state.iso_info = iso_info;
                 state.last_error = None;
                 return;
             }
@@ -552,12 +1200,16 @@ pub fn init_persistent() {
     let empty_error = match preferred {
         FsPreference::Fat32 => "Disk appears empty; format to create FAT32.",
         FsPreference::Ext2 => "Disk appears empty; format to create EXT2.",
-        FsPreference::Auto => "Disk appears empty; format to create FAT32 or EXT2.",
+        FsPreference::Exfat => "Disk appears empty; format to create exFAT.",
+        FsPreference::Iso9660 => "No ISO9660 disc found.",
+        FsPreference::Auto => "Disk appears empty; format to create FAT32, EXT2 or exFAT.",
     };
     let missing_error = match preferred {
         FsPreference::Fat32 => "No FAT32 partition found.",
         FsPreference::Ext2 => "No EXT2 partition found.",
-        FsPreference::Auto => "No FAT32 or EXT2 partition found.",
+        FsPreference::Exfat => "No exFAT partition found.",
+        FsPreference::Iso9660 => "No ISO9660 disc found.",
+        FsPreference::Auto => "No FAT32, EXT2 or exFAT partition found.",
     };
     let mut state = PERSIST.lock();
     state.enabled = false;
@@ -572,11 +1224,41 @@ pub fn init_persistent() {
     };
 }
 
+type VolumeInfos = (
+    Option<fat32::Fat32Info>,
+    Option<ext2::Ext2Info>,
+    Option<exfat::ExfatInfo>,
+    Option<iso9660::IsoInfo>,
+);
+
+// Driver details of whichever volume is mounted, for `PersistInfo`.
+fn volume_infos(volume: &VfsVolume) -> VolumeInfos {
+    match volume {
+        VfsVolume::Fat32(vol) => (Some(vol.info()), None, None, None),
+        VfsVolume::Ext2(vol) => (None, Some(vol.info()), None, None),
+        VfsVolume::Exfat(vol) => (None, None, Some(vol.info()), None),
+        VfsVolume::Iso9660(vol) => (None, None, None, Some(vol.info())),
+    }
+}
+
+// EXT3 and EXT4 volumes are opened by the EXT2 driver and told apart by their features.
+fn ext_kind(info: &ext2::Ext2Info) -> FsKind {
+    match info.generation() {
+        4 => FsKind::Ext4,
+        3 => FsKind::Ext3,
+        _ => FsKind::Ext2,
+    }
+}
+
 fn probe_drive(drive: ata::DriveSelect) -> ProbeResult {
     let dev = match ata::identify(drive) {
         Ok(dev) => dev,
         Err(err) => return ProbeResult::IdentifyError(err),
     };
+    if dev.atapi {
+        let iso = iso9660::find_iso_volume(&dev, dev.sectors).is_some();
+        return ProbeResult::Optical { sectors: dev.sectors, iso };
+    }
 
     match fat32::read_mbr(&dev) {
         Ok(mbr) => ProbeResult::Identified {
@@ -588,11 +1270,17 @@ fn probe_drive(drive: ata::DriveSelect) -> ProbeResult {
 }
 
 pub fn format_disk(target: Option<FsKind>) -> Result<(), &'static str> {
+    if users::current_ids().0 != 0 {
+        return Err("Only root may format the disk.");
+    }
     let (drive, sectors) = {
         let state = PERSIST.lock();
         let Some(drive) = state.drive else {
             return Err("No ATA disk selected.");
         };
+        if state.atapi {
+            return Err("Cannot format a CD-ROM.");
+        }
         (drive, state.sectors)
     };
 
@@ -602,52 +1290,69 @@ pub fn format_disk(target: Option<FsKind>) -> Result<(), &'static str> {
         Some(kind) => kind,
         None => match preference {
             FsPreference::Ext2 => FsKind::Ext2,
-            FsPreference::Fat32 | FsPreference::Auto => FsKind::Fat32,
+            FsPreference::Exfat => FsKind::Exfat,
+            FsPreference::Fat32 | FsPreference::Auto | FsPreference::Iso9660 => FsKind::Fat32,
         },
     };
 
     let usable = cmp::min(sectors, 0x0FFF_FFFFu64);
     let total = usable as u32;
     let part_start = 2048u32;
-    if target == FsKind::Fat32 && total <= part_start + 8192 {
-        return Err("Disk too small for FAT32.");
+    // Smaller disks get FAT16 or FAT12; `format` refuses ones too small even for those.
+    if target == FsKind::Fat32 && total <= part_start {
+        return Err("Disk too small for FAT.");
+    }
+    if target == FsKind::Exfat && total <= part_start + 8192 {
+        return Err("Disk too small for exFAT.");
     }
     let part_sectors = total - part_start;
 
-    let dev = ata::AtaDevice { drive, sectors };
-    let volume = match target {
+    let dev = ata::AtaDevice { drive, sectors, atapi: false };
+    let mut volume = match target {
         FsKind::Fat32 => {
             let volume = fat32::Fat32Volume::format(dev, part_start, part_sectors, "AXIOMATA")?;
             VfsVolume::Fat32(volume)
         }
-        FsKind::Ext2 => {
+        // Volumes large enough for a journal get one, which makes them EXT3.
+        FsKind::Ext2 | FsKind::Ext3 => {
             let volume = ext2::Ext2Volume::format(dev, part_start, part_sectors, "AXIOMATA")?;
             VfsVolume::Ext2(volume)
         }
+        FsKind::Ext4 => return Err("EXT4 volumes can be read but not formatted."),
+        FsKind::Iso9660 => return Err("ISO9660 volumes can be read but not formatted."),
+        FsKind::Exfat => {
+            let volume = exfat::ExfatVolume::format(dev, part_start, part_sectors, "AXIOMATA")?;
+            VfsVolume::Exfat(volume)
+        }
     };
-    let (fat32_info, ext2_info) = match &volume {
-        VfsVolume::Fat32(vol) => (Some(vol.info()), None),
-        VfsVolume::Ext2(vol) => (None, Some(vol.info())),
-    };
+    let unclean = mount(&mut volume);
+    let (fat32_info, ext2_info, exfat_info, iso_info) = volume_infos(&volume);
 
     *VOLUME.lock() = Some(volume);
     let mut state = PERSIST.lock();
     state.enabled = true;
+    state.unclean = unclean;
     state.partition = Some(fat32::PartitionInfo {
         type_code: match target {
-            FsKind::Fat32 => 0x0C,
-            FsKind::Ext2 => ext2::EXT2_PART_TYPE,
+            FsKind::Fat32 => fat32_info.map_or(0x0C, |info| info.fat_type.partition_type(part_sectors)),
+            FsKind::Ext2 | FsKind::Ext3 | FsKind::Ext4 => ext2::EXT2_PART_TYPE,
+            FsKind::Exfat => exfat::EXFAT_PART_TYPE,
+            FsKind::Iso9660 => iso9660::ISO_PART_TYPE,
         },
         lba_start: part_start,
         sectors: part_sectors,
     });
-    state.fs_kind = Some(target);
+    state.fs_kind = Some(ext2_info.as_ref().map_or(target, ext_kind));
     state.fat32_info = fat32_info;
     state.ext2_info = ext2_info;
+    state.exfat_info = exfat_info;
+    state.iso_info = iso_info;
     if explicit {
         state.preferred_fs = match target {
             FsKind::Fat32 => FsPreference::Fat32,
-            FsKind::Ext2 => FsPreference::Ext2,
+            FsKind::Ext2 | FsKind::Ext3 | FsKind::Ext4 => FsPreference::Ext2,
+            FsKind::Exfat => FsPreference::Exfat,
+            FsKind::Iso9660 => FsPreference::Iso9660,
         };
     }
     state.last_error = None;
@@ -662,9 +1367,10 @@ pub fn format_disk(target: Option<FsKind>) -> Result<(), &'static str> {
     Ok(())
 }
 
-fn list_dir_internal(volume: &mut VfsVolume, cluster: u32) -> Result<Vec<ListingEntry>, &'static str> {
+fn list_dir_internal(volume: &mut VfsVolume, dir: &Dir) -> Result<Vec<ListingEntry>, &'static str> {
+    check_access(volume, dir.id, dir.entry.as_ref(), MAY_READ)?;
     let mut entries = Vec::new();
-    for entry in volume.read_directory(cluster)? {
+    for entry in volume.read_directory(dir.id)? {
         if entry.name() == "." || entry.name() == ".." {
             continue;
         }
@@ -672,6 +1378,8 @@ fn list_dir_internal(volume: &mut VfsVolume, cluster: u32) -> Result<Vec<Listing
             name: entry.name().to_string(),
             size: size_to_usize(entry.size()),
             is_dir: entry.is_dir(),
+            hidden: entry.is_hidden(),
+            modified: entry.modified(),
         });
     }
     Ok(entries)
@@ -712,14 +1420,8 @@ pub fn prompt_path() -> String {
 pub fn set_current_dir(path: &str) -> Result<(), &'static str> {
     with_volume(|volume| {
         let components = resolve_from_cwd(path)?;
-        let (cluster, display) = resolve_dir(volume, &components)?;
-        let new_path = if display.is_empty() {
-            ROOT_DIR.to_string()
-        } else {
-            format!("{}{}", ROOT_DIR, display.join("\\"))
-        };
-        *CWD.lock() = new_path;
-        let _ = cluster;
+        let (_, display) = resolve_dir(volume, &components)?;
+        *CWD.lock() = path_string(&display);
         Ok(())
     })
 }
@@ -736,14 +1438,15 @@ pub fn canonical_name(name: &str) -> Result<String, &'static str> {
 pub fn touch(name: &str) -> Result<(), &'static str> {
     with_volume(|volume| {
         let (parent_components, file_name) = resolve_parent(name)?;
-        let (parent_cluster, _) = resolve_dir(volume, &parent_components)?;
-        if let Some(entry) = volume.find_entry(parent_cluster, &file_name)? {
+        let (parent, _) = resolve_dir(volume, &parent_components)?;
+        if let Some(entry) = volume.find_entry(parent.id, &file_name)? {
             if entry.is_dir() {
                 return Err("A directory with that name already exists.");
             }
             return Err("File already exists.");
         }
-        let entry = volume.create_entry(parent_cluster, &file_name, false)?;
+        check_access(volume, parent.id, parent.entry.as_ref(), MAY_WRITE | MAY_EXEC)?;
+        let entry = volume.create_entry(parent.id, &file_name, false)?;
         let _ = entry;
         Ok(())
     })
@@ -752,103 +1455,400 @@ pub fn touch(name: &str) -> Result<(), &'static str> {
 pub fn ensure_file(name: &str) -> Result<String, &'static str> {
     with_volume(|volume| {
         let (parent_components, file_name) = resolve_parent(name)?;
-        let (parent_cluster, _) = resolve_dir(volume, &parent_components)?;
-        if let Some(entry) = volume.find_entry(parent_cluster, &file_name)? {
+        let mut components = parent_components;
+        components.push(file_name);
+        let found = lookup(volume, &components, true)?.ok_or("A directory with that name already exists.")?;
+        if let Some(entry) = found.entry {
             if entry.is_dir() {
                 return Err("A directory with that name already exists.");
             }
             return Ok(entry.name().to_string());
         }
-        let entry = volume.create_entry(parent_cluster, &file_name, false)?;
+        check_access(volume, found.dir, found.dir_entry.as_ref(), MAY_WRITE | MAY_EXEC)?;
+        let entry = volume.create_entry(found.dir, &found.name, false)?;
         Ok(entry.name().to_string())
     })
 }
 
 pub fn write_file(name: &str, contents: &str) -> Result<(), &'static str> {
+    write_file_bytes(name, contents.as_bytes())
+}
+
+/// Writes through symlinks; a dangling one gets its target created.
+pub fn write_file_bytes(name: &str, contents: &[u8]) -> Result<(), &'static str> {
     with_volume(|volume| {
-        let (parent_components, file_name) = resolve_parent(name)?;
-        let (parent_cluster, _) = resolve_dir(volume, &parent_components)?;
-        let entry = if let Some(entry) = volume.find_entry(parent_cluster, &file_name)? {
-            if entry.is_dir() {
-                return Err("Not a file.");
-            }
-            entry
-        } else {
-            volume.create_entry(parent_cluster, &file_name, false)?
-        };
-        volume.write_file(parent_cluster, &entry, contents.as_bytes())
+        let (dir, entry) = open_for_write(volume, name)?;
+        volume.write_file(dir, &entry, contents)
+    })
+}
+
+/// Writes `data` at `offset` without rewriting the rest of the file, creating it if need
+/// be. A gap past the old end reads as zeros.
+pub fn write_at(name: &str, offset: u64, data: &[u8]) -> Result<(), &'static str> {
+    with_volume(|volume| {
+        let (dir, entry) = open_for_write(volume, name)?;
+        volume.write_at(dir, &entry, offset, data)
+    })
+}
+
+/// Cuts or extends a file to `len` bytes, creating it if need be.
+pub fn truncate(name: &str, len: u64) -> Result<(), &'static str> {
+    with_volume(|volume| {
+        let (dir, entry) = open_for_write(volume, name)?;
+        volume.truncate(dir, &entry, len)
     })
 }
 
+// Finds the file `name` names, following symlinks, and checks it may be written; a missing
+// one is created. Returns its directory and entry.
+fn open_for_write(volume: &mut VfsVolume, name: &str) -> Result<(u32, VfsEntry), &'static str> {
+    let (parent_components, file_name) = resolve_parent(name)?;
+    let mut components = parent_components;
+    components.push(file_name);
+    let found = lookup(volume, &components, true)?.ok_or("Not a file.")?;
+    let entry = if let Some(entry) = found.entry {
+        if entry.is_dir() {
+            return Err("Not a file.");
+        }
+        check_access(volume, found.dir, Some(&entry), MAY_WRITE)?;
+        entry
+    } else {
+        check_access(volume, found.dir, found.dir_entry.as_ref(), MAY_WRITE | MAY_EXEC)?;
+        volume.create_entry(found.dir, &found.name, false)?
+    };
+    Ok((found.dir, entry))
+}
+
+/// Adds `line` to the end of a file, on a line of its own, writing only the new bytes.
 pub fn append_line(name: &str, line: &str) -> Result<(), &'static str> {
-    let mut body = read_file(name).unwrap_or_default();
-    if !body.is_empty() {
-        body.push('\n');
-    }
-    body.push_str(line);
-    write_file(name, &body)
+    with_volume(|volume| {
+        let (dir, entry) = open_for_write(volume, name)?;
+        let size = entry.size();
+        let mut text = String::new();
+        if size > 0 {
+            text.push('\n');
+        }
+        text.push_str(line);
+        volume.write_at(dir, &entry, size, text.as_bytes())
+    })
 }
 
 pub fn read_file(name: &str) -> Option<String> {
+    let data = read_file_bytes(name).ok()?;
+    String::from_utf8(data).ok()
+}
+
+pub fn read_file_bytes(name: &str) -> Result<Vec<u8>, &'static str> {
+    read_checked(name, MAY_READ)
+}
+
+/// Reads a program to run, which takes execute as well as read permission.
+pub fn read_executable(name: &str) -> Result<Vec<u8>, &'static str> {
+    read_checked(name, MAY_READ | MAY_EXEC)
+}
+
+fn read_checked(name: &str, want: u16) -> Result<Vec<u8>, &'static str> {
     with_volume(|volume| {
         let components = resolve_from_cwd(name)?;
-        let (parent_components, file_name) = match components.split_last() {
-            Some((name, parent)) => (parent.to_vec(), name.clone()),
-            None => return Err("Invalid file path."),
-        };
-        let (parent_cluster, _) = resolve_dir(volume, &parent_components)?;
-        let Some(entry) = volume.find_entry(parent_cluster, &file_name)? else {
+        let found = lookup(volume, &components, true)?.ok_or("Invalid file path.")?;
+        let Some(entry) = found.entry else {
             return Err("File not found.");
         };
         if entry.is_dir() {
             return Err("Not a file.");
         }
+        check_access(volume, found.dir, Some(&entry), want)?;
         let data = volume.read_file(&entry)?;
-        let _ = volume.update_access_date(parent_cluster, &entry);
-        String::from_utf8(data).map_err(|_| "Invalid file encoding.")
-    }).ok()
+        let _ = volume.update_access_date(found.dir, &entry);
+        Ok(data)
+    })
 }
 
 pub fn delete_file(name: &str) -> Result<(), &'static str> {
     with_volume(|volume| {
         let (parent_components, file_name) = resolve_parent(name)?;
-        let (parent_cluster, _) = resolve_dir(volume, &parent_components)?;
-        let Some(entry) = volume.find_entry(parent_cluster, &file_name)? else {
+        let (parent, _) = resolve_dir(volume, &parent_components)?;
+        let Some(entry) = volume.find_entry(parent.id, &file_name)? else {
             return Err("File not found.");
         };
         if entry.is_dir() {
             return Err("Not a file.");
         }
-        volume.delete_file(parent_cluster, &entry)
+        check_access(volume, parent.id, parent.entry.as_ref(), MAY_WRITE | MAY_EXEC)?;
+        volume.delete_file(parent.id, &entry)
     })
 }
 
-#[allow(dead_code)]
 pub fn exists(name: &str) -> bool {
     with_volume(|volume| {
         let components = resolve_from_cwd(name)?;
-        let (parent_components, file_name) = match components.split_last() {
-            Some((name, parent)) => (parent.to_vec(), name.clone()),
-            None => return Err("Invalid file path."),
+        let found = lookup(volume, &components, true)?.ok_or("Invalid file path.")?;
+        Ok(found.entry.is_some())
+    })
+    .unwrap_or(false)
+}
+
+/// Creates `path` as a symbolic link to `target`. The target is stored as given and only
+/// resolved when the link is used, relative to the directory holding the link.
+pub fn symlink(target: &str, path: &str) -> Result<(), &'static str> {
+    if target.trim().is_empty() {
+        return Err("Link target cannot be empty.");
+    }
+    with_volume(|volume| {
+        let (parent_components, name) = resolve_parent(path)?;
+        let (parent, _) = resolve_dir(volume, &parent_components)?;
+        check_access(volume, parent.id, parent.entry.as_ref(), MAY_WRITE | MAY_EXEC)?;
+        volume.create_symlink(parent.id, &name, target)?;
+        Ok(())
+    })
+}
+
+/// Gives the file at `existing` a second name, `path`. A symlink at `existing` is linked
+/// itself rather than followed.
+pub fn hard_link(existing: &str, path: &str) -> Result<(), &'static str> {
+    with_volume(|volume| {
+        let components = resolve_from_cwd(existing)?;
+        let Some(Lookup { entry: Some(entry), .. }) = lookup(volume, &components, false)? else {
+            return Err("File not found.");
+        };
+        if entry.is_dir() {
+            return Err("Hard links to directories are not allowed.");
+        }
+        let (parent_components, name) = resolve_parent(path)?;
+        let (parent, _) = resolve_dir(volume, &parent_components)?;
+        check_access(volume, parent.id, parent.entry.as_ref(), MAY_WRITE | MAY_EXEC)?;
+        volume.link(parent.id, &name, &entry)?;
+        Ok(())
+    })
+}
+
+/// Sets the permission bits of `path` to what `change` makes of the current ones. Only
+/// the owner and root may. On FAT32 and exFAT this only sets or clears the read-only attribute.
+pub fn chmod(path: &str, change: impl Fn(u16) -> u16) -> Result<(), &'static str> {
+    with_volume(|volume| {
+        let components = resolve_from_cwd(path)?;
+        let (dir, entry) = match lookup(volume, &components, true)? {
+            Some(Lookup { dir, entry: Some(entry), .. }) => (dir, entry),
+            Some(_) => return Err("File not found."),
+            None => return Err("Cannot change the root directory."),
+        };
+        let (mode, uid, _) = volume.owner_mode(dir, Some(&entry))?;
+        let creds = users::credentials();
+        if creds.uid != 0 && creds.uid != uid {
+            return Err("Operation not permitted.");
+        }
+        volume.set_mode(dir, &entry, change(mode & 0o7777))
+    })
+}
+
+/// Gives `path` a new owner and group, each kept when None. Only root may change the
+/// owner; the owner may move a file to one of their own groups.
+pub fn chown(path: &str, uid: Option<u16>, gid: Option<u16>) -> Result<(), &'static str> {
+    with_volume(|volume| {
+        let components = resolve_from_cwd(path)?;
+        let (dir, entry) = match lookup(volume, &components, true)? {
+            Some(Lookup { dir, entry: Some(entry), .. }) => (dir, entry),
+            Some(_) => return Err("File not found."),
+            None => return Err("Cannot change the root directory."),
         };
-        let (parent_cluster, _) = resolve_dir(volume, &parent_components)?;
-        Ok(volume.find_entry(parent_cluster, &file_name)?.is_some())
+        let (_, old_uid, old_gid) = volume.owner_mode(dir, Some(&entry))?;
+        let (new_uid, new_gid) = (uid.unwrap_or(old_uid), gid.unwrap_or(old_gid));
+        let creds = users::credentials();
+        let permitted = creds.uid == 0
+            || (creds.uid == old_uid && new_uid == old_uid && (new_gid == old_gid || creds.in_group(new_gid)));
+        if !permitted {
+            return Err("Operation not permitted.");
+        }
+        volume.set_owner(dir, &entry, new_uid, new_gid)
+    })
+}
+
+/// True when only root can have written `path`: it and each directory above it, bar the
+/// volume root, are root's and not writable by anyone else. On FAT and exFAT that means
+/// marked system. Symlinks on the way do not count.
+pub fn root_only(path: &str) -> bool {
+    with_volume(|volume| {
+        let components = resolve_from_cwd(path)?;
+        for depth in 1..=components.len() {
+            let Some(Lookup { dir, entry: Some(entry), .. }) = lookup(volume, &components[..depth], false)? else {
+                return Ok(false);
+            };
+            let trusted = match &entry {
+                VfsEntry::Fat32(fat) => fat.attr & FAT_ATTR_SYSTEM != 0,
+                VfsEntry::Exfat(exfat) => exfat.attr as u8 & FAT_ATTR_SYSTEM != 0,
+                _ if entry.is_symlink() => false,
+                _ => {
+                    let (mode, uid, _) = volume.owner_mode(dir, Some(&entry))?;
+                    uid == 0 && mode & 0o022 == 0
+                }
+            };
+            if !trusted {
+                return Ok(false);
+            }
+        }
+        Ok(true)
     })
     .unwrap_or(false)
 }
 
+/// The target stored in the symlink at `path`.
+pub fn read_link(path: &str) -> Result<String, &'static str> {
+    with_volume(|volume| {
+        let components = resolve_from_cwd(path)?;
+        let Some(Lookup { entry: Some(entry), .. }) = lookup(volume, &components, false)? else {
+            return Err("File not found.");
+        };
+        if !entry.is_symlink() {
+            return Err("Not a symbolic link.");
+        }
+        volume.read_link(&entry)
+    })
+}
+
 #[derive(Clone)]
 pub struct ListingEntry {
     pub name: String,
     pub size: usize,
     pub is_dir: bool,
+    /// Dot files, and files with FAT's hidden attribute.
+    pub hidden: bool,
+    /// Last write, in Unix seconds, when the filesystem recorded one.
+    pub modified: Option<u64>,
+}
+
+/// What `stat` reports about a file or directory.
+#[derive(Clone)]
+pub struct FileStat {
+    pub name: String,
+    /// Where a symlink points; None for everything else.
+    pub link_target: Option<String>,
+    /// As in `ListingEntry`.
+    pub hidden: bool,
+    pub size: u64,
+    pub is_dir: bool,
+    /// First cluster on FAT32 and exFAT, inode number on EXT2, first block on ISO9660.
+    pub id: u32,
+    /// File type and permission bits. FAT32 and exFAT have none, so they are derived from
+    /// the directory and read-only attributes.
+    pub mode: u16,
+    /// FAT or exFAT attribute byte (read-only, hidden, system, directory, archive); zero on EXT2 and ISO9660.
+    pub attributes: u8,
+    pub links: u16,
+    pub uid: u16,
+    pub gid: u16,
+    /// 512-byte sectors allocated.
+    pub blocks: u64,
+    /// Unix seconds. FAT32 has no change time and EXT2 no creation time.
+    pub created: Option<u64>,
+    pub modified: Option<u64>,
+    pub accessed: Option<u64>,
+    pub changed: Option<u64>,
+}
+
+const FAT_ATTR_READ_ONLY: u8 = 0x01;
+const FAT_ATTR_HIDDEN: u8 = 0x02;
+const FAT_ATTR_SYSTEM: u8 = 0x04;
+const FAT_ATTR_DIRECTORY: u8 = 0x10;
+
+// FAT32 keeps no permissions; as with Linux's vfat, present files as owner-writable unless
+// read-only, directories as always writable, and `.elf` programs as executable. System
+// files, which belong to root, are private to it.
+fn fat_mode(attr: u8, name: &str) -> u16 {
+    if attr & FAT_ATTR_DIRECTORY != 0 {
+        return 0x4000 | 0o755;
+    }
+    let exec = if name.to_ascii_lowercase().ends_with(".elf") { 0o111 } else { 0 };
+    let write = if attr & FAT_ATTR_READ_ONLY != 0 { 0 } else { 0o200 };
+    if attr & FAT_ATTR_SYSTEM != 0 {
+        return 0x8000 | 0o400 | (exec & 0o100) | write;
+    }
+    0x8000 | 0o444 | exec | write
+}
+
+// Nor owners: system files and directories belong to root and everything else to whoever
+// is logged in.
+fn fat_owner(attr: u8) -> (u16, u16) {
+    if attr & FAT_ATTR_SYSTEM != 0 {
+        (0, 0)
+    } else {
+        users::current_ids()
+    }
+}
+
+// ISO9660 without Rock Ridge has no permissions either; a disc is read-only, so present
+// everything as root-owned and readable, with `.elf` programs executable.
+fn iso_owner_mode(entry: Option<&iso9660::DirEntryInfo>) -> (u16, u16, u16) {
+    let Some(entry) = entry else {
+        return (0x4000 | 0o555, 0, 0);
+    };
+    if let Some(mode) = entry.mode {
+        return (mode, entry.uid, entry.gid);
+    }
+    let mode = if entry.is_dir {
+        0x4000 | 0o555
+    } else if entry.link_target.is_some() {
+        0xA000 | 0o777
+    } else if entry.name.to_ascii_lowercase().ends_with(".elf") {
+        0x8000 | 0o555
+    } else {
+        0x8000 | 0o444
+    };
+    (mode, entry.uid, entry.gid)
+}
+
+/// `drwxr-xr-x`-style rendering of a mode.
+pub fn mode_string(mode: u16) -> String {
+    let mut out = String::from(match mode & 0xF000 {
+        0x4000 => "d",
+        0xA000 => "l",
+        0x2000 => "c",
+        0x6000 => "b",
+        0x1000 => "p",
+        0xC000 => "s",
+        _ => "-",
+    });
+    for shift in [6, 3, 0] {
+        let bits = (mode >> shift) & 7;
+        out.push(if bits & 4 != 0 { 'r' } else { '-' });
+        out.push(if bits & 2 != 0 { 'w' } else { '-' });
+        out.push(if bits & 1 != 0 { 'x' } else { '-' });
+    }
+    out
+}
+
+pub fn stat(path: &str) -> Result<FileStat, &'static str> {
+    with_volume(|volume| {
+        let components = resolve_from_cwd(path)?;
+        let Some((name, parent)) = components.split_last() else {
+            return volume.stat(None);
+        };
+        let (parent, _) = resolve_dir(volume, parent)?;
+        let entry = volume.find_entry(parent.id, name)?.ok_or("File not found.")?;
+        volume.stat(Some(&entry))
+    })
+}
+
+/// `stat` for every entry of a directory, as `ls -l` shows them.
+pub fn list_dir_stat(path: &str) -> Result<Vec<FileStat>, &'static str> {
+    with_volume(|volume| {
+        let components = resolve_from_cwd(path)?;
+        let (dir, _) = resolve_dir(volume, &components)?;
+        check_access(volume, dir.id, dir.entry.as_ref(), MAY_READ)?;
+        let mut stats = Vec::new();
+        for entry in volume.read_directory(dir.id)? {
+            if entry.name() != "." && entry.name() != ".." {
+                stats.push(volume.stat(Some(&entry))?);
+            }
+        }
+        Ok(stats)
+    })
 }
 
 pub fn list_files() -> Vec<ListingEntry> {
     with_volume(|volume| {
         let components = resolve_from_cwd(".")?;
-        let (cluster, _) = resolve_dir(volume, &components)?;
-        list_dir_internal(volume, cluster)
+        let (dir, _) = resolve_dir(volume, &components)?;
+        list_dir_internal(volume, &dir)
     })
     .unwrap_or_default()
 }
@@ -856,22 +1856,23 @@ pub fn list_files() -> Vec<ListingEntry> {
 pub fn list_dir(path: &str) -> Result<Vec<ListingEntry>, &'static str> {
     with_volume(|volume| {
         let components = resolve_from_cwd(path)?;
-        let (cluster, _) = resolve_dir(volume, &components)?;
-        list_dir_internal(volume, cluster)
+        let (dir, _) = resolve_dir(volume, &components)?;
+        list_dir_internal(volume, &dir)
     })
 }
 
 pub fn mkdir(path: &str) -> Result<(), &'static str> {
     with_volume(|volume| {
         let (parent_components, dir_name) = resolve_parent(path)?;
-        let (parent_cluster, _) = resolve_dir(volume, &parent_components)?;
-        if let Some(entry) = volume.find_entry(parent_cluster, &dir_name)? {
+        let (parent, _) = resolve_dir(volume, &parent_components)?;
+        if let Some(entry) = volume.find_entry(parent.id, &dir_name)? {
             if entry.is_dir() {
                 return Err("Directory already exists.");
             }
             return Err("File already exists.");
         }
-        volume.create_entry(parent_cluster, &dir_name, true)?;
+        check_access(volume, parent.id, parent.entry.as_ref(), MAY_WRITE | MAY_EXEC)?;
+        volume.create_entry(parent.id, &dir_name, true)?;
         Ok(())
     })
 }
@@ -883,13 +1884,14 @@ pub fn rmdir(path: &str) -> Result<(), &'static str> {
             return Err("Cannot remove root directory.");
         }
         let (parent_components, dir_name) = resolve_parent(path)?;
-        let (parent_cluster, _) = resolve_dir(volume, &parent_components)?;
-        let Some(entry) = volume.find_entry(parent_cluster, &dir_name)? else {
+        let (parent, _) = resolve_dir(volume, &parent_components)?;
+        let Some(entry) = volume.find_entry(parent.id, &dir_name)? else {
             return Err("Directory not found.");
         };
         if !entry.is_dir() {
             return Err("Not a directory.");
         }
-        volume.delete_dir(parent_cluster, &entry)
+        check_access(volume, parent.id, parent.entry.as_ref(), MAY_WRITE | MAY_EXEC)?;
+        volume.delete_dir(parent.id, &entry)
     })
 }
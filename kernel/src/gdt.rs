@@ -0,0 +1,80 @@
+use core::ptr::{addr_of, addr_of_mut};
+use lazy_static::lazy_static;
+use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
+use x86_64::instructions::tables::load_tss;
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::VirtAddr;
+
+use crate::interrupts::DOUBLE_FAULT_IST_INDEX;
+
+const DOUBLE_FAULT_STACK_SIZE: usize = 4096 * 5;
+pub const KERNEL_STACK_SIZE: usize = 4096 * 16;
+
+#[repr(align(16))]
+struct Stack<const N: usize>([u8; N]);
+
+static mut DOUBLE_FAULT_STACK: Stack<DOUBLE_FAULT_STACK_SIZE> = Stack([0; DOUBLE_FAULT_STACK_SIZE]);
+static mut KERNEL_ENTRY_STACK: Stack<KERNEL_STACK_SIZE> = Stack([0; KERNEL_STACK_SIZE]);
+
+#[derive(Copy, Clone)]
+pub struct Selectors {
+    pub kernel_code: SegmentSelector,
+    pub kernel_data: SegmentSelector,
+    pub user_data: SegmentSelector,
+    pub user_code: SegmentSelector,
+    pub tss: SegmentSelector,
+}
+
+// Not behind a reference so that RSP0 can follow whichever task is running; the CPU
+// reads it afresh on every switch to ring 0.
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+// Kernel code/data followed by user data/code is the layout SYSCALL/SYSRET expect in STAR.
+lazy_static! {
+    static ref GDT: (GlobalDescriptorTable, Selectors) = {
+        unsafe {
+            let tss = &mut *addr_of_mut!(TSS);
+            tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
+                let start = VirtAddr::from_ptr(addr_of!(DOUBLE_FAULT_STACK));
+                start + DOUBLE_FAULT_STACK_SIZE
+            };
+            tss.privilege_stack_table[0] = kernel_entry_stack_top();
+        }
+        let mut gdt = GlobalDescriptorTable::new();
+        let kernel_code = gdt.add_entry(Descriptor::kernel_code_segment());
+        let kernel_data = gdt.add_entry(Descriptor::kernel_data_segment());
+        let user_data = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code = gdt.add_entry(Descriptor::user_code_segment());
+        let tss = gdt.add_entry(unsafe { Descriptor::tss_segment_unchecked(addr_of!(TSS)) });
+        (gdt, Selectors { kernel_code, kernel_data, user_data, user_code, tss })
+    };
+}
+
+pub fn init() {
+    GDT.0.load();
+    let sel = GDT.1;
+    unsafe {
+        CS::set_reg(sel.kernel_code);
+        SS::set_reg(sel.kernel_data);
+        DS::set_reg(sel.kernel_data);
+        ES::set_reg(sel.kernel_data);
+        load_tss(sel.tss);
+    }
+}
+
+pub fn selectors() -> Selectors {
+    GDT.1
+}
+
+/// Top of the stack used for ring 3 -> ring 0 transitions (interrupts via RSP0 and SYSCALL)
+/// until a task brings its own.
+pub fn kernel_entry_stack_top() -> VirtAddr {
+    let start = VirtAddr::from_ptr(addr_of!(KERNEL_ENTRY_STACK));
+    start + KERNEL_STACK_SIZE
+}
+
+/// Points interrupts from ring 3 at the entry stack of the task about to run.
+pub fn set_kernel_entry_stack(top: VirtAddr) {
+    unsafe { (*addr_of_mut!(TSS)).privilege_stack_table[0] = top };
+}
@@ -0,0 +1,191 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::fs;
+
+enum Pat {
+    Char(char),
+    /// `*`
+    Any,
+    /// `?`
+    One,
+    /// `[abc]`, `[a-z]`, or negated with `[!...]` / `[^...]`.
+    Class { negate: bool, ranges: Vec<(char, char)> },
+}
+
+/// True if the unquoted characters of a word (flagged in `live`) include a wildcard.
+pub fn has_wildcards(word: &str, live: &[bool]) -> bool {
+    word.chars().zip(live).any(|(c, &live)| live && matches!(c, '*' | '?' | '['))
+}
+
+/// Expands a word holding unquoted `*`, `?` or `[...]` into the paths it matches, sorted.
+/// Wildcards may appear in any component, but never match the `\` separator, and a
+/// leading `.` has to be matched literally. Returns nothing when no path matches.
+pub fn expand(word: &str, live: &[bool]) -> Vec<String> {
+    let ignore_case = fs::names_ignore_case();
+    let chars: Vec<(char, bool)> = word.chars().zip(live.iter().copied()).collect();
+    let components: Vec<&[(char, bool)]> = chars.split(|&(c, _)| c == '\\' || c == '/').collect();
+    let absolute = chars.first().is_some_and(|&(c, _)| c == '\\' || c == '/');
+
+    let mut found: Vec<String> = Vec::from([String::from(if absolute { "\\" } else { "" })]);
+    let last = components.len() - 1;
+    for (i, component) in components.iter().enumerate().skip(usize::from(absolute)) {
+        if component.is_empty() {
+            continue;
+        }
+        let Some(pattern) = compile(component) else {
+            let name: String = component.iter().map(|&(c, _)| c).collect();
+            for path in &mut found {
+                path.push_str(&name);
+                if i < last {
+                    path.push('\\');
+                }
+            }
+            continue;
+        };
+        let mut next = Vec::new();
+        for base in &found {
+            let dir = if base.is_empty() { "." } else { base.trim_end_matches('\\') };
+            let dir = if dir.is_empty() { "\\" } else { dir };
+            let Ok(entries) = fs::list_dir(dir) else { continue; };
+            for entry in entries {
+                if entry.name == "." || entry.name == ".." || (i < last && !entry.is_dir) {
+                    continue;
+                }
+                if entry.name.starts_with('.') && !matches!(pattern.first(), Some(Pat::Char('.'))) {
+                    continue;
+                }
+                if matches(&pattern, &entry.name, ignore_case) {
+                    let mut path = String::from(base.as_str());
+                    path.push_str(&entry.name);
+                    if i < last {
+                        path.push('\\');
+                    }
+                    next.push(path);
+                }
+            }
+        }
+        found = next;
+    }
+    // Literal components after the last wildcard still have to name something.
+    found.retain(|path| fs::exists(path));
+    found.sort();
+    found
+}
+
+/// Matches a single name against a wildcard pattern, as `find -name` does.
+pub fn matches_name(pattern: &str, name: &str) -> bool {
+    let chars: Vec<(char, bool)> = pattern.chars().map(|c| (c, true)).collect();
+    match compile(&chars) {
+        Some(pattern) => matches(&pattern, name, fs::names_ignore_case()),
+        None if fs::names_ignore_case() => pattern.eq_ignore_ascii_case(name),
+        None => pattern == name,
+    }
+}
+
+// Turns one path component into a pattern, or `None` if it has no live wildcard.
+fn compile(component: &[(char, bool)]) -> Option<Vec<Pat>> {
+    let mut pattern = Vec::new();
+    let mut wild = false;
+    let mut i = 0;
+    while i < component.len() {
+        let (c, live) = component[i];
+        i += 1;
+        if !live {
+            pattern.push(Pat::Char(c));
+            continue;
+        }
+        match c {
+            '*' => pattern.push(Pat::Any),
+            '?' => pattern.push(Pat::One),
+            '[' => match class(&component[i..]) {
+                Some((pat, used)) => {
+                    pattern.push(pat);
+                    i += used;
+                }
+                // An unclosed bracket is an ordinary character.
+                None => {
+                    pattern.push(Pat::Char('['));
+                    continue;
+                }
+            },
+            _ => {
+                pattern.push(Pat::Char(c));
+                continue;
+            }
+        }
+        wild = true;
+    }
+    wild.then_some(pattern)
+}
+
+// Parses the rest of a `[...]` class after its opening bracket, returning how much it used.
+fn class(rest: &[(char, bool)]) -> Option<(Pat, usize)> {
+    let mut i = 0;
+    let negate = matches!(rest.first(), Some(('!' | '^', _)));
+    if negate {
+        i += 1;
+    }
+    let mut ranges = Vec::new();
+    // A `]` right after the opening bracket is a member, not the end.
+    let mut first = true;
+    while let Some(&(c, _)) = rest.get(i) {
+        if c == ']' && !first {
+            return Some((Pat::Class { negate, ranges }, i + 1));
+        }
+        first = false;
+        match (rest.get(i + 1), rest.get(i + 2)) {
+            (Some(('-', _)), Some(&(end, _))) if end != ']' => {
+                ranges.push((c, end));
+                i += 3;
+            }
+            _ => {
+                ranges.push((c, c));
+                i += 1;
+            }
+        }
+    }
+    None
+}
+
+fn matches(pattern: &[Pat], name: &str, ignore_case: bool) -> bool {
+    let name: Vec<char> = name.chars().collect();
+    let same = |a: char, b: char| if ignore_case { a.eq_ignore_ascii_case(&b) } else { a == b };
+    let in_class = |c: char, negate: bool, ranges: &[(char, char)]| {
+        let hit = ranges.iter().any(|&(lo, hi)| {
+            (lo..=hi).contains(&c)
+                || (ignore_case
+                    && ((lo..=hi).contains(&c.to_ascii_lowercase())
+                        || (lo..=hi).contains(&c.to_ascii_uppercase())))
+        });
+        hit != negate
+    };
+
+    // Greedy match that backtracks to the most recent `*` on a mismatch.
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        let step = match pattern.get(p) {
+            Some(Pat::Char(c)) => same(*c, name[n]),
+            Some(Pat::One) => true,
+            Some(Pat::Class { negate, ranges }) => in_class(name[n], *negate, ranges),
+            Some(Pat::Any) => {
+                star = Some((p, n));
+                p += 1;
+                continue;
+            }
+            None => false,
+        };
+        if step {
+            p += 1;
+            n += 1;
+        } else if let Some((sp, sn)) = star {
+            p = sp + 1;
+            n = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|pat| matches!(pat, Pat::Any))
+}
@@ -1,14 +1,22 @@
 #![allow(dead_code)]
 
+use alloc::format;
+use alloc::string::String;
 use alloc::vec::Vec;
-use heapless::String;
 use spin::Mutex;
 
+use crate::{console, env, fs};
+
 const HISTORY_LIMIT: usize = 64;
 
-static HISTORY: Mutex<Vec<String<128>>> = Mutex::new(Vec::new());
+static HISTORY: Mutex<Vec<String>> = Mutex::new(Vec::new());
 static ENABLED: Mutex<bool> = Mutex::new(true);
 
+/// History file of the current user, `$HOME\history`.
+fn path() -> String {
+    env::home_file("history")
+}
+
 pub fn push(cmd: &str) {
     if !is_enabled() {
         return;
@@ -16,6 +24,11 @@ pub fn push(cmd: &str) {
     if cmd.is_empty() {
         return;
     }
+    remember(cmd);
+    save();
+}
+
+fn remember(cmd: &str) {
     let mut history = HISTORY.lock();
     if let Some(pos) = history.iter().position(|h| h == cmd) {
         history.remove(pos);
@@ -23,9 +36,7 @@ pub fn push(cmd: &str) {
     if history.len() >= HISTORY_LIMIT {
         history.remove(0);
     }
-    let mut s = String::<128>::new();
-    let _ = s.push_str(cmd);
-    history.push(s);
+    history.push(String::from(cmd));
 }
 
 pub fn len() -> usize {
@@ -36,13 +47,134 @@ pub fn is_empty() -> bool {
     HISTORY.lock().is_empty()
 }
 
-pub fn entry(idx: usize) -> Option<String<128>> {
+pub fn entry(idx: usize) -> Option<String> {
     HISTORY.lock().get(idx).cloned()
 }
 
 pub fn clear() {
     let mut history = HISTORY.lock();
     *history = Vec::new();
+    drop(history);
+    if fs::exists(&path()) {
+        let _ = fs::delete_file(&path());
+    }
+}
+
+/// Reloads history saved by earlier sessions of the current user.
+pub fn load() {
+    if !is_enabled() {
+        return;
+    }
+    // Another user's history must not carry over when there is no file to replace it.
+    HISTORY.lock().clear();
+    let Some(text) = fs::read_file(&path()) else { return; };
+    for line in text.lines() {
+        if !line.is_empty() {
+            remember(line);
+        }
+    }
+}
+
+// Best effort: without a mounted volume history simply stays in memory.
+fn save() {
+    if !fs::persist_info().enabled {
+        return;
+    }
+    let mut body = String::new();
+    for entry in HISTORY.lock().iter() {
+        body.push_str(entry);
+        body.push('\n');
+    }
+    let _ = fs::write_file(&path(), &body);
+}
+
+/// Expands a `!!`, `!n`, `!-n` or `!prefix` event at the start of `line`, keeping whatever
+/// follows the event word. Returns `Ok(None)` when the line does not start with one.
+pub fn expand(line: &str) -> Result<Option<String>, &'static str> {
+    let trimmed = line.trim_start();
+    let Some(event) = trimmed.strip_prefix('!') else { return Ok(None); };
+    if event.is_empty() || event.starts_with(char::is_whitespace) || event.starts_with('=') {
+        return Ok(None);
+    }
+    let end = event.find(char::is_whitespace).unwrap_or(event.len());
+    let (word, rest) = event.split_at(end);
+
+    let history = HISTORY.lock();
+    let found = if word == "!" {
+        history.last()
+    } else if let Ok(n) = word.parse::<isize>() {
+        let index = if n < 0 { history.len() as isize + n } else { n - 1 };
+        usize::try_from(index).ok().and_then(|i| history.get(i))
+    } else {
+        history.iter().rev().find(|h| h.starts_with(word))
+    };
+    match found {
+        Some(entry) => Ok(Some(format!("{}{}", entry, rest))),
+        None => Err("event not found"),
+    }
+}
+
+/// `history [-c]`: lists numbered entries for `!n`, or clears them.
+pub fn command(args: &[&str]) -> Result<(), &'static str> {
+    match args.first() {
+        None => {
+            for (i, entry) in HISTORY.lock().iter().enumerate() {
+                console::write_line(&format!("{:>4}  {}", i + 1, entry));
+            }
+            Ok(())
+        }
+        Some(&"-c") => {
+            clear();
+            Ok(())
+        }
+        Some(_) => Err("Usage: history [-c]"),
+    }
+}
+
+/// An interactive Ctrl+R reverse search through the history.
+#[derive(Default)]
+pub struct Search {
+    query: String,
+    index: Option<usize>,
+    failed: bool,
+}
+
+impl Search {
+    /// Text shown in place of the prompt while searching.
+    pub fn label(&self) -> String {
+        let prefix = if self.failed { "(failed reverse-i-search)" } else { "(reverse-i-search)" };
+        format!("{}'{}': ", prefix, self.query)
+    }
+
+    pub fn matched(&self) -> Option<String> {
+        self.index.and_then(entry)
+    }
+
+    pub fn push(&mut self, ch: char) {
+        self.query.push(ch);
+        self.find(self.index.map_or(usize::MAX, |i| i + 1));
+    }
+
+    pub fn pop(&mut self) {
+        self.query.pop();
+        self.find(usize::MAX);
+    }
+
+    /// Moves to the next older match, as pressing Ctrl+R again does.
+    pub fn older(&mut self) {
+        self.find(self.index.unwrap_or(usize::MAX));
+    }
+
+    // Searches entries below `before`, newest first.
+    fn find(&mut self, before: usize) {
+        let history = HISTORY.lock();
+        let end = before.min(history.len());
+        let found = history[..end].iter().rposition(|h| h.contains(self.query.as_str()));
+        self.failed = found.is_none() && !self.query.is_empty();
+        if found.is_some() {
+            self.index = found;
+        }
+    }
 }
 
 pub fn is_enabled() -> bool {
@@ -0,0 +1,275 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use x86_64::PhysAddr;
+
+use crate::ata::{self, DriveSelect};
+use crate::block::{BlockDevice, BlockDeviceError};
+use crate::{console, fd, fs, memory, users};
+
+const SECTOR_SIZE: usize = 512;
+const MAX_READ_SECTORS: u64 = 64;
+const MAX_PEEK: u64 = 4096;
+
+const HEXDUMP_USAGE: &str = "Usage: hexdump [-s offset] [-n length] [path]";
+const XXD_USAGE: &str = "Usage: xxd [-s offset] [-l length] [path]";
+const READSEC_USAGE: &str = "Usage: readsec <0-3|disk> <lba> [count]";
+const PEEK_USAGE: &str = "Usage: peek <phys-addr> [length]";
+const POKE_USAGE: &str = "Usage: poke <phys-addr> <byte>...";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Style {
+    /// `hexdump -C`: single bytes in two groups of eight, then the text in bars.
+    Canonical,
+    /// `xxd`: two-byte groups, then the text.
+    Xxd,
+}
+
+// Raw sectors and physical memory bypass every file permission, so they are root's.
+fn require_root(command: &str) -> bool {
+    if users::current_ids().0 == 0 {
+        return true;
+    }
+    console::write_line(&format!("{}: Permission denied; only root may read raw devices and memory.", command));
+    false
+}
+
+/// Accepts decimal or `0x`-prefixed hexadecimal.
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(&hex.replace('_', ""), 16).ok(),
+        None => text.replace('_', "").parse().ok(),
+    }
+}
+
+fn printable(byte: u8) -> char {
+    if byte.is_ascii_graphic() || byte == b' ' {
+        byte as char
+    } else {
+        '.'
+    }
+}
+
+/// Prints `data` sixteen bytes a line, labelled with offsets counting from `base`.
+fn dump(data: &[u8], base: u64, style: Style) {
+    for (i, row) in data.chunks(16).enumerate() {
+        let offset = base + (i * 16) as u64;
+        let mut line = match style {
+            Style::Canonical => format!("{:08x}  ", offset),
+            Style::Xxd => format!("{:08x}: ", offset),
+        };
+        for col in 0..16 {
+            match (row.get(col), style) {
+                (Some(byte), Style::Canonical) => line.push_str(&format!("{:02x} ", byte)),
+                (None, Style::Canonical) => line.push_str("   "),
+                (Some(byte), Style::Xxd) => line.push_str(&format!("{:02x}", byte)),
+                (None, Style::Xxd) => line.push_str("  "),
+            }
+            match style {
+                Style::Canonical if col == 7 => line.push(' '),
+                Style::Xxd if col % 2 == 1 => line.push(' '),
+                _ => {}
+            }
+        }
+        let text: String = row.iter().map(|&b| printable(b)).collect();
+        match style {
+            Style::Canonical => line.push_str(&format!(" |{}|", text)),
+            Style::Xxd => line.push_str(&format!(" {}", text)),
+        }
+        console::write_line(&line);
+    }
+    if style == Style::Canonical {
+        console::write_line(&format!("{:08x}", base + data.len() as u64));
+    }
+}
+
+fn dump_command(args: &[&str], style: Style, usage: &str) -> i64 {
+    let mut skip = 0u64;
+    let mut length: Option<u64> = None;
+    let mut rest = args;
+    loop {
+        match rest {
+            ["-s", value, tail @ ..] => {
+                let Some(value) = parse_number(value) else {
+                    console::write_line(usage);
+                    return 1;
+                };
+                skip = value;
+                rest = tail;
+            }
+            ["-n" | "-l", value, tail @ ..] => {
+                let Some(value) = parse_number(value) else {
+                    console::write_line(usage);
+                    return 1;
+                };
+                length = Some(value);
+                rest = tail;
+            }
+            _ => break,
+        }
+    }
+    let data = match rest {
+        [] => match fd::read_stdin_to_end() {
+            Some(data) => data,
+            None => {
+                console::write_line(usage);
+                return 1;
+            }
+        },
+        [path] => match fs::read_file_bytes(path) {
+            Ok(data) => data,
+            Err(e) => {
+                console::write_line(&format!("{}: {}", path, e));
+                return 1;
+            }
+        },
+        _ => {
+            console::write_line(usage);
+            return 1;
+        }
+    };
+    let start = (skip as usize).min(data.len());
+    let end = length.map_or(data.len(), |n| start.saturating_add(n as usize).min(data.len()));
+    dump(&data[start..end], start as u64, style);
+    0
+}
+
+/// `hexdump [-s offset] [-n length] [path]`: bytes of a file or piped input, `hexdump -C` style.
+pub fn hexdump(args: &[&str]) -> i64 {
+    dump_command(args, Style::Canonical, HEXDUMP_USAGE)
+}
+
+/// `xxd [-s offset] [-l length] [path]`: like `hexdump`, laid out as `xxd` does.
+pub fn xxd(args: &[&str]) -> i64 {
+    dump_command(args, Style::Xxd, XXD_USAGE)
+}
+
+fn parse_drive(name: &str) -> Option<DriveSelect> {
+    match name.to_ascii_lowercase().as_str() {
+        "0" => Some(DriveSelect::PrimaryMaster),
+        "1" => Some(DriveSelect::PrimarySlave),
+        "2" => Some(DriveSelect::SecondaryMaster),
+        "3" => Some(DriveSelect::SecondarySlave),
+        "disk" => fs::persist_info().drive,
+        _ => None,
+    }
+}
+
+/// `readsec <drive> <lba> [count]`: raw sectors of an ATA drive. Drives are numbered
+/// 0-3 (ATA0 master, ATA0 slave, ATA1 master, ATA1 slave); `disk` is the mounted one.
+pub fn readsec(args: &[&str]) -> i64 {
+    if !require_root("readsec") {
+        return 1;
+    }
+    let (drive, lba, count) = match args {
+        [drive, lba] => (parse_drive(drive), parse_number(lba), Some(1)),
+        [drive, lba, count] => (parse_drive(drive), parse_number(lba), parse_number(count)),
+        _ => (None, None, None),
+    };
+    let (Some(drive), Some(lba), Some(count)) = (drive, lba, count) else {
+        console::write_line(READSEC_USAGE);
+        return 1;
+    };
+    if count == 0 || count > MAX_READ_SECTORS {
+        console::write_line(&format!("readsec: count must be 1-{}", MAX_READ_SECTORS));
+        return 1;
+    }
+    let device = match ata::identify(drive) {
+        Ok(device) => device,
+        Err(e) => {
+            console::write_line(&format!("readsec: {}", e.as_str()));
+            return 1;
+        }
+    };
+    if lba.saturating_add(count) > device.sectors {
+        console::write_line(&format!("readsec: drive has {} sectors", device.sectors));
+        return 1;
+    }
+    let mut data = Vec::new();
+    let mut sector = [0u8; SECTOR_SIZE];
+    for block in lba..lba + count {
+        if let Err(e) = device.read_block(block, &mut sector) {
+            console::write_line(&format!("readsec: sector {}: {}", block, e.as_str()));
+            return 1;
+        }
+        data.extend_from_slice(&sector);
+    }
+    dump(&data, lba * SECTOR_SIZE as u64, Style::Canonical);
+    0
+}
+
+// Checks that `[addr, addr + len)` is mapped through the physical memory offset.
+fn phys_range(addr: u64, len: u64) -> Result<*mut u8, &'static str> {
+    let mapped = addr.checked_add(len).is_some_and(|end| end <= memory::phys_limit());
+    if !mapped {
+        return Err("address outside physical memory");
+    }
+    let virt = memory::phys_to_virt(PhysAddr::new(addr)).ok_or("physical memory is not mapped")?;
+    Ok(virt.as_mut_ptr())
+}
+
+/// `peek <phys-addr> [length]`: dumps physical memory. Only with the debug token, as root.
+pub fn peek(args: &[&str]) -> i64 {
+    if !require_root("peek") {
+        return 1;
+    }
+    let (addr, len) = match args {
+        [addr] => (parse_number(addr), Some(64)),
+        [addr, len] => (parse_number(addr), parse_number(len)),
+        _ => (None, None),
+    };
+    let (Some(addr), Some(len)) = (addr, len) else {
+        console::write_line(PEEK_USAGE);
+        return 1;
+    };
+    if len == 0 || len > MAX_PEEK {
+        console::write_line(&format!("peek: length must be 1-{}", MAX_PEEK));
+        return 1;
+    }
+    let ptr = match phys_range(addr, len) {
+        Ok(ptr) => ptr,
+        Err(e) => {
+            console::write_line(&format!("peek: {}", e));
+            return 1;
+        }
+    };
+    let data: Vec<u8> = (0..len as usize).map(|i| unsafe { ptr.add(i).read_volatile() }).collect();
+    dump(&data, addr, Style::Canonical);
+    0
+}
+
+/// `poke <phys-addr> <byte>...`: writes bytes (hex) to physical memory. Only with the
+/// debug token, as root.
+pub fn poke(args: &[&str]) -> i64 {
+    if !require_root("poke") {
+        return 1;
+    }
+    let Some((addr, bytes)) = args.split_first() else {
+        console::write_line(POKE_USAGE);
+        return 1;
+    };
+    let bytes: Option<Vec<u8>> = bytes
+        .iter()
+        .map(|b| u8::from_str_radix(b.trim_start_matches("0x"), 16).ok())
+        .collect();
+    let (Some(addr), Some(bytes)) = (parse_number(addr), bytes) else {
+        console::write_line(POKE_USAGE);
+        return 1;
+    };
+    if bytes.is_empty() {
+        console::write_line(POKE_USAGE);
+        return 1;
+    }
+    let ptr = match phys_range(addr, bytes.len() as u64) {
+        Ok(ptr) => ptr,
+        Err(e) => {
+            console::write_line(&format!("poke: {}", e));
+            return 1;
+        }
+    };
+    for (i, byte) in bytes.iter().enumerate() {
+        unsafe { ptr.add(i).write_volatile(*byte) };
+    }
+    console::write_line(&format!("poke: wrote {} byte(s) at {:#x}", bytes.len(), addr));
+    0
+}
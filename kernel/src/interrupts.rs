@@ -7,7 +7,7 @@ use x86_64::{
     registers::control::Cr2,
     structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
 };
-use crate::{console, timer, serial, keyboard, mouse, pic, ps2};
+use crate::{console, timer, serial, keyboard, mouse, pic, ps2, process};
 
 use alloc::format;
 #[cfg(target_arch = "x86_64")]
@@ -135,6 +135,9 @@ macro_rules! simple_exc {
     ($name:ident, $msg:expr) => {
         extern "x86-interrupt" fn $name(stack_frame: InterruptStackFrame) {
             with_sse_guard(|| {
+                if process::is_user_mode(stack_frame.code_segment) {
+                    process::fault_current($msg, stack_frame.instruction_pointer.as_u64());
+                }
                 print_err("CPU EXCEPTION");
                 print_line(concat!($msg, " detected, halting..."));
                 print_line(&format!("{:#?}", stack_frame));
@@ -156,6 +159,9 @@ macro_rules! errcode_exc {
     ($name:ident, $msg:expr) => {
         extern "x86-interrupt" fn $name(stack_frame: InterruptStackFrame, _error_code: u64) {
             with_sse_guard(|| {
+                if process::is_user_mode(stack_frame.code_segment) {
+                    process::fault_current($msg, stack_frame.instruction_pointer.as_u64());
+                }
                 print_err("CPU EXCEPTION");
                 print_line(concat!($msg, " detected, halting..."));
                 print_line(&format!("{:#?}", stack_frame));
@@ -198,6 +204,9 @@ extern "x86-interrupt" fn exc_page_fault(
 ) {
     with_sse_guard(|| {
         let addr = Cr2::read();
+        if process::is_user_mode(stack_frame.code_segment) {
+            process::fault_current("#PF Page Fault", stack_frame.instruction_pointer.as_u64());
+        }
         print_err("PAGE FAULT");
         {
             print_line(&format!("Accessed address: {:?}", addr));
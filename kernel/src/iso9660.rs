@@ -0,0 +1,553 @@
+use alloc::collections::BTreeSet;
+use alloc::{format, string::String, string::ToString, vec, vec::Vec};
+use core::cmp;
+
+use crate::{
+    block::{BlockDevice, BlockDeviceError},
+    fat32::{FsckReport, PartitionInfo},
+    time,
+};
+
+const SECTOR_SIZE: usize = 512;
+// Volume descriptors sit in 2048-byte slots from byte 32768, whatever the block size.
+const DESCRIPTOR_SIZE: usize = 2048;
+const DESCRIPTOR_START: u32 = 16;
+const MAX_DESCRIPTORS: u32 = 64;
+const DESCRIPTOR_PRIMARY: u8 = 1;
+const DESCRIPTOR_SUPPLEMENTARY: u8 = 2;
+const DESCRIPTOR_TERMINATOR: u8 = 255;
+const STANDARD_ID: &[u8; 5] = b"CD001";
+// Escape sequences marking a supplementary descriptor as Joliet (UCS-2 levels 1-3).
+const JOLIET_ESCAPES: [&[u8; 3]; 3] = [b"%/@", b"%/C", b"%/E"];
+/// Stand-in partition type for a disc-wide ISO9660 volume, which has no partition table.
+pub const ISO_PART_TYPE: u8 = 0x96;
+
+const FLAG_HIDDEN: u8 = 0x01;
+const FLAG_DIRECTORY: u8 = 0x02;
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+// Offset of the root directory record in a volume descriptor.
+const ROOT_RECORD_OFFSET: usize = 156;
+// SUSP continuation areas followed for one record before giving up on a loop.
+const MAX_CONTINUATIONS: u32 = 16;
+
+#[derive(Copy, Clone)]
+pub struct IsoInfo {
+    pub volume_id: [u8; 32],
+    pub block_size: u16,
+    pub volume_blocks: u32,
+    pub root_extent: u32,
+    /// Names come from the Joliet tree, used when there is no Rock Ridge.
+    pub joliet: bool,
+    pub rock_ridge: bool,
+}
+
+impl IsoInfo {
+    pub fn label(&self) -> String {
+        let end = self.volume_id.iter().rposition(|&b| b != b' ' && b != 0).map_or(0, |at| at + 1);
+        self.volume_id[..end].iter().map(|&b| b as char).collect()
+    }
+}
+
+/// Looks for the CD001 signature of a primary volume descriptor on a disc of `sectors`
+/// 512-byte blocks, and describes the whole disc as its partition if there is one.
+pub fn find_iso_volume<D: BlockDevice>(dev: &D, sectors: u64) -> Option<PartitionInfo> {
+    let mut sector = [0u8; SECTOR_SIZE];
+    let lba = DESCRIPTOR_START as u64 * (DESCRIPTOR_SIZE / SECTOR_SIZE) as u64;
+    dev.read_block(lba, &mut sector).ok()?;
+    if sector[0] != DESCRIPTOR_PRIMARY || &sector[1..6] != STANDARD_ID {
+        return None;
+    }
+    Some(PartitionInfo {
+        type_code: ISO_PART_TYPE,
+        lba_start: 0,
+        sectors: cmp::min(sectors, u32::MAX as u64) as u32,
+    })
+}
+
+#[derive(Clone)]
+pub struct DirEntryInfo {
+    pub name: String,
+    pub is_dir: bool,
+    pub hidden: bool,
+    /// First block of the file or directory, which also names a directory to the VFS.
+    pub extent: u32,
+    pub size: u64,
+    /// Block and length of each extent; files over 4 GiB are split across several.
+    pub extents: Vec<(u32, u32)>,
+    /// Rock Ridge (PX) mode, links and owner, when recorded.
+    pub mode: Option<u16>,
+    pub links: u32,
+    pub uid: u16,
+    pub gid: u16,
+    /// Rock Ridge (SL) symlink target.
+    pub link_target: Option<String>,
+    pub created: Option<u64>,
+    pub modified: Option<u64>,
+    pub accessed: Option<u64>,
+}
+
+// What the System Use entries of one directory record say.
+#[derive(Default)]
+struct RockRidge {
+    name: Option<String>,
+    mode: Option<u16>,
+    links: u32,
+    uid: u16,
+    gid: u16,
+    link_target: Option<String>,
+    created: Option<u64>,
+    modified: Option<u64>,
+    accessed: Option<u64>,
+    child_link: Option<u32>,
+    relocated: bool,
+}
+
+pub struct IsoVolume<D: BlockDevice> {
+    device: D,
+    part_start: u32,
+    block_size: u32,
+    volume_blocks: u32,
+    volume_id: [u8; 32],
+    root_extent: u32,
+    joliet: bool,
+    rock_ridge: bool,
+    // Bytes of each System Use area to skip before the SUSP entries (from SP).
+    susp_skip: usize,
+}
+
+impl<D: BlockDevice> IsoVolume<D> {
+    pub fn info(&self) -> IsoInfo {
+        IsoInfo {
+            volume_id: self.volume_id,
+            block_size: self.block_size as u16,
+            volume_blocks: self.volume_blocks,
+            root_extent: self.root_extent,
+            joliet: self.joliet,
+            rock_ridge: self.rock_ridge,
+        }
+    }
+
+    pub fn root_extent(&self) -> u32 {
+        self.root_extent
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.volume_blocks as u64 * self.block_size as u64
+    }
+
+    /// Mounts the volume, preferring Rock Ridge names in the primary tree, then the
+    /// Joliet tree, then plain ISO9660 names.
+    pub fn open(dev: D, part: PartitionInfo) -> Result<Self, &'static str> {
+        let mut primary: Option<Vec<u8>> = None;
+        let mut joliet: Option<Vec<u8>> = None;
+        for index in 0..MAX_DESCRIPTORS {
+            let descriptor = read_bytes(&dev, part.lba_start as u64 * SECTOR_SIZE as u64 + (DESCRIPTOR_START + index) as u64 * DESCRIPTOR_SIZE as u64, DESCRIPTOR_SIZE)?;
+            if &descriptor[1..6] != STANDARD_ID {
+                return Err("Invalid ISO9660 volume descriptor.");
+            }
+            match descriptor[0] {
+                DESCRIPTOR_PRIMARY if primary.is_none() => primary = Some(descriptor),
+                DESCRIPTOR_SUPPLEMENTARY if JOLIET_ESCAPES.iter().any(|esc| &descriptor[88..91] == *esc) => {
+                    joliet = Some(descriptor);
+                }
+                DESCRIPTOR_TERMINATOR => break,
+                _ => {}
+            }
+        }
+        let primary = primary.ok_or("No ISO9660 primary volume descriptor.")?;
+
+        let block_size = u16::from_le_bytes([primary[128], primary[129]]) as u32;
+        if !matches!(block_size, 512 | 1024 | 2048) {
+            return Err("Unsupported ISO9660 block size.");
+        }
+        let volume_blocks = le32(&primary, 80);
+        let mut volume_id = [b' '; 32];
+        volume_id.copy_from_slice(&primary[40..72]);
+        let root_extent = le32(&primary, ROOT_RECORD_OFFSET + 2);
+
+        let mut volume = Self {
+            device: dev,
+            part_start: part.lba_start,
+            block_size,
+            volume_blocks,
+            volume_id,
+            root_extent,
+            joliet: false,
+            rock_ridge: false,
+            susp_skip: 0,
+        };
+
+        // Rock Ridge announces itself with an SP entry in the root's "." record.
+        let first = volume.read_extent(root_extent, block_size)?;
+        let len = first[0] as usize;
+        if len >= 34 && first[32] == 1 {
+            let area = &first[34..len];
+            if area.len() >= 7 && &area[0..2] == b"SP" && area[4] == 0xBE && area[5] == 0xEF {
+                volume.rock_ridge = true;
+                volume.susp_skip = area[6] as usize;
+            }
+        }
+        if !volume.rock_ridge {
+            if let Some(joliet) = joliet {
+                volume.joliet = true;
+                volume.root_extent = le32(&joliet, ROOT_RECORD_OFFSET + 2);
+            }
+        }
+        Ok(volume)
+    }
+
+    /// Lists a directory, `.` and `..` excluded; zero names the root.
+    pub fn read_directory(&mut self, extent: u32) -> Result<Vec<DirEntryInfo>, &'static str> {
+        let extent = if extent == 0 { self.root_extent } else { extent };
+        // A directory's own size is only recorded in its "." entry.
+        let first = self.read_extent(extent, self.block_size)?;
+        if first[0] < 34 || first[32] != 1 || first[33] != 0 {
+            return Err("Invalid ISO9660 directory.");
+        }
+        let size = le32(&first, 10);
+        if size as u64 > 64 * 1024 * 1024 {
+            return Err("ISO9660 directory is too large.");
+        }
+        let data = self.read_extent(extent, size)?;
+
+        let mut entries: Vec<DirEntryInfo> = Vec::new();
+        let mut continues = false;
+        let mut offset = 0usize;
+        while offset < data.len() {
+            let len = data[offset] as usize;
+            if len == 0 {
+                // Records never cross a block; the rest of this one is padding.
+                offset = (offset / self.block_size as usize + 1) * self.block_size as usize;
+                continue;
+            }
+            if len < 34 || offset + len > data.len() {
+                return Err("Invalid ISO9660 directory record.");
+            }
+            let record = &data[offset..offset + len];
+            offset += len;
+
+            let name_len = record[32] as usize;
+            if 33 + name_len > len {
+                return Err("Invalid ISO9660 directory record.");
+            }
+            let raw_name = &record[33..33 + name_len];
+            let flags = record[25];
+            let extent = le32(record, 2) + record[1] as u32;
+            let length = le32(record, 10);
+            let was_continued = continues;
+            continues = flags & FLAG_MULTI_EXTENT != 0;
+            if was_continued {
+                // Further extents of a file follow its first record directly.
+                if let Some(last) = entries.last_mut() {
+                    last.extents.push((extent, length));
+                    last.size += length as u64;
+                }
+                continue;
+            }
+            if name_len == 1 && (raw_name[0] == 0 || raw_name[0] == 1) {
+                continue;
+            }
+            if record[26] != 0 || record[27] != 0 {
+                return Err("Interleaved ISO9660 files are not supported.");
+            }
+
+            let rr = if self.rock_ridge {
+                let start = 33 + name_len + (1 - name_len % 2);
+                self.parse_system_use(record.get(start..).unwrap_or(&[]))?
+            } else {
+                RockRidge::default()
+            };
+            if rr.relocated {
+                continue;
+            }
+
+            let name = match rr.name {
+                Some(name) => name,
+                None if self.joliet => decode_ucs2(raw_name),
+                None => iso_name(raw_name),
+            };
+            let is_dir = flags & FLAG_DIRECTORY != 0 || rr.child_link.is_some();
+            let extent = rr.child_link.unwrap_or(extent);
+            let recorded = record_time(&record[18..25]);
+            entries.push(DirEntryInfo {
+                name,
+                is_dir,
+                hidden: flags & FLAG_HIDDEN != 0,
+                extent,
+                size: if is_dir { 0 } else { length as u64 },
+                extents: vec![(extent, length)],
+                mode: rr.mode,
+                links: cmp::max(rr.links, 1),
+                uid: rr.uid,
+                gid: rr.gid,
+                link_target: rr.link_target,
+                created: rr.created,
+                modified: rr.modified.or(recorded),
+                accessed: rr.accessed,
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Rock Ridge names are case-sensitive; ISO9660 and Joliet names are matched ignoring case.
+    pub fn find_entry(&mut self, dir: u32, name: &str) -> Result<Option<DirEntryInfo>, &'static str> {
+        let entries = self.read_directory(dir)?;
+        let rock_ridge = self.rock_ridge;
+        Ok(entries
+            .into_iter()
+            .find(|entry| if rock_ridge { entry.name == name } else { entry.name.eq_ignore_ascii_case(name) }))
+    }
+
+    pub fn read_file(&mut self, entry: &DirEntryInfo) -> Result<Vec<u8>, &'static str> {
+        if entry.size > usize::MAX as u64 / 2 {
+            return Err("File too large.");
+        }
+        let mut data = Vec::with_capacity(entry.size as usize);
+        for &(extent, length) in &entry.extents {
+            data.extend_from_slice(&self.read_extent(extent, length)?);
+        }
+        Ok(data)
+    }
+
+    pub fn read_link(&mut self, entry: &DirEntryInfo) -> Result<String, &'static str> {
+        entry.link_target.clone().ok_or("Not a symbolic link.")
+    }
+
+    /// Walks the tree checking that every extent lies inside the volume and that no
+    /// directory is reached twice. Nothing can be repaired on a read-only volume.
+    pub fn fsck(&mut self, _repair: bool) -> Result<FsckReport, &'static str> {
+        let mut report = FsckReport::new(false);
+        let mut seen = BTreeSet::new();
+        let mut pending = vec![(self.root_extent, String::from("\\"))];
+        while let Some((dir, path)) = pending.pop() {
+            if !seen.insert(dir) {
+                report.problems.push(format!("{}: directory is reached more than once", path));
+                continue;
+            }
+            report.dirs += 1;
+            let entries = match self.read_directory(dir) {
+                Ok(entries) => entries,
+                Err(err) => {
+                    report.problems.push(format!("{}: {}", path, err));
+                    continue;
+                }
+            };
+            for entry in entries {
+                let child = format!("{}{}{}", path, if path.ends_with('\\') { "" } else { "\\" }, entry.name);
+                for &(extent, length) in &entry.extents {
+                    let blocks = (length as u64).div_ceil(self.block_size as u64);
+                    if extent as u64 + blocks > self.volume_blocks as u64 {
+                        report.problems.push(format!("{}: extent at block {} runs past the volume", child, extent));
+                    }
+                }
+                if entry.is_dir {
+                    pending.push((entry.extent, child));
+                } else {
+                    report.files += 1;
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    fn read_extent(&self, block: u32, length: u32) -> Result<Vec<u8>, &'static str> {
+        let offset = self.part_start as u64 * SECTOR_SIZE as u64 + block as u64 * self.block_size as u64;
+        read_bytes(&self.device, offset, length as usize)
+    }
+
+    // Decodes the SUSP entries of a System Use area, following CE continuation areas.
+    fn parse_system_use(&self, area: &[u8]) -> Result<RockRidge, &'static str> {
+        let mut rr = RockRidge::default();
+        let mut name = String::new();
+        let mut has_name = false;
+        let mut link = String::new();
+        let mut link_component = String::new();
+        let mut has_link = false;
+
+        let mut current = area.get(self.susp_skip..).unwrap_or(&[]).to_vec();
+        let mut continuations = 0;
+        loop {
+            let mut next: Option<(u32, u32, u32)> = None;
+            let mut at = 0usize;
+            while at + 4 <= current.len() {
+                let sig = [current[at], current[at + 1]];
+                let len = current[at + 2] as usize;
+                if len < 4 || at + len > current.len() {
+                    break;
+                }
+                let body = &current[at + 4..at + len];
+                at += len;
+                match &sig {
+                    b"ST" => break,
+                    b"CE" if body.len() >= 24 => next = Some((le32(body, 0), le32(body, 8), le32(body, 16))),
+                    b"NM" if !body.is_empty() => {
+                        let flags = body[0];
+                        if flags & 0x02 != 0 {
+                            name.push('.');
+                        } else if flags & 0x04 != 0 {
+                            name.push_str("..");
+                        } else {
+                            name.push_str(&String::from_utf8_lossy(&body[1..]));
+                        }
+                        has_name = true;
+                    }
+                    b"PX" if body.len() >= 32 => {
+                        rr.mode = Some(le32(body, 0) as u16);
+                        rr.links = le32(body, 8);
+                        rr.uid = le32(body, 16) as u16;
+                        rr.gid = le32(body, 24) as u16;
+                    }
+                    b"TF" if !body.is_empty() => {
+                        let flags = body[0];
+                        let width = if flags & 0x80 != 0 { 17 } else { 7 };
+                        let mut pos = 1usize;
+                        for bit in 0..7 {
+                            if flags & (1 << bit) == 0 {
+                                continue;
+                            }
+                            let Some(stamp) = body.get(pos..pos + width) else {
+                                break;
+                            };
+                            pos += width;
+                            let secs = if width == 17 { long_time(stamp) } else { record_time(stamp) };
+                            match bit {
+                                0 => rr.created = secs,
+                                1 => rr.modified = secs,
+                                2 => rr.accessed = secs,
+                                _ => {}
+                            }
+                        }
+                    }
+                    b"SL" if !body.is_empty() => {
+                        has_link = true;
+                        let mut pos = 1usize;
+                        while pos + 2 <= body.len() {
+                            let flags = body[pos];
+                            let len = body[pos + 1] as usize;
+                            let content = body.get(pos + 2..pos + 2 + len).unwrap_or(&[]);
+                            pos += 2 + len;
+                            if flags & 0x08 != 0 {
+                                link_component.push('/');
+                            } else if flags & 0x02 != 0 {
+                                link_component.push('.');
+                            } else if flags & 0x04 != 0 {
+                                link_component.push_str("..");
+                            } else {
+                                link_component.push_str(&String::from_utf8_lossy(content));
+                            }
+                            // A component continued in the next SL entry is not yet whole.
+                            if flags & 0x01 == 0 {
+                                if !link.is_empty() && !link.ends_with('/') {
+                                    link.push('/');
+                                }
+                                link.push_str(&link_component);
+                                link_component.clear();
+                            }
+                        }
+                    }
+                    b"CL" if body.len() >= 8 => rr.child_link = Some(le32(body, 0)),
+                    b"RE" => rr.relocated = true,
+                    _ => {}
+                }
+            }
+
+            let Some((block, offset, length)) = next else {
+                break;
+            };
+            continuations += 1;
+            // The area must lie within its block; the sum is taken in u64 so a huge offset
+            // cannot wrap around.
+            let end = offset as u64 + length as u64;
+            if continuations > MAX_CONTINUATIONS || length as usize > DESCRIPTOR_SIZE || end > self.block_size as u64 {
+                return Err("Invalid Rock Ridge continuation area.");
+            }
+            let data = self.read_extent(block, end as u32)?;
+            current = data.get(offset as usize..).ok_or("Invalid Rock Ridge continuation area.")?.to_vec();
+        }
+
+        if has_name {
+            rr.name = Some(name);
+        }
+        if has_link {
+            rr.link_target = Some(link);
+        }
+        Ok(rr)
+    }
+}
+
+// Reads `length` bytes starting at byte `offset` of the device.
+fn read_bytes<D: BlockDevice>(dev: &D, offset: u64, length: usize) -> Result<Vec<u8>, &'static str> {
+    let mut data = Vec::with_capacity(length);
+    let mut block = offset / SECTOR_SIZE as u64;
+    let mut skip = (offset % SECTOR_SIZE as u64) as usize;
+    let mut sector = [0u8; SECTOR_SIZE];
+    while data.len() < length {
+        dev.read_block(block, &mut sector).map_err(|err| err.as_str())?;
+        let take = cmp::min(SECTOR_SIZE - skip, length - data.len());
+        data.extend_from_slice(&sector[skip..skip + take]);
+        skip = 0;
+        block += 1;
+    }
+    Ok(data)
+}
+
+// The little-endian half of a both-endian field.
+fn le32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+// Plain ISO9660 names: the ";1" version suffix and a trailing dot are dropped.
+fn iso_name(raw: &[u8]) -> String {
+    let name: String = raw.iter().map(|&b| b as char).collect();
+    let name = name.split(';').next().unwrap_or("");
+    name.strip_suffix('.').unwrap_or(name).to_string()
+}
+
+// Joliet names are big-endian UCS-2, with the same version suffix.
+fn decode_ucs2(raw: &[u8]) -> String {
+    let units = raw.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+    let name: String = core::char::decode_utf16(units)
+        .map(|ch| ch.unwrap_or('\u{FFFD}'))
+        .collect();
+    match name.rfind(';') {
+        Some(at) => name[..at].to_string(),
+        None => name,
+    }
+}
+
+// Seven-byte recording time: years since 1900, month, day, hour, minute, second and the
+// offset from GMT in 15-minute steps.
+fn record_time(stamp: &[u8]) -> Option<u64> {
+    let (month, day) = (stamp[1], stamp[2]);
+    if !(1..=12).contains(&month) || day == 0 {
+        return None;
+    }
+    let secs = time::ymd_hms_to_secs(
+        1900 + stamp[0] as u64,
+        month as u64,
+        day as u64,
+        stamp[3] as u64,
+        stamp[4] as u64,
+        stamp[5] as u64,
+    );
+    Some(apply_gmt_offset(secs, stamp[6] as i8))
+}
+
+// Seventeen-byte form: "YYYYMMDDHHMMSScc" in ASCII digits, then the GMT offset.
+fn long_time(stamp: &[u8]) -> Option<u64> {
+    let digits = |from: usize, to: usize| -> Option<u64> {
+        let text = core::str::from_utf8(&stamp[from..to]).ok()?;
+        text.parse().ok()
+    };
+    let (year, month, day) = (digits(0, 4)?, digits(4, 6)?, digits(6, 8)?);
+    if year < 1970 || !(1..=12).contains(&month) || day == 0 {
+        return None;
+    }
+    let secs = time::ymd_hms_to_secs(year, month, day, digits(8, 10)?, digits(10, 12)?, digits(12, 14)?);
+    Some(apply_gmt_offset(secs, stamp[16] as i8))
+}
+
+fn apply_gmt_offset(secs: u64, quarter_hours: i8) -> u64 {
+    let offset = quarter_hours as i64 * 15 * 60;
+    (secs as i64 - offset).max(0) as u64
+}
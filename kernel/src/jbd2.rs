@@ -0,0 +1,377 @@
+use alloc::collections::BTreeMap;
+use alloc::{vec, vec::Vec};
+
+use crate::ext2::crc32c;
+
+// The journal is big-endian throughout, unlike the filesystem around it.
+const JBD2_MAGIC: u32 = 0xC03B_3998;
+const JBD2_DESCRIPTOR_BLOCK: u32 = 1;
+const JBD2_COMMIT_BLOCK: u32 = 2;
+const JBD2_SUPERBLOCK_V1: u32 = 3;
+const JBD2_SUPERBLOCK_V2: u32 = 4;
+const JBD2_REVOKE_BLOCK: u32 = 5;
+const JBD2_HEADER_SIZE: usize = 12;
+const JBD2_SUPERBLOCK_SIZE: usize = 1024;
+const JBD2_SB_CHECKSUM_OFFSET: usize = 0xFC;
+
+const JBD2_FLAG_ESCAPE: u32 = 1;
+const JBD2_FLAG_SAME_UUID: u32 = 2;
+const JBD2_FLAG_LAST_TAG: u32 = 8;
+
+const JBD2_FEATURE_INCOMPAT_REVOKE: u32 = 0x1;
+const JBD2_FEATURE_INCOMPAT_64BIT: u32 = 0x2;
+const JBD2_FEATURE_INCOMPAT_ASYNC_COMMIT: u32 = 0x4;
+const JBD2_FEATURE_INCOMPAT_CSUM_V2: u32 = 0x8;
+const JBD2_FEATURE_INCOMPAT_CSUM_V3: u32 = 0x10;
+// Fast commits (0x20) log changes as operations, which this driver cannot replay.
+const SUPPORTED_INCOMPAT: u32 = JBD2_FEATURE_INCOMPAT_REVOKE
+    | JBD2_FEATURE_INCOMPAT_64BIT
+    | JBD2_FEATURE_INCOMPAT_ASYNC_COMMIT
+    | JBD2_FEATURE_INCOMPAT_CSUM_V2
+    | JBD2_FEATURE_INCOMPAT_CSUM_V3;
+// Transactions are written without checksums, so journals that need them are only replayed.
+const LOGGABLE_INCOMPAT: u32 =
+    JBD2_FEATURE_INCOMPAT_REVOKE | JBD2_FEATURE_INCOMPAT_64BIT | JBD2_FEATURE_INCOMPAT_ASYNC_COMMIT;
+const JBD2_UUID_SIZE: usize = 16;
+// Smallest journal e2fsck accepts.
+pub const JBD2_MIN_JOURNAL_BLOCKS: u32 = 1024;
+
+/// Block access for a journal kept inside a filesystem. Journal blocks are numbered from
+/// the start of the journal; filesystem blocks as the filesystem numbers them.
+pub trait JournalIo {
+    fn journal_len(&self) -> u32;
+    fn read_journal(&mut self, block: u32, buf: &mut [u8]) -> Result<(), &'static str>;
+    fn write_journal(&mut self, block: u32, buf: &[u8]) -> Result<(), &'static str>;
+    fn write_fs(&mut self, block: u32, buf: &[u8]) -> Result<(), &'static str>;
+}
+
+#[derive(Clone, Copy)]
+pub struct JournalSuperblock {
+    pub block_size: u32,
+    pub max_len: u32,
+    pub first: u32,
+    pub sequence: u32,
+    /// First block of the log; zero when the journal is empty.
+    pub start: u32,
+    pub feature_incompat: u32,
+}
+
+impl JournalSuperblock {
+    pub fn needs_recovery(&self) -> bool {
+        self.start != 0
+    }
+
+    fn has(&self, feature: u32) -> bool {
+        self.feature_incompat & feature != 0
+    }
+
+    fn has_csum(&self) -> bool {
+        self.has(JBD2_FEATURE_INCOMPAT_CSUM_V2 | JBD2_FEATURE_INCOMPAT_CSUM_V3)
+    }
+
+    // Bytes per descriptor block tag, not counting the UUID that follows the first one.
+    fn tag_size(&self) -> usize {
+        if self.has(JBD2_FEATURE_INCOMPAT_CSUM_V3) {
+            return 16;
+        }
+        let size = if self.has(JBD2_FEATURE_INCOMPAT_CSUM_V2) { 10 } else { 8 };
+        if self.has(JBD2_FEATURE_INCOMPAT_64BIT) {
+            size + 4
+        } else {
+            size
+        }
+    }
+
+    /// True when this driver can write transactions into the journal.
+    pub fn can_log(&self) -> bool {
+        self.feature_incompat & !LOGGABLE_INCOMPAT == 0
+    }
+
+    /// True when a transaction of `count` blocks fits in the log in one piece.
+    pub fn fits(&self, count: usize) -> bool {
+        let descriptors = count.div_ceil(self.tags_per_descriptor());
+        descriptors + count < (self.max_len - self.first) as usize
+    }
+
+    // Every descriptor written here leaves room for a UUID after its first tag.
+    fn tags_per_descriptor(&self) -> usize {
+        (self.block_size as usize - JBD2_HEADER_SIZE - JBD2_UUID_SIZE) / self.tag_size()
+    }
+
+    // Log blocks wrap from the end of the journal back to `first`.
+    fn next(&self, block: u32, count: u32) -> u32 {
+        let mut block = block + count;
+        while block >= self.max_len {
+            block = block - self.max_len + self.first;
+        }
+        block
+    }
+}
+
+// One block logged by a committed transaction.
+struct LoggedBlock {
+    sequence: u32,
+    target: u32,
+    log_block: u32,
+    escaped: bool,
+}
+
+// What a scan of the log found: the blocks logged by committed transactions, the latest
+// transaction to revoke each block, and the sequence number after the last commit.
+struct Log {
+    blocks: Vec<LoggedBlock>,
+    revoked: BTreeMap<u32, u32>,
+    sequence: u32,
+}
+
+pub fn read_superblock(io: &mut impl JournalIo, block_size: u32) -> Result<JournalSuperblock, &'static str> {
+    let mut buf = vec![0u8; block_size as usize];
+    io.read_journal(0, &mut buf)?;
+    let block_type = read_be32(&buf, 4);
+    if read_be32(&buf, 0) != JBD2_MAGIC || (block_type != JBD2_SUPERBLOCK_V1 && block_type != JBD2_SUPERBLOCK_V2) {
+        return Err("Invalid journal superblock.");
+    }
+    let jsb = JournalSuperblock {
+        block_size: read_be32(&buf, 12),
+        max_len: read_be32(&buf, 16),
+        first: read_be32(&buf, 20),
+        sequence: read_be32(&buf, 24),
+        start: read_be32(&buf, 28),
+        // Version 1 journals have no feature flags.
+        feature_incompat: if block_type == JBD2_SUPERBLOCK_V2 { read_be32(&buf, 40) } else { 0 },
+    };
+    if jsb.block_size != block_size
+        || jsb.first == 0
+        || jsb.first >= jsb.max_len
+        || jsb.max_len > io.journal_len()
+        || (jsb.start != 0 && (jsb.start < jsb.first || jsb.start >= jsb.max_len))
+    {
+        return Err("Invalid journal geometry.");
+    }
+    Ok(jsb)
+}
+
+/// Replays the transactions committed to the journal into the filesystem, then marks the
+/// journal empty. Returns how many transactions were replayed.
+pub fn recover(io: &mut impl JournalIo, block_size: u32) -> Result<u32, &'static str> {
+    let jsb = read_superblock(io, block_size)?;
+    if !jsb.needs_recovery() {
+        return Ok(0);
+    }
+    if jsb.feature_incompat & !SUPPORTED_INCOMPAT != 0 {
+        return Err("Journal uses features this driver cannot replay.");
+    }
+
+    let log = scan(io, &jsb)?;
+    let transactions = log.sequence.wrapping_sub(jsb.sequence);
+    let mut buf = vec![0u8; block_size as usize];
+    for block in log.blocks {
+        // A revocation cancels copies from its own transaction and earlier ones.
+        if log.revoked.get(&block.target).is_some_and(|&seq| !seq_before(seq, block.sequence)) {
+            continue;
+        }
+        io.read_journal(block.log_block, &mut buf)?;
+        if block.escaped {
+            buf[..4].copy_from_slice(&JBD2_MAGIC.to_be_bytes());
+        }
+        io.write_fs(block.target, &buf)?;
+    }
+
+    write_log_start(io, &jsb, 0, log.sequence)?;
+    Ok(transactions)
+}
+
+/// Writes a new journal of `len` blocks: a version 2 superblock with no features and an
+/// empty log. The rest of the journal should already be zeroed.
+pub fn format(io: &mut impl JournalIo, block_size: u32, len: u32) -> Result<(), &'static str> {
+    let mut buf = vec![0u8; block_size as usize];
+    buf[0..4].copy_from_slice(&JBD2_MAGIC.to_be_bytes());
+    buf[4..8].copy_from_slice(&JBD2_SUPERBLOCK_V2.to_be_bytes());
+    buf[12..16].copy_from_slice(&block_size.to_be_bytes());
+    buf[16..20].copy_from_slice(&len.to_be_bytes());
+    // s_first, s_sequence; s_start stays zero.
+    buf[20..24].copy_from_slice(&1u32.to_be_bytes());
+    buf[24..28].copy_from_slice(&1u32.to_be_bytes());
+    // One filesystem, the one the journal lives in, uses it.
+    buf[0x40..0x44].copy_from_slice(&1u32.to_be_bytes());
+    io.write_journal(0, &buf)
+}
+
+/// Logs `blocks` (filesystem block number to contents) as one committed transaction at the
+/// head of the empty log, copies them to their places and empties the log again. A crash
+/// before the commit block lands leaves the filesystem as it was; one after it is finished
+/// by `recover` at the next mount.
+pub fn commit(
+    io: &mut impl JournalIo,
+    jsb: &mut JournalSuperblock,
+    blocks: &BTreeMap<u32, Vec<u8>>,
+) -> Result<(), &'static str> {
+    if blocks.is_empty() {
+        return Ok(());
+    }
+    if !jsb.can_log() || !jsb.fits(blocks.len()) {
+        return Err("Change is too large for the journal; nothing was written.");
+    }
+    let block_size = jsb.block_size as usize;
+    let sequence = jsb.sequence;
+    let entries: Vec<(&u32, &Vec<u8>)> = blocks.iter().collect();
+    let mut log_block = jsb.first;
+    let mut copy = vec![0u8; block_size];
+    for chunk in entries.chunks(jsb.tags_per_descriptor()) {
+        let mut descriptor = vec![0u8; block_size];
+        write_header(&mut descriptor, JBD2_DESCRIPTOR_BLOCK, sequence);
+        let mut offset = JBD2_HEADER_SIZE;
+        for (i, &(&target, data)) in chunk.iter().enumerate() {
+            copy.copy_from_slice(data);
+            let mut flags = 0;
+            // A block that looks like a journal block is logged with its magic cleared.
+            if read_be32(&copy, 0) == JBD2_MAGIC {
+                copy[..4].fill(0);
+                flags |= JBD2_FLAG_ESCAPE;
+            }
+            if i > 0 {
+                flags |= JBD2_FLAG_SAME_UUID;
+            }
+            if i + 1 == chunk.len() {
+                flags |= JBD2_FLAG_LAST_TAG;
+            }
+            descriptor[offset..offset + 4].copy_from_slice(&target.to_be_bytes());
+            descriptor[offset + 6..offset + 8].copy_from_slice(&(flags as u16).to_be_bytes());
+            offset += jsb.tag_size();
+            if i == 0 {
+                offset += JBD2_UUID_SIZE;
+            }
+            io.write_journal(log_block + 1 + i as u32, &copy)?;
+        }
+        io.write_journal(log_block, &descriptor)?;
+        log_block += 1 + chunk.len() as u32;
+    }
+    let mut commit_block = vec![0u8; block_size];
+    write_header(&mut commit_block, JBD2_COMMIT_BLOCK, sequence);
+    io.write_journal(log_block, &commit_block)?;
+    write_log_start(io, jsb, jsb.first, sequence)?;
+
+    for (&target, data) in blocks {
+        io.write_fs(target, data)?;
+    }
+    jsb.sequence = sequence.wrapping_add(1);
+    write_log_start(io, jsb, 0, jsb.sequence)
+}
+
+// Walks the log from `start`. A transaction without its commit block is ignored.
+fn scan(io: &mut impl JournalIo, jsb: &JournalSuperblock) -> Result<Log, &'static str> {
+    let block_size = jsb.block_size as usize;
+    let tail = if jsb.has_csum() { 4 } else { 0 };
+    let record_size = if jsb.has(JBD2_FEATURE_INCOMPAT_64BIT) { 8 } else { 4 };
+    let mut logged = Vec::new();
+    let mut revoked = BTreeMap::new();
+    let (mut pending, mut pending_revokes) = (Vec::new(), Vec::new());
+    let mut sequence = jsb.sequence;
+    let mut block = jsb.start;
+    let mut buf = vec![0u8; block_size];
+
+    // Every log block is visited at most once.
+    let mut budget = jsb.max_len - jsb.first;
+    while budget > 0 {
+        io.read_journal(block, &mut buf)?;
+        if read_be32(&buf, 0) != JBD2_MAGIC || read_be32(&buf, 8) != sequence {
+            break;
+        }
+        match read_be32(&buf, 4) {
+            JBD2_DESCRIPTOR_BLOCK => {
+                let mut offset = JBD2_HEADER_SIZE;
+                let mut count = 0u32;
+                while offset + jsb.tag_size() <= block_size - tail {
+                    let (target, flags, high) = if jsb.has(JBD2_FEATURE_INCOMPAT_CSUM_V3) {
+                        (read_be32(&buf, offset), read_be32(&buf, offset + 4), read_be32(&buf, offset + 8))
+                    } else {
+                        let high = if jsb.has(JBD2_FEATURE_INCOMPAT_64BIT) { read_be32(&buf, offset + 8) } else { 0 };
+                        (read_be32(&buf, offset), read_be16(&buf, offset + 6) as u32, high)
+                    };
+                    if high != 0 {
+                        return Err("Journal refers to blocks past the first 2^32.");
+                    }
+                    count += 1;
+                    pending.push(LoggedBlock {
+                        sequence,
+                        target,
+                        log_block: jsb.next(block, count),
+                        escaped: flags & JBD2_FLAG_ESCAPE != 0,
+                    });
+                    offset += jsb.tag_size();
+                    if flags & JBD2_FLAG_SAME_UUID == 0 {
+                        offset += 16;
+                    }
+                    if flags & JBD2_FLAG_LAST_TAG != 0 {
+                        break;
+                    }
+                }
+                if count >= budget {
+                    break;
+                }
+                budget -= count;
+                block = jsb.next(block, count);
+            }
+            JBD2_REVOKE_BLOCK => {
+                let used = (read_be32(&buf, JBD2_HEADER_SIZE) as usize).min(block_size - tail);
+                let mut offset = JBD2_HEADER_SIZE + 4;
+                while offset + record_size <= used {
+                    if record_size == 8 && read_be32(&buf, offset) != 0 {
+                        return Err("Journal refers to blocks past the first 2^32.");
+                    }
+                    pending_revokes.push(read_be32(&buf, offset + record_size - 4));
+                    offset += record_size;
+                }
+            }
+            JBD2_COMMIT_BLOCK => {
+                logged.append(&mut pending);
+                for target in pending_revokes.drain(..) {
+                    revoked.insert(target, sequence);
+                }
+                sequence = sequence.wrapping_add(1);
+            }
+            _ => break,
+        }
+        budget -= 1;
+        block = jsb.next(block, 1);
+    }
+    Ok(Log {
+        blocks: logged,
+        revoked,
+        sequence,
+    })
+}
+
+// Records where the log starts (zero when it is empty) and the sequence number of the
+// transaction found there.
+fn write_log_start(io: &mut impl JournalIo, jsb: &JournalSuperblock, start: u32, sequence: u32) -> Result<(), &'static str> {
+    let mut buf = vec![0u8; jsb.block_size as usize];
+    io.read_journal(0, &mut buf)?;
+    buf[24..28].copy_from_slice(&sequence.to_be_bytes());
+    buf[28..32].copy_from_slice(&start.to_be_bytes());
+    if jsb.has_csum() {
+        buf[JBD2_SB_CHECKSUM_OFFSET..JBD2_SB_CHECKSUM_OFFSET + 4].fill(0);
+        let checksum = crc32c(!0, &buf[..JBD2_SUPERBLOCK_SIZE]);
+        buf[JBD2_SB_CHECKSUM_OFFSET..JBD2_SB_CHECKSUM_OFFSET + 4].copy_from_slice(&checksum.to_be_bytes());
+    }
+    io.write_journal(0, &buf)
+}
+
+fn write_header(buf: &mut [u8], block_type: u32, sequence: u32) {
+    buf[0..4].copy_from_slice(&JBD2_MAGIC.to_be_bytes());
+    buf[4..8].copy_from_slice(&block_type.to_be_bytes());
+    buf[8..12].copy_from_slice(&sequence.to_be_bytes());
+}
+
+// Sequence numbers wrap, so they are compared by distance.
+fn seq_before(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn read_be16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn read_be32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
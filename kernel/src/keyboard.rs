@@ -87,6 +87,7 @@ pub enum KeyEvent {
     CtrlA,
     CtrlC,
     CtrlO,
+    CtrlR,
     CtrlS,
     CtrlV,
     CtrlX,
@@ -170,6 +171,7 @@ impl Keyboard {
                             '\u{0001}' | '\u{0041}' if self.ctrl_down => Some(KeyEvent::CtrlA),
                             '\u{0003}' | '\u{0043}' if self.ctrl_down => Some(KeyEvent::CtrlC),
                             '\u{000F}' | '\u{004F}' if self.ctrl_down => Some(KeyEvent::CtrlO),
+                            '\u{0012}' | '\u{0052}' if self.ctrl_down => Some(KeyEvent::CtrlR),
                             '\u{0013}' | '\u{0053}' if self.ctrl_down => Some(KeyEvent::CtrlS),
                             '\u{0016}' | '\u{0056}' if self.ctrl_down => Some(KeyEvent::CtrlV),
                             '\u{0018}' | '\u{0058}' if self.ctrl_down => Some(KeyEvent::CtrlX),
@@ -183,6 +185,7 @@ impl Keyboard {
                                     'a' | 'A' => Some(KeyEvent::CtrlA),
                                     'c' | 'C' => Some(KeyEvent::CtrlC),
                                     'o' | 'O' => Some(KeyEvent::CtrlO),
+                                    'r' | 'R' => Some(KeyEvent::CtrlR),
                                     's' | 'S' => Some(KeyEvent::CtrlS),
                                     'v' | 'V' => Some(KeyEvent::CtrlV),
                                     'x' | 'X' => Some(KeyEvent::CtrlX),
@@ -0,0 +1,237 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::iter::Peekable;
+use core::str::CharIndices;
+
+use crate::{env, glob};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Quote {
+    None,
+    Single,
+    Double,
+}
+
+/// How a character of a command line is to be read.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Kind {
+    /// Unquoted and unescaped: whitespace, operators and `$` are syntax.
+    Live,
+    /// Inside double quotes: only `$` is still expanded.
+    Double,
+    /// Inside single quotes or escaped by a backslash.
+    Literal,
+    /// A quote or escaping backslash, dropped from the word it belongs to.
+    Markup,
+}
+
+// Outside single quotes a backslash only escapes characters the shell would otherwise
+// interpret, so paths like `\bin\hello` keep their separators.
+fn escapable(c: char, quote: Quote) -> bool {
+    match quote {
+        Quote::Double => matches!(c, '"' | '\\' | '$'),
+        _ => matches!(c, '"' | '\'' | '\\' | '$' | ';' | '&' | '|' | '<' | '>' | '#'),
+    }
+}
+
+/// Walks a command line yielding each character with its byte offset and [`Kind`].
+/// The shell uses it to find separators without disturbing quoted text.
+pub struct Scan<'a> {
+    chars: Peekable<CharIndices<'a>>,
+    quote: Quote,
+    escaped: bool,
+}
+
+pub fn scan(line: &str) -> Scan<'_> {
+    Scan { chars: line.char_indices().peekable(), quote: Quote::None, escaped: false }
+}
+
+impl Scan<'_> {
+    /// True while inside an unterminated quote.
+    pub fn is_quoted(&self) -> bool {
+        self.quote != Quote::None
+    }
+
+    fn skip_to(&mut self, end: usize) {
+        while self.chars.next_if(|&(i, _)| i < end).is_some() {}
+    }
+}
+
+impl Iterator for Scan<'_> {
+    type Item = (usize, char, Kind);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (i, c) = self.chars.next()?;
+        if self.escaped {
+            self.escaped = false;
+            return Some((i, c, Kind::Literal));
+        }
+        let kind = match (self.quote, c) {
+            (Quote::Single, '\'') | (Quote::Double, '"') => {
+                self.quote = Quote::None;
+                Kind::Markup
+            }
+            (Quote::Single, _) => Kind::Literal,
+            (quote, '\\') if self.chars.peek().is_some_and(|&(_, n)| escapable(n, quote)) => {
+                self.escaped = true;
+                Kind::Markup
+            }
+            (Quote::Double, _) => Kind::Double,
+            (Quote::None, '\'') => {
+                self.quote = Quote::Single;
+                Kind::Markup
+            }
+            (Quote::None, '"') => {
+                self.quote = Quote::Double;
+                Kind::Markup
+            }
+            (Quote::None, _) => Kind::Live,
+        };
+        Some((i, c, kind))
+    }
+}
+
+pub enum Token {
+    Word(String),
+    /// `|`
+    Pipe,
+    /// `<`
+    Input,
+    /// `>`
+    Output,
+    /// `>>`
+    Append,
+}
+
+/// Splits a pipeline into words and `|`, `<`, `>`, `>>` operators, removing quotes and
+/// escapes, expanding variables outside single quotes and unquoted wildcards to the paths
+/// they match.
+pub fn tokenize(line: &str) -> Result<Vec<Token>, &'static str> {
+    lex(line, true, true)
+}
+
+/// Splits a command line into words like [`tokenize`], with operators kept as text.
+pub fn split(line: &str) -> Result<Vec<String>, &'static str> {
+    Ok(words(lex(line, false, true)?))
+}
+
+/// Like [`split`], but leaves wildcards as typed.
+pub fn split_literal(line: &str) -> Result<Vec<String>, &'static str> {
+    Ok(words(lex(line, false, false)?))
+}
+
+fn words(tokens: Vec<Token>) -> Vec<String> {
+    tokens
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Word(word) => Some(word),
+            _ => None,
+        })
+        .collect()
+}
+
+// Ends the current word. A word with unquoted wildcards becomes the paths it matches,
+// or stays as typed if there are none.
+fn push_word(tokens: &mut Vec<Token>, word: &mut String, live: &mut Vec<bool>, glob: bool) {
+    let word = core::mem::take(word);
+    let live = core::mem::take(live);
+    if glob && glob::has_wildcards(&word, &live) {
+        let paths = glob::expand(&word, &live);
+        if !paths.is_empty() {
+            tokens.extend(paths.into_iter().map(Token::Word));
+            return;
+        }
+    }
+    tokens.push(Token::Word(word));
+}
+
+fn lex(line: &str, operators: bool, glob: bool) -> Result<Vec<Token>, &'static str> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    // Which characters of `word` were typed unquoted, and so may be wildcards.
+    let mut live = Vec::new();
+    // Set by quotes too, so `""` is an empty argument rather than nothing.
+    let mut in_word = false;
+    let mut chars = scan(line);
+
+    while let Some((i, c, kind)) = chars.next() {
+        match kind {
+            Kind::Markup => in_word = true,
+            Kind::Literal => {
+                word.push(c);
+                live.push(false);
+                in_word = true;
+            }
+            Kind::Live | Kind::Double if c == '$' => {
+                let (value, used) = env::expand_var(&line[i + 1..]);
+                chars.skip_to(i + 1 + used);
+                // An unquoted variable that expands to nothing leaves no argument behind.
+                in_word |= !value.is_empty();
+                live.extend(value.chars().map(|_| false));
+                word.push_str(&value);
+            }
+            Kind::Live if c.is_whitespace() => {
+                if in_word {
+                    push_word(&mut tokens, &mut word, &mut live, glob);
+                    in_word = false;
+                }
+            }
+            Kind::Live if operators && matches!(c, '|' | '<' | '>') => {
+                if in_word {
+                    push_word(&mut tokens, &mut word, &mut live, glob);
+                    in_word = false;
+                }
+                let token = match c {
+                    '|' => Token::Pipe,
+                    '<' => Token::Input,
+                    _ if line[i + 1..].starts_with('>') => {
+                        chars.next();
+                        Token::Append
+                    }
+                    _ => Token::Output,
+                };
+                tokens.push(token);
+            }
+            Kind::Live | Kind::Double => {
+                word.push(c);
+                live.push(kind == Kind::Live);
+                in_word = true;
+            }
+        }
+    }
+
+    if chars.is_quoted() {
+        return Err("Unterminated quote.");
+    }
+    if in_word {
+        push_word(&mut tokens, &mut word, &mut live, glob);
+    }
+    Ok(tokens)
+}
+
+/// Quotes each component of a path that the lexer would otherwise split or expand, leaving
+/// the separators outside the quotes so a trailing `\` is never read as an escape.
+pub fn quote_path(path: &str) -> String {
+    let parts: Vec<String> = path.split('\\').map(quote_word).collect();
+    parts.join("\\")
+}
+
+fn quote_word(word: &str) -> String {
+    let special = |c: char| c.is_whitespace() || "\"'$;&|<>#*?[".contains(c);
+    if !word.contains(special) {
+        return word.to_string();
+    }
+    if !word.contains('\'') {
+        return format!("'{}'", word);
+    }
+    let mut quoted = String::from("\"");
+    for c in word.chars() {
+        if matches!(c, '"' | '\\' | '$') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
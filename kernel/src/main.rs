@@ -14,7 +14,10 @@ mod block;
 mod ata;
 mod pci;
 mod fat32;
+mod exfat;
+mod iso9660;
 mod ext2;
+mod jbd2;
 mod keyboard;
 mod font;
 mod font2;
@@ -35,6 +38,23 @@ mod theme_presets;
 mod help;
 mod history;
 mod memory;
+mod gdt;
+mod process;
+mod syscall;
+mod elf;
+mod fd;
+mod pipe;
+mod shell;
+mod task;
+mod lexer;
+mod glob;
+mod textutil;
+mod inspect;
+mod complete;
+mod config;
+mod env;
+mod script;
+mod users;
 mod timer;
 mod interrupts;
 mod pic;
@@ -50,11 +70,12 @@ mod thudmodules {
 
 use bootloader_api::{config::BootloaderConfig, entry_point, BootInfo};
 use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
 use core::panic::PanicInfo;
 use console::{init_console, with_console};
 use keyboard::Keyboard;
 use crate::run_mode::RunMode;
-use heapless::{String, Vec};
 use x86_64::instructions::interrupts as cpu_intr;
 #[cfg(target_arch = "x86_64")]
 use core::arch::asm;
@@ -111,19 +132,28 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     thudmodules::min::init();
     thudmodules::tin::init();
 
+    gdt::init();
     interrupts::init_idt();
+    syscall::init();
+    process::init();
     pic::init_pic();
     timer::init_pit();
     cpu_intr::enable();
     time::init_time();
     wait::init();
     fs::init_persistent();
+    config::init();
+    users::init();
+    history::load();
+    commands::load_aliases();
 
     if SHOWSPLASH {
     boot_splash::show();
     }
 
     run_mode::init(BOOT_MODE);
+    script::run_autoexec();
+    users::login();
     let mut mode = BOOT_MODE;
     loop {
         run_mode::enter(mode);
@@ -176,18 +206,41 @@ fn run_console_shell() -> RunMode {
     });
 
     let mut kbd = Keyboard::new();
-    let mut line = String::<128>::new();
-    let mut draft_line = String::<128>::new();
+    let mut line = String::new();
+    let mut draft_line = String::new();
     let mut history_index: Option<usize> = None;
     let mut cursor_pos: usize = 0;
     let mut rendered_len: usize = 0;
     let mut selection_anchor: Option<usize> = None;
+    let mut search: Option<history::Search> = None;
+    let mut completer = complete::Completer::default();
 
     loop {
         if let Some(next) = run_mode::should_switch() {
             return next;
         }
         if let Some(evt) = kbd.poll_event() {
+            if let Some(active) = search.as_mut() {
+                match evt {
+                    keyboard::KeyEvent::Char(ch) => active.push(ch),
+                    keyboard::KeyEvent::Backspace => active.pop(),
+                    keyboard::KeyEvent::CtrlR => active.older(),
+                    _ => {
+                        // Any other key takes the match into the line and is then handled as usual.
+                        if let Some(found) = active.matched() {
+                            line = found;
+                        }
+                        search = None;
+                        history_index = None;
+                        cursor_pos = line.chars().count();
+                        redraw_input_line(&line, cursor_pos, input_origin, &mut rendered_len, selection_anchor);
+                    }
+                }
+                if let Some(active) = &search {
+                    render_search(active, input_origin, &mut rendered_len);
+                    continue;
+                }
+            }
             match evt {
                 keyboard::KeyEvent::Char(ch) => {
                     if let Some(anchor) = selection_anchor {
@@ -305,7 +358,7 @@ fn run_console_shell() -> RunMode {
                     if let Some(anchor) = selection_anchor {
                         let start = anchor.min(cursor_pos);
                         let end = anchor.max(cursor_pos);
-                        let selected_text: String<128> = line.chars().skip(start).take(end - start).collect();
+                        let selected_text: String = line.chars().skip(start).take(end - start).collect();
                         clipboard::set_text(&selected_text);
                     }
                 }
@@ -313,7 +366,7 @@ fn run_console_shell() -> RunMode {
                     if let Some(anchor) = selection_anchor {
                         let start = anchor.min(cursor_pos);
                         let end = anchor.max(cursor_pos);
-                        let selected_text: String<128> = line.chars().skip(start).take(end - start).collect();
+                        let selected_text: String = line.chars().skip(start).take(end - start).collect();
                         clipboard::set_text(&selected_text);
                         delete_selection(&mut line, &mut cursor_pos, anchor);
                         selection_anchor = None;
@@ -330,12 +383,16 @@ fn run_console_shell() -> RunMode {
                     for ch in clip_text.chars() {
                          if insert_char_at(&mut line, cursor_pos, ch) {
                             cursor_pos += 1;
-                         } else {
-                             break;
                          }
                     }
                     redraw_input_line(&line, cursor_pos, input_origin, &mut rendered_len, selection_anchor);
                 }
+                keyboard::KeyEvent::CtrlR => {
+                    selection_anchor = None;
+                    let active = history::Search::default();
+                    render_search(&active, input_origin, &mut rendered_len);
+                    search = Some(active);
+                }
                 keyboard::KeyEvent::CtrlO | keyboard::KeyEvent::CtrlS => {}
                 keyboard::KeyEvent::Up => {
                     selection_anchor = None;
@@ -344,8 +401,7 @@ fn run_console_shell() -> RunMode {
                         continue;
                     }
                     if history_index.is_none() {
-                        draft_line.clear();
-                        let _ = draft_line.push_str(&line);
+                        draft_line.clone_from(&line);
                     }
                     let new_idx = history_index
                         .map(|i| i.saturating_sub(1))
@@ -382,7 +438,6 @@ fn run_console_shell() -> RunMode {
                 keyboard::KeyEvent::Enter => {
                     with_console(|c| c.newline());
                     commands::handle_line(&line);
-                    history::push(&line);
                     if let Some(next) = run_mode::should_switch() {
                         return next;
                     }
@@ -399,12 +454,22 @@ fn run_console_shell() -> RunMode {
                     });
                 }
                 keyboard::KeyEvent::Tab => {
-                    if let Some(cmd) = commands::suggest_command(&line) {
-                        line.clear();
-                        let _ = line.push_str(cmd.as_str());
-                        cursor_pos = line.chars().count();
-                        redraw_input_line(&line, cursor_pos, input_origin, &mut rendered_len, selection_anchor);
+                    let Some(done) = completer.tab(&line, cursor_pos) else {
+                        continue;
+                    };
+                    selection_anchor = None;
+                    if !done.choices.is_empty() {
+                        input_origin = with_console(|c| {
+                            c.newline();
+                            c.write_line(&done.choices.join("  "));
+                            c.write(&prompt_text());
+                            c.cursor_position()
+                        });
+                        rendered_len = 0;
                     }
+                    line = done.line;
+                    cursor_pos = done.cursor;
+                    redraw_input_line(&line, cursor_pos, input_origin, &mut rendered_len, selection_anchor);
                 }
                 keyboard::KeyEvent::AltTab | keyboard::KeyEvent::Start => {}
             }
@@ -412,71 +477,52 @@ fn run_console_shell() -> RunMode {
     }
 }
 
+// Byte offset of the `idx`th character of `line`, or its end when `idx` is the length.
+fn char_offset(line: &str, idx: usize) -> Option<usize> {
+    line.char_indices().map(|(at, _)| at).chain(core::iter::once(line.len())).nth(idx)
+}
+
 #[allow(dead_code)]
-fn delete_selection(line: &mut String<128>, cursor_pos: &mut usize, anchor: usize) {
+fn delete_selection(line: &mut String, cursor_pos: &mut usize, anchor: usize) {
     let start = anchor.min(*cursor_pos);
     let end = anchor.max(*cursor_pos);
     if start == end { return; }
 
-    let mut new_line = String::<128>::new();
-    for (i, ch) in line.chars().enumerate() {
-        if i >= start && i < end { continue; }
-        let _ = new_line.push(ch);
-    }
-    *line = new_line;
+    *line = line
+        .chars()
+        .enumerate()
+        .filter(|(i, _)| *i < start || *i >= end)
+        .map(|(_, ch)| ch)
+        .collect();
     *cursor_pos = start;
 }
 
 #[allow(dead_code)]
-fn insert_char_at(line: &mut String<128>, idx: usize, ch: char) -> bool {
-    let len = line.chars().count();
-    if idx > len {
+fn insert_char_at(line: &mut String, idx: usize, ch: char) -> bool {
+    let Some(at) = char_offset(line, idx) else {
         return false;
-    }
-    let mut new_line = String::<128>::new();
-    let mut inserted = false;
-    for (i, existing) in line.chars().enumerate() {
-        if i == idx {
-            if new_line.push(ch).is_err() { return false; }
-            inserted = true;
-        }
-        if new_line.push(existing).is_err() { return false; }
-    }
-    if !inserted && new_line.push(ch).is_err() {
-        return false;
-    }
-    *line = new_line;
+    };
+    line.insert(at, ch);
     true
 }
 
 #[allow(dead_code)]
-fn remove_char_at(line: &mut String<128>, idx: usize) -> bool {
-    let len = line.chars().count();
-    if idx >= len {
-        return false;
-    }
-    let mut new_line = String::<128>::new();
-    for (i, ch) in line.chars().enumerate() {
-        if i == idx {
-            continue;
-        }
-        if new_line.push(ch).is_err() {
-            return false;
+fn remove_char_at(line: &mut String, idx: usize) -> bool {
+    match char_offset(line, idx) {
+        Some(at) if at < line.len() => {
+            line.remove(at);
+            true
         }
+        _ => false,
     }
-    *line = new_line;
-    true
 }
 
 #[allow(dead_code)]
-fn delete_prev_word(line: &mut String<128>, cursor_pos: &mut usize) -> bool {
+fn delete_prev_word(line: &mut String, cursor_pos: &mut usize) -> bool {
     if *cursor_pos == 0 {
         return false;
     }
-    let mut chars = Vec::<char, 128>::new();
-    for ch in line.chars() {
-        let _ = chars.push(ch);
-    }
+    let mut chars: Vec<char> = line.chars().collect();
     let mut idx = (*cursor_pos).min(chars.len());
     while idx > 0 && chars[idx - 1].is_ascii_whitespace() {
         idx -= 1;
@@ -487,24 +533,18 @@ fn delete_prev_word(line: &mut String<128>, cursor_pos: &mut usize) -> bool {
     if idx == *cursor_pos {
         return false;
     }
-    let remove_count = *cursor_pos - idx;
-    for _ in 0..remove_count {
-        chars.remove(idx);
-    }
-    line.clear();
-    for ch in chars.iter() {
-        let _ = line.push(*ch);
-    }
+    chars.drain(idx..*cursor_pos);
+    *line = chars.into_iter().collect();
     *cursor_pos = idx;
     true
 }
 
 #[allow(dead_code)]
-fn move_cursor_word_left(line: &String<128>, cursor_pos: &mut usize) -> bool {
+fn move_cursor_word_left(line: &str, cursor_pos: &mut usize) -> bool {
     if *cursor_pos == 0 {
         return false;
     }
-    let chars: Vec<char, 128> = line.chars().collect();
+    let chars: Vec<char> = line.chars().collect();
     let mut idx = (*cursor_pos).min(chars.len());
     while idx > 0 && chars[idx - 1].is_ascii_whitespace() {
         idx -= 1;
@@ -520,8 +560,8 @@ fn move_cursor_word_left(line: &String<128>, cursor_pos: &mut usize) -> bool {
 }
 
 #[allow(dead_code)]
-fn move_cursor_word_right(line: &String<128>, cursor_pos: &mut usize) -> bool {
-    let chars: Vec<char, 128> = line.chars().collect();
+fn move_cursor_word_right(line: &str, cursor_pos: &mut usize) -> bool {
+    let chars: Vec<char> = line.chars().collect();
     if *cursor_pos >= chars.len() {
         return false;
     }
@@ -541,25 +581,21 @@ fn move_cursor_word_right(line: &String<128>, cursor_pos: &mut usize) -> bool {
 
 #[allow(dead_code)]
 fn set_input_line(
-    line: &mut String<128>,
+    line: &mut String,
     new_content: &str,
     cursor_pos: &mut usize,
     origin: (usize, usize),
     rendered_len: &mut usize,
 ) {
     line.clear();
-    for ch in new_content.chars() {
-        if line.push(ch).is_err() {
-            break;
-        }
-    }
+    line.push_str(new_content);
     *cursor_pos = line.chars().count();
     redraw_input_line(line, *cursor_pos, origin, rendered_len, None);
 }
 
 #[allow(dead_code)]
 fn redraw_input_line(
-    line: &String<128>,
+    line: &str,
     cursor_pos: usize,
     origin: (usize, usize),
     rendered_len: &mut usize,
@@ -582,10 +618,20 @@ fn redraw_input_line(
         .as_ref()
         .and_then(|s| s.as_str().get(line.len()..));
 
-    let new_len = console::render_line_at(origin.0, origin.1, line.as_str(), *rendered_len, cursor_pos, selection, suggestion_suffix);
+    let new_len = console::render_line_at(origin.0, origin.1, line, *rendered_len, cursor_pos, selection, suggestion_suffix);
     *rendered_len = new_len;
 }
 
+// Shows a Ctrl+R search in place of the input line.
+fn render_search(search: &history::Search, origin: (usize, usize), rendered_len: &mut usize) {
+    let mut text = search.label();
+    if let Some(found) = search.matched() {
+        text.push_str(&found);
+    }
+    let cursor = text.chars().count();
+    *rendered_len = console::render_line_at(origin.0, origin.1, &text, *rendered_len, cursor, None, None);
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     with_console(|c| {
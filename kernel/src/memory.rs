@@ -7,6 +7,9 @@ use linked_list_allocator::{Heap, LockedHeap};
 use spin::Mutex;
 use core::{sync::atomic::{AtomicBool, AtomicU64, Ordering}, fmt};
 use heapless::String as HString;
+use alloc::vec::Vec;
+use x86_64::structures::paging::{PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
 
 #[global_allocator]
 static ALLOCATOR: LockedHeap = LockedHeap::empty();
@@ -15,6 +18,8 @@ pub const HEAP_SIZE: usize = 32 * 1024 * 1024;
 static mut HEAP: MaybeUninit<[u8; HEAP_SIZE]> = MaybeUninit::uninit();
 static PHYS_OFFSET_VALID: AtomicBool = AtomicBool::new(false);
 static PHYS_OFFSET: AtomicU64 = AtomicU64::new(0);
+// End of the highest region in the boot memory map; the offset mapping covers everything below.
+static PHYS_LIMIT: AtomicU64 = AtomicU64::new(0);
 
 pub unsafe fn init_heap() {
     let heap_ptr = addr_of_mut!(HEAP) as *mut u8;
@@ -120,8 +125,11 @@ pub fn init_memory(boot_info: &BootInfo) {
         .map(|r| (r.end - r.start) as usize)
         .sum();
     unsafe { TOTAL_RAM = total; }
+    let limit = boot_info.memory_regions.iter().map(|r| r.end).max().unwrap_or(0);
+    PHYS_LIMIT.store(limit, Ordering::Relaxed);
     unsafe { init_heap(); }
     init_user_arena();
+    init_frames(boot_info);
     if let Some(off) = boot_info.physical_memory_offset.into_option() {
         PHYS_OFFSET.store(off, Ordering::Relaxed);
         PHYS_OFFSET_VALID.store(true, Ordering::Relaxed);
@@ -134,6 +142,120 @@ pub fn init_memory(boot_info: &BootInfo) {
     }
 }
 
+pub fn phys_offset() -> Option<u64> {
+    if PHYS_OFFSET_VALID.load(Ordering::Relaxed) {
+        Some(PHYS_OFFSET.load(Ordering::Relaxed))
+    } else {
+        None
+    }
+}
+
+pub fn phys_limit() -> u64 {
+    PHYS_LIMIT.load(Ordering::Relaxed)
+}
+
+pub fn phys_to_virt(addr: PhysAddr) -> Option<VirtAddr> {
+    phys_offset().map(|off| VirtAddr::new(off + addr.as_u64()))
+}
+
+const FRAME_SIZE: u64 = 4096;
+const MAX_FRAME_REGIONS: usize = 64;
+const LOW_MEMORY_LIMIT: u64 = 0x10_0000;
+
+struct FrameTable {
+    regions: [(u64, u64); MAX_FRAME_REGIONS],
+    region_count: usize,
+    region_idx: usize,
+    next: u64,
+    free: Vec<u64>,
+    total: usize,
+    used: usize,
+}
+
+static FRAMES: Mutex<FrameTable> = Mutex::new(FrameTable {
+    regions: [(0, 0); MAX_FRAME_REGIONS],
+    region_count: 0,
+    region_idx: 0,
+    next: 0,
+    free: Vec::new(),
+    total: 0,
+    used: 0,
+});
+
+#[derive(Copy, Clone, Default)]
+pub struct FrameStats {
+    pub total: usize,
+    pub used: usize,
+}
+
+fn init_frames(boot_info: &BootInfo) {
+    let mut t = FRAMES.lock();
+    t.region_count = 0;
+    t.total = 0;
+    for r in boot_info.memory_regions.iter() {
+        if r.kind != MemoryRegionKind::Usable {
+            continue;
+        }
+        let start = (r.start.max(LOW_MEMORY_LIMIT) + FRAME_SIZE - 1) & !(FRAME_SIZE - 1);
+        let end = r.end & !(FRAME_SIZE - 1);
+        if end <= start || t.region_count >= MAX_FRAME_REGIONS {
+            continue;
+        }
+        let idx = t.region_count;
+        t.regions[idx] = (start, end);
+        t.region_count += 1;
+        t.total += ((end - start) / FRAME_SIZE) as usize;
+    }
+    t.region_idx = 0;
+    t.next = if t.region_count > 0 { t.regions[0].0 } else { 0 };
+}
+
+/// Hands out a zeroed 4 KiB physical frame. Kernel heap memory lives in the
+/// image itself, so these frames are only used for user address spaces.
+pub fn alloc_frame() -> Option<PhysFrame<Size4KiB>> {
+    let offset = phys_offset()?;
+    let addr = {
+        let mut t = FRAMES.lock();
+        let addr = if let Some(addr) = t.free.pop() {
+            addr
+        } else {
+            loop {
+                if t.region_idx >= t.region_count {
+                    return None;
+                }
+                let (_, end) = t.regions[t.region_idx];
+                if t.next + FRAME_SIZE <= end {
+                    let addr = t.next;
+                    t.next += FRAME_SIZE;
+                    break addr;
+                }
+                t.region_idx += 1;
+                if t.region_idx < t.region_count {
+                    t.next = t.regions[t.region_idx].0;
+                }
+            }
+        };
+        t.used += 1;
+        addr
+    };
+    let virt = VirtAddr::new(offset + addr);
+    unsafe {
+        core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, FRAME_SIZE as usize);
+    }
+    Some(PhysFrame::containing_address(PhysAddr::new(addr)))
+}
+
+pub fn free_frame(frame: PhysFrame<Size4KiB>) {
+    let mut t = FRAMES.lock();
+    t.used = t.used.saturating_sub(1);
+    t.free.push(frame.start_address().as_u64());
+}
+
+pub fn frame_stats() -> FrameStats {
+    let t = FRAMES.lock();
+    FrameStats { total: t.total, used: t.used }
+}
+
 pub type AppId = u32;
 
 pub const USER_ARENA_SIZE: usize = 1024 * 1024;
@@ -378,6 +500,7 @@ pub struct MemoryOverview {
     pub user_arena_total: usize,
     pub user_arena_free_for_new_regions: usize,
     pub apps: [Option<(AppId, AppHeapStats)>; MAX_APPS],
+    pub frames: FrameStats,
     pub display: Option<console::DisplayBufferStats>,
 }
 
@@ -401,6 +524,7 @@ pub fn memory_overview() -> MemoryOverview {
         user_arena_total: t.arena_size,
         user_arena_free_for_new_regions: t.arena_free_for_new_regions(),
         apps,
+        frames: frame_stats(),
         display: console::display_buffer_stats(),
     }
 }
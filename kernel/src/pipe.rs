@@ -0,0 +1,103 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::task;
+
+// Bytes a pipe holds before a writer has to wait for its reader.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+struct PipeState {
+    buf: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+}
+
+pub struct PipeReader {
+    state: Arc<Mutex<PipeState>>,
+}
+
+pub struct PipeWriter {
+    state: Arc<Mutex<PipeState>>,
+}
+
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let state = Arc::new(Mutex::new(PipeState {
+        buf: VecDeque::new(),
+        readers: 1,
+        writers: 1,
+    }));
+    (PipeReader { state: state.clone() }, PipeWriter { state })
+}
+
+impl PipeReader {
+    /// Blocks until data is available or every writer is gone; an empty result means EOF.
+    pub fn read(&self, max: usize) -> Vec<u8> {
+        loop {
+            {
+                let mut state = self.state.lock();
+                if !state.buf.is_empty() || state.writers == 0 || max == 0 {
+                    let take = max.min(state.buf.len());
+                    return state.buf.drain(..take).collect();
+                }
+            }
+            // With no other task to run, no writer can add anything.
+            if !task::yield_now() {
+                return Vec::new();
+            }
+        }
+    }
+}
+
+impl PipeWriter {
+    /// Blocks until all of `data` is in the pipe, letting the reader drain it as needed.
+    /// Fails once every reader is gone; whatever went in before that counts as written.
+    pub fn write(&self, data: &[u8]) -> Result<usize, &'static str> {
+        let mut written = 0;
+        loop {
+            {
+                let mut state = self.state.lock();
+                if state.readers == 0 {
+                    return if written > 0 { Ok(written) } else { Err("Broken pipe.") };
+                }
+                let take = PIPE_CAPACITY.saturating_sub(state.buf.len()).min(data.len() - written);
+                state.buf.extend(data[written..written + take].iter().copied());
+                written += take;
+                if written == data.len() {
+                    return Ok(written);
+                }
+            }
+            // With no other task to run, nothing will ever make room.
+            if !task::yield_now() {
+                return if written > 0 { Ok(written) } else { Err("Pipe buffer full.") };
+            }
+        }
+    }
+}
+
+impl Clone for PipeReader {
+    fn clone(&self) -> Self {
+        self.state.lock().readers += 1;
+        PipeReader { state: self.state.clone() }
+    }
+}
+
+impl Clone for PipeWriter {
+    fn clone(&self) -> Self {
+        self.state.lock().writers += 1;
+        PipeWriter { state: self.state.clone() }
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.state.lock().readers -= 1;
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.state.lock().writers -= 1;
+    }
+}
@@ -0,0 +1,539 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use core::sync::atomic::{AtomicU32, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::registers::control::{Cr3, Cr3Flags};
+use x86_64::structures::paging::{
+    FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+    Translate,
+};
+use x86_64::structures::paging::mapper::TranslateResult;
+use x86_64::VirtAddr;
+
+use crate::fd::{self, FileDesc, ERR_BAD_FD};
+use crate::{console, memory, serial};
+
+pub const PAGE_SIZE: u64 = 4096;
+// User space is the first level-4 slot; the bootloader never places kernel mappings there.
+pub const USER_SPACE_START: u64 = 0x0000_0000_0040_0000;
+pub const USER_SPACE_END: u64 = 0x0000_0080_0000_0000;
+pub const USER_STACK_TOP: u64 = 0x0000_007F_FFFF_0000;
+pub const USER_STACK_PAGES: u64 = 16;
+pub const USER_MMAP_BASE: u64 = 0x0000_0010_0000_0000;
+const USER_P4_INDEX: usize = 0;
+
+pub const FAULT_EXIT_CODE: i64 = -1;
+const MAX_FDS: usize = 32;
+
+static NEXT_PID: AtomicU32 = AtomicU32::new(1);
+static CURRENT: Mutex<Option<Process>> = Mutex::new(None);
+static KERNEL_P4: Mutex<Option<PhysFrame>> = Mutex::new(None);
+
+static mut KERNEL_RETURN_RSP: u64 = 0;
+static mut USER_SLOT_FREE: bool = false;
+
+extern "C" {
+    fn axiomata_enter_user(entry: u64, stack: u64) -> i64;
+    fn axiomata_return_to_kernel(code: i64) -> !;
+}
+
+// enter_user saves the callee-saved registers and the kernel stack pointer, then drops to
+// ring 3 with SYSRET. return_to_kernel unwinds back to that point from exit() or a user fault.
+global_asm!(
+    ".global axiomata_enter_user",
+    "axiomata_enter_user:",
+    "    cli",
+    "    push rbx",
+    "    push rbp",
+    "    push r12",
+    "    push r13",
+    "    push r14",
+    "    push r15",
+    "    mov [rip + {ret_rsp}], rsp",
+    "    mov rcx, rdi",
+    "    mov rsp, rsi",
+    "    mov r11, 0x202",
+    "    xor eax, eax",
+    "    xor ebx, ebx",
+    "    xor edx, edx",
+    "    xor esi, esi",
+    "    xor edi, edi",
+    "    xor ebp, ebp",
+    "    xor r8d, r8d",
+    "    xor r9d, r9d",
+    "    xor r10d, r10d",
+    "    xor r12d, r12d",
+    "    xor r13d, r13d",
+    "    xor r14d, r14d",
+    "    xor r15d, r15d",
+    "    sysretq",
+    "",
+    ".global axiomata_return_to_kernel",
+    "axiomata_return_to_kernel:",
+    "    cli",
+    "    mov rax, rdi",
+    "    mov rsp, [rip + {ret_rsp}]",
+    "    pop r15",
+    "    pop r14",
+    "    pop r13",
+    "    pop r12",
+    "    pop rbp",
+    "    pop rbx",
+    "    ret",
+    ret_rsp = sym KERNEL_RETURN_RSP,
+);
+
+struct TrackingAllocator<'a> {
+    frames: &'a mut Vec<PhysFrame>,
+}
+
+unsafe impl FrameAllocator<Size4KiB> for TrackingAllocator<'_> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        let frame = memory::alloc_frame()?;
+        self.frames.push(frame);
+        Some(frame)
+    }
+}
+
+pub struct AddressSpace {
+    p4: PhysFrame,
+    frames: Vec<PhysFrame>,
+}
+
+impl AddressSpace {
+    fn new() -> Result<Self, &'static str> {
+        let kernel_p4 = kernel_p4();
+        let p4 = memory::alloc_frame().ok_or("Out of physical memory.")?;
+        let src = table_ptr(kernel_p4).ok_or("Physical memory offset unavailable.")?;
+        let dst = table_ptr(p4).ok_or("Physical memory offset unavailable.")?;
+        unsafe {
+            for i in 0..512 {
+                if i == USER_P4_INDEX {
+                    continue;
+                }
+                (&mut *dst)[i] = (&*src)[i].clone();
+            }
+        }
+        Ok(Self { p4, frames: Vec::new() })
+    }
+
+    fn mapper(&self) -> Option<OffsetPageTable<'static>> {
+        let offset = memory::phys_offset()?;
+        let table = table_ptr(self.p4)?;
+        Some(unsafe { OffsetPageTable::new(&mut *table, VirtAddr::new(offset)) })
+    }
+
+    pub fn map(&mut self, addr: u64, len: u64, flags: PageTableFlags) -> Result<(), &'static str> {
+        if len == 0 {
+            return Ok(());
+        }
+        let end = addr.checked_add(len).ok_or("Mapping out of range.")?;
+        if addr < USER_SPACE_START || end > USER_SPACE_END {
+            return Err("Mapping outside user space.");
+        }
+        let mut mapper = self.mapper().ok_or("Physical memory offset unavailable.")?;
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        let first = Page::<Size4KiB>::containing_address(VirtAddr::new(addr));
+        let last = Page::<Size4KiB>::containing_address(VirtAddr::new(end - 1));
+        let active = self.is_active();
+        for page in Page::range_inclusive(first, last) {
+            if let TranslateResult::Mapped { .. } = mapper.translate(page.start_address()) {
+                continue;
+            }
+            let frame = memory::alloc_frame().ok_or("Out of physical memory.")?;
+            self.frames.push(frame);
+            let mut alloc = TrackingAllocator { frames: &mut self.frames };
+            let parent = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+            let flush = unsafe { mapper.map_to_with_table_flags(page, frame, flags, parent, &mut alloc) }
+                .map_err(|_| "Failed to map user page.")?;
+            if active {
+                flush.flush();
+            } else {
+                flush.ignore();
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the permission flags of already-mapped pages in `[addr, addr + len)`.
+    pub fn protect(&mut self, addr: u64, len: u64, flags: PageTableFlags) -> Result<(), &'static str> {
+        if len == 0 {
+            return Ok(());
+        }
+        let end = addr.checked_add(len).ok_or("Mapping out of range.")?;
+        if addr < USER_SPACE_START || end > USER_SPACE_END {
+            return Err("Mapping outside user space.");
+        }
+        let mut mapper = self.mapper().ok_or("Physical memory offset unavailable.")?;
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        let first = Page::<Size4KiB>::containing_address(VirtAddr::new(addr));
+        let last = Page::<Size4KiB>::containing_address(VirtAddr::new(end - 1));
+        let active = self.is_active();
+        for page in Page::range_inclusive(first, last) {
+            let flush = unsafe { mapper.update_flags(page, flags) }
+                .map_err(|_| "Page is not mapped.")?;
+            if active {
+                flush.flush();
+            } else {
+                flush.ignore();
+            }
+        }
+        Ok(())
+    }
+
+    fn user_frame_ptr(&self, addr: u64, write: bool) -> Option<*mut u8> {
+        if !(USER_SPACE_START..USER_SPACE_END).contains(&addr) {
+            return None;
+        }
+        let mapper = self.mapper()?;
+        match mapper.translate(VirtAddr::new(addr)) {
+            TranslateResult::Mapped { frame, offset, flags } => {
+                if !flags.contains(PageTableFlags::USER_ACCESSIBLE) {
+                    return None;
+                }
+                if write && !flags.contains(PageTableFlags::WRITABLE) {
+                    return None;
+                }
+                let virt = memory::phys_to_virt(frame.start_address() + offset)?;
+                Some(virt.as_mut_ptr())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn write(&mut self, addr: u64, data: &[u8]) -> Result<(), &'static str> {
+        self.copy_out(addr, data, false)
+    }
+
+    fn copy_out(&mut self, addr: u64, data: &[u8], check_writable: bool) -> Result<(), &'static str> {
+        let mut done = 0usize;
+        while done < data.len() {
+            let cur = addr.checked_add(done as u64).ok_or("Bad user address.")?;
+            let in_page = (PAGE_SIZE - (cur % PAGE_SIZE)) as usize;
+            let chunk = in_page.min(data.len() - done);
+            let ptr = self.user_frame_ptr(cur, check_writable).ok_or("Bad user address.")?;
+            unsafe {
+                core::ptr::copy_nonoverlapping(data[done..].as_ptr(), ptr, chunk);
+            }
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    fn copy_in(&self, addr: u64, len: usize) -> Result<Vec<u8>, &'static str> {
+        let mut out = Vec::with_capacity(len);
+        let mut done = 0usize;
+        while done < len {
+            let cur = addr.checked_add(done as u64).ok_or("Bad user address.")?;
+            let in_page = (PAGE_SIZE - (cur % PAGE_SIZE)) as usize;
+            let chunk = in_page.min(len - done);
+            let ptr = self.user_frame_ptr(cur, false).ok_or("Bad user address.")?;
+            unsafe {
+                out.extend_from_slice(core::slice::from_raw_parts(ptr, chunk));
+            }
+            done += chunk;
+        }
+        Ok(out)
+    }
+
+    fn is_active(&self) -> bool {
+        Cr3::read().0 == self.p4
+    }
+
+    fn activate(&self) {
+        unsafe { Cr3::write(self.p4, Cr3Flags::empty()) };
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        if self.is_active() {
+            unsafe { Cr3::write(kernel_p4(), Cr3Flags::empty()) };
+        }
+        for frame in self.frames.drain(..) {
+            memory::free_frame(frame);
+        }
+        memory::free_frame(self.p4);
+    }
+}
+
+fn table_ptr(frame: PhysFrame) -> Option<*mut PageTable> {
+    let virt = memory::phys_to_virt(frame.start_address())?;
+    Some(virt.as_mut_ptr())
+}
+
+fn kernel_p4() -> PhysFrame {
+    let mut guard = KERNEL_P4.lock();
+    *guard.get_or_insert_with(|| Cr3::read().0)
+}
+
+pub fn init() {
+    let p4 = kernel_p4();
+    let free = match table_ptr(p4) {
+        Some(table) => unsafe { (&*table)[USER_P4_INDEX].is_unused() },
+        None => false,
+    };
+    unsafe { USER_SLOT_FREE = free; }
+    if !free {
+        serial::write("process: user address slot unavailable; user mode disabled");
+    }
+}
+
+pub fn is_available() -> bool {
+    unsafe { USER_SLOT_FREE }
+}
+
+pub struct Process {
+    pub pid: u32,
+    pub name: String,
+    pub space: AddressSpace,
+    pub files: Vec<Option<FileDesc>>,
+    pub entry: u64,
+    pub stack_pointer: u64,
+    pub mmap_next: u64,
+    pub stdin_pending: Vec<u8>,
+}
+
+impl Process {
+    pub fn new(name: &str) -> Result<Self, &'static str> {
+        if !is_available() {
+            return Err("User mode is unavailable.");
+        }
+        let space = AddressSpace::new()?;
+        let files = fd::inherited_stdio().into_iter().map(Some).collect();
+        Ok(Self {
+            pid: NEXT_PID.fetch_add(1, Ordering::Relaxed),
+            name: String::from(name),
+            space,
+            files,
+            entry: 0,
+            stack_pointer: 0,
+            mmap_next: USER_MMAP_BASE,
+            stdin_pending: Vec::new(),
+        })
+    }
+
+    pub fn map_stack(&mut self) -> Result<(), &'static str> {
+        let size = USER_STACK_PAGES * PAGE_SIZE;
+        let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        self.space.map(USER_STACK_TOP - size, size, flags)?;
+        // argc = 0 and a null argv terminator.
+        self.stack_pointer = USER_STACK_TOP - 16;
+        Ok(())
+    }
+
+    /// Lays out argc, argv, envp and auxv at the top of the stack the way the SysV ABI
+    /// expects, with the strings stored just above the vectors.
+    pub fn push_args(&mut self, argv: &[&str], envp: &[&str], auxv: &[(u64, u64)]) -> Result<(), &'static str> {
+        let stack_bottom = USER_STACK_TOP - USER_STACK_PAGES * PAGE_SIZE;
+        let mut sp = USER_STACK_TOP;
+        let mut push_str = |space: &mut AddressSpace, s: &str| -> Result<u64, &'static str> {
+            let len = s.len() as u64 + 1;
+            if sp - stack_bottom < len + PAGE_SIZE {
+                return Err("Arguments too large.");
+            }
+            sp -= len;
+            space.write(sp, s.as_bytes())?;
+            space.write(sp + s.len() as u64, &[0])?;
+            Ok(sp)
+        };
+        let mut env_ptrs = Vec::with_capacity(envp.len());
+        for s in envp {
+            env_ptrs.push(push_str(&mut self.space, s)?);
+        }
+        let mut arg_ptrs = Vec::with_capacity(argv.len());
+        for s in argv {
+            arg_ptrs.push(push_str(&mut self.space, s)?);
+        }
+
+        let mut words: Vec<u64> = Vec::new();
+        words.push(argv.len() as u64);
+        words.extend_from_slice(&arg_ptrs);
+        words.push(0);
+        words.extend_from_slice(&env_ptrs);
+        words.push(0);
+        for &(key, value) in auxv {
+            words.push(key);
+            words.push(value);
+        }
+
+        let size = (words.len() * 8) as u64;
+        let top = sp & !0xF;
+        if top - stack_bottom < size + PAGE_SIZE {
+            return Err("Arguments too large.");
+        }
+        let start = (top - size) & !0xF;
+        let mut bytes = Vec::with_capacity(words.len() * 8);
+        for word in &words {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        self.space.write(start, &bytes)?;
+        self.stack_pointer = start;
+        Ok(())
+    }
+
+    pub fn alloc_fd(&mut self, desc: FileDesc) -> Option<usize> {
+        if let Some(idx) = self.files.iter().position(|f| f.is_none()) {
+            self.files[idx] = Some(desc);
+            return Some(idx);
+        }
+        if self.files.len() >= MAX_FDS {
+            return None;
+        }
+        self.files.push(Some(desc));
+        Some(self.files.len() - 1)
+    }
+
+    pub fn close_fd(&mut self, fd: usize) -> Result<(), &'static str> {
+        let Some(slot) = self.files.get_mut(fd) else {
+            return Err(ERR_BAD_FD);
+        };
+        let Some(desc) = slot.take() else {
+            return Err(ERR_BAD_FD);
+        };
+        desc.close()
+    }
+
+    pub fn copy_from_user(&self, addr: u64, len: usize) -> Result<Vec<u8>, &'static str> {
+        self.space.copy_in(addr, len)
+    }
+
+    pub fn copy_to_user(&mut self, addr: u64, data: &[u8]) -> Result<(), &'static str> {
+        self.space.copy_out(addr, data, true)
+    }
+
+    fn close_all(&mut self) {
+        for fd in 0..self.files.len() {
+            let _ = self.close_fd(fd);
+        }
+    }
+}
+
+pub fn with_current<T>(f: impl FnOnce(&mut Process) -> T) -> Option<T> {
+    let mut guard = CURRENT.lock();
+    guard.as_mut().map(f)
+}
+
+pub fn current_pid() -> Option<u32> {
+    CURRENT.lock().as_ref().map(|p| p.pid)
+}
+
+pub fn is_running() -> bool {
+    CURRENT.lock().is_some()
+}
+
+/// The process a task is running and where its kernel side resumes when that process
+/// ends, set aside while another task runs.
+#[derive(Default)]
+pub struct TaskState {
+    current: Option<Process>,
+    return_rsp: u64,
+}
+
+pub fn switch_out() -> TaskState {
+    TaskState { current: CURRENT.lock().take(), return_rsp: unsafe { KERNEL_RETURN_RSP } }
+}
+
+/// Puts a task's process back, along with its address space.
+pub fn switch_in(state: TaskState) {
+    let p4 = state.current.as_ref().map_or_else(kernel_p4, |process| process.space.p4);
+    if Cr3::read().0 != p4 {
+        unsafe { Cr3::write(p4, Cr3Flags::empty()) };
+    }
+    unsafe { KERNEL_RETURN_RSP = state.return_rsp };
+    *CURRENT.lock() = state.current;
+}
+
+/// Runs `process` in ring 3 until it exits or faults and returns its exit code.
+/// Processes run in the foreground: the caller blocks until the program is gone.
+pub fn run(process: Process) -> Result<i64, &'static str> {
+    if is_running() {
+        return Err("A user process is already running.");
+    }
+    if process.entry == 0 || process.stack_pointer == 0 {
+        return Err("Process image is not loaded.");
+    }
+    let entry = process.entry;
+    let stack = process.stack_pointer;
+    let were_enabled = interrupts::are_enabled();
+    {
+        let mut guard = CURRENT.lock();
+        process.space.activate();
+        *guard = Some(process);
+    }
+
+    let code = unsafe { axiomata_enter_user(entry, stack) };
+
+    unsafe { Cr3::write(kernel_p4(), Cr3Flags::empty()) };
+    let finished = CURRENT.lock().take();
+    if let Some(mut process) = finished {
+        process.close_all();
+    }
+    if were_enabled {
+        interrupts::enable();
+    }
+    Ok(code)
+}
+
+/// Leaves the current process for good; only valid from syscall or fault context.
+pub fn exit_current(code: i64) -> ! {
+    unsafe { axiomata_return_to_kernel(code) }
+}
+
+pub fn fault_current(msg: &str, rip: u64) -> ! {
+    let (pid, name) = with_current(|process| (process.pid, process.name.clone())).unwrap_or_default();
+    console::write_line(&alloc::format!(
+        "Process {} ({}) killed: {} at {:#x}",
+        pid, name, msg, rip
+    ));
+    exit_current(FAULT_EXIT_CODE)
+}
+
+pub fn is_user_mode(code_segment: u64) -> bool {
+    code_segment & 3 == 3
+}
+
+// A tiny position-independent ring 3 program used by `debug usermode` to exercise
+// SYSRET, the syscall path and the fault-free exit path without a loader.
+global_asm!(
+    ".pushsection .rodata",
+    ".global axiomata_usertest_start",
+    ".global axiomata_usertest_end",
+    "axiomata_usertest_start:",
+    "    lea rsi, [rip + 2f]",
+    "    mov edi, 1",
+    "    mov edx, 19",
+    "    mov eax, 1",
+    "    syscall",
+    "    mov eax, 6",
+    "    syscall",
+    "    mov rdi, rax",
+    "    mov eax, 0",
+    "    syscall",
+    "    ud2",
+    "2:",
+    "    .ascii \"Hello from ring 3!\\n\"",
+    "axiomata_usertest_end:",
+    ".popsection",
+);
+
+extern "C" {
+    static axiomata_usertest_start: u8;
+    static axiomata_usertest_end: u8;
+}
+
+pub fn run_selftest() -> Result<i64, &'static str> {
+    let code = unsafe {
+        let start = core::ptr::addr_of!(axiomata_usertest_start);
+        let end = core::ptr::addr_of!(axiomata_usertest_end);
+        core::slice::from_raw_parts(start, end as usize - start as usize)
+    };
+    let mut process = Process::new("usertest")?;
+    process.space.map(USER_SPACE_START, code.len() as u64, PageTableFlags::empty())?;
+    process.space.write(USER_SPACE_START, code)?;
+    process.map_stack()?;
+    process.entry = USER_SPACE_START;
+    run(process)
+}
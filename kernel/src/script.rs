@@ -0,0 +1,610 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use crate::lexer::{self, Kind};
+use crate::{console, editor, env, forth, fs, shell, users};
+
+/// Script run once at boot, before the console or desktop starts.
+const AUTOEXEC_PATH: &str = "\\etc\\autoexec.sh";
+
+// Scripts and functions recurse through `commands::handle_command` on the kernel stack.
+const MAX_DEPTH: usize = 8;
+
+enum Node {
+    List(String),
+    If { branches: Vec<(String, Vec<Node>)>, otherwise: Vec<Node> },
+    While { cond: String, body: Vec<Node> },
+    For { var: String, words: String, body: Vec<Node> },
+    Function { name: String, body: Arc<Vec<Node>> },
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Flow {
+    Break,
+    Continue,
+    Return,
+    Exit,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Seq,
+    And,
+    Or,
+}
+
+#[derive(Default)]
+struct State {
+    depth: usize,
+    loops: usize,
+    flow: Option<Flow>,
+    // `$?` as it was before the current command reset it, for a bare `return`/`exit`.
+    prev_status: i64,
+}
+
+static STATE: Mutex<State> = Mutex::new(State { depth: 0, loops: 0, flow: None, prev_status: 0 });
+
+/// A task's script nesting and pending `break`/`return`, set aside while another task
+/// runs. A new task starts outside any script, as a subshell would.
+#[derive(Default)]
+pub struct TaskState(State);
+
+pub fn switch_out() -> TaskState {
+    TaskState(core::mem::take(&mut *STATE.lock()))
+}
+
+pub fn switch_in(state: TaskState) {
+    *STATE.lock() = state.0;
+}
+
+lazy_static! {
+    static ref FUNCTIONS: Mutex<BTreeMap<String, Arc<Vec<Node>>>> = Mutex::new(BTreeMap::new());
+}
+
+/// Runs one line typed at the prompt. It may hold a whole `if`/`while`/`for` block or a
+/// function definition as long as the block is closed on the same line.
+pub fn run_line(line: &str) {
+    match parse(line) {
+        Ok(nodes) => exec(&nodes),
+        Err((_, e)) => {
+            console::write_line(&format!("sh: {}", e));
+            env::set_status(2);
+        }
+    }
+    // `exit` or `return` typed outside any script has nothing to unwind.
+    STATE.lock().flow = None;
+}
+
+/// `source <file> [args]` / `sh <file> [args]`: runs a script with `$1`.. set to `args`.
+/// `exit` and `return` end the script.
+pub fn source_command(args: &[&str]) {
+    let Some(path) = args.first() else {
+        console::write_line("Usage: source <file> [args]");
+        env::set_status(2);
+        return;
+    };
+    let Some(text) = fs::read_file(path) else {
+        console::write_line(&format!("sh: {}: File not found.", path));
+        env::set_status(127);
+        return;
+    };
+    let nodes = match parse(&text) {
+        Ok(nodes) => nodes,
+        Err((line, e)) => {
+            console::write_line(&format!("sh: {}:{}: {}", path, line, e));
+            env::set_status(2);
+            return;
+        }
+    };
+    frame(args, |flow| matches!(flow, Flow::Return | Flow::Exit), || exec(&nodes));
+}
+
+/// Runs `\etc\autoexec.sh` as root. Once there are accounts it must be root's alone, or
+/// whoever could edit it would get their commands run as root.
+pub fn run_autoexec() {
+    if !fs::exists(AUTOEXEC_PATH) {
+        return;
+    }
+    if users::accounts_enabled() && !fs::root_only(AUTOEXEC_PATH) {
+        console::write_line("sh: \\etc\\autoexec.sh is writable by users other than root; not running it.");
+        return;
+    }
+    source_command(&[AUTOEXEC_PATH]);
+}
+
+/// Calls a shell function if one named `name` exists. `args[0]` is the function name.
+pub fn call_function(args: &[&str]) -> bool {
+    let body = match args.first() {
+        Some(name) => FUNCTIONS.lock().get(*name).cloned(),
+        None => None,
+    };
+    let Some(body) = body else { return false; };
+    frame(args, |flow| flow == Flow::Return, || exec(&body));
+    true
+}
+
+pub fn function_names() -> Vec<String> {
+    FUNCTIONS.lock().keys().cloned().collect()
+}
+
+/// `break`, `continue`, `return [n]` and `exit [n]`.
+pub fn control_command(command: &str, args: &[&str]) {
+    let mut state = STATE.lock();
+    let flow = match command {
+        "break" if state.loops > 0 => Flow::Break,
+        "continue" if state.loops > 0 => Flow::Continue,
+        "break" | "continue" => {
+            drop(state);
+            console::write_line(&format!("{}: only meaningful in a loop", command));
+            env::set_status(1);
+            return;
+        }
+        "return" if state.depth > 0 => Flow::Return,
+        "exit" if state.depth > 0 => Flow::Exit,
+        _ => {
+            drop(state);
+            console::write_line(&format!("{}: not running a script or function", command));
+            env::set_status(1);
+            return;
+        }
+    };
+    let code = match args.first() {
+        Some(arg) => match arg.parse::<i64>() {
+            Ok(code) => code,
+            Err(_) => {
+                drop(state);
+                console::write_line(&format!("{}: {}: numeric argument required", command, arg));
+                env::set_status(2);
+                return;
+            }
+        },
+        None if matches!(flow, Flow::Return | Flow::Exit) => state.prev_status,
+        None => 0,
+    };
+    state.flow = Some(flow);
+    drop(state);
+    env::set_status(code);
+}
+
+/// `test <expr>` / `[ <expr> ]`: string, integer and file checks. Returns the exit status.
+pub fn test_command(command: &str, args: &[&str]) -> i64 {
+    let args = if command == "[" {
+        match args.split_last() {
+            Some((&"]", rest)) => rest,
+            _ => {
+                console::write_line("[: missing ']'");
+                return 2;
+            }
+        }
+    } else {
+        args
+    };
+    let (negate, args) = match args.split_first() {
+        Some((&"!", rest)) => (true, rest),
+        _ => (false, args),
+    };
+    let result = match *args {
+        [] => Ok(false),
+        [s] => Ok(!s.is_empty()),
+        ["-n", s] => Ok(!s.is_empty()),
+        ["-z", s] => Ok(s.is_empty()),
+        ["-e", path] => Ok(fs::exists(path) || fs::list_dir(path).is_ok()),
+        ["-d", path] => Ok(fs::list_dir(path).is_ok()),
+        ["-f", path] => Ok(fs::exists(path) && fs::list_dir(path).is_err()),
+        [a, "=", b] | [a, "==", b] => Ok(a == b),
+        [a, "!=", b] => Ok(a != b),
+        [a, op, b] => compare(a, op, b),
+        _ => Err("too many arguments"),
+    };
+    match result {
+        Ok(value) => {
+            if value != negate {
+                0
+            } else {
+                1
+            }
+        }
+        Err(e) => {
+            console::write_line(&format!("{}: {}", command, e));
+            2
+        }
+    }
+}
+
+fn compare(a: &str, op: &str, b: &str) -> Result<bool, &'static str> {
+    let (Ok(a), Ok(b)) = (a.parse::<i64>(), b.parse::<i64>()) else {
+        return Err("integer expression expected");
+    };
+    match op {
+        "-eq" => Ok(a == b),
+        "-ne" => Ok(a != b),
+        "-lt" => Ok(a < b),
+        "-le" => Ok(a <= b),
+        "-gt" => Ok(a > b),
+        "-ge" => Ok(a >= b),
+        _ => Err("unknown operator"),
+    }
+}
+
+// Runs `f` as a new script or function invocation with its own positional parameters.
+// `catches` decides which pending `return`/`exit` stops here instead of unwinding further.
+fn frame(args: &[&str], catches: impl Fn(Flow) -> bool, f: impl FnOnce()) {
+    let saved_loops = {
+        let mut state = STATE.lock();
+        if state.depth >= MAX_DEPTH {
+            drop(state);
+            console::write_line("sh: maximum script nesting depth exceeded");
+            env::set_status(1);
+            return;
+        }
+        state.depth += 1;
+        core::mem::replace(&mut state.loops, 0)
+    };
+    env::push_args(args.iter().map(|a| a.to_string()).collect());
+
+    f();
+
+    env::pop_args();
+    let mut state = STATE.lock();
+    state.depth -= 1;
+    state.loops = saved_loops;
+    if state.flow.is_some_and(catches) {
+        state.flow = None;
+    }
+}
+
+// A pending `break`/`continue`/`return`/`exit`, or an editor or Forth session that took
+// over the console, stops the rest of the block.
+fn interrupted() -> bool {
+    STATE.lock().flow.is_some() || editor::is_active() || forth::is_active()
+}
+
+fn exec(nodes: &[Node]) {
+    for node in nodes {
+        match node {
+            Node::List(line) => run_list(line),
+            Node::If { branches, otherwise } => {
+                let mut taken = false;
+                for (cond, body) in branches {
+                    run_list(cond);
+                    if interrupted() {
+                        return;
+                    }
+                    if env::last_status() == 0 {
+                        exec(body);
+                        taken = true;
+                        break;
+                    }
+                }
+                if !taken {
+                    env::set_status(0);
+                    exec(otherwise);
+                }
+            }
+            Node::While { cond, body } => run_loop(|| {
+                run_list(cond);
+                if interrupted() {
+                    return false;
+                }
+                if env::last_status() != 0 {
+                    env::set_status(0);
+                    return false;
+                }
+                exec(body);
+                true
+            }),
+            Node::For { var, words, body } => {
+                let words = match lexer::split(words) {
+                    Ok(words) => words,
+                    Err(e) => {
+                        console::write_line(&format!("for: {}", e));
+                        env::set_status(2);
+                        continue;
+                    }
+                };
+                let mut words = words.into_iter();
+                run_loop(|| {
+                    let Some(word) = words.next() else { return false; };
+                    if let Err(e) = env::set(var, &word, false) {
+                        console::write_line(&format!("for: {}", e));
+                        env::set_status(1);
+                        return false;
+                    }
+                    exec(body);
+                    true
+                });
+            }
+            Node::Function { name, body } => {
+                FUNCTIONS.lock().insert(name.clone(), body.clone());
+                env::set_status(0);
+            }
+        }
+        if interrupted() {
+            return;
+        }
+    }
+}
+
+// Calls `step` until it returns false, consuming `break` and `continue` on the way.
+fn run_loop(mut step: impl FnMut() -> bool) {
+    STATE.lock().loops += 1;
+    env::set_status(0);
+    loop {
+        let more = step();
+        let mut state = STATE.lock();
+        match state.flow {
+            Some(Flow::Continue) => state.flow = None,
+            Some(Flow::Break) => {
+                state.flow = None;
+                break;
+            }
+            Some(_) => break,
+            None => {}
+        }
+        drop(state);
+        if !more {
+            break;
+        }
+    }
+    STATE.lock().loops -= 1;
+}
+
+// Runs commands joined by `;`, `&&` and `||`. `! cmd` inverts the status of `cmd`.
+fn run_list(line: &str) {
+    for (op, segment) in split_list(line) {
+        let status = env::last_status();
+        let run = match op {
+            Op::Seq => true,
+            Op::And => status == 0,
+            Op::Or => status != 0,
+        };
+        if !run {
+            continue;
+        }
+        STATE.lock().prev_status = status;
+        match segment.strip_prefix('!').filter(|rest| rest.starts_with(char::is_whitespace)) {
+            Some(rest) => {
+                shell::run_pipeline(rest.trim());
+                env::set_status(if env::last_status() == 0 { 1 } else { 0 });
+            }
+            None => shell::run_pipeline(&segment),
+        }
+        if interrupted() {
+            return;
+        }
+    }
+}
+
+// Splits a command list at unquoted `;`, `&&` and `||`. Quotes stay for the lexer.
+fn split_list(line: &str) -> Vec<(Op, String)> {
+    let mut out = Vec::new();
+    let mut op = Op::Seq;
+    let mut start = 0;
+    let mut chars = lexer::scan(line);
+
+    while let Some((i, c, kind)) = chars.next() {
+        if kind != Kind::Live {
+            continue;
+        }
+        let (next_op, len) = match c {
+            ';' => (Op::Seq, 1),
+            '&' if line[i + 1..].starts_with('&') => (Op::And, 2),
+            '|' if line[i + 1..].starts_with('|') => (Op::Or, 2),
+            _ => continue,
+        };
+        if len == 2 {
+            chars.next();
+        }
+        let segment = line[start..i].trim();
+        if !segment.is_empty() {
+            out.push((op, segment.to_string()));
+        }
+        op = next_op;
+        start = i + len;
+    }
+
+    let segment = line[start..].trim();
+    if !segment.is_empty() {
+        out.push((op, segment.to_string()));
+    }
+    out
+}
+
+// Breaks script text into statements: one per line or `;`, with `#` comments removed.
+// Block keywords that share a statement with a command (`then echo hi`) are split off.
+fn statements(text: &str) -> Vec<(usize, String)> {
+    let mut out = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let mut start = 0;
+        let mut end = line.len();
+        let mut prev = ' ';
+        for (i, c, kind) in lexer::scan(line) {
+            if kind == Kind::Live {
+                if c == '#' && prev.is_whitespace() {
+                    end = i;
+                    break;
+                }
+                if c == ';' {
+                    push_statement(&mut out, number, &line[start..i]);
+                    start = i + 1;
+                }
+            }
+            prev = c;
+        }
+        push_statement(&mut out, number, &line[start..end]);
+    }
+    out
+}
+
+fn push_statement(out: &mut Vec<(usize, String)>, line: usize, text: &str) {
+    let mut text = text.trim();
+    loop {
+        if text.is_empty() {
+            return;
+        }
+        let (word, rest) = split_first_word(text);
+        let head_len = if matches!(word, "then" | "do" | "else" | "{") {
+            word.len()
+        } else if let Some(brace) = function_header(text) {
+            brace + 1
+        } else {
+            out.push((line, text.to_string()));
+            return;
+        };
+        if rest.is_empty() {
+            out.push((line, text.to_string()));
+            return;
+        }
+        out.push((line, text[..head_len].trim().to_string()));
+        text = text[head_len..].trim();
+    }
+}
+
+// For `name() { ...` or `function name { ...`, the index of the opening brace.
+fn function_header(text: &str) -> Option<usize> {
+    let brace = text.find('{')?;
+    let head = text[..brace].trim();
+    let name = head.strip_suffix("()").map(str::trim).or_else(|| head.strip_prefix("function "));
+    name.filter(|n| env::is_valid_name(n.trim())).map(|_| brace)
+}
+
+fn split_first_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim_start()),
+        None => (text, ""),
+    }
+}
+
+fn parse(text: &str) -> Result<Vec<Node>, (usize, &'static str)> {
+    let mut parser = Parser { stmts: statements(text), pos: 0 };
+    let (nodes, _) = parser.block(&[]).map_err(|e| (parser.line(), e))?;
+    Ok(nodes)
+}
+
+struct Parser {
+    stmts: Vec<(usize, String)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn line(&self) -> usize {
+        let index = self.pos.min(self.stmts.len()).saturating_sub(1);
+        self.stmts.get(index).map_or(0, |(line, _)| *line)
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let stmt = self.stmts.get(self.pos)?.1.clone();
+        self.pos += 1;
+        Some(stmt)
+    }
+
+    fn expect(&mut self, keyword: &str, err: &'static str) -> Result<(), &'static str> {
+        match self.next() {
+            Some(stmt) if stmt == keyword => Ok(()),
+            _ => Err(err),
+        }
+    }
+
+    // Parses statements until one starting with a word in `until`, which is returned.
+    fn block(&mut self, until: &[&str]) -> Result<(Vec<Node>, String), &'static str> {
+        let mut nodes = Vec::new();
+        while let Some(stmt) = self.next() {
+            let (word, rest) = split_first_word(&stmt);
+            if until.contains(&word) {
+                return Ok((nodes, stmt));
+            }
+            let node = match word {
+                "if" => self.parse_if(rest)?,
+                "while" => {
+                    let cond = require(rest, "Missing condition after 'while'.")?;
+                    self.expect("do", "Expected 'do' after 'while'.")?;
+                    let (body, _) = self.block_until("done", "Missing 'done'.")?;
+                    Node::While { cond, body }
+                }
+                "for" => self.parse_for(rest)?,
+                "then" | "do" | "done" | "else" | "elif" | "fi" | "{" | "}" => {
+                    return Err("Unexpected block keyword.");
+                }
+                _ => match self.parse_function(&stmt)? {
+                    Some(node) => node,
+                    None => Node::List(stmt),
+                },
+            };
+            nodes.push(node);
+        }
+        if until.is_empty() {
+            Ok((nodes, String::new()))
+        } else {
+            Err("Unexpected end of script.")
+        }
+    }
+
+    fn block_until(&mut self, keyword: &str, err: &'static str) -> Result<(Vec<Node>, String), &'static str> {
+        self.block(&[keyword]).map_err(|e| if e == "Unexpected end of script." { err } else { e })
+    }
+
+    fn parse_if(&mut self, cond: &str) -> Result<Node, &'static str> {
+        let mut branches = Vec::new();
+        let mut cond = require(cond, "Missing condition after 'if'.")?;
+        loop {
+            self.expect("then", "Expected 'then' after condition.")?;
+            let (body, end) = self
+                .block(&["elif", "else", "fi"])
+                .map_err(|e| if e == "Unexpected end of script." { "Missing 'fi'." } else { e })?;
+            branches.push((cond, body));
+            let (word, rest) = split_first_word(&end);
+            match word {
+                "elif" => cond = require(rest, "Missing condition after 'elif'.")?,
+                "else" => {
+                    let (otherwise, _) = self.block_until("fi", "Missing 'fi'.")?;
+                    return Ok(Node::If { branches, otherwise });
+                }
+                _ => return Ok(Node::If { branches, otherwise: Vec::new() }),
+            }
+        }
+    }
+
+    fn parse_for(&mut self, rest: &str) -> Result<Node, &'static str> {
+        let (var, rest) = split_first_word(rest);
+        let (keyword, words) = split_first_word(rest);
+        if !env::is_valid_name(var) || keyword != "in" {
+            return Err("Expected 'for <name> in <words>'.");
+        }
+        self.expect("do", "Expected 'do' after 'for'.")?;
+        let (body, _) = self.block_until("done", "Missing 'done'.")?;
+        Ok(Node::For { var: var.to_string(), words: words.to_string(), body })
+    }
+
+    fn parse_function(&mut self, stmt: &str) -> Result<Option<Node>, &'static str> {
+        let head = stmt.strip_suffix('{').unwrap_or(stmt).trim();
+        let name = match head.strip_suffix("()") {
+            Some(name) => name.trim(),
+            None => match head.strip_prefix("function ") {
+                Some(name) => name.trim(),
+                None => return Ok(None),
+            },
+        };
+        if !env::is_valid_name(name) {
+            return Ok(None);
+        }
+        if !stmt.ends_with('{') {
+            self.expect("{", "Expected '{' after function name.")?;
+        }
+        let (body, _) = self.block_until("}", "Missing '}'.")?;
+        Ok(Some(Node::Function { name: name.to_string(), body: Arc::new(body) }))
+    }
+}
+
+fn require(text: &str, err: &'static str) -> Result<String, &'static str> {
+    if text.is_empty() {
+        Err(err)
+    } else {
+        Ok(text.to_string())
+    }
+}
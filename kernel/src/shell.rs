@@ -0,0 +1,143 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::fd::{self, FileDesc, OpenFile, OpenOptions};
+use crate::lexer::{self, Token};
+use crate::{commands, console, env, pipe, task};
+
+#[derive(Default)]
+struct Stage {
+    args: Vec<String>,
+    input: Option<String>,
+    output: Option<Redirect>,
+}
+
+struct Redirect {
+    path: String,
+    append: bool,
+}
+
+/// Runs one command line segment: a `|` pipeline whose stages may redirect with `<`, `>`
+/// and `>>`. Stages run side by side, each in a task of its own, and one writing to a full
+/// pipe waits for the next to read. The pipeline's status is that of the last stage.
+pub fn run_pipeline(line: &str) {
+    let stages = match parse_pipeline(line) {
+        Ok(stages) => stages,
+        Err(e) => {
+            console::write_line(&format!("sh: {}", e));
+            env::set_status(2);
+            return;
+        }
+    };
+    if stages.is_empty() {
+        return;
+    }
+    env::set_status(0);
+    if stages.len() == 1 && stages[0].input.is_none() && stages[0].output.is_none() {
+        commands::handle_command(&stages[0].args);
+        return;
+    }
+
+    let last = stages.len() - 1;
+    let mut jobs: Vec<task::Job> = Vec::new();
+    let mut next_stdin: Option<FileDesc> = None;
+    for (i, stage) in stages.into_iter().enumerate() {
+        let piped = next_stdin.take();
+        let stdin = match &stage.input {
+            Some(path) => open_input(path).map_err(|e| format!("sh: {}: {}", path, e)),
+            None => Ok(piped.unwrap_or(FileDesc::Console)),
+        };
+
+        let (stdout, reader) = match &stage.output {
+            // A stage that redirects its own output leaves the next one an empty pipe.
+            Some(redirect) => (
+                open_output(redirect).map_err(|e| format!("sh: {}: {}", redirect.path, e)),
+                (i < last).then(|| FileDesc::PipeRead(pipe::pipe().0)),
+            ),
+            None if i < last => {
+                let (r, w) = pipe::pipe();
+                (Ok(FileDesc::PipeWrite(w)), Some(FileDesc::PipeRead(r)))
+            }
+            None => (Ok(FileDesc::Console), None),
+        };
+        next_stdin = reader;
+
+        let args = stage.args;
+        jobs.push(match (stdin, stdout) {
+            (Ok(stdin), Ok(stdout)) => Box::new(move || {
+                env::set_status(0);
+                let ((), closed) = fd::with_stdio(stdin, stdout, || commands::handle_command(&args));
+                if let Err(e) = closed {
+                    console::write_line(&format!("sh: {}", e));
+                }
+                env::last_status()
+            }),
+            // The stage does not run, and the pipe ends it got are closed here, so its
+            // neighbours see end of input or a broken pipe.
+            (Err(e), _) | (_, Err(e)) => Box::new(move || {
+                console::write_line(&e);
+                1
+            }),
+        });
+    }
+    let statuses = task::run_all(jobs);
+    env::set_status(statuses.last().copied().unwrap_or(0));
+}
+
+fn open_input(path: &str) -> Result<FileDesc, &'static str> {
+    let opts = OpenOptions { read: true, write: false, create: false, truncate: false, append: false };
+    OpenFile::open(path, &opts).map(FileDesc::file)
+}
+
+fn open_output(redirect: &Redirect) -> Result<FileDesc, &'static str> {
+    let opts = OpenOptions {
+        read: false,
+        write: true,
+        create: true,
+        truncate: !redirect.append,
+        append: redirect.append,
+    };
+    OpenFile::open(&redirect.path, &opts).map(FileDesc::file)
+}
+
+fn parse_pipeline(line: &str) -> Result<Vec<Stage>, &'static str> {
+    let mut stages = Vec::new();
+    let mut current = Stage::default();
+    let mut tokens = lexer::tokenize(line)?.into_iter();
+
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Word(word) => current.args.push(word),
+            Token::Pipe => {
+                if current.args.is_empty() {
+                    return Err("Missing command in pipeline.");
+                }
+                stages.push(core::mem::take(&mut current));
+            }
+            Token::Input => current.input = Some(redirect_target(tokens.next())?),
+            Token::Output | Token::Append => {
+                let append = matches!(token, Token::Append);
+                let path = redirect_target(tokens.next())?;
+                current.output = Some(Redirect { path, append });
+            }
+        }
+    }
+
+    if current.args.is_empty() {
+        if !stages.is_empty() || current.input.is_some() || current.output.is_some() {
+            return Err("Missing command in pipeline.");
+        }
+        return Ok(stages);
+    }
+    stages.push(current);
+    Ok(stages)
+}
+
+fn redirect_target(token: Option<Token>) -> Result<String, &'static str> {
+    match token {
+        Some(Token::Word(path)) if !path.is_empty() => Ok(path),
+        _ => Err("Missing file name for redirection."),
+    }
+}
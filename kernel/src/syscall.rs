@@ -0,0 +1,366 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::arch::{asm, global_asm};
+use x86_64::instructions::interrupts;
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+use crate::keyboard::{self, Keyboard};
+use crate::fd::{FileDesc, OpenFile, OpenOptions, ERR_BAD_FD};
+use crate::process::{self, PAGE_SIZE, USER_SPACE_END};
+use crate::{console, gdt, task, time, timer};
+
+pub const SYS_EXIT: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_READ: u64 = 2;
+pub const SYS_OPEN: u64 = 3;
+pub const SYS_CLOSE: u64 = 4;
+pub const SYS_MMAP: u64 = 5;
+pub const SYS_GETPID: u64 = 6;
+pub const SYS_YIELD: u64 = 7;
+pub const SYS_TIME: u64 = 8;
+
+pub const ENOENT: i64 = -2;
+pub const EIO: i64 = -5;
+pub const EBADF: i64 = -9;
+pub const ENOMEM: i64 = -12;
+pub const EFAULT: i64 = -14;
+pub const EINVAL: i64 = -22;
+pub const EMFILE: i64 = -24;
+pub const EPIPE: i64 = -32;
+pub const ENOSYS: i64 = -38;
+
+pub const O_RDONLY: u64 = 0;
+pub const O_WRONLY: u64 = 1;
+pub const O_RDWR: u64 = 2;
+pub const O_CREAT: u64 = 0x40;
+pub const O_TRUNC: u64 = 0x200;
+pub const O_APPEND: u64 = 0x400;
+
+pub const PROT_WRITE: u64 = 2;
+pub const PROT_EXEC: u64 = 4;
+
+const MAX_IO_CHUNK: usize = 64 * 1024;
+const MAX_PATH_LEN: usize = 1024;
+
+#[repr(C)]
+pub struct SyscallFrame {
+    r9: u64,
+    r8: u64,
+    r10: u64,
+    rdx: u64,
+    rsi: u64,
+    rdi: u64,
+    rax: u64,
+    r11: u64,
+    rcx: u64,
+    user_rsp: u64,
+}
+
+#[repr(align(16))]
+struct FxArea([u8; 512]);
+
+static mut USER_RSP_SCRATCH: u64 = 0;
+static mut SYSCALL_STACK_TOP: u64 = 0;
+
+// SYSCALL leaves us on the user stack with RCX/R11 holding the return RIP/RFLAGS.
+// Switch to the kernel entry stack, spill the argument registers into a SyscallFrame
+// and hand it to Rust; the frame's rax slot carries the return value back.
+global_asm!(
+    ".global axiomata_syscall_entry",
+    "axiomata_syscall_entry:",
+    "    mov [rip + {user_rsp}], rsp",
+    "    mov rsp, [rip + {stack_top}]",
+    "    push qword ptr [rip + {user_rsp}]",
+    "    push rcx",
+    "    push r11",
+    "    push rax",
+    "    push rdi",
+    "    push rsi",
+    "    push rdx",
+    "    push r10",
+    "    push r8",
+    "    push r9",
+    "    mov rdi, rsp",
+    "    call {dispatch}",
+    "    pop r9",
+    "    pop r8",
+    "    pop r10",
+    "    pop rdx",
+    "    pop rsi",
+    "    pop rdi",
+    "    pop rax",
+    "    pop r11",
+    "    pop rcx",
+    "    pop rsp",
+    "    sysretq",
+    user_rsp = sym USER_RSP_SCRATCH,
+    stack_top = sym SYSCALL_STACK_TOP,
+    dispatch = sym syscall_dispatch,
+);
+
+extern "C" {
+    fn axiomata_syscall_entry();
+}
+
+pub fn init() {
+    let sel = gdt::selectors();
+    unsafe {
+        SYSCALL_STACK_TOP = gdt::kernel_entry_stack_top().as_u64();
+        Efer::update(|flags| {
+            flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS);
+            flags.insert(EferFlags::NO_EXECUTE_ENABLE);
+        });
+    }
+    if Star::write(sel.user_code, sel.user_data, sel.kernel_code, sel.kernel_data).is_err() {
+        crate::serial::write("syscall: GDT layout rejected by STAR");
+        return;
+    }
+    LStar::write(VirtAddr::new(axiomata_syscall_entry as *const () as usize as u64));
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG);
+}
+
+/// Moves SYSCALL to the entry stack of the task about to run.
+pub fn set_entry_stack(top: VirtAddr) {
+    unsafe { SYSCALL_STACK_TOP = top.as_u64() };
+}
+
+extern "C" fn syscall_dispatch(frame: &mut SyscallFrame) {
+    // On the stack rather than static, as the call may block while another task's
+    // program makes calls of its own.
+    let mut fx = FxArea([0; 512]);
+    unsafe {
+        let area = fx.0.as_mut_ptr();
+        asm!("fxsave [{}]", in(reg) area, options(nostack, preserves_flags));
+    }
+    interrupts::enable();
+
+    let nr = frame.rax;
+    let args = [frame.rdi, frame.rsi, frame.rdx, frame.r10, frame.r8, frame.r9];
+    let ret = match nr {
+        SYS_EXIT => {
+            interrupts::disable();
+            process::exit_current(args[0] as i64)
+        }
+        SYS_WRITE => sys_write(args[0], args[1], args[2]),
+        SYS_READ => sys_read(args[0], args[1], args[2]),
+        SYS_OPEN => sys_open(args[0], args[1], args[2]),
+        SYS_CLOSE => sys_close(args[0]),
+        SYS_MMAP => sys_mmap(args[1], args[2]),
+        SYS_GETPID => process::current_pid().map(|pid| pid as i64).unwrap_or(EINVAL),
+        SYS_YIELD => sys_yield(),
+        SYS_TIME => sys_time(args[0]),
+        _ => ENOSYS,
+    };
+    frame.rax = ret as u64;
+
+    interrupts::disable();
+    unsafe {
+        let area = fx.0.as_ptr();
+        asm!("fxrstor [{}]", in(reg) area, options(nostack, preserves_flags));
+    }
+}
+
+fn user_buffer(ptr: u64, len: u64) -> Result<(u64, usize), i64> {
+    let len = len as usize;
+    let end = ptr.checked_add(len as u64).ok_or(EFAULT)?;
+    if end > USER_SPACE_END {
+        return Err(EFAULT);
+    }
+    Ok((ptr, len))
+}
+
+fn errno(err: &str) -> i64 {
+    match err {
+        ERR_BAD_FD => EBADF,
+        "Broken pipe." => EPIPE,
+        "File not found." => ENOENT,
+        _ => EIO,
+    }
+}
+
+fn descriptor(fd: u64) -> Result<FileDesc, i64> {
+    process::with_current(|p| p.files.get(fd as usize).cloned().flatten())
+        .ok_or(EINVAL)?
+        .ok_or(EBADF)
+}
+
+// Descriptors are cloned out of the table before any I/O so that a blocking pipe read
+// never holds the process lock.
+fn sys_write(fd: u64, ptr: u64, len: u64) -> i64 {
+    let (ptr, len) = match user_buffer(ptr, len) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+    let len = len.min(MAX_IO_CHUNK);
+    let desc = match descriptor(fd) {
+        Ok(desc) => desc,
+        Err(e) => return e,
+    };
+    let data = match process::with_current(|p| p.copy_from_user(ptr, len)) {
+        Some(Ok(data)) => data,
+        Some(Err(_)) => return EFAULT,
+        None => return EINVAL,
+    };
+    match desc.write(&data) {
+        Ok(n) => n as i64,
+        Err(e) => errno(e),
+    }
+}
+
+fn sys_read(fd: u64, ptr: u64, len: u64) -> i64 {
+    let (ptr, len) = match user_buffer(ptr, len) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+    let len = len.min(MAX_IO_CHUNK);
+    let desc = match descriptor(fd) {
+        Ok(desc) => desc,
+        Err(e) => return e,
+    };
+    if desc.is_console() {
+        return read_console(ptr, len);
+    }
+    let data = match desc.read(len) {
+        Ok(data) => data,
+        Err(e) => return errno(e),
+    };
+    match process::with_current(|p| p.copy_to_user(ptr, &data)) {
+        Some(Ok(())) => data.len() as i64,
+        Some(Err(_)) => EFAULT,
+        None => EINVAL,
+    }
+}
+
+fn read_console(ptr: u64, len: usize) -> i64 {
+    if len == 0 {
+        return 0;
+    }
+    let pending_empty = process::with_current(|p| p.stdin_pending.is_empty()).unwrap_or(true);
+    if pending_empty {
+        let line = read_console_line();
+        process::with_current(|p| p.stdin_pending.extend_from_slice(line.as_bytes()));
+    }
+    let result = process::with_current(|p| {
+        let take = len.min(p.stdin_pending.len());
+        let chunk: Vec<u8> = p.stdin_pending.drain(..take).collect();
+        p.copy_to_user(ptr, &chunk).map_err(|_| EFAULT)?;
+        Ok(chunk.len() as i64)
+    });
+    result.unwrap_or(Err(EINVAL)).unwrap_or_else(|e| e)
+}
+
+fn read_console_line() -> String {
+    let mut kbd = Keyboard::new();
+    let mut line = String::new();
+    loop {
+        let Some(evt) = kbd.poll_event() else {
+            x86_64::instructions::hlt();
+            continue;
+        };
+        match evt {
+            keyboard::KeyEvent::Char(ch) => {
+                line.push(ch);
+                let mut buf = [0u8; 4];
+                console::write(ch.encode_utf8(&mut buf));
+            }
+            keyboard::KeyEvent::Backspace => {
+                if line.pop().is_some() {
+                    console::with_console(|c| c.backspace());
+                }
+            }
+            keyboard::KeyEvent::Enter => {
+                console::write("\n");
+                line.push('\n');
+                return line;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn sys_open(path_ptr: u64, path_len: u64, flags: u64) -> i64 {
+    if path_len as usize > MAX_PATH_LEN {
+        return EINVAL;
+    }
+    let (ptr, len) = match user_buffer(path_ptr, path_len) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+    let raw = match process::with_current(|p| p.copy_from_user(ptr, len)) {
+        Some(Ok(raw)) => raw,
+        Some(Err(_)) => return EFAULT,
+        None => return EINVAL,
+    };
+    let Ok(path) = core::str::from_utf8(&raw) else {
+        return EINVAL;
+    };
+    let access = flags & 3;
+    let opts = OpenOptions {
+        read: access == O_RDONLY || access == O_RDWR,
+        write: access == O_WRONLY || access == O_RDWR,
+        create: flags & O_CREAT != 0,
+        truncate: flags & O_TRUNC != 0,
+        append: flags & O_APPEND != 0,
+    };
+    let file = match OpenFile::open(path, &opts) {
+        Ok(file) => file,
+        Err(e) => return if opts.create { EIO } else { errno(e) },
+    };
+    match process::with_current(|p| p.alloc_fd(FileDesc::file(file))) {
+        Some(Some(fd)) => fd as i64,
+        Some(None) => EMFILE,
+        None => EINVAL,
+    }
+}
+
+fn sys_close(fd: u64) -> i64 {
+    match process::with_current(|p| p.close_fd(fd as usize)) {
+        Some(Ok(())) => 0,
+        Some(Err(e)) => errno(e),
+        None => EINVAL,
+    }
+}
+
+fn sys_mmap(len: u64, prot: u64) -> i64 {
+    if len == 0 {
+        return EINVAL;
+    }
+    let Some(size) = len.checked_add(PAGE_SIZE - 1).map(|v| v & !(PAGE_SIZE - 1)) else {
+        return EINVAL;
+    };
+    let mut flags = PageTableFlags::empty();
+    if prot & PROT_WRITE != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if prot & PROT_EXEC == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    let result = process::with_current(|p| {
+        let addr = p.mmap_next;
+        if addr.checked_add(size).map(|end| end > process::USER_STACK_TOP).unwrap_or(true) {
+            return Err(ENOMEM);
+        }
+        p.space.map(addr, size, flags).map_err(|_| ENOMEM)?;
+        p.mmap_next = addr + size;
+        Ok(addr as i64)
+    });
+    result.unwrap_or(Err(EINVAL)).unwrap_or_else(|e| e)
+}
+
+fn sys_yield() -> i64 {
+    if !task::yield_now() {
+        x86_64::instructions::hlt();
+    }
+    0
+}
+
+// Returns wall-clock seconds since the epoch; with a non-zero argument, uptime in ticks.
+fn sys_time(kind: u64) -> i64 {
+    if kind != 0 {
+        return timer::ticks() as i64;
+    }
+    time::current_time_secs().map(|s| s as i64).unwrap_or(EIO)
+}
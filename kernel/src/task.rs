@@ -0,0 +1,253 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use spin::Mutex;
+use x86_64::instructions::{hlt, interrupts};
+use x86_64::registers::rflags;
+use x86_64::VirtAddr;
+
+use crate::{env, fd, gdt, process, script, syscall};
+
+// Roomier than the boot stack the shell runs on, which built-in commands are written for.
+const TASK_STACK_SIZE: usize = 128 * 1024;
+
+pub type Job = Box<dyn FnOnce() -> i64 + Send>;
+
+// Tasks are cooperative: one only gives up the CPU in `yield_now`, so none is ever
+// stopped while holding a lock. Whoever starts the first one becomes a task itself and
+// keeps the boot stacks.
+struct Task {
+    id: u64,
+    // Where the stack pointer was left while another task runs.
+    rsp: u64,
+    // Top of the stack SYSCALL and interrupts from ring 3 land on.
+    entry_top: VirtAddr,
+    // The task's own kernel and entry stacks, freed with it.
+    _stacks: Vec<Box<[u8]>>,
+    job: Option<Job>,
+    // Set once the job has returned.
+    status: Option<i64>,
+    parked: Option<Parked>,
+}
+
+// What the rest of the kernel keeps in globals for the running task, set aside while
+// another one runs.
+struct Parked {
+    process: process::TaskState,
+    stdio: fd::TaskState,
+    env: env::TaskState,
+    script: script::TaskState,
+}
+
+impl Parked {
+    fn take() -> Self {
+        Parked {
+            process: process::switch_out(),
+            stdio: fd::switch_out(),
+            env: env::switch_out(),
+            script: script::switch_out(),
+        }
+    }
+
+    fn restore(self) {
+        process::switch_in(self.process);
+        fd::switch_in(self.stdio);
+        env::switch_in(self.env);
+        script::switch_in(self.script);
+    }
+}
+
+struct Scheduler {
+    tasks: Vec<Task>,
+    current: usize,
+    next_id: u64,
+}
+
+impl Scheduler {
+    // The first unfinished task after `from`, going round.
+    fn next_after(&self, from: usize) -> Option<usize> {
+        let count = self.tasks.len();
+        (1..count).map(|step| (from + step) % count).find(|&i| self.tasks[i].status.is_none())
+    }
+
+    // Puts task `to`'s state back in place and makes it current. Returns the stack
+    // pointer to switch to.
+    fn enter(&mut self, to: usize) -> u64 {
+        self.current = to;
+        let task = &mut self.tasks[to];
+        if let Some(parked) = task.parked.take() {
+            parked.restore();
+        }
+        gdt::set_kernel_entry_stack(task.entry_top);
+        syscall::set_entry_stack(task.entry_top);
+        task.rsp
+    }
+}
+
+static SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler { tasks: Vec::new(), current: 0, next_id: 1 });
+
+extern "C" {
+    fn axiomata_switch_task(save_rsp: *mut u64, load_rsp: u64);
+    fn axiomata_task_start();
+}
+
+// switch_task saves the flags and callee-saved registers on the old stack and restores
+// them from the new one. A new task's stack is laid out so that this returns into
+// task_start.
+global_asm!(
+    ".global axiomata_switch_task",
+    "axiomata_switch_task:",
+    "    pushfq",
+    "    push rbx",
+    "    push rbp",
+    "    push r12",
+    "    push r13",
+    "    push r14",
+    "    push r15",
+    "    mov [rdi], rsp",
+    "    mov rsp, rsi",
+    "    pop r15",
+    "    pop r14",
+    "    pop r13",
+    "    pop r12",
+    "    pop rbp",
+    "    pop rbx",
+    "    popfq",
+    "    ret",
+    "",
+    ".global axiomata_task_start",
+    "axiomata_task_start:",
+    "    and rsp, -16",
+    "    call {main}",
+    "    ud2",
+    main = sym task_main,
+);
+
+extern "C" fn task_main() -> ! {
+    let job = {
+        let mut sched = SCHEDULER.lock();
+        let current = sched.current;
+        sched.tasks[current].job.take()
+    };
+    let status = job.map_or(0, |job| job());
+
+    interrupts::disable();
+    let mut sched = SCHEDULER.lock();
+    let current = sched.current;
+    sched.tasks[current].status = Some(status);
+    // Dropping what the task still holds closes its ends of any pipes.
+    drop(Parked::take());
+    // The task waiting in run_all is still unfinished, so there is always one.
+    let next = sched.next_after(current).expect("no task left to run");
+    let load = sched.enter(next);
+    drop(sched);
+    let mut discarded = 0;
+    unsafe { axiomata_switch_task(&mut discarded, load) };
+    unreachable!()
+}
+
+/// Lets the other tasks run and returns when the turn comes back round. False when there
+/// is no other task, so nothing but an interrupt can change in the meantime.
+pub fn yield_now() -> bool {
+    interrupts::without_interrupts(|| {
+        let mut sched = SCHEDULER.lock();
+        let from = sched.current;
+        let Some(to) = sched.next_after(from) else {
+            return false;
+        };
+        sched.tasks[from].parked = Some(Parked::take());
+        let load = sched.enter(to);
+        // Written by the switch itself, before anything else can touch the list.
+        let save: *mut u64 = &mut sched.tasks[from].rsp;
+        drop(sched);
+        unsafe { axiomata_switch_task(save, load) };
+        true
+    })
+}
+
+/// Runs each job in a task of its own, all taking turns, and returns their statuses in
+/// order once every one has returned. New tasks start with the caller's stdio and
+/// positional parameters but no process.
+pub fn run_all(jobs: Vec<Job>) -> Vec<i64> {
+    let ids: Vec<u64> = jobs.into_iter().map(spawn).collect();
+    loop {
+        let done = interrupts::without_interrupts(|| {
+            let sched = SCHEDULER.lock();
+            sched.tasks.iter().filter(|task| ids.contains(&task.id)).all(|task| task.status.is_some())
+        });
+        if done {
+            break;
+        }
+        if !yield_now() {
+            hlt();
+        }
+    }
+    interrupts::without_interrupts(|| {
+        let mut sched = SCHEDULER.lock();
+        let statuses = ids
+            .iter()
+            .map(|id| sched.tasks.iter().find(|task| task.id == *id).and_then(|task| task.status).unwrap_or(0))
+            .collect();
+        let current = sched.tasks[sched.current].id;
+        sched.tasks.retain(|task| !ids.contains(&task.id));
+        if sched.tasks.len() == 1 {
+            sched.tasks.clear();
+        }
+        sched.current = sched.tasks.iter().position(|task| task.id == current).unwrap_or(0);
+        statuses
+    })
+}
+
+fn spawn(job: Job) -> u64 {
+    let mut stack = vec![0u8; TASK_STACK_SIZE].into_boxed_slice();
+    let mut entry_stack = vec![0u8; gdt::KERNEL_STACK_SIZE].into_boxed_slice();
+    let rsp = initial_rsp(&mut stack);
+    let entry_top = VirtAddr::new(stack_top(&mut entry_stack));
+    let parked = Parked {
+        process: process::TaskState::default(),
+        stdio: fd::inherit(),
+        env: env::inherit(),
+        script: script::TaskState::default(),
+    };
+    interrupts::without_interrupts(|| {
+        let mut sched = SCHEDULER.lock();
+        if sched.tasks.is_empty() {
+            sched.tasks.push(Task {
+                id: 0,
+                rsp: 0,
+                entry_top: gdt::kernel_entry_stack_top(),
+                _stacks: Vec::new(),
+                job: None,
+                status: None,
+                parked: None,
+            });
+            sched.current = 0;
+        }
+        let id = sched.next_id;
+        sched.next_id += 1;
+        sched.tasks.push(Task {
+            id,
+            rsp,
+            entry_top,
+            _stacks: vec![stack, entry_stack],
+            job: Some(job),
+            status: None,
+            parked: Some(parked),
+        });
+        id
+    })
+}
+
+fn stack_top(stack: &mut [u8]) -> u64 {
+    (stack.as_mut_ptr() as u64 + stack.len() as u64) & !0xF
+}
+
+// What switch_task pops on the first switch to a task: zeroed registers, the spawner's
+// flags and a return into task_start.
+fn initial_rsp(stack: &mut [u8]) -> u64 {
+    let frame = [0, 0, 0, 0, 0, 0, rflags::read_raw(), axiomata_task_start as *const () as usize as u64];
+    let rsp = stack_top(stack) - (frame.len() * 8) as u64;
+    unsafe { core::ptr::copy_nonoverlapping(frame.as_ptr(), rsp as *mut u64, frame.len()) };
+    rsp
+}
@@ -1,3 +1,4 @@
+use alloc::string::String;
 use alloc::vec::Vec;
 use heapless::String as HString;
 use spin::Mutex;
@@ -24,11 +25,11 @@ impl TerminalLine {
 
 pub struct TerminalState {
     pub lines: Vec<TerminalLine>,
-    pub input: HString<128>,
+    pub input: String,
     pub cursor_pos: usize,
     pub selection_anchor: Option<usize>,
     pub history_index: Option<usize>,
-    pub draft_line: HString<128>,
+    pub draft_line: String,
     pub scroll: usize,
     view_rows: usize,
     view_cols: usize,
@@ -40,11 +41,11 @@ impl TerminalState {
     const fn new() -> Self {
         Self {
             lines: Vec::new(),
-            input: HString::new(),
+            input: String::new(),
             cursor_pos: 0,
             selection_anchor: None,
             history_index: None,
-            draft_line: HString::new(),
+            draft_line: String::new(),
             scroll: 0,
             view_rows: 0,
             view_cols: 0,
@@ -0,0 +1,518 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::{console, fd, fs, glob};
+
+// `grep -r` and `find` stop descending below this many directories.
+const MAX_WALK_DEPTH: usize = 16;
+
+const GREP_USAGE: &str = "Usage: grep [-i] [-n] [-r] [-v] [-c] <pattern> [path...]";
+const HEAD_USAGE: &str = "Usage: head [-n count] [path...]";
+const TAIL_USAGE: &str = "Usage: tail [-n count] [path...]";
+const WC_USAGE: &str = "Usage: wc [-l] [-w] [-c] [path...]";
+const SORT_USAGE: &str = "Usage: sort [-r] [-n] [-u] [path...]";
+const UNIQ_USAGE: &str = "Usage: uniq [-c] [-d] [path]";
+const FIND_USAGE: &str = "Usage: find [dir] [-name pattern] [-type f|d]";
+
+/// Splits leading `-x` flags (which may be combined, as in `-in`) from the operands.
+/// Returns `None` on a flag not in `known`.
+fn flags<'a>(args: &'a [&'a str], known: &str) -> Option<(Vec<char>, &'a [&'a str])> {
+    let mut set = Vec::new();
+    let mut rest = args;
+    while let Some(arg) = rest.first() {
+        if *arg == "--" {
+            rest = &rest[1..];
+            break;
+        }
+        let Some(letters) = arg.strip_prefix('-').filter(|l| !l.is_empty()) else { break; };
+        for c in letters.chars() {
+            if !known.contains(c) {
+                return None;
+            }
+            set.push(c);
+        }
+        rest = &rest[1..];
+    }
+    Some((set, rest))
+}
+
+/// One file (or stdin) to process, with the name it is reported under.
+struct Input {
+    name: String,
+    text: String,
+}
+
+/// Reads every path, or the piped stdin when there are none. Unreadable paths are reported
+/// and skipped; the flag is true if any were.
+fn read_inputs(command: &str, paths: &[&str]) -> Option<(Vec<Input>, bool)> {
+    if paths.is_empty() {
+        let data = fd::read_stdin_to_end()?;
+        let text = String::from_utf8_lossy(&data).into_owned();
+        return Some((Vec::from([Input { name: String::from("-"), text }]), false));
+    }
+    let mut inputs = Vec::new();
+    let mut failed = false;
+    for path in paths {
+        match fs::read_file(path) {
+            Some(text) => inputs.push(Input { name: path.to_string(), text }),
+            None => {
+                console::write_line(&format!("{}: {}: No such file.", command, path));
+                failed = true;
+            }
+        }
+    }
+    Some((inputs, failed))
+}
+
+// `-n count`, `-ncount` or `-count`, defaulting to 10.
+fn parse_count<'a>(args: &'a [&'a str]) -> Option<(usize, &'a [&'a str])> {
+    match args {
+        ["-n", count, rest @ ..] => Some((count.parse().ok()?, rest)),
+        [flag, rest @ ..] if flag.len() > 1 && flag.starts_with('-') => {
+            let count = flag[1..].strip_prefix('n').unwrap_or(&flag[1..]);
+            Some((count.parse().ok()?, rest))
+        }
+        _ => Some((10, args)),
+    }
+}
+
+fn join_path(dir: &str, name: &str) -> String {
+    if dir.ends_with('\\') {
+        format!("{}{}", dir, name)
+    } else {
+        format!("{}\\{}", dir, name)
+    }
+}
+
+/// Visits everything below `dir`, depth first, with its path and whether it is a directory.
+fn walk(dir: &str, depth: usize, visit: &mut dyn FnMut(&str, bool)) {
+    let Ok(entries) = fs::list_dir(dir) else { return; };
+    for entry in entries {
+        if entry.name == "." || entry.name == ".." {
+            continue;
+        }
+        let path = join_path(dir, &entry.name);
+        visit(&path, entry.is_dir);
+        if entry.is_dir && depth < MAX_WALK_DEPTH {
+            walk(&path, depth + 1, visit);
+        }
+    }
+}
+
+enum Atom {
+    Char(char),
+    Any,
+    Class { negate: bool, ranges: Vec<(char, char)> },
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Repeat {
+    One,
+    /// `*`
+    Star,
+    /// `+`
+    Plus,
+    /// `?`
+    Opt,
+}
+
+/// A small regular expression: literals, `.`, `[...]` classes, the `*`, `+` and `?`
+/// repeats, `^` and `$` anchors and `\` to escape any of them.
+pub struct Regex {
+    nodes: Vec<(Atom, Repeat)>,
+    anchor_start: bool,
+    anchor_end: bool,
+    ignore_case: bool,
+}
+
+impl Regex {
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<Self, &'static str> {
+        let mut chars: Vec<char> = pattern.chars().collect();
+        let anchor_start = chars.first() == Some(&'^');
+        if anchor_start {
+            chars.remove(0);
+        }
+        let escaped_end = chars.len() >= 2 && chars[chars.len() - 2] == '\\';
+        let anchor_end = chars.last() == Some(&'$') && !escaped_end;
+        if anchor_end {
+            chars.pop();
+        }
+
+        let mut nodes: Vec<(Atom, Repeat)> = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            i += 1;
+            let repeat = match c {
+                '*' => Some(Repeat::Star),
+                '+' => Some(Repeat::Plus),
+                '?' => Some(Repeat::Opt),
+                _ => None,
+            };
+            if let Some(repeat) = repeat {
+                match nodes.last_mut() {
+                    Some((_, r)) if *r == Repeat::One => *r = repeat,
+                    _ => return Err("nothing to repeat"),
+                }
+                continue;
+            }
+            let atom = match c {
+                '.' => Atom::Any,
+                '\\' => {
+                    let Some(&next) = chars.get(i) else { return Err("trailing backslash") };
+                    i += 1;
+                    Atom::Char(next)
+                }
+                '[' => {
+                    let negate = chars.get(i) == Some(&'^');
+                    if negate {
+                        i += 1;
+                    }
+                    let mut ranges = Vec::new();
+                    let mut first = true;
+                    loop {
+                        let Some(&c) = chars.get(i) else { return Err("unterminated [") };
+                        if c == ']' && !first {
+                            i += 1;
+                            break;
+                        }
+                        first = false;
+                        match (chars.get(i + 1), chars.get(i + 2)) {
+                            (Some('-'), Some(&end)) if end != ']' => {
+                                ranges.push((c, end));
+                                i += 3;
+                            }
+                            _ => {
+                                ranges.push((c, c));
+                                i += 1;
+                            }
+                        }
+                    }
+                    Atom::Class { negate, ranges }
+                }
+                c => Atom::Char(c),
+            };
+            nodes.push((atom, Repeat::One));
+        }
+        Ok(Regex { nodes, anchor_start, anchor_end, ignore_case })
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        let text: Vec<char> = line.chars().collect();
+        if self.anchor_start {
+            return self.match_at(0, &text, 0);
+        }
+        (0..=text.len()).any(|start| self.match_at(0, &text, start))
+    }
+
+    fn atom_matches(&self, atom: &Atom, c: char) -> bool {
+        match atom {
+            Atom::Any => true,
+            Atom::Char(a) if self.ignore_case => a.eq_ignore_ascii_case(&c),
+            Atom::Char(a) => *a == c,
+            Atom::Class { negate, ranges } => {
+                let hit = ranges.iter().any(|&(lo, hi)| {
+                    (lo..=hi).contains(&c)
+                        || (self.ignore_case
+                            && ((lo..=hi).contains(&c.to_ascii_lowercase())
+                                || (lo..=hi).contains(&c.to_ascii_uppercase())))
+                });
+                hit != *negate
+            }
+        }
+    }
+
+    fn match_at(&self, node: usize, text: &[char], pos: usize) -> bool {
+        let Some((atom, repeat)) = self.nodes.get(node) else {
+            return !self.anchor_end || pos == text.len();
+        };
+        let hit = |p: usize| p < text.len() && self.atom_matches(atom, text[p]);
+        match repeat {
+            Repeat::One => hit(pos) && self.match_at(node + 1, text, pos + 1),
+            Repeat::Opt => (hit(pos) && self.match_at(node + 1, text, pos + 1)) || self.match_at(node + 1, text, pos),
+            Repeat::Star | Repeat::Plus => {
+                let mut end = pos;
+                while hit(end) {
+                    end += 1;
+                }
+                let min = if *repeat == Repeat::Plus { pos + 1 } else { pos };
+                // Greedy: try the longest run first.
+                (min..=end).rev().any(|p| self.match_at(node + 1, text, p))
+            }
+        }
+    }
+}
+
+/// `grep [-i] [-n] [-r] [-v] [-c] <pattern> [path...]`. Exits 0 if a line matched, 1 if
+/// none did and 2 on an error.
+pub fn grep(args: &[&str]) -> i64 {
+    let Some((flags, rest)) = flags(args, "inrvc") else {
+        console::write_line(GREP_USAGE);
+        return 2;
+    };
+    let Some((pattern, paths)) = rest.split_first() else {
+        console::write_line(GREP_USAGE);
+        return 2;
+    };
+    let regex = match Regex::new(pattern, flags.contains(&'i')) {
+        Ok(regex) => regex,
+        Err(e) => {
+            console::write_line(&format!("grep: {}", e));
+            return 2;
+        }
+    };
+
+    let mut paths: Vec<String> = paths.iter().map(|p| p.to_string()).collect();
+    if flags.contains(&'r') {
+        if paths.is_empty() {
+            paths.push(String::from("."));
+        }
+        let mut files = Vec::new();
+        for path in &paths {
+            if fs::list_dir(path).is_ok() {
+                walk(path, 0, &mut |found, is_dir| {
+                    if !is_dir {
+                        files.push(found.to_string());
+                    }
+                });
+            } else {
+                files.push(path.clone());
+            }
+        }
+        paths = files;
+    }
+    let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+    let Some((inputs, failed)) = read_inputs("grep", &paths) else {
+        console::write_line(GREP_USAGE);
+        return 2;
+    };
+
+    let invert = flags.contains(&'v');
+    let prefix_name = inputs.len() > 1 || flags.contains(&'r');
+    let mut matched = false;
+    for input in &inputs {
+        let mut count = 0;
+        for (number, line) in input.text.lines().enumerate() {
+            if regex.is_match(line) == invert {
+                continue;
+            }
+            count += 1;
+            if flags.contains(&'c') {
+                continue;
+            }
+            let mut out = String::new();
+            if prefix_name {
+                out.push_str(&input.name);
+                out.push(':');
+            }
+            if flags.contains(&'n') {
+                out.push_str(&format!("{}:", number + 1));
+            }
+            out.push_str(line);
+            console::write_line(&out);
+        }
+        if flags.contains(&'c') {
+            if prefix_name {
+                console::write_line(&format!("{}:{}", input.name, count));
+            } else {
+                console::write_line(&format!("{}", count));
+            }
+        }
+        matched |= count > 0;
+    }
+    if failed {
+        2
+    } else if matched {
+        0
+    } else {
+        1
+    }
+}
+
+fn head_or_tail(args: &[&str], command: &str, usage: &str, tail: bool) -> i64 {
+    let Some((count, paths)) = parse_count(args) else {
+        console::write_line(usage);
+        return 1;
+    };
+    let Some((inputs, failed)) = read_inputs(command, paths) else {
+        console::write_line(usage);
+        return 1;
+    };
+    let headers = inputs.len() > 1;
+    for (i, input) in inputs.iter().enumerate() {
+        if headers {
+            if i > 0 {
+                console::write_line("");
+            }
+            console::write_line(&format!("==> {} <==", input.name));
+        }
+        let lines: Vec<&str> = input.text.lines().collect();
+        let shown = if tail { &lines[lines.len().saturating_sub(count)..] } else { &lines[..count.min(lines.len())] };
+        shown.iter().for_each(|line| console::write_line(line));
+    }
+    i64::from(failed)
+}
+
+/// `head [-n count] [path...]`: the first lines of each file, 10 by default.
+pub fn head(args: &[&str]) -> i64 {
+    head_or_tail(args, "head", HEAD_USAGE, false)
+}
+
+/// `tail [-n count] [path...]`: the last lines of each file, 10 by default.
+pub fn tail(args: &[&str]) -> i64 {
+    head_or_tail(args, "tail", TAIL_USAGE, true)
+}
+
+/// `wc [-l] [-w] [-c] [path...]`: line, word and byte counts, with a total for several files.
+pub fn wc(args: &[&str]) -> i64 {
+    let Some((mut flags, paths)) = flags(args, "lwc") else {
+        console::write_line(WC_USAGE);
+        return 1;
+    };
+    if flags.is_empty() {
+        flags = Vec::from(['l', 'w', 'c']);
+    }
+    let Some((inputs, failed)) = read_inputs("wc", paths) else {
+        console::write_line(WC_USAGE);
+        return 1;
+    };
+    let report = |counts: [usize; 3], name: &str| {
+        let mut out = String::new();
+        for (flag, count) in ['l', 'w', 'c'].iter().zip(counts) {
+            if flags.contains(flag) {
+                out.push_str(&format!("{:>7} ", count));
+            }
+        }
+        out.push_str(name);
+        console::write_line(out.trim_end());
+    };
+    let mut total = [0usize; 3];
+    for input in &inputs {
+        let counts = [input.text.lines().count(), input.text.split_whitespace().count(), input.text.len()];
+        for (sum, count) in total.iter_mut().zip(counts) {
+            *sum += count;
+        }
+        report(counts, if paths.is_empty() { "" } else { &input.name });
+    }
+    if inputs.len() > 1 {
+        report(total, "total");
+    }
+    i64::from(failed)
+}
+
+// Leading number of a line for `sort -n`; lines without one sort first, as zero.
+fn leading_number(line: &str) -> i64 {
+    let line = line.trim_start();
+    let end = line
+        .char_indices()
+        .find(|&(i, c)| !(c.is_ascii_digit() || (i == 0 && c == '-')))
+        .map_or(line.len(), |(i, _)| i);
+    line[..end].parse().unwrap_or(0)
+}
+
+/// `sort [-r] [-n] [-u] [path...]`: the lines of all inputs in order.
+pub fn sort(args: &[&str]) -> i64 {
+    let Some((flags, paths)) = flags(args, "rnu") else {
+        console::write_line(SORT_USAGE);
+        return 1;
+    };
+    let Some((inputs, failed)) = read_inputs("sort", paths) else {
+        console::write_line(SORT_USAGE);
+        return 1;
+    };
+    let mut lines: Vec<&str> = inputs.iter().flat_map(|input| input.text.lines()).collect();
+    if flags.contains(&'n') {
+        lines.sort_by(|a, b| leading_number(a).cmp(&leading_number(b)).then(a.cmp(b)));
+    } else {
+        lines.sort();
+    }
+    if flags.contains(&'u') {
+        lines.dedup();
+    }
+    if flags.contains(&'r') {
+        lines.reverse();
+    }
+    lines.iter().for_each(|line| console::write_line(line));
+    i64::from(failed)
+}
+
+/// `uniq [-c] [-d] [path]`: collapses runs of identical adjacent lines.
+pub fn uniq(args: &[&str]) -> i64 {
+    let Some((flags, paths)) = flags(args, "cd") else {
+        console::write_line(UNIQ_USAGE);
+        return 1;
+    };
+    if paths.len() > 1 {
+        console::write_line(UNIQ_USAGE);
+        return 1;
+    }
+    let Some((inputs, failed)) = read_inputs("uniq", paths) else {
+        console::write_line(UNIQ_USAGE);
+        return 1;
+    };
+    let mut runs: Vec<(&str, usize)> = Vec::new();
+    for line in inputs.iter().flat_map(|input| input.text.lines()) {
+        match runs.last_mut() {
+            Some((last, count)) if *last == line => *count += 1,
+            _ => runs.push((line, 1)),
+        }
+    }
+    for (line, count) in runs {
+        if flags.contains(&'d') && count < 2 {
+            continue;
+        }
+        if flags.contains(&'c') {
+            console::write_line(&format!("{:>7} {}", count, line));
+        } else {
+            console::write_line(line);
+        }
+    }
+    i64::from(failed)
+}
+
+/// `find [dir] [-name pattern] [-type f|d]`: paths below `dir` (default `.`) whose name
+/// matches a wildcard pattern. Quote the pattern so the shell does not expand it first.
+pub fn find(args: &[&str]) -> i64 {
+    let (dir, mut rest) = match args.split_first() {
+        Some((dir, rest)) if !dir.starts_with('-') => (*dir, rest),
+        _ => (".", args),
+    };
+    let mut name: Option<&str> = None;
+    let mut kind: Option<bool> = None;
+    while !rest.is_empty() {
+        match rest {
+            ["-name", pattern, tail @ ..] => {
+                name = Some(pattern);
+                rest = tail;
+            }
+            ["-type", "f", tail @ ..] => {
+                kind = Some(false);
+                rest = tail;
+            }
+            ["-type", "d", tail @ ..] => {
+                kind = Some(true);
+                rest = tail;
+            }
+            _ => {
+                console::write_line(FIND_USAGE);
+                return 1;
+            }
+        }
+    }
+    if let Err(e) = fs::list_dir(dir) {
+        console::write_line(&format!("find: {}: {}", dir, e));
+        return 1;
+    }
+    walk(dir, 0, &mut |path, is_dir| {
+        let base = path.rsplit('\\').next().unwrap_or(path);
+        if kind.is_some_and(|want_dir| want_dir != is_dir) {
+            return;
+        }
+        if name.is_some_and(|pattern| !glob::matches_name(pattern, base)) {
+            return;
+        }
+        console::write_line(path);
+    });
+    0
+}
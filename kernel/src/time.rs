@@ -536,7 +536,7 @@ fn maybe_auto_resync() {
     }
 }
 
-fn ymd_hms_to_secs(y: u64, m: u64, d: u64, h: u64, min: u64, s: u64) -> u64 {
+pub fn ymd_hms_to_secs(y: u64, m: u64, d: u64, h: u64, min: u64, s: u64) -> u64 {
     let mut days = 0u64;
 
     for year in 1970..y {
@@ -708,6 +708,14 @@ pub fn time_cmd(args: &[&str]) {
     }
 }
 
+/// Formats Unix seconds as `MM/DD/YYYY HH:MM`, the way file listings show dates.
+pub fn format_timestamp(secs: u64) -> HString<16> {
+    let (y, m, d, h, min, _) = secs_to_ymd_hms(secs);
+    let mut out: HString<16> = HString::new();
+    let _ = core::fmt::write(&mut out, format_args!("{:02}/{:02}/{:04} {:02}:{:02}", m, d, y, h, min));
+    out
+}
+
 pub fn format_hud_time() -> HString<32> {
     let mut out: HString<32> = HString::new();
 
@@ -0,0 +1,707 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::keyboard::{self, Keyboard};
+use crate::{commands, console, env, fs, history, time, wait};
+
+const PASSWD_PATH: &str = "\\etc\\passwd";
+const GROUP_PATH: &str = "\\etc\\group";
+const ETC_DIR: &str = "\\etc";
+const HOME_DIR: &str = "\\home";
+// SHA-256 rounds stretching each new password hash.
+const HASH_ROUNDS: u32 = 5000;
+// First id useradd hands out; lower ones are left for system accounts.
+const FIRST_USER_ID: u16 = 1000;
+const FAILED_LOGIN_DELAY_MS: u64 = 2000;
+// Id `permission_selftest` runs as, belonging to no account.
+const NOBODY_ID: u16 = 65534;
+const SELFTEST_PATH: &str = "\\etc\\selftest";
+
+#[derive(Clone)]
+pub struct User {
+    pub name: String,
+    // `$sha256$rounds$salt$digest`, empty for no password, or `!` while locked.
+    hash: String,
+    pub uid: u16,
+    pub gid: u16,
+    pub home: String,
+}
+
+#[derive(Clone)]
+pub struct Group {
+    pub name: String,
+    pub gid: u16,
+    pub members: Vec<String>,
+}
+
+/// Who file accesses are checked against.
+#[derive(Clone)]
+pub struct Credentials {
+    pub uid: u16,
+    pub gid: u16,
+    /// Supplementary groups, from the member lists in `\etc\group`.
+    pub groups: Vec<u16>,
+}
+
+impl Credentials {
+    pub fn in_group(&self, gid: u16) -> bool {
+        self.gid == gid || self.groups.contains(&gid)
+    }
+}
+
+#[derive(Clone)]
+struct Database {
+    users: Vec<User>,
+    groups: Vec<Group>,
+    // False while there is no passwd file and root is the only account.
+    on_disk: bool,
+}
+
+static DATABASE: Mutex<Database> = Mutex::new(Database {
+    users: Vec::new(),
+    groups: Vec::new(),
+    on_disk: false,
+});
+static CURRENT: Mutex<Credentials> = Mutex::new(Credentials {
+    uid: 0,
+    gid: 0,
+    groups: Vec::new(),
+});
+
+fn builtin_root() -> User {
+    User {
+        name: "root".to_string(),
+        hash: String::new(),
+        uid: 0,
+        gid: 0,
+        home: "\\".to_string(),
+    }
+}
+
+/// Loads `\etc\passwd` and `\etc\group`. Without a passwd file root is the only account,
+/// has no password and is never asked to log in. Call after `fs::init_persistent`.
+pub fn init() {
+    let mut users = Vec::new();
+    if let Some(text) = fs::read_file(PASSWD_PATH) {
+        users = text.lines().filter_map(parse_user).collect::<Vec<_>>();
+        if !users.iter().any(|user| user.uid == 0) {
+            console::write_line("users: \\etc\\passwd has no root account; ignoring it.");
+            users.clear();
+        }
+    }
+    let mut groups: Vec<Group> = fs::read_file(GROUP_PATH)
+        .map(|text| text.lines().filter_map(parse_group).collect())
+        .unwrap_or_default();
+    if !groups.iter().any(|group| group.gid == 0) {
+        groups.insert(0, Group { name: "root".to_string(), gid: 0, members: Vec::new() });
+    }
+    let on_disk = !users.is_empty();
+    if !on_disk {
+        users.push(builtin_root());
+    }
+    *DATABASE.lock() = Database { users, groups, on_disk };
+    set_credentials(&builtin_root());
+    let _ = env::set("USER", "root", true);
+}
+
+// `name:hash:uid:gid:home`; blank lines and `#` comments are skipped.
+fn parse_user(line: &str) -> Option<User> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let mut fields = line.split(':');
+    let name = fields.next()?.to_string();
+    let hash = fields.next()?.to_string();
+    let uid = fields.next()?.parse().ok()?;
+    let gid = fields.next()?.parse().ok()?;
+    let home = fields.next().filter(|home| !home.is_empty()).unwrap_or("\\").to_string();
+    Some(User { name, hash, uid, gid, home })
+}
+
+// `name:gid:member,member`.
+fn parse_group(line: &str) -> Option<Group> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let mut fields = line.split(':');
+    let name = fields.next()?.to_string();
+    let gid = fields.next()?.parse().ok()?;
+    let members = fields
+        .next()
+        .unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|member| !member.is_empty())
+        .map(ToString::to_string)
+        .collect();
+    Some(Group { name, gid, members })
+}
+
+// Writes both files as root. Password hashes stay readable by root alone. On FAT and
+// exFAT, making root the owner marks the files and `\etc` system, the only way to keep
+// other users out of them.
+fn save(db: &Database) -> Result<(), &'static str> {
+    let mut passwd = String::from("# name:password hash:uid:gid:home\n");
+    for user in &db.users {
+        passwd.push_str(&format!("{}:{}:{}:{}:{}\n", user.name, user.hash, user.uid, user.gid, user.home));
+    }
+    let mut group = String::from("# name:gid:members\n");
+    for entry in &db.groups {
+        group.push_str(&format!("{}:{}:{}\n", entry.name, entry.gid, entry.members.join(",")));
+    }
+    as_root(|| {
+        if !fs::exists(ETC_DIR) {
+            fs::mkdir(ETC_DIR)?;
+        }
+        fs::chown(ETC_DIR, Some(0), Some(0))?;
+        fs::chmod(ETC_DIR, |_| 0o755)?;
+        fs::write_file(PASSWD_PATH, &passwd)?;
+        fs::chown(PASSWD_PATH, Some(0), Some(0))?;
+        fs::chmod(PASSWD_PATH, |_| 0o600)?;
+        fs::write_file(GROUP_PATH, &group)?;
+        fs::chown(GROUP_PATH, Some(0), Some(0))
+    })
+}
+
+// Runs `action` with root's credentials, for the account files ordinary users can't touch.
+fn as_root<T>(action: impl FnOnce() -> T) -> T {
+    run_as(Credentials { uid: 0, gid: 0, groups: Vec::new() }, action)
+}
+
+fn run_as<T>(creds: Credentials, action: impl FnOnce() -> T) -> T {
+    let saved = core::mem::replace(&mut *CURRENT.lock(), creds);
+    let result = action();
+    *CURRENT.lock() = saved;
+    result
+}
+
+/// Checks, as a user with no groups, that `\etc\passwd` can be neither deleted nor
+/// overwritten and that nothing can be created beside it. Whatever got through is undone.
+/// `debug permissions` runs it once the account files exist.
+pub fn permission_selftest() -> Result<(), &'static str> {
+    if !DATABASE.lock().on_disk {
+        return Err("no \\etc\\passwd yet; set a password with passwd first");
+    }
+    let nobody = Credentials { uid: NOBODY_ID, gid: NOBODY_ID, groups: Vec::new() };
+    let (deleted, written, created) = run_as(nobody, || {
+        (
+            fs::delete_file(PASSWD_PATH).is_ok(),
+            fs::write_file(PASSWD_PATH, "").is_ok(),
+            fs::touch(SELFTEST_PATH).is_ok(),
+        )
+    });
+    if deleted || written {
+        save(&DATABASE.lock())?;
+    }
+    if created {
+        as_root(|| fs::delete_file(SELFTEST_PATH))?;
+    }
+    match (deleted, written, created) {
+        (true, _, _) => Err("a non-root user deleted \\etc\\passwd"),
+        (_, true, _) => Err("a non-root user overwrote \\etc\\passwd"),
+        (_, _, true) => Err("a non-root user created a file in \\etc"),
+        _ => Ok(()),
+    }
+}
+
+fn set_credentials(user: &User) {
+    let groups = DATABASE
+        .lock()
+        .groups
+        .iter()
+        .filter(|group| group.members.contains(&user.name))
+        .map(|group| group.gid)
+        .collect();
+    *CURRENT.lock() = Credentials { uid: user.uid, gid: user.gid, groups };
+}
+
+pub fn credentials() -> Credentials {
+    CURRENT.lock().clone()
+}
+
+/// User and group id of whoever is logged in.
+pub fn current_ids() -> (u16, u16) {
+    let current = CURRENT.lock();
+    (current.uid, current.gid)
+}
+
+fn find_user(name: &str) -> Option<User> {
+    DATABASE.lock().users.iter().find(|user| user.name == name).cloned()
+}
+
+pub fn user_name(uid: u16) -> Option<String> {
+    DATABASE.lock().users.iter().find(|user| user.uid == uid).map(|user| user.name.clone())
+}
+
+pub fn group_name(gid: u16) -> Option<String> {
+    DATABASE.lock().groups.iter().find(|group| group.gid == gid).map(|group| group.name.clone())
+}
+
+// Accepts an account name or a numeric id.
+fn parse_uid(name: &str) -> Option<u16> {
+    let db = DATABASE.lock();
+    db.users.iter().find(|user| user.name == name).map(|user| user.uid).or_else(|| name.parse().ok())
+}
+
+fn parse_gid(name: &str) -> Option<u16> {
+    let db = DATABASE.lock();
+    db.groups.iter().find(|group| group.name == name).map(|group| group.gid).or_else(|| name.parse().ok())
+}
+
+/// Name of the logged-in user, or their uid when it has no account.
+pub fn whoami() -> String {
+    let uid = current_ids().0;
+    user_name(uid).unwrap_or_else(|| format!("{}", uid))
+}
+
+/// True once accounts are kept in `\etc\passwd` and users have to log in.
+pub fn accounts_enabled() -> bool {
+    DATABASE.lock().on_disk
+}
+
+/// Asks for a user name and password until they match an account, then starts that user's
+/// session. Does nothing while there is no passwd file.
+pub fn login() {
+    if !DATABASE.lock().on_disk {
+        return;
+    }
+    console::write_line("");
+    loop {
+        console::write("login: ");
+        let name = read_line(true);
+        if name.is_empty() {
+            continue;
+        }
+        let user = find_user(&name);
+        // Unknown names are asked for a password too, so they can't be told from real ones.
+        let accepted = match &user {
+            Some(user) if user.hash.is_empty() => true,
+            _ => {
+                console::write("Password: ");
+                let password = read_line(false);
+                user.as_ref().is_some_and(|user| verify(&user.hash, &password))
+            }
+        };
+        if let (true, Some(user)) = (accepted, user) {
+            start_session(&user);
+            console::write_line(&format!("Welcome, {}.", user.name));
+            return;
+        }
+        wait::bms(FAILED_LOGIN_DELAY_MS);
+        console::write_line("Login incorrect.");
+    }
+}
+
+// Takes on `user`'s identity, home directory, history and aliases.
+fn start_session(user: &User) {
+    set_credentials(user);
+    let _ = env::set("USER", &user.name, true);
+    let _ = env::set("HOME", &user.home, true);
+    if fs::set_current_dir(&user.home).is_err() {
+        let _ = fs::set_current_dir("\\");
+    }
+    history::load();
+    commands::load_aliases();
+}
+
+// Reads a line from the keyboard. With `echo` off nothing typed is shown.
+fn read_line(echo: bool) -> String {
+    let mut kbd = Keyboard::new();
+    let mut line = String::new();
+    loop {
+        let Some(evt) = kbd.poll_event() else {
+            x86_64::instructions::hlt();
+            continue;
+        };
+        match evt {
+            keyboard::KeyEvent::Char(ch) => {
+                line.push(ch);
+                if echo {
+                    let mut buf = [0u8; 4];
+                    console::write(ch.encode_utf8(&mut buf));
+                }
+            }
+            keyboard::KeyEvent::Backspace => {
+                if line.pop().is_some() && echo {
+                    console::with_console(|c| c.backspace());
+                }
+            }
+            keyboard::KeyEvent::Enter => {
+                console::write("\n");
+                return line;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn verify(stored: &str, password: &str) -> bool {
+    let mut fields = stored.split('$');
+    let (Some(""), Some("sha256"), Some(rounds), Some(salt), Some(_), None) = (
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+    ) else {
+        return false;
+    };
+    let Ok(rounds) = rounds.parse() else {
+        return false;
+    };
+    let computed = hash_password(password, salt, rounds);
+    // Compare every byte, so the time taken says nothing about where they differ.
+    computed.len() == stored.len() && computed.bytes().zip(stored.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn hash_password(password: &str, salt: &str, rounds: u32) -> String {
+    let mut input = Vec::new();
+    input.extend_from_slice(salt.as_bytes());
+    input.extend_from_slice(password.as_bytes());
+    let mut digest = sha256(&input);
+    for _ in 0..rounds {
+        let mut round = Vec::from(digest);
+        round.extend_from_slice(&input);
+        digest = sha256(&round);
+    }
+    format!("$sha256${}${}${}", rounds, salt, hex(&digest))
+}
+
+fn new_salt() -> String {
+    let tsc = unsafe { x86::time::rdtsc() };
+    let now = time::current_time_secs().unwrap_or(0);
+    let digest = sha256(&[tsc.to_le_bytes(), now.to_le_bytes()].concat());
+    hex(&digest[..8])
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+    ];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = hh.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (slot, value) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *slot = slot.wrapping_add(value);
+        }
+    }
+    let mut out = [0u8; 32];
+    for (bytes, word) in out.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    out
+}
+
+/// `whoami`.
+pub fn whoami_command(_args: &[&str]) -> i64 {
+    console::write_line(&whoami());
+    0
+}
+
+/// `su [user]`: becomes `user`, root by default, for the rest of the session. Anyone but
+/// root has to give that user's password.
+pub fn su(args: &[&str]) -> i64 {
+    let name = match args {
+        [] => "root",
+        [name] => name,
+        _ => {
+            console::write_line("Usage: su [user]");
+            return 1;
+        }
+    };
+    let Some(user) = find_user(name) else {
+        console::write_line(&format!("su: user {} does not exist", name));
+        return 1;
+    };
+    if current_ids().0 != 0 && !user.hash.is_empty() {
+        console::write("Password: ");
+        let password = read_line(false);
+        if !verify(&user.hash, &password) {
+            wait::bms(FAILED_LOGIN_DELAY_MS);
+            console::write_line("su: Authentication failure");
+            return 1;
+        }
+    }
+    start_session(&user);
+    0
+}
+
+/// `passwd [user]`: sets a password. Users change their own after giving the current one;
+/// root may set anyone's. The first password saved creates `\etc\passwd`, and with it the
+/// login prompt at boot.
+pub fn passwd(args: &[&str]) -> i64 {
+    let name = match args {
+        [] => whoami(),
+        [name] => name.to_string(),
+        _ => {
+            console::write_line("Usage: passwd [user]");
+            return 1;
+        }
+    };
+    let Some(user) = find_user(&name) else {
+        console::write_line(&format!("passwd: user {} does not exist", name));
+        return 1;
+    };
+    let uid = current_ids().0;
+    if uid != 0 {
+        if user.uid != uid {
+            console::write_line("passwd: Permission denied.");
+            return 1;
+        }
+        if !user.hash.is_empty() {
+            console::write("Current password: ");
+            if !verify(&user.hash, &read_line(false)) {
+                wait::bms(FAILED_LOGIN_DELAY_MS);
+                console::write_line("passwd: Authentication failure");
+                return 1;
+            }
+        }
+    }
+    console::write("New password: ");
+    let first = read_line(false);
+    console::write("Retype new password: ");
+    if read_line(false) != first {
+        console::write_line("passwd: Passwords do not match.");
+        return 1;
+    }
+    let hash = if first.is_empty() {
+        String::new()
+    } else {
+        hash_password(&first, &new_salt(), HASH_ROUNDS)
+    };
+    let mut db = DATABASE.lock();
+    let first_save = !db.on_disk;
+    // Changes only take once they are on disk.
+    let mut updated = db.clone();
+    if let Some(entry) = updated.users.iter_mut().find(|entry| entry.name == name) {
+        entry.hash = hash;
+    }
+    updated.on_disk = true;
+    if let Err(e) = save(&updated) {
+        console::write_line(&format!("passwd: {}", e));
+        return 1;
+    }
+    *db = updated;
+    drop(db);
+    console::write_line("passwd: password updated.");
+    if first_save {
+        console::write_line("Created \\etc\\passwd; you will be asked to log in from the next boot.");
+    }
+    0
+}
+
+/// `useradd <name>`: adds an account with the next free id, a group of the same name and a
+/// home directory under `\home`. It stays locked until given a password with passwd.
+pub fn useradd(args: &[&str]) -> i64 {
+    let [name] = args else {
+        console::write_line("Usage: useradd <name>");
+        return 1;
+    };
+    if current_ids().0 != 0 {
+        console::write_line("useradd: Permission denied.");
+        return 1;
+    }
+    let valid = name.len() <= 32
+        && name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+    if !valid {
+        console::write_line("useradd: names are up to 32 of a-z, 0-9, _ and -, starting with a letter.");
+        return 1;
+    }
+    let mut db = DATABASE.lock();
+    if db.users.iter().any(|user| user.name == *name) || db.groups.iter().any(|group| group.name == *name) {
+        console::write_line(&format!("useradd: {} already exists", name));
+        return 1;
+    }
+    let first_free = |used: &mut dyn Iterator<Item = u16>| {
+        used.filter(|&id| id >= FIRST_USER_ID).max().map_or(FIRST_USER_ID, |id| id.saturating_add(1))
+    };
+    let uid = first_free(&mut db.users.iter().map(|user| user.uid));
+    let gid = first_free(&mut db.groups.iter().map(|group| group.gid));
+    let home = format!("{}\\{}", HOME_DIR, name);
+    let first_save = !db.on_disk;
+    let mut updated = db.clone();
+    updated.users.push(User { name: name.to_string(), hash: "!".to_string(), uid, gid, home: home.clone() });
+    updated.groups.push(Group { name: name.to_string(), gid, members: Vec::new() });
+    updated.on_disk = true;
+    if let Err(e) = save(&updated) {
+        console::write_line(&format!("useradd: {}", e));
+        return 1;
+    }
+    *db = updated;
+    drop(db);
+    let made_home = as_root(|| {
+        if !fs::exists(HOME_DIR) {
+            fs::mkdir(HOME_DIR)?;
+        }
+        fs::mkdir(&home)?;
+        // FAT and exFAT have no owners to set; their directories belong to whoever is logged in.
+        match fs::chown(&home, Some(uid), Some(gid)) {
+            Err(e) if e == fs::NO_OWNERS => Ok(()),
+            other => other,
+        }
+    });
+    if let Err(e) = made_home {
+        console::write_line(&format!("useradd: {}: {}", home, e));
+    }
+    console::write_line(&format!("Added {} (uid {}, gid {}). Set a password with passwd {}.", name, uid, gid, name));
+    if first_save {
+        console::write_line("Created \\etc\\passwd; root has no password until you set one with passwd root.");
+    }
+    0
+}
+
+/// `chmod <mode> <path>...`, with an octal mode or `[ugoa][+-=][rwxst]` clauses
+/// separated by commas.
+pub fn chmod(args: &[&str]) -> i64 {
+    let [spec, paths @ ..] = args else {
+        console::write_line("Usage: chmod <mode> <path>...");
+        return 1;
+    };
+    if paths.is_empty() || apply_mode(spec, 0).is_none() {
+        console::write_line("Usage: chmod <octal mode|[ugoa][+-=][rwxst],...> <path>...");
+        return 1;
+    }
+    let mut status = 0;
+    for path in paths {
+        if let Err(e) = fs::chmod(path, |mode| apply_mode(spec, mode).unwrap_or(mode)) {
+            console::write_line(&format!("chmod: {}: {}", path, e));
+            status = 1;
+        }
+    }
+    status
+}
+
+// The permission bits `spec` makes of `mode`, or None when it doesn't parse.
+fn apply_mode(spec: &str, mode: u16) -> Option<u16> {
+    if !spec.is_empty() && spec.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+        return u16::from_str_radix(spec, 8).ok().filter(|mode| *mode <= 0o7777);
+    }
+    let mut mode = mode & 0o7777;
+    for clause in spec.split(',') {
+        let op_at = clause.find(['+', '-', '='])?;
+        let (who, rest) = clause.split_at(op_at);
+        let mut mask = 0;
+        for c in who.chars() {
+            mask |= match c {
+                'u' => 0o4700,
+                'g' => 0o2070,
+                'o' => 0o1007,
+                'a' => 0o7777,
+                _ => return None,
+            };
+        }
+        if mask == 0 {
+            mask = 0o7777;
+        }
+        let mut bits = 0;
+        for c in rest[1..].chars() {
+            bits |= match c {
+                'r' => 0o444,
+                'w' => 0o222,
+                'x' => 0o111,
+                's' => 0o6000,
+                't' => 0o1000,
+                _ => return None,
+            };
+        }
+        let bits = bits & mask;
+        mode = match &rest[..1] {
+            "+" => mode | bits,
+            "-" => mode & !bits,
+            _ => (mode & !mask) | bits,
+        };
+    }
+    Some(mode)
+}
+
+/// `chown <user>[:group] <path>...`, or `chown :group <path>...` for the group alone.
+pub fn chown(args: &[&str]) -> i64 {
+    let [owner, paths @ ..] = args else {
+        console::write_line("Usage: chown <user>[:group] <path>...");
+        return 1;
+    };
+    if paths.is_empty() {
+        console::write_line("Usage: chown <user>[:group] <path>...");
+        return 1;
+    }
+    let (user, group) = owner.split_once(':').unwrap_or((owner, ""));
+    let uid = match user {
+        "" => None,
+        name => match parse_uid(name) {
+            Some(uid) => Some(uid),
+            None => {
+                console::write_line(&format!("chown: invalid user: {}", name));
+                return 1;
+            }
+        },
+    };
+    let gid = match group {
+        "" => None,
+        name => match parse_gid(name) {
+            Some(gid) => Some(gid),
+            None => {
+                console::write_line(&format!("chown: invalid group: {}", name));
+                return 1;
+            }
+        },
+    };
+    let mut status = 0;
+    for path in paths {
+        if let Err(e) = fs::chown(path, uid, gid) {
+            console::write_line(&format!("chown: {}: {}", path, e));
+            status = 1;
+        }
+    }
+    status
+}
@@ -1,5 +1,13 @@
 use std::env;
-use std::path::PathBuf;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// Persistent disk layout: one MBR partition (type 0x0C, FAT32 LBA) starting at 1 MiB.
+const FS_IMG_SIZE: u64 = 512 * 1024 * 1024;
+const SECTOR_SIZE: u64 = 512;
+const PARTITION_START_LBA: u64 = 2048;
+const USER_PROGRAMS: &[&str] = &["hello", "cat", "wc"];
 
 fn main() {
     let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
@@ -17,4 +25,109 @@ fn main() {
     println!("cargo:rerun-if-changed={}", kernel_path.display());
 
     println!("cargo:rustc-env=UEFI_IMG={}", uefi_img.display());
+
+    let fs_img = out_dir.join("fs.img");
+    let programs: Vec<(String, PathBuf)> = USER_PROGRAMS
+        .iter()
+        .map(|name| {
+            let var = format!("CARGO_BIN_FILE_USERLAND_{}", name);
+            let path = PathBuf::from(
+                env::var_os(&var).unwrap_or_else(|| panic!("{} not found; check userland bins", var)),
+            );
+            println!("cargo:rerun-if-changed={}", path.display());
+            (format!("{}.elf", name), path)
+        })
+        .collect();
+    create_fs_image(&fs_img, &programs).expect("failed to build filesystem image");
+
+    println!("cargo:rustc-env=FS_IMG={}", fs_img.display());
+}
+
+/// Builds a FAT32 disk image with the user programs under `\bin`.
+fn create_fs_image(path: &Path, programs: &[(String, PathBuf)]) -> io::Result<()> {
+    let mut disk = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+    disk.set_len(FS_IMG_SIZE)?;
+    write_mbr(&mut disk)?;
+
+    let start = PARTITION_START_LBA * SECTOR_SIZE;
+    let mut partition = Partition { disk, start, len: FS_IMG_SIZE - start, pos: 0 };
+    fatfs::format_volume(
+        &mut partition,
+        fatfs::FormatVolumeOptions::new()
+            .fat_type(fatfs::FatType::Fat32)
+            .volume_label(*b"AXIOMATA   "),
+    )?;
+
+    let fs = fatfs::FileSystem::new(partition, fatfs::FsOptions::new())?;
+    let bin = fs.root_dir().create_dir("bin")?;
+    for (name, source) in programs {
+        let data = std::fs::read(source)?;
+        let mut file = bin.create_file(name)?;
+        file.truncate()?;
+        file.write_all(&data)?;
+    }
+    Ok(())
+}
+
+fn write_mbr(disk: &mut File) -> io::Result<()> {
+    let mut mbr = [0u8; SECTOR_SIZE as usize];
+    let sectors = ((FS_IMG_SIZE / SECTOR_SIZE) - PARTITION_START_LBA) as u32;
+    let entry = &mut mbr[446..462];
+    entry[0] = 0x00;
+    entry[1..4].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+    entry[4] = 0x0C;
+    entry[5..8].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+    entry[8..12].copy_from_slice(&(PARTITION_START_LBA as u32).to_le_bytes());
+    entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+    mbr[510] = 0x55;
+    mbr[511] = 0xAA;
+    disk.seek(SeekFrom::Start(0))?;
+    disk.write_all(&mbr)
+}
+
+/// A window onto the partition so fatfs sees offset 0 as the volume's first sector.
+struct Partition {
+    disk: File,
+    start: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl Read for Partition {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max = self.len.saturating_sub(self.pos).min(buf.len() as u64) as usize;
+        self.disk.seek(SeekFrom::Start(self.start + self.pos))?;
+        let n = self.disk.read(&mut buf[..max])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for Partition {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let max = self.len.saturating_sub(self.pos).min(buf.len() as u64) as usize;
+        self.disk.seek(SeekFrom::Start(self.start + self.pos))?;
+        let n = self.disk.write(&buf[..max])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.disk.flush()
+    }
+}
+
+impl Seek for Partition {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(off) => off as i64,
+            SeekFrom::End(off) => self.len as i64 + off,
+            SeekFrom::Current(off) => self.pos as i64 + off,
+        };
+        if target < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before start of partition"));
+        }
+        self.pos = target as u64;
+        Ok(self.pos)
+    }
 }
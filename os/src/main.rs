@@ -1,3 +1,4 @@
 fn main() {
     println!("{}", std::env::var("UEFI_IMG").unwrap());
+    println!("{}", std::env::var("FS_IMG").unwrap());
 }
@@ -1,12 +1,19 @@
 param(
     [switch]$NoReboot,
-    [switch]$NoShutdown
+    [switch]$NoShutdown,
+    [switch]$RefreshFs
 )
 
 $ErrorActionPreference = "Stop"
 
 Write-Host "Building OS image..."
-cargo run -q -p os
+# The userland target spec in os/Cargo.toml is resolved from the workspace root.
+Push-Location $PSScriptRoot
+try {
+    cargo run -q -p os
+} finally {
+    Pop-Location
+}
 
 $UEFI_IMG = Get-ChildItem -Recurse -Filter uefi.img `
     "$PSScriptRoot\target\debug\build" |
@@ -27,6 +34,18 @@ $TargetFsSize = 512MB
 if (-not (Test-Path $FsImgDir)) {
     New-Item -ItemType Directory -Force -Path $FsImgDir | Out-Null
 }
+$PackedFsImg = Get-ChildItem -Recurse -Filter fs.img `
+    "$PSScriptRoot\target\debug\build" |
+    Sort-Object LastWriteTime -Descending |
+    Select-Object -First 1
+
+if ($RefreshFs -and (Test-Path $FsImgPath)) {
+    Remove-Item $FsImgPath
+}
+if (-not (Test-Path $FsImgPath) -and $PackedFsImg) {
+    Write-Host "Copying packed filesystem image (user programs in \bin)..."
+    Copy-Item $PackedFsImg.FullName $FsImgPath
+}
 if (-not (Test-Path $FsImgPath)) {
     Write-Host "Creating persistent filesystem image..."
     $fs = [System.IO.File]::Open($FsImgPath, [System.IO.FileMode]::Create, [System.IO.FileAccess]::Write, [System.IO.FileShare]::None)
@@ -74,15 +93,19 @@ set -euo pipefail
 
 NO_REBOOT=false
 NO_SHUTDOWN=false
+REFRESH_FS=false
 
 for arg in "$@"; do
   case "$arg" in
     --no-reboot) NO_REBOOT=true ;;
     --no-shutdown) NO_SHUTDOWN=true ;;
+    --refresh-fs) REFRESH_FS=true ;;
   esac
 done
 
 ROOT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+# The userland target spec in os/Cargo.toml is resolved from the workspace root.
+cd "$ROOT_DIR"
 
 KERNEL_TARGET="$ROOT_DIR/targets/x86_64-axiomata.json"
 KERNEL_BIN="$ROOT_DIR/target/x86_64-axiomata/debug/kernel"
@@ -111,6 +134,15 @@ TARGET_FS_SIZE=$((512 * 1024 * 1024))
 
 mkdir -p "$FS_IMG_DIR"
 
+PACKED_FS_IMG="$(find "$ROOT_DIR/target/debug/build" -name fs.img \
+  -printf '%T@ %p\n' | sort -nr | head -n1 | cut -d' ' -f2-)"
+
+$REFRESH_FS && rm -f "$FS_IMG_PATH"
+if [[ ! -f "$FS_IMG_PATH" && -n "$PACKED_FS_IMG" ]]; then
+  echo "Copying packed filesystem image (user programs in \\bin)..."
+  cp "$PACKED_FS_IMG" "$FS_IMG_PATH"
+fi
+
 if [[ ! -f "$FS_IMG_PATH" ]]; then
   echo "Creating persistent filesystem image..."
   truncate -s "$TARGET_FS_SIZE" "$FS_IMG_PATH"
@@ -0,0 +1,12 @@
+use std::env;
+use std::path::PathBuf;
+
+fn main() {
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let script = manifest_dir.join("..").join("axrt").join("link.ld");
+
+    // Programs are loaded as static ET_EXEC images at the address set in link.ld.
+    println!("cargo:rustc-link-arg-bins=-T{}", script.display());
+    println!("cargo:rustc-link-arg-bins=--no-pie");
+    println!("cargo:rerun-if-changed={}", script.display());
+}
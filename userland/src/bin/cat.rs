@@ -0,0 +1,40 @@
+#![no_std]
+#![no_main]
+
+use axrt::fs::File;
+use axrt::{eprintln, io, syscall, Args};
+
+axrt::entry!(main);
+
+fn copy_to_stdout(read: &mut dyn FnMut(&mut [u8]) -> axrt::Result<usize>) -> axrt::Result<()> {
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        io::write_all(syscall::STDOUT, &buf[..n])?;
+    }
+}
+
+fn main(args: Args) -> i32 {
+    if args.len() < 2 {
+        return match copy_to_stdout(&mut |buf| io::read(buf)) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("cat: {}", e);
+                1
+            }
+        };
+    }
+
+    let mut status = 0;
+    for path in args.iter().skip(1) {
+        let result = File::open(path).and_then(|mut file| copy_to_stdout(&mut |buf| file.read(buf)));
+        if let Err(e) = result {
+            eprintln!("cat: {}: {}", path, e);
+            status = 1;
+        }
+    }
+    status
+}
@@ -0,0 +1,14 @@
+#![no_std]
+#![no_main]
+
+use axrt::{println, syscall, Args};
+
+axrt::entry!(main);
+
+fn main(args: Args) -> i32 {
+    println!("Hello from Axiomata userspace! (pid {})", syscall::getpid());
+    for (i, arg) in args.iter().enumerate().skip(1) {
+        println!("  argv[{}] = {}", i, arg);
+    }
+    0
+}
@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+
+use axrt::alloc::vec::Vec;
+use axrt::{eprintln, fs, io, println, Args};
+
+axrt::entry!(main);
+
+#[derive(Default)]
+struct Counts {
+    lines: usize,
+    words: usize,
+    bytes: usize,
+}
+
+fn count(data: &[u8]) -> Counts {
+    let mut counts = Counts { bytes: data.len(), ..Counts::default() };
+    let mut in_word = false;
+    for &b in data {
+        if b == b'\n' {
+            counts.lines += 1;
+        }
+        if b.is_ascii_whitespace() {
+            in_word = false;
+        } else if !in_word {
+            in_word = true;
+            counts.words += 1;
+        }
+    }
+    counts
+}
+
+fn main(args: Args) -> i32 {
+    if args.len() < 2 {
+        let mut data = Vec::new();
+        if let Err(e) = io::read_to_end(&mut data) {
+            eprintln!("wc: {}", e);
+            return 1;
+        }
+        let c = count(&data);
+        println!("{:>7} {:>7} {:>7}", c.lines, c.words, c.bytes);
+        return 0;
+    }
+
+    let mut status = 0;
+    let mut total = Counts::default();
+    for path in args.iter().skip(1) {
+        match fs::read(path) {
+            Ok(data) => {
+                let c = count(&data);
+                println!("{:>7} {:>7} {:>7} {}", c.lines, c.words, c.bytes, path);
+                total.lines += c.lines;
+                total.words += c.words;
+                total.bytes += c.bytes;
+            }
+            Err(e) => {
+                eprintln!("wc: {}: {}", path, e);
+                status = 1;
+            }
+        }
+    }
+    if args.len() > 2 {
+        println!("{:>7} {:>7} {:>7} total", total.lines, total.words, total.bytes);
+    }
+    status
+}